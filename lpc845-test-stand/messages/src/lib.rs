@@ -2,22 +2,39 @@
 
 
 pub use protocol::{
+    AnalogOutput,
     AssistantToHost,
+    Heartbeat,
     HostToAssistant,
+    Identify,
     InputPin,
+    Location,
+    Node,
     OutputPin,
+    Panic,
+    Reset,
+    SendUsart,
     UsartMode,
+    analog,
+    i2c,
     pin,
+    rpc,
+    spi,
+    usart,
 };
 
 
-use core::convert::TryFrom;
-
 use serde::{
     Deserialize,
     Serialize,
 };
 
+use protocol::try_from_message;
+
+
+/// The address that the test assistant's I2C slave responds to
+pub const ASSISTANT_I2C_ADDRESS: u8 = 0x48;
+
 
 /// A message from the test suite on the host to the target
 ///
@@ -33,6 +50,22 @@ pub enum HostToTarget<'r> {
         data: &'r [u8],
     },
 
+    /// Instruct the target to change the baud rate and frame format of a USART
+    ///
+    /// The target replies with `UsartConfigured`. The configuration is kept
+    /// until the target is reset.
+    ConfigureUsart(usart::Configure<UsartMode>),
+
+    /// Instruct the target to stall the assistant's USART via flow control
+    ///
+    /// The target replies with `UsartStalled`.
+    StallUsart(usart::Stall<UsartMode>),
+
+    /// Instruct the target to release the assistant's stalled USART
+    ///
+    /// The target replies with `UsartReleased`.
+    ReleaseUsart(usart::Release<UsartMode>),
+
     /// Instruct the target to ignore USART data until address is matched
     WaitForAddress(u8),
 
@@ -54,29 +87,74 @@ pub enum HostToTarget<'r> {
     /// Instruct the target to stop the PWM signal
     StopPwmSignal,
 
-    /// Instruct the target to start an I2C transaction
-    StartI2cTransaction {
-        /// Which mode to use for the transaction
-        mode: DmaMode,
+    /// Instruct the target to write to an I2C slave
+    I2cWrite(i2c::Write<'r, DmaMode>),
 
-        /// The address of the slave
-        address: u8,
+    /// Instruct the target to read from an I2C slave
+    I2cRead(i2c::Read<DmaMode>),
 
-        /// The data to send to the slave
-        data: u8,
-    },
+    /// Instruct the target to write to, then read from an I2C slave
+    I2cWriteRead(i2c::WriteRead<'r, DmaMode>),
 
-    /// Instruct the target to start an SPI transaction
-    StartSpiTransaction {
-        /// Which mode to use for the transaction
-        mode: DmaMode,
+    /// Instruct the target to start an SPI transfer
+    ///
+    /// If the target is in SPI slave mode, it switches back to master mode.
+    SpiTransfer(spi::Transfer<'r, DmaMode>),
 
-        /// The data to send to the slave
-        data: u8,
-    },
+    /// Instruct the target to emulate an I2C slave device
+    ///
+    /// The target's I2C master remains available, so the I2C requests above
+    /// keep working.
+    EmulateI2cDevice(#[serde(borrow)] i2c::Emulate<'r, ()>),
+
+    /// Ask the target for the registers of the emulated I2C slave device
+    ReadI2cRegisters(i2c::ReadRegisters<()>),
+
+    /// Instruct the target to switch to SPI slave mode, using this
+    /// configuration
+    ///
+    /// Without an emulated device, the slave replies with `0xff`.
+    ConfigureSpi(spi::Configure<()>),
+
+    /// Instruct the target to switch to SPI slave mode, and emulate a device
+    EmulateSpiDevice(#[serde(borrow)] spi::Emulate<'r, ()>),
+
+    /// Ask the target what its SPI slave has received
+    ReadSpiLog(spi::ReadLog<()>),
 
     /// Instruct the target to read from the ADC
-    ReadAdc,
+    ReadAdc { input: AdcInput },
+
+    /// Instruct the target to reset all pins, peripherals, and buffers
+    ///
+    /// This stops any running timer interrupt or PWM signal, among other
+    /// things. The target confirms with `ResetComplete`, once it is back in its
+    /// initial state.
+    Reset,
+
+    /// Ask the node to identify itself
+    ///
+    /// Used by the host to confirm that it has opened the right serial port.
+    /// The target replies with `Identity`.
+    Identify,
+}
+
+impl From<usart::Configure<UsartMode>> for HostToTarget<'_> {
+    fn from(configure: usart::Configure<UsartMode>) -> Self {
+        Self::ConfigureUsart(configure)
+    }
+}
+
+impl From<usart::Stall<UsartMode>> for HostToTarget<'_> {
+    fn from(stall: usart::Stall<UsartMode>) -> Self {
+        Self::StallUsart(stall)
+    }
+}
+
+impl From<usart::Release<UsartMode>> for HostToTarget<'_> {
+    fn from(release: usart::Release<UsartMode>) -> Self {
+        Self::ReleaseUsart(release)
+    }
 }
 
 impl From<pin::SetLevel<()>> for HostToTarget<'_> {
@@ -91,6 +169,115 @@ impl From<pin::ReadLevel<()>> for HostToTarget<'_> {
     }
 }
 
+impl<'r> From<i2c::Write<'r, DmaMode>> for HostToTarget<'r> {
+    fn from(write: i2c::Write<'r, DmaMode>) -> Self {
+        Self::I2cWrite(write)
+    }
+}
+
+impl From<i2c::Read<DmaMode>> for HostToTarget<'_> {
+    fn from(read: i2c::Read<DmaMode>) -> Self {
+        Self::I2cRead(read)
+    }
+}
+
+impl<'r> From<i2c::WriteRead<'r, DmaMode>> for HostToTarget<'r> {
+    fn from(write_read: i2c::WriteRead<'r, DmaMode>) -> Self {
+        Self::I2cWriteRead(write_read)
+    }
+}
+
+impl<'r> From<spi::Transfer<'r, DmaMode>> for HostToTarget<'r> {
+    fn from(transfer: spi::Transfer<'r, DmaMode>) -> Self {
+        Self::SpiTransfer(transfer)
+    }
+}
+
+impl<'r> From<i2c::Emulate<'r, ()>> for HostToTarget<'r> {
+    fn from(emulate: i2c::Emulate<'r, ()>) -> Self {
+        Self::EmulateI2cDevice(emulate)
+    }
+}
+
+impl From<i2c::ReadRegisters<()>> for HostToTarget<'_> {
+    fn from(read_registers: i2c::ReadRegisters<()>) -> Self {
+        Self::ReadI2cRegisters(read_registers)
+    }
+}
+
+impl From<spi::Configure<()>> for HostToTarget<'_> {
+    fn from(configure: spi::Configure<()>) -> Self {
+        Self::ConfigureSpi(configure)
+    }
+}
+
+impl<'r> From<spi::Emulate<'r, ()>> for HostToTarget<'r> {
+    fn from(emulate: spi::Emulate<'r, ()>) -> Self {
+        Self::EmulateSpiDevice(emulate)
+    }
+}
+
+impl From<spi::ReadLog<()>> for HostToTarget<'_> {
+    fn from(read_log: spi::ReadLog<()>) -> Self {
+        Self::ReadSpiLog(read_log)
+    }
+}
+
+try_from_message!(HostToTarget {
+    ConfigureUsart   => usart::Configure<UsartMode>,
+    StallUsart       => usart::Stall<UsartMode>,
+    ReleaseUsart     => usart::Release<UsartMode>,
+    SetPin           => pin::SetLevel<()>,
+    ReadPin          => pin::ReadLevel<()>,
+    I2cWrite         => i2c::Write<'r, DmaMode>,
+    I2cRead          => i2c::Read<DmaMode>,
+    I2cWriteRead     => i2c::WriteRead<'r, DmaMode>,
+    SpiTransfer      => spi::Transfer<'r, DmaMode>,
+    EmulateI2cDevice => i2c::Emulate<'r, ()>,
+    ReadI2cRegisters => i2c::ReadRegisters<()>,
+    ConfigureSpi     => spi::Configure<()>,
+    EmulateSpiDevice => spi::Emulate<'r, ()>,
+    ReadSpiLog       => spi::ReadLog<()>,
+});
+
+try_from_message!(HostToTarget => SendUsart<'r> {
+    HostToTarget::SendUsart { mode, data } => SendUsart { mode, data }
+});
+
+try_from_message!(HostToTarget => WaitForAddress {
+    HostToTarget::WaitForAddress(address) => WaitForAddress(address)
+});
+
+try_from_message!(HostToTarget => StartTimerInterrupt {
+    HostToTarget::StartTimerInterrupt { period_ms } => {
+        StartTimerInterrupt { period_ms }
+    }
+});
+
+try_from_message!(HostToTarget => StopTimerInterrupt {
+    HostToTarget::StopTimerInterrupt => StopTimerInterrupt
+});
+
+try_from_message!(HostToTarget => StartPwmSignal {
+    HostToTarget::StartPwmSignal => StartPwmSignal
+});
+
+try_from_message!(HostToTarget => StopPwmSignal {
+    HostToTarget::StopPwmSignal => StopPwmSignal
+});
+
+try_from_message!(HostToTarget => ReadAdc {
+    HostToTarget::ReadAdc { input } => ReadAdc { input }
+});
+
+try_from_message!(HostToTarget => Reset {
+    HostToTarget::Reset => Reset
+});
+
+try_from_message!(HostToTarget => Identify {
+    HostToTarget::Identify => Identify
+});
+
 
 /// An message from the target to the test suite on the host
 ///
@@ -106,38 +293,152 @@ pub enum TargetToHost<'r> {
         data: &'r [u8],
     },
 
+    /// Notify the host that a USART has detected an error on its line
+    ///
+    /// The data of a frame that had an error is discarded.
+    UsartLineEvent(usart::LineEvent<UsartMode>),
+
+    /// Reply to a `ConfigureUsart` request
+    UsartConfigured(usart::Configured<UsartMode>),
+
+    /// Reply to a `StallUsart` request
+    UsartStalled(usart::Stalled<UsartMode>),
+
+    /// Reply to a `ReleaseUsart` request
+    UsartReleased(usart::Released<UsartMode>),
+
     /// Reply to a `ReadPin` request
     ReadPinResult(Option<pin::ReadLevelResult<()>>),
 
-    /// Notify the host that the I2C transaction completed
-    I2cReply(u8),
+    /// Notify the host that an I2C transaction completed
+    I2cCompleted(#[serde(borrow)] i2c::Completed<'r, DmaMode>),
+
+    /// Notify the host that an SPI transfer completed
+    SpiCompleted(#[serde(borrow)] spi::Completed<'r, DmaMode>),
+
+    /// Reply to an `EmulateI2cDevice` request
+    I2cEmulated(i2c::Emulated<()>),
+
+    /// Reply to a `ReadI2cRegisters` request
+    I2cRegisters(#[serde(borrow)] i2c::Registers<'r, ()>),
 
-    /// Notify the host that the SPI transaction completed
-    SpiReply(u8),
+    /// Reply to a `ConfigureSpi` request
+    SpiConfigured(spi::Configured<()>),
+
+    /// Reply to an `EmulateSpiDevice` request
+    SpiEmulated(spi::Emulated<()>),
+
+    /// Reply to a `ReadSpiLog` request
+    SpiLog(#[serde(borrow)] spi::Log<'r, ()>),
 
     /// Reply to `ReadAdc` request
     AdcValue(u16),
+
+    /// Confirms that the target has been reset
+    ResetComplete,
+
+    /// Reply to an `Identify` request
+    Identity(Node),
+
+    /// Notify the host that the target has started up
+    ///
+    /// Sent once on startup, before the target processes any requests.
+    Booted {
+        /// The cause of the reset that preceded startup
+        reset_cause: ResetCause,
+    },
 }
 
-impl<'r> TryFrom<TargetToHost<'r>> for pin::ReadLevelResult<()> {
-    type Error = TargetToHost<'r>;
+try_from_message!(TargetToHost {
+    UsartLineEvent  => usart::LineEvent<UsartMode>,
+    UsartConfigured => usart::Configured<UsartMode>,
+    UsartStalled    => usart::Stalled<UsartMode>,
+    UsartReleased   => usart::Released<UsartMode>,
+    I2cCompleted    => i2c::Completed<'r, DmaMode>,
+    SpiCompleted    => spi::Completed<'r, DmaMode>,
+    I2cEmulated     => i2c::Emulated<()>,
+    I2cRegisters    => i2c::Registers<'r, ()>,
+    SpiConfigured   => spi::Configured<()>,
+    SpiEmulated     => spi::Emulated<()>,
+    SpiLog          => spi::Log<'r, ()>,
+});
+
+try_from_message!(TargetToHost => pin::ReadLevelResult<()> {
+    TargetToHost::ReadPinResult(Some(result)) => result
+});
+
+
+/// The contents of a `WaitForAddress` request
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct WaitForAddress(pub u8);
+
+/// The contents of a `StartTimerInterrupt` request
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct StartTimerInterrupt {
+    pub period_ms: u32,
+}
 
-    fn try_from(value: TargetToHost<'r>) -> Result<Self, Self::Error> {
-        match value {
-            TargetToHost::ReadPinResult(Some(result)) => {
-                Ok(result)
-            }
-            _ => {
-                Err(value)
-            }
-        }
-    }
+/// The contents of a `StopTimerInterrupt` request
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct StopTimerInterrupt;
+
+/// The contents of a `StartPwmSignal` request
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct StartPwmSignal;
+
+/// The contents of a `StopPwmSignal` request
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct StopPwmSignal;
+
+/// The contents of a `ReadAdc` request
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct ReadAdc {
+    pub input: AdcInput,
+}
+
+
+/// The cause of a reset, as reported by the target on startup
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, Eq, PartialEq)]
+pub enum ResetCause {
+    /// The device was powered on
+    PowerOn,
+
+    /// The RESET pin was asserted
+    External,
+
+    /// The supply voltage dropped below the brown-out threshold
+    Brownout,
+
+    /// The watchdog timer expired
+    Watchdog,
+
+    /// The firmware requested a reset
+    Software,
+
+    /// The reset cause couldn't be determined
+    Unknown,
 }
 
 
 /// Specifies whether a transmission uses DMA or not
+///
+/// Also serves as the bus ID for the generic types in [`i2c`] and [`spi`].
+///
+/// [`i2c`]: i2c/index.html
+/// [`spi`]: spi/index.html
 #[derive(Clone, Copy, Debug, Deserialize, Serialize, Eq, PartialEq)]
 pub enum DmaMode {
     Regular,
     Dma,
 }
+
+
+/// Represents one of the target's analog inputs
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, Eq, PartialEq)]
+pub enum AdcInput {
+    /// The input that is connected to the assistant's pin 5
+    Pin5,
+
+    /// The input that is connected to the assistant's analog output
+    Analog,
+}
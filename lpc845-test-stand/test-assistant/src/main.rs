@@ -8,14 +8,17 @@
 #![no_std]
 
 
-extern crate panic_rtt_target;
-
-
 use core::marker::PhantomData;
 
 use heapless::{
+    ArrayLength,
     FnvIndexMap,
-    consts::U8,
+    consts::{
+        U8,
+        U64,
+        U256,
+    },
+    spsc,
 };
 use lpc8xx_hal::{
     prelude::*,
@@ -35,11 +38,9 @@ use lpc8xx_hal::{
         MRT2,
         MRT3,
     },
-    nb::{
-        self,
-        block,
-    },
+    nb,
     pac::{
+        self,
         I2C0,
         SPI0,
         USART0,
@@ -57,6 +58,7 @@ use lpc8xx_hal::{
         PIO0_8,
         PIO0_9,
         PIO0_20,
+        PIO0_21,
         PIO0_23,
         PIO1_0,
         PIO1_1,
@@ -79,38 +81,95 @@ use lpc8xx_hal::{
     },
 };
 use rtt_target::rprintln;
+use void::ResultVoidExt;
 
 #[cfg(feature = "sleep")]
 use lpc8xx_hal::cortex_m::asm;
 
 use firmware_lib::{
+    analog::Scale,
+    heartbeat::{
+        self,
+        Ticker,
+    },
+    dispatch::Dispatcher,
+    i2c::Device as I2cDevice,
+    lpc8xx::{
+        self,
+        EdgeCapture,
+        PinInt,
+        QueuedTx,
+        RxInt,
+        Tx,
+        TxInt,
+    },
+    panic,
     pin_interrupt::{
         self,
         PinInterrupt,
     },
+    spi::{
+        Device as SpiDevice,
+        Log as SpiLog,
+    },
     usart::{
         RxIdle,
-        RxInt,
-        Tx,
+        TxQueue,
         Usart,
+        rx::ProcessError,
+        timing::Meter,
+        tx,
     },
 };
 use lpc845_messages::{
+    ASSISTANT_I2C_ADDRESS,
+    AnalogOutput,
     AssistantToHost,
+    Heartbeat,
     HostToAssistant,
+    Identify,
     InputPin,
+    Node,
     OutputPin,
+    Reset,
+    SendUsart,
     UsartMode,
+    analog,
+    i2c as protocol_i2c,
     pin,
+    rpc,
+    spi as protocol_spi,
+    usart as protocol_usart,
 };
 
 
+/// The frequency of the clock that drives the USARTs, in Hz
+///
+/// The FRG divides the 12 MHz FRO by `1 + 22 / 256`. See `init`.
+const USART_CLOCK_HZ: u32 = 11_050_359;
+
+/// The baud rate the USARTs connected to the target use, until reconfigured
+const USART_BAUD: u32 = 115_200;
+
+/// The frequency of the clock that drives CTIMER0, in Hz
+///
+/// CTIMER0 runs from the 12 MHz system clock, without a prescaler.
+const CTIMER_CLOCK_HZ: u32 = 12_000_000;
+
+/// The range and resolution of DAC1
+///
+/// The DAC outputs voltages between ground and VREFP, which is connected to
+/// 3.3 V on the LPC845-BRK.
+const DAC_SCALE: Scale = Scale::new(10, 3300);
+
+
 #[rtic::app(device = lpc8xx_hal::pac)]
 const APP: () = {
     struct Resources {
         host_rx_int:  RxInt<'static, USART0, AsyncMode>,
         host_rx_idle: RxIdle<'static>,
-        host_tx:      Tx<USART0, AsyncMode>,
+        host_tx_int:  TxInt<'static, USART0, AsyncMode>,
+        host_tx:      QueuedTx<'static, USART0>,
 
         target_rx_int:   RxInt<'static, USART1, AsyncMode>,
         target_rx_idle:  RxIdle<'static>,
@@ -120,46 +179,64 @@ const APP: () = {
             usart::state::Enabled<u8, AsyncMode>,
             usart::state::NoThrottle,
         >,
-        target_rts_int:  pin_interrupt::Int<'static, PININT2, PIO0_9, MRT2>,
+        target_rts_int:  PinInt<'static, PININT2, PIO0_9, MRT2>,
         target_rts_idle: pin_interrupt::Idle<'static>,
 
+        target_timing_int:  EdgeCapture<'static>,
+        target_timing_idle: spsc::Consumer<'static, pin_interrupt::Event, U64>,
+
         target_sync_rx_int:  RxInt<'static, USART3, SyncMode>,
         target_sync_rx_idle: RxIdle<'static>,
         target_sync_tx:      Tx<USART3, SyncMode>,
 
-        green_int:  pin_interrupt::Int<'static, PININT0, PIO1_0, MRT0>,
+        green_int:  PinInt<'static, PININT0, PIO1_0, MRT0>,
         green_idle: pin_interrupt::Idle<'static>,
 
-        blue_int:  pin_interrupt::Int<'static, PININT1, PIO1_1, MRT1>,
+        blue_int:  PinInt<'static, PININT1, PIO1_1, MRT1>,
         blue_idle: pin_interrupt::Idle<'static>,
 
-        pwm_int:  pin_interrupt::Int<'static, PININT3, PIO0_23, MRT3>,
-        pwm_idle: pin_interrupt::Idle<'static>,
+        pwm_int:  PinInt<'static, PININT3, PIO0_23, MRT3, U64>,
+        pwm_idle: pin_interrupt::Idle<'static, U64>,
 
         pin_5: GpioPin<PIO0_20, Output>,
+        dac: pac::DAC1,
         cts: GpioPin<PIO0_8, Output>,
         red: GpioPin<PIO1_2, Output>,
+        target_reset: GpioPin<PIO0_21, Output>,
         green: GpioPin<PIO1_0, Input>,
 
-        i2c: i2c::Slave<I2C0, Enabled<PhantomData<IOSC>>, Enabled>,
-        spi: SPI<SPI0, Enabled<spi::Slave>>,
+        i2c:        i2c::Slave<I2C0, Enabled<PhantomData<IOSC>>, Enabled>,
+        i2c_master: i2c::Master<I2C0, Enabled<PhantomData<IOSC>>, Enabled>,
+        i2c_device: Option<I2cDevice>,
+
+        spi:        SPI<SPI0, Enabled<spi::Slave>>,
+        spi_device: Option<SpiDevice>,
+        spi_log:    SpiLog,
+
+        ticker: Ticker,
     }
 
     #[init]
-    fn init(_: init::Context) -> init::LateResources {
+    fn init(cx: init::Context) -> init::LateResources {
         // Normally, access to a `static mut` would be unsafe, but we know that
         // this method is only called once, which means we have exclusive access
         // here. RTFM knows this too, and by putting these statics right here,
         // at the beginning of the method, we're opting into some RTFM magic
         // that gives us safe access to them.
         static mut HOST:        Usart = Usart::new();
+        static mut HOST_TX:     TxQueue = TxQueue::new();
         static mut TARGET:      Usart = Usart::new();
         static mut TARGET_SYNC: Usart = Usart::new();
 
         static mut GREEN: PinInterrupt = PinInterrupt::new();
         static mut BLUE:  PinInterrupt = PinInterrupt::new();
         static mut RTS:   PinInterrupt = PinInterrupt::new();
-        static mut PWM:   PinInterrupt = PinInterrupt::new();
+        // The PWM signal can produce events faster than the idle loop
+        // processes them, so give it some more room.
+        static mut PWM:   PinInterrupt<U64> = PinInterrupt::new();
+
+        static mut TIMING: spsc::Queue<pin_interrupt::Event, U64> =
+            spsc::Queue(heapless::i::Queue::new());
 
         rtt_target::rtt_init_print!();
         rprintln!("Starting assistant.");
@@ -209,6 +286,12 @@ const APP: () = {
             gpio::Level::Low,
         );
 
+        // DAC1 drives the target's analog input, once the host requests an
+        // analog voltage. Until then, its pin is used as USART2's TX.
+        syscon.handle.enable_clock(&p.IOCON);
+        enable_dac1();
+        let dac = p.DAC1;
+
         // Configure pin connected to target's input pin
         let red = p.pins.pio1_2.into_output_pin(
             gpio.tokens.pio1_2,
@@ -220,6 +303,13 @@ const APP: () = {
             gpio::Level::Low,
         );
 
+        // Configure pin connected to the target's RESET pin. RESET is active
+        // low, so the target is running while this is high.
+        let target_reset = p.pins.pio0_21.into_output_pin(
+            gpio.tokens.pio0_21,
+            gpio::Level::High,
+        );
+
         // Configure the clock for USART0, using the Fractional Rate Generator
         // (FRG) and the USART's own baud rate divider value (BRG). See user
         // manual, section 17.7.1.
@@ -286,6 +376,13 @@ const APP: () = {
             .. usart::Interrupts::default()
         });
 
+        // Capture the edges on the target's TX line, to measure its timing.
+        // CTIMER0 counts system clock cycles, and wraps around freely.
+        assign_timing_capture(26);
+        let ctimer = p.CTIMER0.enable(u32::MAX, 0, &mut syscon.handle).free();
+        let (timing_prod, target_timing_idle) = TIMING.split();
+        let target_timing_int = EdgeCapture::new(ctimer, timing_prod);
+
         // Configure interrupt for RTS pin
         let rts = p.pins.pio0_9.into_input_pin(gpio.tokens.pio0_9);
         let mut rts_int = pinint
@@ -343,10 +440,16 @@ const APP: () = {
             .. usart::Interrupts::default()
         });
 
-        let (host_rx_int,   host_rx_idle,   host_tx)   = HOST.init(host);
-        let (target_rx_int, target_rx_idle, target_tx) = TARGET.init(target);
+        let (host_rx_int, host_rx_idle, host_tx) =
+            HOST.init(host.rx, host.tx);
+        let (host_tx_int, host_tx) = HOST_TX.init(host_tx);
+        let (target_rx_int, target_rx_idle, target_tx) =
+            TARGET.init(target.rx, target.tx);
         let (target_sync_rx_int, target_sync_rx_idle, target_sync_tx) =
-            TARGET_SYNC.init(target_sync);
+            TARGET_SYNC.init(target_sync.rx, target_sync.tx);
+
+        // From now on, report panics to the host, not just via RTT.
+        panic::report_to(lpc8xx::write_raw::<USART0>);
 
         let (green_int, green_idle) = GREEN.init(green_int, timers.mrt0);
         let (blue_int,  blue_idle)  = BLUE.init(blue_int, timers.mrt1);
@@ -366,8 +469,11 @@ const APP: () = {
                 i2c0_sda,
                 &mut syscon.handle,
             )
+            .enable_master_mode(
+                &i2c::Clock::new_400khz(),
+            )
             .enable_slave_mode(
-                0x48,
+                ASSISTANT_I2C_ADDRESS,
             )
             .expect("Not using a valid address");
         i2c.enable_interrupts(i2c::Interrupts {
@@ -401,10 +507,6 @@ const APP: () = {
             spi0_miso,
             spi0_ssel0,
         );
-        spi.enable_interrupts(spi::Interrupts {
-            rx_ready: true,
-            .. Default::default()
-        });
         spi.enable_interrupts(spi::Interrupts {
             rx_ready: true,
             slave_select_asserted: true,
@@ -412,9 +514,19 @@ const APP: () = {
             .. Default::default()
         });
 
+        // Use SysTick to keep track of time for the heartbeat. By default (and
+        // we haven't changed that setting), the SysTick timer runs at half the
+        // system frequency of 12 MHz. At 6 MHz, 1 ms are 6000 timer ticks.
+        let mut systick = cx.core.SYST;
+        systick.set_reload(heartbeat::PERIOD_MS * 6000);
+        systick.clear_current();
+        systick.enable_interrupt();
+        systick.enable_counter();
+
         init::LateResources {
             host_rx_int,
             host_rx_idle,
+            host_tx_int,
             host_tx,
 
             target_rx_int,
@@ -424,6 +536,9 @@ const APP: () = {
             target_rts_int:  rts_int,
             target_rts_idle: rts_idle,
 
+            target_timing_int,
+            target_timing_idle,
+
             target_sync_rx_int,
             target_sync_rx_idle,
             target_sync_tx,
@@ -438,12 +553,21 @@ const APP: () = {
             pwm_idle,
 
             pin_5,
+            dac,
             red,
             green,
             cts,
+            target_reset,
+
+            i2c:        i2c.slave,
+            i2c_master: i2c.master,
+            i2c_device: None,
 
-            i2c: i2c.slave,
             spi,
+            spi_device: None,
+            spi_log:    SpiLog::default(),
+
+            ticker: Ticker::new(),
         }
     }
 
@@ -460,10 +584,28 @@ const APP: () = {
             blue_idle,
             pwm_idle,
             target_rts_idle,
+            target_timing_int,
+            target_timing_idle,
             pin_5,
+            dac,
             red,
             green,
             cts,
+            target_reset,
+            host_rx_int,
+            target_rx_int,
+            target_sync_rx_int,
+            i2c,
+            i2c_device,
+            i2c_master,
+            spi,
+            spi_device,
+            spi_log,
+            ticker,
+            green_int,
+            blue_int,
+            pwm_int,
+            target_rts_int,
         ]
     )]
     fn idle(cx: idle::Context) -> ! {
@@ -478,129 +620,432 @@ const APP: () = {
         let blue           = cx.resources.blue_idle;
         let pwm            = cx.resources.pwm_idle;
         let rts            = cx.resources.target_rts_idle;
+        let timing_events  = cx.resources.target_timing_idle;
         let pin_5          = cx.resources.pin_5;
+        let dac            = cx.resources.dac;
         let red            = cx.resources.red;
         let green          = cx.resources.green;
         let cts            = cx.resources.cts;
+        let target_reset   = cx.resources.target_reset;
+        let i2c_master     = cx.resources.i2c_master;
+
+        let mut host_rx_int        = cx.resources.host_rx_int;
+        let mut target_rx_int      = cx.resources.target_rx_int;
+        let mut target_sync_rx_int = cx.resources.target_sync_rx_int;
+        let mut i2c                = cx.resources.i2c;
+        let mut i2c_device         = cx.resources.i2c_device;
+        let mut spi                = cx.resources.spi;
+        let mut spi_device         = cx.resources.spi_device;
+        let mut spi_log            = cx.resources.spi_log;
+        let mut timing_int         = cx.resources.target_timing_int;
+        let mut ticker             = cx.resources.ticker;
+        let mut green_int          = cx.resources.green_int;
+        let mut blue_int           = cx.resources.blue_int;
+        let mut pwm_int            = cx.resources.pwm_int;
+        let mut rts_int            = cx.resources.target_rts_int;
 
         let mut pins = FnvIndexMap::<_, _, U8>::new();
+        read_initial_levels(green, &mut pins);
 
-        // ensure that the initial level for green is known before the first level change
-        let level = match green.is_high() {
-            true  => pin::Level::High,
-            false => pin::Level::Low,
-        };
-        pins.insert(InputPin::Green as usize, (level, None)).unwrap();
+        // Data that is sent to the target with flow control. Sending it might
+        // take a while, if the target stalls us, and we need to keep serving
+        // the host in the meantime.
+        let mut flow_control_tx = spsc::Queue::<u8, U256>::new();
 
-        let mut buf = [0; 256];
+        let mut usart_timing = Meter::new(CTIMER_CLOCK_HZ);
+
+        let mut buf           = [0; 256];
+        let mut i2c_registers = [0; 128];
+        let mut spi_transfers = [0; 128];
+
+        // The replies to I2C and SPI transactions borrow the data that was
+        // read. Each handler needs a buffer of its own for that, as all
+        // replies share the same lifetime.
+        let mut i2c_read       = [0; 32];
+        let mut i2c_write_read = [0; 32];
+        let mut spi_received   = [0; 32];
 
         loop {
             target_rx
                 .process_raw(|data| {
-                    host_tx.send_message(
-                        &AssistantToHost::UsartReceive {
-                            mode: UsartMode::Regular,
-                            data,
-                        },
-                        &mut buf,
-                    )
+                    forward(host_tx, UsartMode::Regular, data, &mut buf)
                 })
                 .expect("Error processing USART data");
             target_sync_rx
                 .process_raw(|data| {
-                    host_tx.send_message(
-                        &AssistantToHost::UsartReceive {
-                            mode: UsartMode::Sync,
-                            data,
-                        },
-                        &mut buf,
-                    )
+                    forward(host_tx, UsartMode::Sync, data, &mut buf)
                 })
                 .expect("Error processing USART data");
+            lpc8xx::usart_send_throttled(
+                &mut target_tx.usart,
+                &mut flow_control_tx,
+            );
+            while let Some(event) = timing_events.dequeue() {
+                usart_timing.process(event);
+            }
 
             host_rx
-                .process_message(|message| {
-                    match message {
-                        HostToAssistant::SendUsart {
-                            mode: UsartMode::Regular,
-                            data,
-                        } => {
-                            target_tx.send_raw(data)
-                        }
-                        HostToAssistant::SendUsart {
-                            mode: UsartMode::Dma,
-                            data,
-                        } => {
-                            rprintln!("Sending USART message using DMA.");
-                            target_tx_dma.bwrite_all(data)
-                        }
-                        HostToAssistant::SendUsart {
-                            mode: UsartMode::FlowControl,
-                            data: _,
-                        } => {
-                            Ok(())
-                        }
-                        HostToAssistant::SendUsart {
-                            mode: UsartMode::Sync,
-                            data,
-                        } => {
-                            target_sync_tx.send_raw(data)
-                        }
-                        HostToAssistant::SetPin(
-                            pin::SetLevel {
-                                pin: OutputPin::Pin5,
-                                level,
+                .process_messages(|frame| {
+                    let request: rpc::Request<HostToAssistant> =
+                        frame.decode()?;
+
+                    Dispatcher::new(request, host_tx, &mut buf)
+                        .on(|SendUsart { mode, data }| {
+                            let result = match mode {
+                                UsartMode::Regular => {
+                                    target_tx.send_raw(data)
+                                        .void_unwrap();
+                                    Ok(())
+                                }
+                                UsartMode::Dma => {
+                                    rprintln!(
+                                        "Sending USART message using DMA."
+                                    );
+                                    target_tx_dma.bwrite_all(data)
+                                        .void_unwrap();
+                                    Ok(())
+                                }
+                                UsartMode::FlowControl => {
+                                    // Reject the data as a whole, instead of
+                                    // sending only what fits into the queue.
+                                    let free = flow_control_tx.capacity()
+                                        - flow_control_tx.len();
+                                    if data.len() > free {
+                                        Err(protocol_usart::Error::QueueFull)
+                                    }
+                                    else {
+                                        // The target's RTS signal is connected
+                                        // to PIO0_9. The data is sent from the
+                                        // top of the loop.
+                                        assign_usart_cts(Some(9));
+                                        for &b in data {
+                                            flow_control_tx.enqueue(b)
+                                                .unwrap();
+                                        }
+                                        Ok(())
+                                    }
+                                }
+                                UsartMode::Sync => {
+                                    target_sync_tx.send_raw(data)
+                                        .void_unwrap();
+                                    Ok(())
+                                }
+                            };
+                            Some(AssistantToHost::UsartSent(
+                                protocol_usart::Sent { usart: mode, result }
+                            ))
+                        })
+                        .on(|config: protocol_usart::Configure<UsartMode>| {
+                            let result = configure_usart(
+                                target_tx,
+                                target_tx_dma,
+                                &config,
+                            );
+                            Some(AssistantToHost::UsartConfigured(
+                                protocol_usart::Configured {
+                                    usart: config.usart,
+                                    result,
+                                }
+                            ))
+                        })
+                        .on(|stall: protocol_usart::Stall<UsartMode>| {
+                            let result = match stall.usart {
+                                UsartMode::FlowControl => {
+                                    // The target's CTS signal is connected to
+                                    // PIO0_8, which is otherwise controlled as
+                                    // a GPIO output.
+                                    assign_usart_rts(Some(8));
+                                    target_rx_int.lock(|rx| {
+                                        rx.stall(stall.after)
+                                    });
+                                    Ok(())
+                                }
+                                _ => {
+                                    Err(protocol_usart::Error::UnsupportedMode)
+                                }
+                            };
+                            Some(AssistantToHost::UsartStalled(
+                                protocol_usart::Stalled {
+                                    usart: stall.usart,
+                                    result,
+                                }
+                            ))
+                        })
+                        .on(|release: protocol_usart::Release<UsartMode>| {
+                            let result = match release.usart {
+                                UsartMode::FlowControl => {
+                                    target_rx_int.lock(|rx| {
+                                        lpc8xx::usart_release(rx)
+                                    });
+                                    Ok(())
+                                }
+                                _ => {
+                                    Err(protocol_usart::Error::UnsupportedMode)
+                                }
+                            };
+                            Some(AssistantToHost::UsartReleased(
+                                protocol_usart::Released {
+                                    usart: release.usart,
+                                    result,
+                                }
+                            ))
+                        })
+                        .on(|params: protocol_usart::MeasureTiming<UsartMode>| {
+                            timing_int.lock(|int| int.disable());
+                            while timing_events.dequeue().is_some() {}
+
+                            // Only the target's USART1 sends to us, which the
+                            // regular and flow control modes share.
+                            let result = match params.usart {
+                                UsartMode::Regular
+                                    | UsartMode::FlowControl =>
+                                {
+                                    usart_timing.start(&params);
+                                    timing_int.lock(|int| int.enable());
+                                    Ok(())
+                                }
+                                _ => {
+                                    usart_timing.stop();
+                                    Err(protocol_usart::Error::UnsupportedMode)
+                                }
+                            };
+
+                            Some(AssistantToHost::UsartTimingStarted(
+                                protocol_usart::TimingStarted {
+                                    usart: params.usart,
+                                    result,
+                                }
+                            ))
+                        })
+                        .on(|protocol_usart::ReadTiming { usart }| {
+                            while let Some(event) = timing_events.dequeue() {
+                                usart_timing.process(event);
                             }
-                        ) => {
-                            match level {
-                                pin::Level::High => {
+
+                            Some(AssistantToHost::UsartTiming(
+                                usart_timing.timing(usart)
+                            ))
+                        })
+                        .on(|inject: protocol_usart::Inject<UsartMode>| {
+                            let protocol_usart::Inject {
+                                usart,
+                                fault,
+                                data,
+                            } = inject;
+
+                            // Faults can only be injected into the USART that
+                            // the regular mode uses.
+                            let result = match usart {
+                                UsartMode::Regular => {
+                                    lpc8xx::usart_inject(
+                                        &mut target_tx.usart,
+                                        fault,
+                                        data,
+                                        USART_CLOCK_HZ,
+                                    )
+                                }
+                                _ => {
+                                    Err(protocol_usart::Error::UnsupportedFault)
+                                }
+                            };
+
+                            Some(AssistantToHost::UsartInjected(
+                                protocol_usart::Injected { usart, result }
+                            ))
+                        })
+                        .on(|analog::SetOutput { channel, millivolts }| {
+                            let value = DAC_SCALE.value(millivolts);
+
+                            match channel {
+                                AnalogOutput::Adc => {
+                                    dac.cr.write(|w| unsafe {
+                                        w.value().bits(value)
+                                    });
+                                    assign_analog_output(true);
+                                }
+                            }
+
+                            Some(AssistantToHost::AnalogSet(
+                                analog::OutputSet {
+                                    channel,
+                                    millivolts: DAC_SCALE.millivolts(value),
+                                }
+                            ))
+                        })
+                        .on(|pin::SetLevel { pin, level }| {
+                            match (pin, level) {
+                                (OutputPin::Pin5, pin::Level::High) => {
                                     pin_5.set_high();
                                 }
-                                pin::Level::Low => {
+                                (OutputPin::Pin5, pin::Level::Low) => {
                                     pin_5.set_low();
                                 }
-                            }
-                            Ok(())
-                        }
-                        HostToAssistant::SetPin(
-                            pin::SetLevel {
-                                pin: OutputPin::Red,
-                                level,
-                            }
-                        ) => {
-                            match level {
-                                pin::Level::High => {
+                                (OutputPin::Red, pin::Level::High) => {
                                     red.set_high();
                                 }
-                                pin::Level::Low => {
+                                (OutputPin::Red, pin::Level::Low) => {
                                     red.set_low();
                                 }
+                                (OutputPin::Cts, pin::Level::High) => {
+                                    rprintln!("Setting CTS HIGH");
+                                    cts.set_high();
+                                }
+                                (OutputPin::Cts, pin::Level::Low) => {
+                                    rprintln!("Setting CTS LOW");
+                                    cts.set_low();
+                                }
+                                (OutputPin::TargetReset, pin::Level::High) => {
+                                    rprintln!("Releasing target from reset");
+                                    target_reset.set_high();
+                                }
+                                (OutputPin::TargetReset, pin::Level::Low) => {
+                                    rprintln!("Holding target in reset");
+                                    target_reset.set_low();
+                                }
                             }
-                            Ok(())
-                        }
-                        HostToAssistant::SetPin(
-                            pin::SetLevel {
-                                pin: OutputPin::Cts,
-                                level: pin::Level::High,
-                            }
-                        ) => {
-                            rprintln!("Setting CTS HIGH");
-                            cts.set_high();
-                            Ok(())
-                        }
-                        HostToAssistant::SetPin(
-                            pin::SetLevel {
-                                pin: OutputPin::Cts,
-                                level: pin::Level::Low,
-                            }
-                        ) => {
-                            rprintln!("Setting CTS LOW");
-                            cts.set_low();
-                            Ok(())
-                        }
-                        HostToAssistant::ReadPin(
-                            pin::ReadLevel { pin }
-                        ) => {
+                            None
+                        })
+                        .on(|protocol_spi::Configure { bus: (), config }| {
+                            let result = spi.lock(|spi| {
+                                lpc8xx::spi_configure(spi, &config)
+                            });
+
+                            Some(AssistantToHost::SpiConfigured(
+                                protocol_spi::Configured { bus: (), result }
+                            ))
+                        })
+                        .on(|protocol_spi::Emulate { bus: (), device }| {
+                            let result = SpiDevice::new(&device)
+                                .map(|mut device| {
+                                    spi.lock(|spi| {
+                                        lpc8xx::spi_preload(
+                                            spi,
+                                            device.preload().into(),
+                                        );
+                                        spi_device.lock(|spi_device| {
+                                            *spi_device = Some(device);
+                                        });
+                                    });
+                                });
+
+                            Some(AssistantToHost::SpiEmulated(
+                                protocol_spi::Emulated { bus: (), result }
+                            ))
+                        })
+                        .on(|protocol_spi::ReadLog { bus: () }| {
+                            let (len, overflowed) = spi_log.lock(|log| {
+                                let transfers = log.transfers();
+                                spi_transfers[..transfers.len()]
+                                    .copy_from_slice(transfers);
+                                let result =
+                                    (transfers.len(), log.overflowed());
+
+                                log.clear();
+                                result
+                            });
+
+                            Some(AssistantToHost::SpiLog(
+                                protocol_spi::Log {
+                                    bus:       (),
+                                    transfers: &spi_transfers[..len],
+                                    overflowed,
+                                }
+                            ))
+                        })
+                        .on(|transfer: protocol_spi::Transfer<()>| {
+                            let result = spi.lock(|spi| {
+                                spi_transfer(spi, transfer, &mut spi_received)
+                            });
+
+                            Some(AssistantToHost::SpiCompleted(
+                                protocol_spi::Completed { bus: (), result }
+                            ))
+                        })
+                        .on(|protocol_i2c::Write { bus: (), address, data }| {
+                            let result = i2c_transaction(
+                                i2c_master,
+                                address,
+                                data,
+                                0,
+                                &mut [],
+                            );
+
+                            Some(AssistantToHost::I2cCompleted(
+                                protocol_i2c::Completed { bus: (), result }
+                            ))
+                        })
+                        .on(|protocol_i2c::Read { bus: (), address, len }| {
+                            let result = i2c_transaction(
+                                i2c_master,
+                                address,
+                                &[],
+                                len,
+                                &mut i2c_read,
+                            );
+
+                            Some(AssistantToHost::I2cCompleted(
+                                protocol_i2c::Completed { bus: (), result }
+                            ))
+                        })
+                        .on(|write_read: protocol_i2c::WriteRead<()>| {
+                            let protocol_i2c::WriteRead {
+                                bus: (),
+                                address,
+                                data,
+                                len,
+                            } = write_read;
+                            let result = i2c_transaction(
+                                i2c_master,
+                                address,
+                                data,
+                                len,
+                                &mut i2c_write_read,
+                            );
+
+                            Some(AssistantToHost::I2cCompleted(
+                                protocol_i2c::Completed { bus: (), result }
+                            ))
+                        })
+                        .on(|protocol_i2c::Emulate { bus: (), device }| {
+                            let result = I2cDevice::new(&device)
+                                .and_then(|device| {
+                                    i2c.lock(|i2c| {
+                                        lpc8xx::i2c_set_slave_address(
+                                            i2c,
+                                            device.address(),
+                                        )
+                                        .map_err(|_| {
+                                            protocol_i2c::Error::Other
+                                        })?;
+                                        i2c_device.lock(|i2c_device| {
+                                            *i2c_device = Some(device);
+                                        });
+                                        Ok(())
+                                    })
+                                });
+
+                            Some(AssistantToHost::I2cEmulated(
+                                protocol_i2c::Emulated { bus: (), result }
+                            ))
+                        })
+                        .on(|protocol_i2c::ReadRegisters { bus: () }| {
+                            let len = i2c_device.lock(|i2c_device| {
+                                let registers = i2c_device.as_ref()
+                                    .map(|device| device.registers())
+                                    .unwrap_or(&[]);
+                                i2c_registers[..registers.len()]
+                                    .copy_from_slice(registers);
+                                registers.len()
+                            });
+
+                            Some(AssistantToHost::I2cRegisters(
+                                protocol_i2c::Registers {
+                                    bus:       (),
+                                    registers: &i2c_registers[..len],
+                                }
+                            ))
+                        })
+                        .on(|pin::ReadLevel { pin }| {
                             let result = pins.get(&(pin as usize))
                                 .map(|&(level, period_ms)| {
                                     pin::ReadLevelResult {
@@ -610,25 +1055,133 @@ const APP: () = {
                                     }
                                 });
 
-                            host_tx
-                                .send_message(
-                                    &AssistantToHost::ReadPinResult(result),
-                                    &mut buf,
+                            Some(AssistantToHost::ReadPinResult(result))
+                        })
+                        .on(|Reset| {
+                            rprintln!("Resetting assistant.");
+
+                            // Restore the initial levels of all output pins.
+                            pin_5.set_low();
+                            red.set_high();
+                            cts.set_low();
+                            target_reset.set_high();
+
+                            // Give the DAC's pin back to USART2.
+                            assign_analog_output(false);
+                            dac.cr.reset();
+
+                            // Stop emulating any I2C device.
+                            i2c.lock(|i2c| {
+                                lpc8xx::i2c_set_slave_address(
+                                    i2c,
+                                    ASSISTANT_I2C_ADDRESS,
+                                )
+                            })
+                            .unwrap();
+                            i2c_device.lock(|i2c_device| *i2c_device = None);
+
+                            // Stop emulating any SPI device, and restore the
+                            // initial SPI configuration.
+                            spi_device.lock(|spi_device| *spi_device = None);
+                            spi_log.lock(|log| log.clear());
+                            spi.lock(|spi| {
+                                lpc8xx::spi_configure(
+                                    spi,
+                                    &protocol_spi::Config::default(),
+                                )
+                            })
+                            .unwrap();
+
+                            // Stop stalling the target's USART, and give
+                            // control over the CTS pin back to the host. Drop
+                            // anything the target has stalled us on.
+                            assign_usart_rts(None);
+                            while flow_control_tx.dequeue().is_some() {}
+                            target_rx_int.lock(|rx| lpc8xx::usart_release(rx));
+
+                            // Stop measuring the target's USART timing.
+                            timing_int.lock(|int| int.disable());
+                            usart_timing.stop();
+
+                            // Restore the initial USART configuration.
+                            for &mode in &[UsartMode::Regular, UsartMode::Dma] {
+                                configure_usart(
+                                    target_tx,
+                                    target_tx_dma,
+                                    &protocol_usart::Configure::new(
+                                        mode,
+                                        USART_BAUD,
+                                    ),
                                 )
                                 .unwrap();
+                            }
 
-                            Ok(())
-                        }
-                    }
+                            // Forget anything that happened before the reset.
+                            green_int.lock(|int| int.reset());
+                            blue_int.lock(|int| int.reset());
+                            pwm_int.lock(|int| int.reset());
+                            rts_int.lock(|int| int.reset());
+                            target_rx.discard();
+                            target_sync_rx.discard();
+                            green_idle.discard();
+                            blue.discard();
+                            rts.discard();
+                            pwm.discard();
+                            while timing_events.dequeue().is_some() {}
+
+                            pins = FnvIndexMap::new();
+                            read_initial_levels(green, &mut pins);
+
+                            Some(AssistantToHost::ResetComplete)
+                        })
+                        .on(|Identify| {
+                            Some(AssistantToHost::Identity(Node::Assistant))
+                        })
+                        .finish()
+                        .map_err(|err| ProcessError::Other(err))
                 })
-                .expect("Error processing host request");
-            host_rx.clear_buf();
+                .unwrap_or_else(|err| {
+                    // The request has been dropped. Nothing we can do about
+                    // that, except to carry on with the next one.
+                    rprintln!("Error processing host request: {:?}", err);
+                });
 
             handle_pin_interrupt(green_idle, InputPin::Green, &mut pins);
             handle_pin_interrupt(blue,  InputPin::Blue,  &mut pins);
             handle_pin_interrupt(rts,   InputPin::Rts,   &mut pins);
             handle_pin_interrupt(pwm,   InputPin::Pwm,   &mut pins);
 
+            if let Some(uptime_ms) = ticker.lock(|ticker| ticker.due()) {
+                let rx_errors = host_rx_int.lock(|rx| rx.errors())
+                    .wrapping_add(target_rx_int.lock(|rx| rx.errors()))
+                    .wrapping_add(target_sync_rx_int.lock(|rx| rx.errors()));
+                let queue_high_water = host_rx.take_high_water()
+                    .max(target_rx.take_high_water())
+                    .max(target_sync_rx.take_high_water());
+                let events_dropped = green_int.lock(|int| int.overflows())
+                    .wrapping_add(blue_int.lock(|int| int.overflows()))
+                    .wrapping_add(rts_int.lock(|int| int.overflows()))
+                    .wrapping_add(pwm_int.lock(|int| int.overflows()))
+                    .wrapping_add(timing_int.lock(|int| int.overflows()));
+
+                let heartbeat = Heartbeat {
+                    uptime_ms,
+                    rx_errors,
+                    queue_high_water: queue_high_water as u32,
+                    events_dropped,
+                };
+
+                // If the host transmit queue is full, drop the heartbeat
+                // instead of waiting for room. The next one is due soon.
+                let result = host_tx.try_send_message(
+                    &rpc::Response::heartbeat(heartbeat),
+                    &mut buf,
+                );
+                if let Err(tx::Error::Postcard(err)) = result {
+                    panic!("Error serializing heartbeat: {:?}", err);
+                }
+            }
+
             // We need this critical section to protect against a race
             // conditions with the interrupt handlers. Otherwise, the following
             // sequence of events could occur:
@@ -644,7 +1197,9 @@ const APP: () = {
                 let should_sleep =
                     !host_rx.can_process()
                     && !target_rx.can_process()
-                    && !green_idle.is_ready();
+                    && !green_idle.is_ready()
+                    && !timing_events.ready()
+                    && flow_control_tx.is_empty();
 
                 if should_sleep {
                     // On LPC84x MCUs, debug mode is not supported when
@@ -658,22 +1213,35 @@ const APP: () = {
         }
     }
 
-    #[task(binds = USART0, resources = [host_rx_int])]
+    // Receive errors are counted by `RxInt` and reported to the host with the
+    // next heartbeat, so there's no need to panic here.
+
+    #[task(binds = USART0, resources = [host_rx_int, host_tx_int])]
     fn usart0(cx: usart0::Context) {
-        cx.resources.host_rx_int.receive()
-            .expect("Error receiving from USART0");
+        if let Err(err) = cx.resources.host_rx_int.receive() {
+            rprintln!("Error receiving from USART0: {:?}", err);
+        }
+        cx.resources.host_tx_int.transmit()
+            .void_unwrap();
     }
 
     #[task(binds = USART1, resources = [target_rx_int])]
     fn usart1(cx: usart1::Context) {
-        cx.resources.target_rx_int.receive()
-            .expect("Error receiving from USART1");
+        if let Err(err) = lpc8xx::usart_receive(cx.resources.target_rx_int) {
+            rprintln!("Error receiving from USART1: {:?}", err);
+        }
     }
 
     #[task(binds = PIN_INT6_USART3, resources = [target_sync_rx_int])]
     fn usart3(cx: usart3::Context) {
-        cx.resources.target_sync_rx_int.receive()
-            .expect("Error receiving from USART3");
+        if let Err(err) = cx.resources.target_sync_rx_int.receive() {
+            rprintln!("Error receiving from USART3: {:?}", err);
+        }
+    }
+
+    #[task(binds = SysTick, resources = [ticker])]
+    fn syst(cx: syst::Context) {
+        cx.resources.ticker.tick();
     }
 
     #[task(binds = PIN_INT0, resources = [green_int])]
@@ -696,16 +1264,30 @@ const APP: () = {
         context.resources.pwm_int.handle_interrupt();
     }
 
-    #[task(binds = I2C0, resources = [i2c])]
+    // This has to run before the next edge of the same kind is captured, so
+    // don't let any other interrupt handler delay it.
+    #[task(binds = CTIMER0, priority = 2, resources = [target_timing_int])]
+    fn ctimer0(context: ctimer0::Context) {
+        context.resources.target_timing_int.handle_interrupt();
+    }
+
+    #[task(binds = I2C0, resources = [i2c, i2c_device])]
     fn i2c0(context: i2c0::Context) {
         static mut DATA: Option<u8> = None;
 
+        // Without an emulated device, the slave replies to the last byte it
+        // received, shifted left by one.
+        let device = context.resources.i2c_device;
+
         rprintln!("I2C: Handling I2C0 interrupt...");
 
         match context.resources.i2c.wait() {
             Ok(i2c::slave::State::AddressMatched(i2c)) => {
                 rprintln!("I2C: Address matched.");
 
+                if let Some(device) = device {
+                    device.start();
+                }
                 i2c.ack().unwrap();
 
                 rprintln!("I2C: Ack'ed address.");
@@ -713,18 +1295,43 @@ const APP: () = {
             Ok(i2c::slave::State::RxReady(i2c)) => {
                 rprintln!("I2C: Ready to receive.");
 
-                *DATA = Some(i2c.read().unwrap());
-                i2c.ack().unwrap();
+                let data = i2c.read().unwrap();
+
+                match device {
+                    Some(device) => {
+                        stretch_clock(device);
+
+                        if device.write(data) {
+                            i2c.ack().unwrap();
+                        }
+                        else {
+                            i2c.nack().unwrap();
+                        }
+                    }
+                    None => {
+                        *DATA = Some(data);
+                        i2c.ack().unwrap();
+                    }
+                }
 
-                rprintln!("I2C: Received and ack'ed.");
+                rprintln!("I2C: Received and handled.");
             }
             Ok(i2c::slave::State::TxReady(i2c)) => {
                 rprintln!("I2C: Ready to transmit.");
 
-                if let Some(data) = *DATA {
-                    i2c.transmit(data << 1).unwrap();
-                    rprintln!("I2C: Transmitted.");
+                match device {
+                    Some(device) => {
+                        stretch_clock(device);
+                        i2c.transmit(device.read()).unwrap();
+                    }
+                    None => {
+                        if let Some(data) = *DATA {
+                            i2c.transmit(data << 1).unwrap();
+                        }
+                    }
                 }
+
+                rprintln!("I2C: Transmitted.");
             }
             Err(nb::Error::WouldBlock) => {
                 // I2C not ready; nothing to do
@@ -735,42 +1342,289 @@ const APP: () = {
         }
     }
 
-    #[task(binds = SPI0, resources = [spi])]
+    #[task(binds = SPI0, resources = [spi, spi_device, spi_log])]
     fn spi0(context: spi0::Context) {
         static mut ACTIVE: bool = false;
 
-        let spi = context.resources.spi;
+        let spi    = context.resources.spi;
+        let device = context.resources.spi_device;
+        let log    = context.resources.spi_log;
 
         if spi.is_slave_select_asserted() {
             *ACTIVE = true;
+
+            if let Some(device) = device {
+                device.start();
+            }
         }
         if *ACTIVE {
-            if spi.is_ready_to_receive() {
-                let data = spi.receive().unwrap();
-                block!(spi.transmit(data << 1))
-                    .unwrap();
+            if let Some(data) = lpc8xx::spi_receive(spi) {
+                log.push(data as u8);
+
+                // Without an emulated device, the slave replies to each frame
+                // with the frame shifted left by one.
+                let reply = match device {
+                    Some(device) => device.receive(data as u8).into(),
+                    None         => data << 1,
+                };
+                lpc8xx::spi_transmit(spi, reply);
             }
         }
         if spi.is_slave_select_deasserted() {
             *ACTIVE = false;
+            log.end();
         }
     }
 };
 
 
-fn handle_pin_interrupt(
-    int:  &mut pin_interrupt::Idle,
+/// Hold the I2C clock low, if the emulated device is configured to do that
+///
+/// The slave holds the clock low until it has been serviced, so all we need to
+/// do is wait. The system clock runs at 12 MHz, so 1 µs is 12 cycles.
+fn stretch_clock(device: &I2cDevice) {
+    let us = device.clock_stretch_us();
+    if us > 0 {
+        lpc8xx_hal::cortex_m::asm::delay(us.saturating_mul(12));
+    }
+}
+
+/// Forward data received from the target to the host
+///
+/// Splits the data into several messages, if it doesn't fit into one. Only
+/// waits for the host transmit queue, if it doesn't have enough room left.
+fn forward(
+    host_tx: &mut QueuedTx<'static, USART0>,
+    mode:    UsartMode,
+    data:    &[u8],
+    buf:     &mut [u8],
+)
+    -> Result<(), tx::Error<tx::QueueFull>>
+{
+    // Leaves enough room for the message overhead, both in the buffer and
+    // in the host transmit queue.
+    const MAX_CHUNK: usize = 128;
+
+    for data in data.chunks(MAX_CHUNK) {
+        host_tx.send_notification(
+            AssistantToHost::UsartReceive { mode, data },
+            buf,
+        )?;
+    }
+
+    Ok(())
+}
+
+/// Configures a USART connected to the target, as the host requested
+///
+/// Only the USARTs used by the regular and DMA modes can be configured.
+/// Returns the baud rate that was actually configured.
+fn configure_usart(
+    target_tx:     &mut Tx<USART1, AsyncMode>,
+    target_tx_dma: &mut usart::Tx<
+        USART2,
+        usart::state::Enabled<u8, AsyncMode>,
+        usart::state::NoThrottle,
+    >,
+    config:        &protocol_usart::Configure<UsartMode>,
+)
+    -> Result<u32, protocol_usart::Error>
+{
+    match config.usart {
+        UsartMode::Regular => {
+            lpc8xx::usart_configure(
+                &mut target_tx.usart,
+                config,
+                USART_CLOCK_HZ,
+            )
+        }
+        UsartMode::Dma => {
+            lpc8xx::usart_configure(target_tx_dma, config, USART_CLOCK_HZ)
+        }
+        UsartMode::FlowControl | UsartMode::Sync => {
+            Err(protocol_usart::Error::UnsupportedConfig)
+        }
+    }
+}
+
+/// Assigns the CTS function of USART1 to a PIO0 pin, or unassigns it
+///
+/// CTS is an input, so the pin remains usable as a GPIO input.
+fn assign_usart_cts(pin: Option<u8>) {
+    // Sound, as `lpc8xx-hal` doesn't know about this assignment, and nothing
+    // else accesses the switch matrix after initialization.
+    let swm = unsafe { &*pac::SWM0::ptr() };
+
+    swm.pinassign2.modify(|_, w| unsafe {
+        w.u1_cts_i().bits(pin.unwrap_or(0xff))
+    });
+}
+
+/// Assigns the RTS function of USART1 to a PIO0 pin, or unassigns it
+///
+/// RTS overrides the GPIO output of the pin, while it is assigned.
+fn assign_usart_rts(pin: Option<u8>) {
+    // Sound, as `lpc8xx-hal` doesn't know about this assignment, and nothing
+    // else accesses the switch matrix after initialization.
+    let swm = unsafe { &*pac::SWM0::ptr() };
+
+    swm.pinassign1.modify(|_, w| unsafe {
+        w.u1_rts_o().bits(pin.unwrap_or(0xff))
+    });
+}
+
+/// Assigns the capture inputs 0 and 1 of CTIMER0 to a PIO0 pin
+///
+/// `lpc8xx-hal` treats these functions as outputs, so it can't assign them to a
+/// pin that is already in use by another function.
+fn assign_timing_capture(pin: u8) {
+    // Sound, as `lpc8xx-hal` doesn't know about this assignment, and nothing
+    // else accesses the switch matrix after initialization.
+    let swm = unsafe { &*pac::SWM0::ptr() };
+
+    swm.pinassign14.modify(|_, w| unsafe {
+        w.t0_cap0().bits(pin);
+        w.t0_cap1().bits(pin)
+    });
+}
+
+/// Enables the clock of DAC1 and powers it up
+///
+/// `lpc8xx-hal` doesn't support the DAC, so this accesses the registers
+/// directly.
+fn enable_dac1() {
+    // Sound, as this is only called during initialization, and `lpc8xx-hal`
+    // doesn't know about DAC1.
+    let syscon = unsafe { &*pac::SYSCON::ptr() };
+
+    syscon.sysahbclkctrl1.modify(|_, w| w.dac1().enable());
+    // The DAC is powered down, while this bit is set.
+    syscon.pdruncfg.modify(|_, w| w.dac1().clear_bit());
+}
+
+/// Switches PIO0_29 between DAC1's output and USART2's TX
+///
+/// The DAC output is a fixed function, which takes precedence over the
+/// movable USART2 TX function assigned to the same pin.
+fn assign_analog_output(enable: bool) {
+    // Sound, as `lpc8xx-hal` doesn't know about this assignment, and nothing
+    // else accesses the switch matrix or IOCON after initialization.
+    let swm   = unsafe { &*pac::SWM0::ptr() };
+    let iocon = unsafe { &*pac::IOCON::ptr() };
+
+    if enable {
+        // The PAC doesn't know about the DACMODE bit (16) of PIO0_29. Also
+        // disable the pull-up (bits 3 and 4), so it doesn't load the output.
+        iocon.pio0_29.modify(|r, w| unsafe {
+            w.bits((r.bits() & !(0b11 << 3)) | (1 << 16))
+        });
+        swm.pinenable0.modify(|_, w| w.dacout1().enabled());
+    }
+    else {
+        swm.pinenable0.modify(|_, w| w.dacout1().disabled());
+        iocon.pio0_29.reset();
+    }
+}
+
+/// Executes an I2C transaction as master, as the host requested
+///
+/// Writes `data`, then reads `len` bytes into `buf` after a repeated start,
+/// skipping either if it's empty. Returns the data that was read.
+fn i2c_transaction<'b>(
+    master:  &mut i2c::Master<I2C0, Enabled<PhantomData<IOSC>>, Enabled>,
+    address: u8,
+    data:    &[u8],
+    len:     u16,
+    buf:     &'b mut [u8],
+)
+    -> Result<&'b [u8], protocol_i2c::Error>
+{
+    let len = len as usize;
+    if len > buf.len() {
+        return Err(protocol_i2c::Error::TooLong);
+    }
+    let buf = &mut buf[..len];
+
+    lpc8xx::i2c_transaction(master, address, data, buf)?;
+
+    Ok(buf)
+}
+
+/// Executes an SPI transfer as master, as the host requested
+///
+/// The SPI peripheral is a slave for the target the rest of the time. It only
+/// switches to master mode for the duration of the transfer. Returns the
+/// frames received from the slave, which are stored in `buf`.
+fn spi_transfer<'b>(
+    spi:      &mut SPI<SPI0, Enabled<spi::Slave>>,
+    transfer: protocol_spi::Transfer<()>,
+    buf:      &'b mut [u8],
+)
+    -> Result<&'b [u8], protocol_spi::Error>
+{
+    use protocol_spi::ChipSelect;
+
+    let protocol_spi::Transfer { bus: (), config, chip_select, data } =
+        transfer;
+
+    if data.len() > buf.len() {
+        return Err(protocol_spi::Error::TooLong);
+    }
+    // Switching back to slave mode releases chip select, so we can't hold it.
+    if chip_select == ChipSelect::Hold {
+        return Err(protocol_spi::Error::UnsupportedConfig);
+    }
+    let buf = &mut buf[..data.len()];
+    buf.copy_from_slice(data);
+
+    // Use the same SPI clock as the target does, when it's the master.
+    lpc8xx::spi_enable_master_mode(spi, 0x0fff);
+
+    let result = lpc8xx::spi_configure(spi, &config);
+    if result.is_ok() {
+        if chip_select == ChipSelect::Frame {
+            let frame_len = if config.frame_size > 8 { 2 } else { 1 };
+
+            for frame in buf.chunks_mut(frame_len) {
+                lpc8xx::spi_transfer(spi, config.frame_size, frame);
+                lpc8xx::spi_end_transfer(spi);
+            }
+        }
+        else {
+            lpc8xx::spi_transfer(spi, config.frame_size, buf);
+            lpc8xx::spi_end_transfer(spi);
+        }
+    }
+
+    lpc8xx::spi_enable_slave_mode(spi);
+    lpc8xx::spi_preload(spi, 0xffff);
+
+    result?;
+    Ok(buf)
+}
+
+/// Records the levels of input pins that are known before the first change
+fn read_initial_levels(
+    green: &GpioPin<PIO1_0, Input>,
+    pins:  &mut FnvIndexMap<usize, (pin::Level, Option<u32>), U8>,
+) {
+    let level = match green.is_high() {
+        true  => pin::Level::High,
+        false => pin::Level::Low,
+    };
+    pins.insert(InputPin::Green as usize, (level, None)).unwrap();
+}
+
+fn handle_pin_interrupt<N>(
+    int:  &mut pin_interrupt::Idle<N>,
     pin:  InputPin,
     pins: &mut FnvIndexMap<usize, (pin::Level, Option<u32>), U8>,
-) {
+)
+    where N: ArrayLength<pin_interrupt::Event>
+{
     while let Some(event) = int.next() {
         match event {
             pin_interrupt::Event { level, period } => {
-                let level = match level {
-                    gpio::Level::High => pin::Level::High,
-                    gpio::Level::Low  => pin::Level::Low,
-                };
-
                 let period_ms = period.map(|value| value / 12_000);
                 pins.insert(pin as usize, (level, period_ms)).unwrap();
             }
@@ -1,18 +1,30 @@
 /// Test-suite specific error module
 
 
-use host_lib::assistant::AssistantError;
+use host_lib::{
+    assistant::AssistantError,
+    serial::SerialError,
+    test_stand::NotConfiguredError,
+};
 use super::{
     target::{
+        TargetI2cEmulateError,
         TargetI2cError,
+        TargetI2cReadRegistersError,
         TargetPinReadError,
         TargetSetPinHighError,
         TargetSetPinLowError,
+        TargetSpiConfigureError,
+        TargetSpiEmulateError,
         TargetSpiError,
+        TargetSpiReadLogError,
         TargetStartTimerInterruptError,
+        TargetUsartConfigureError,
         TargetUsartSendError,
+        TargetUsartStallError,
         TargetUsartWaitError,
         TargetWaitForAddressError,
+        TargetWaitForBootError,
     },
     test_stand::TestStandInitError,
 };
@@ -26,15 +38,25 @@ pub type Result<T = ()> = std::result::Result<T, Error>;
 #[derive(Debug)]
 pub enum Error {
     Assistant(AssistantError),
+    NotConfigured(NotConfiguredError),
+    Serial(SerialError),
     TargetI2c(TargetI2cError),
+    TargetI2cEmulate(TargetI2cEmulateError),
+    TargetI2cReadRegisters(TargetI2cReadRegistersError),
     TargetPinRead(TargetPinReadError),
     TargetSetPinHigh(TargetSetPinHighError),
     TargetSetPinLow(TargetSetPinLowError),
     TargetSpi(TargetSpiError),
+    TargetSpiConfigure(TargetSpiConfigureError),
+    TargetSpiEmulate(TargetSpiEmulateError),
+    TargetSpiReadLog(TargetSpiReadLogError),
     TargetStartTimerInterrupt(TargetStartTimerInterruptError),
+    TargetUsartConfigure(TargetUsartConfigureError),
     TargetUsartSend(TargetUsartSendError),
+    TargetUsartStall(TargetUsartStallError),
     TargetUsartWait(TargetUsartWaitError),
     TargetWaitForAddress(TargetWaitForAddressError),
+    TargetWaitForBoot(TargetWaitForBootError),
     TestStandInit(TestStandInitError),
 }
 
@@ -44,12 +66,36 @@ impl From<AssistantError> for Error {
     }
 }
 
+impl From<NotConfiguredError> for Error {
+    fn from(err: NotConfiguredError) -> Self {
+        Self::NotConfigured(err)
+    }
+}
+
+impl From<SerialError> for Error {
+    fn from(err: SerialError) -> Self {
+        Self::Serial(err)
+    }
+}
+
 impl From<TargetI2cError> for Error {
     fn from(err: TargetI2cError) -> Self {
         Self::TargetI2c(err)
     }
 }
 
+impl From<TargetI2cEmulateError> for Error {
+    fn from(err: TargetI2cEmulateError) -> Self {
+        Self::TargetI2cEmulate(err)
+    }
+}
+
+impl From<TargetI2cReadRegistersError> for Error {
+    fn from(err: TargetI2cReadRegistersError) -> Self {
+        Self::TargetI2cReadRegisters(err)
+    }
+}
+
 impl From<TargetPinReadError> for Error {
     fn from(err: TargetPinReadError) -> Self {
         Self::TargetPinRead(err)
@@ -62,18 +108,48 @@ impl From<TargetSpiError> for Error {
     }
 }
 
+impl From<TargetSpiConfigureError> for Error {
+    fn from(err: TargetSpiConfigureError) -> Self {
+        Self::TargetSpiConfigure(err)
+    }
+}
+
+impl From<TargetSpiEmulateError> for Error {
+    fn from(err: TargetSpiEmulateError) -> Self {
+        Self::TargetSpiEmulate(err)
+    }
+}
+
+impl From<TargetSpiReadLogError> for Error {
+    fn from(err: TargetSpiReadLogError) -> Self {
+        Self::TargetSpiReadLog(err)
+    }
+}
+
 impl From<TargetStartTimerInterruptError> for Error {
     fn from(err: TargetStartTimerInterruptError) -> Self {
         Self::TargetStartTimerInterrupt(err)
     }
 }
 
+impl From<TargetUsartConfigureError> for Error {
+    fn from(err: TargetUsartConfigureError) -> Self {
+        Self::TargetUsartConfigure(err)
+    }
+}
+
 impl From<TargetUsartSendError> for Error {
     fn from(err: TargetUsartSendError) -> Self {
         Self::TargetUsartSend(err)
     }
 }
 
+impl From<TargetUsartStallError> for Error {
+    fn from(err: TargetUsartStallError) -> Self {
+        Self::TargetUsartStall(err)
+    }
+}
+
 impl From<TargetSetPinHighError> for Error {
     fn from(err: TargetSetPinHighError) -> Self {
         Self::TargetSetPinHigh(err)
@@ -98,6 +174,12 @@ impl From<TargetWaitForAddressError> for Error {
     }
 }
 
+impl From<TargetWaitForBootError> for Error {
+    fn from(err: TargetWaitForBootError) -> Self {
+        Self::TargetWaitForBoot(err)
+    }
+}
+
 impl From<TestStandInitError> for Error {
     fn from(err: TestStandInitError) -> Self {
         Self::TestStandInit(err)
@@ -5,22 +5,52 @@ use std::time::{
 
 use lpc845_messages::{
     DmaMode,
+    Heartbeat,
     HostToTarget,
+    Node,
+    ResetCause,
     TargetToHost,
     UsartMode,
+    i2c,
     pin,
+    spi,
+    usart::{
+        Configure as UsartConfigure,
+        LineCondition,
+    },
 };
 
 use host_lib::{
+    config::Timeouts,
     conn::{
         Conn,
+        ConnClearError,
         ConnReceiveError,
         ConnSendError,
     },
+    i2c::{
+        EmulateError,
+        I2c,
+        ReadRegistersError,
+        TransactionError,
+    },
     pin::{
         Pin,
         ReadLevelError,
     },
+    spi::{
+        ConfigureError as SpiConfigureError,
+        EmulateError as SpiEmulateError,
+        Log as SpiLog,
+        ReadLogError,
+        Spi,
+        TransferError,
+    },
+    usart::{
+        ConfigureError,
+        StallError,
+        Usart,
+    },
 };
 
 
@@ -38,6 +68,118 @@ impl Target {
         }
     }
 
+    /// The default timeouts for communicating with the target
+    pub fn timeouts(&self) -> &Timeouts {
+        self.conn.timeouts()
+    }
+
+    /// The last heartbeat received from the target, and when it arrived
+    ///
+    /// Heartbeats are only received while waiting for other messages from the
+    /// target.
+    pub fn last_heartbeat(&self) -> Option<(Instant, Heartbeat)> {
+        self.conn.last_heartbeat()
+    }
+
+    /// Confirm that the connection actually leads to the test target
+    ///
+    /// Asks the node to identify itself and returns an error, if it doesn't
+    /// reply as the target within the given timeout.
+    pub fn handshake(&mut self, timeout: Duration)
+        -> Result<(), TargetHandshakeError>
+    {
+        let id = self.conn
+            .send(&HostToTarget::Identify)
+            .map_err(|err| TargetHandshakeError::Send(err))?;
+
+        let mut tmp = Vec::new();
+        let message = self.conn
+            .receive_reply::<TargetToHost>(id, timeout, &mut tmp)
+            .map_err(|err| TargetHandshakeError::Receive(err))?;
+
+        match message {
+            TargetToHost::Identity(Node::Target) => {
+                Ok(())
+            }
+            TargetToHost::Identity(node) => {
+                Err(TargetHandshakeError::WrongNode(node))
+            }
+            message => {
+                Err(
+                    TargetHandshakeError::UnexpectedMessage(
+                        format!("{:?}", message)
+                    )
+                )
+            }
+        }
+    }
+
+    /// Reset the target to its initial state
+    ///
+    /// Discards any data from the target that hasn't been processed yet, then
+    /// instructs the target to reset all its pins, peripherals, and buffers.
+    /// Waits until the target confirms the reset.
+    pub fn reset(&mut self) -> Result<(), TargetResetError> {
+        let timeout = self.conn.timeouts().reply();
+
+        self.conn
+            .clear_input()
+            .map_err(|err| TargetResetError::Clear(err))?;
+        let id = self.conn
+            .send(&HostToTarget::Reset)
+            .map_err(|err| TargetResetError::Send(err))?;
+        self.conn.restart_heartbeat();
+
+        let start = Instant::now();
+
+        loop {
+            if start.elapsed() > timeout {
+                return Err(TargetResetError::Timeout);
+            }
+
+            let mut tmp = Vec::new();
+            let message = self.conn
+                .receive_reply::<TargetToHost>(id, timeout, &mut tmp)
+                .map_err(|err| TargetResetError::Receive(err))?;
+
+            // Anything the target sent before the reset is left over from
+            // whatever happened before. We can safely ignore it.
+            if let TargetToHost::ResetComplete = message {
+                return Ok(());
+            }
+        }
+    }
+
+    /// Wait for the target to boot
+    ///
+    /// Call this after resetting the target in hardware, for example using
+    /// `Assistant::reset_target`. Returns the cause of the reset, as reported
+    /// by the target.
+    pub fn wait_for_boot(&mut self, timeout: Duration)
+        -> Result<ResetCause, TargetWaitForBootError>
+    {
+        self.conn.restart_heartbeat();
+
+        let start = Instant::now();
+
+        loop {
+            if start.elapsed() > timeout {
+                return Err(TargetWaitForBootError::Timeout);
+            }
+
+            let mut tmp = Vec::new();
+            let message = self.conn
+                .receive::<TargetToHost>(timeout, &mut tmp)
+                .map_err(|err| TargetWaitForBootError::Receive(err))?;
+
+            // Anything the target sent before it was reset is of no interest
+            // to us.
+            if let TargetToHost::Booted { reset_cause } = message {
+                return Ok(reset_cause);
+            }
+        }
+    }
+
     /// Instruct the target to set a GPIO pin high
     pub fn set_pin_high(&mut self) -> Result<(), TargetSetPinHighError> {
         self.pin
@@ -63,8 +205,9 @@ impl Target {
     /// Uses `pin_state` internally.
     pub fn pin_is_high(&mut self) -> Result<bool, TargetPinReadError> {
         let pin_state = self.pin.read_level::<HostToTarget, TargetToHost>(
-            Duration::from_millis(10),
+            self.conn.timeouts().read(),
             &mut self.conn,
+            &mut Vec::new(),
         )?;
         Ok(pin_state.0 == pin::Level::High)
     }
@@ -74,19 +217,38 @@ impl Target {
     /// Uses `pin_state` internally.
     pub fn pin_is_low(&mut self) -> Result<bool, TargetPinReadError> {
         let pin_state = self.pin.read_level::<HostToTarget, TargetToHost>(
-            Duration::from_millis(10),
+            self.conn.timeouts().read(),
             &mut self.conn,
+            &mut Vec::new(),
         )?;
         Ok(pin_state.0 == pin::Level::Low)
     }
 
+    /// Change the baud rate and frame format of one of the target's USARTs
+    ///
+    /// Returns the baud rate that the target's USART actually runs at. The
+    /// configuration is kept until the target is reset.
+    pub fn configure_usart(&mut self, config: UsartConfigure<UsartMode>)
+        -> Result<u32, TargetUsartConfigureError>
+    {
+        Usart::new(config.usart)
+            .configure::<HostToTarget, TargetToHost>(
+                config,
+                &mut self.conn,
+                &mut Vec::new(),
+            )
+            .map_err(|err| TargetUsartConfigureError(err))
+    }
+
     /// Instruct the target to send this message via USART
     pub fn send_usart(&mut self, data: &[u8])
         -> Result<(), TargetUsartSendError>
     {
         self.conn
             .send(&HostToTarget::SendUsart { mode: UsartMode::Regular, data })
-            .map_err(|err| TargetUsartSendError(err))
+            .map_err(|err| TargetUsartSendError(err))?;
+
+        Ok(())
     }
 
     /// Instruct the target to send this message via USART using DMA
@@ -95,7 +257,9 @@ impl Target {
     {
         self.conn
             .send(&HostToTarget::SendUsart { mode: UsartMode::Dma, data })
-            .map_err(|err| TargetUsartSendError(err))
+            .map_err(|err| TargetUsartSendError(err))?;
+
+        Ok(())
     }
 
     /// Instruct the target to send this message via USART using DMA
@@ -104,7 +268,9 @@ impl Target {
     {
         self.conn
             .send(&HostToTarget::SendUsart { mode: UsartMode::Sync, data })
-            .map_err(|err| TargetUsartSendError(err))
+            .map_err(|err| TargetUsartSendError(err))?;
+
+        Ok(())
     }
 
     /// Instruct the target to send this message via USART using DMA
@@ -116,7 +282,36 @@ impl Target {
                 mode: UsartMode::FlowControl,
                 data,
             })
-            .map_err(|err| TargetUsartSendError(err))
+            .map_err(|err| TargetUsartSendError(err))?;
+
+        Ok(())
+    }
+
+    /// Instruct the target to stall the assistant's USART, using flow control
+    ///
+    /// The target deasserts RTS, once it has received `after` bytes from the
+    /// assistant, plus one that it holds back until the assistant is released.
+    /// The target drives RTS from now on, until it is reset.
+    pub fn stall_usart(&mut self, after: u32)
+        -> Result<(), TargetUsartStallError>
+    {
+        Usart::new(UsartMode::FlowControl)
+            .stall::<HostToTarget, TargetToHost>(
+                after,
+                &mut self.conn,
+                &mut Vec::new(),
+            )
+            .map_err(TargetUsartStallError)
+    }
+
+    /// Instruct the target to release the assistant's stalled USART
+    pub fn release_usart(&mut self) -> Result<(), TargetUsartStallError> {
+        Usart::new(UsartMode::FlowControl)
+            .release::<HostToTarget, TargetToHost>(
+                &mut self.conn,
+                &mut Vec::new(),
+            )
+            .map_err(TargetUsartStallError)
     }
 
     /// Wait to receive the provided data via USART
@@ -189,13 +384,64 @@ impl Target {
         }
     }
 
+    /// Wait for the USART to report a condition on its line
+    ///
+    /// Returns all conditions reported, once `condition` was among them. Data
+    /// received in the meantime is ignored, as a faulty line might produce
+    /// garbage. Returns an error, if it times out before that, or an I/O error
+    /// occurs.
+    pub fn wait_for_usart_line_event(&mut self,
+        condition: LineCondition,
+        timeout:   Duration,
+    )
+        -> Result<Vec<LineCondition>, TargetUsartWaitError>
+    {
+        let mut conditions = Vec::new();
+        let     start      = Instant::now();
+
+        loop {
+            if conditions.contains(&condition) {
+                return Ok(conditions);
+            }
+            if start.elapsed() > timeout {
+                return Err(TargetUsartWaitError::Timeout);
+            }
+
+            let mut tmp = Vec::new();
+            let message = self.conn
+                .receive::<TargetToHost>(timeout, &mut tmp)
+                .map_err(|err| TargetUsartWaitError::Receive(err))?;
+
+            match message {
+                TargetToHost::UsartLineEvent(event)
+                    if event.usart == UsartMode::Regular =>
+                {
+                    conditions.push(event.condition);
+                }
+                TargetToHost::UsartReceive {
+                    mode: UsartMode::Regular,
+                    ..
+                } => {}
+                message => {
+                    return Err(
+                        TargetUsartWaitError::UnexpectedMessage(
+                            format!("{:?}", message)
+                        )
+                    );
+                }
+            }
+        }
+    }
+
     /// Enable address matching
     pub fn wait_for_address(&mut self, address: u8)
         -> Result<(), TargetWaitForAddressError>
     {
         self.conn
             .send(&HostToTarget::WaitForAddress(address))
-            .map_err(|err| TargetWaitForAddressError(err))
+            .map_err(|err| TargetWaitForAddressError(err))?;
+
+        Ok(())
     }
 
     /// Start a timer interrupt with the given period in milliseconds
@@ -209,100 +455,173 @@ impl Target {
         Ok(TimerInterrupt(self))
     }
 
-    /// Start an I2C transaction
-    ///
-    /// Sends the provided `data` and returns the reply.
-    pub fn start_i2c_transaction(&mut self, data: u8, timeout: Duration)
-        -> Result<u8, TargetI2cError>
+    /// Write to an I2C slave
+    pub fn i2c_write(&mut self,
+        mode:    DmaMode,
+        address: u8,
+        data:    &[u8],
+        timeout: Duration,
+    )
+        -> Result<(), TargetI2cError>
     {
-        self.start_i2c_transaction_inner(data, timeout, DmaMode::Regular)
+        I2c::new(mode)
+            .write::<HostToTarget, TargetToHost>(
+                address,
+                data,
+                timeout,
+                &mut self.conn,
+                &mut Vec::new(),
+            )
+            .map_err(|err| TargetI2cError(err))
     }
 
-    /// Start an I2C/DMA transaction
+    /// Read from an I2C slave
     ///
-    /// Sends the provided `data` and returns the reply.
-    pub fn start_i2c_transaction_dma(&mut self, data: u8, timeout: Duration)
-        -> Result<u8, TargetI2cError>
+    /// Returns the data that was read.
+    pub fn i2c_read(&mut self,
+        mode:    DmaMode,
+        address: u8,
+        len:     u16,
+        timeout: Duration,
+    )
+        -> Result<Vec<u8>, TargetI2cError>
     {
-        self.start_i2c_transaction_inner(data, timeout, DmaMode::Dma)
+        I2c::new(mode)
+            .read::<HostToTarget, TargetToHost>(
+                address,
+                len,
+                timeout,
+                &mut self.conn,
+                &mut Vec::new(),
+            )
+            .map_err(|err| TargetI2cError(err))
     }
 
-    fn start_i2c_transaction_inner(&mut self,
-        data:    u8,
-        timeout: Duration,
+    /// Write to, then read from an I2C slave, using a repeated start
+    ///
+    /// Returns the data that was read.
+    pub fn i2c_write_read(&mut self,
         mode:    DmaMode,
+        address: u8,
+        data:    &[u8],
+        len:     u16,
+        timeout: Duration,
     )
-        -> Result<u8, TargetI2cError>
+        -> Result<Vec<u8>, TargetI2cError>
     {
-        let address = 0x48;
-
-        self.conn
-            .send(&HostToTarget::StartI2cTransaction { mode, address, data })
-            .map_err(|err| TargetI2cError::Send(err))?;
-
-        let mut tmp = Vec::new();
-        let message = self.conn
-            .receive::<TargetToHost>(timeout, &mut tmp)
-            .map_err(|err| TargetI2cError::Receive(err))?;
-
-        match message {
-            TargetToHost::I2cReply(reply) => {
-                Ok(reply)
-            }
-            message => {
-                Err(
-                    TargetI2cError::UnexpectedMessage(
-                        format!("{:?}", message)
-                    )
-                )
-            }
-        }
+        I2c::new(mode)
+            .write_read::<HostToTarget, TargetToHost>(
+                address,
+                data,
+                len,
+                timeout,
+                &mut self.conn,
+                &mut Vec::new(),
+            )
+            .map_err(|err| TargetI2cError(err))
     }
 
-    /// Start an SPI transaction
+    /// Transfer frames to and from an SPI slave
     ///
-    /// Sends the provided `data` and returns the reply.
-    pub fn start_spi_transaction(&mut self, data: u8, timeout: Duration)
-        -> Result<u8, TargetSpiError>
+    /// Returns the frames received from the slave.
+    pub fn spi_transfer(&mut self,
+        mode:        DmaMode,
+        config:      spi::Config,
+        chip_select: spi::ChipSelect,
+        data:        &[u8],
+        timeout:     Duration,
+    )
+        -> Result<Vec<u8>, TargetSpiError>
     {
-        self.start_spi_transaction_inner(data, timeout, DmaMode::Regular)
+        Spi::new(mode)
+            .transfer::<HostToTarget, TargetToHost>(
+                config,
+                chip_select,
+                data,
+                timeout,
+                &mut self.conn,
+                &mut Vec::new(),
+            )
+            .map_err(|err| TargetSpiError(err))
     }
 
-    /// Start an SPI/DMA transaction
+    /// Instruct the target to emulate an I2C slave device
     ///
-    /// Sends the provided `data` and returns the reply.
-    pub fn start_spi_transaction_dma(&mut self, data: u8, timeout: Duration)
-        -> Result<u8, TargetSpiError>
+    /// The target's I2C slave doesn't respond to anything, until it emulates a
+    /// device. The device is emulated until the target is reset.
+    pub fn emulate_i2c_device(&mut self, device: i2c::Device)
+        -> Result<(), TargetI2cEmulateError>
     {
-        self.start_spi_transaction_inner(data, timeout, DmaMode::Dma)
+        I2c::new(())
+            .emulate::<HostToTarget, TargetToHost>(
+                device,
+                &mut self.conn,
+                &mut Vec::new(),
+            )
+            .map_err(|err| TargetI2cEmulateError(err))
     }
 
-    fn start_spi_transaction_inner(&mut self,
-        data:    u8,
-        timeout: Duration,
-        mode:    DmaMode,
-    )
-        -> Result<u8, TargetSpiError>
+    /// Read the current registers of the emulated I2C slave device
+    pub fn read_i2c_registers(&mut self)
+        -> Result<Vec<u8>, TargetI2cReadRegistersError>
     {
-        self.conn.send(&HostToTarget::StartSpiTransaction { mode, data })
-            .map_err(|err| TargetSpiError::Send(err))?;
+        I2c::new(())
+            .read_registers::<HostToTarget, TargetToHost>(
+                self.conn.timeouts().reply(),
+                &mut self.conn,
+                &mut Vec::new(),
+            )
+            .map_err(|err| TargetI2cReadRegistersError(err))
+    }
 
-        let mut tmp = Vec::new();
-        let message = self.conn.receive::<TargetToHost>(timeout, &mut tmp)
-            .map_err(|err| TargetSpiError::Receive(err))?;
+    /// Switch the target's SPI peripheral to slave mode, and configure it
+    ///
+    /// Without an emulated device, the slave replies with `0xff`. The next
+    /// call to [`spi_transfer`] switches back to master mode.
+    ///
+    /// [`spi_transfer`]: #method.spi_transfer
+    pub fn configure_spi_slave(&mut self, config: spi::Config)
+        -> Result<(), TargetSpiConfigureError>
+    {
+        Spi::new(())
+            .configure::<HostToTarget, TargetToHost>(
+                config,
+                &mut self.conn,
+                &mut Vec::new(),
+            )
+            .map_err(|err| TargetSpiConfigureError(err))
+    }
 
-        match message {
-            TargetToHost::SpiReply(reply) => {
-                Ok(reply)
-            }
-            message => {
-                Err(
-                    TargetSpiError::UnexpectedMessage(
-                        format!("{:?}", message)
-                    )
-                )
-            }
-        }
+    /// Switch the target's SPI peripheral to slave mode, and emulate a device
+    ///
+    /// The device is emulated until the target is reset, or until the next
+    /// call to [`spi_transfer`].
+    ///
+    /// [`spi_transfer`]: #method.spi_transfer
+    pub fn emulate_spi_device(&mut self, device: spi::Device)
+        -> Result<(), TargetSpiEmulateError>
+    {
+        Spi::new(())
+            .emulate::<HostToTarget, TargetToHost>(
+                device,
+                &mut self.conn,
+                &mut Vec::new(),
+            )
+            .map_err(|err| TargetSpiEmulateError(err))
+    }
+
+    /// Read what the target's SPI slave has received
+    ///
+    /// Returns everything received since the last call, or since the target
+    /// was reset.
+    pub fn read_spi_log(&mut self) -> Result<SpiLog, TargetSpiReadLogError> {
+        Spi::new(())
+            .read_log::<HostToTarget, TargetToHost>(
+                self.conn.timeouts().reply(),
+                &mut self.conn,
+                &mut Vec::new(),
+            )
+            .map_err(|err| TargetSpiReadLogError(err))
     }
 }
 
@@ -315,19 +634,44 @@ pub struct TimerInterrupt<'r>(&'r mut Target);
 impl Drop for TimerInterrupt<'_> {
     fn drop(&mut self) {
         (self.0).conn.send(&HostToTarget::StopTimerInterrupt)
-            .unwrap()
+            .unwrap();
     }
 }
 
 
 #[derive(Debug)]
-pub struct TargetSetPinHighError(ConnSendError);
+pub enum TargetHandshakeError {
+    Send(ConnSendError),
+    Receive(ConnReceiveError),
+
+    /// A different kind of node replied to the handshake
+    WrongNode(Node),
+
+    UnexpectedMessage(String),
+}
 
 #[derive(Debug)]
-pub struct TargetSetPinLowError(ConnSendError);
+pub enum TargetResetError {
+    Clear(ConnClearError),
+    Send(ConnSendError),
+    Receive(ConnReceiveError),
+    Timeout,
+}
 
 #[derive(Debug)]
-pub struct TargetPinReadError(ReadLevelError);
+pub enum TargetWaitForBootError {
+    Receive(ConnReceiveError),
+    Timeout,
+}
+
+#[derive(Debug)]
+pub struct TargetSetPinHighError(pub ConnSendError);
+
+#[derive(Debug)]
+pub struct TargetSetPinLowError(pub ConnSendError);
+
+#[derive(Debug)]
+pub struct TargetPinReadError(pub ReadLevelError);
 
 impl From<ReadLevelError> for TargetPinReadError {
     fn from(err: ReadLevelError) -> Self {
@@ -337,10 +681,16 @@ impl From<ReadLevelError> for TargetPinReadError {
 
 
 #[derive(Debug)]
-pub struct TargetUsartSendError(ConnSendError);
+pub struct TargetUsartConfigureError(pub ConfigureError);
+
+#[derive(Debug)]
+pub struct TargetUsartSendError(pub ConnSendError);
+
+#[derive(Debug)]
+pub struct TargetUsartStallError(pub StallError);
 
 #[derive(Debug)]
-pub struct TargetStartTimerInterruptError(ConnSendError);
+pub struct TargetStartTimerInterruptError(pub ConnSendError);
 
 #[derive(Debug)]
 pub enum TargetUsartWaitError {
@@ -350,18 +700,25 @@ pub enum TargetUsartWaitError {
 }
 
 #[derive(Debug)]
-pub struct TargetWaitForAddressError(ConnSendError);
+pub struct TargetWaitForAddressError(pub ConnSendError);
 
 #[derive(Debug)]
-pub enum TargetI2cError {
-    Send(ConnSendError),
-    Receive(ConnReceiveError),
-    UnexpectedMessage(String),
-}
+pub struct TargetI2cError(pub TransactionError);
 
 #[derive(Debug)]
-pub enum TargetSpiError {
-    Send(ConnSendError),
-    Receive(ConnReceiveError),
-    UnexpectedMessage(String),
-}
+pub struct TargetSpiError(pub TransferError);
+
+#[derive(Debug)]
+pub struct TargetI2cEmulateError(pub EmulateError);
+
+#[derive(Debug)]
+pub struct TargetI2cReadRegistersError(pub ReadRegistersError);
+
+#[derive(Debug)]
+pub struct TargetSpiConfigureError(pub SpiConfigureError);
+
+#[derive(Debug)]
+pub struct TargetSpiEmulateError(pub SpiEmulateError);
+
+#[derive(Debug)]
+pub struct TargetSpiReadLogError(pub ReadLogError);
@@ -1,42 +1,115 @@
-use std::sync::{
-    LockResult,
-    MutexGuard,
-};
-
 use host_lib::{
-    assistant::Assistant,
+    assistant::{
+        Assistant,
+        AssistantError,
+    },
+    pool::StandGuard,
+    serial::{
+        Serial,
+        SerialError,
+    },
     test_stand::NotConfiguredError,
 };
 
-use super::target::Target;
+use super::target::{
+    Target,
+    TargetHandshakeError,
+    TargetResetError,
+    TargetWaitForBootError,
+};
 
 
 /// An instance of the test stand
 ///
 /// Used to access all resources that a test case requires.
 pub struct TestStand {
-    _guard: LockResult<MutexGuard<'static, ()>>,
-
     pub target:    Target,
     pub assistant: Assistant,
+
+    serial: Result<Serial, NotConfiguredError>,
+
+    // Must be declared last. Fields are dropped in declaration order, and the
+    // test stand must stay checked out until the ports above are closed.
+    _guard: StandGuard,
 }
 
 impl TestStand {
     /// Initializes the test stand
     ///
     /// Reads the `test-stand.toml` configuration file and initializes test
-    /// stand resources, as configured in there.
+    /// stand resources, as configured in there. Resets the target and the
+    /// assistant, so no state from previous test cases can leak into this one.
     pub fn new() -> Result<Self, TestStandInitError> {
         let test_stand = host_lib::TestStand::new()
             .map_err(|err| TestStandInitError::Inner(err))?;
 
-        Ok(
-            Self {
-                _guard:    test_stand.guard,
-                target:    Target::new(test_stand.target?),
-                assistant: test_stand.assistant?,
-            }
-        )
+        let mut test_stand = Self {
+            target:    Target::new(test_stand.target?),
+            assistant: test_stand.assistant?,
+            serial:    test_stand.serial,
+            _guard:    test_stand.guard,
+        };
+
+        test_stand.reset()
+            .map_err(|err| TestStandInitError::Reset(err))?;
+
+        // Make sure we're actually talking to the target. If the port
+        // configuration is wrong, we might have opened some other device.
+        // This happens after the reset, which recovers a target that has
+        // stopped responding.
+        let timeout = test_stand.target.timeouts().reply();
+        test_stand.target.handshake(timeout)
+            .map_err(|err| TestStandInitError::Handshake(err))?;
+
+        Ok(test_stand)
+    }
+
+    /// Access the raw serial connection to the USB/serial converter
+    ///
+    /// The converter is optional, so test cases that require it should be
+    /// ignored by default.
+    pub fn serial(&mut self) -> Result<&mut Serial, NotConfiguredError> {
+        self.serial.as_mut().map_err(|err| *err)
+    }
+
+    /// Reset the target and the assistant to their initial state
+    ///
+    /// This is done automatically, when the test stand is initialized and
+    /// when it is dropped.
+    ///
+    /// If the target doesn't respond to the reset request, it is reset in
+    /// hardware by the assistant instead. If a USB/serial converter is
+    /// configured, its line settings are restored and its input discarded.
+    pub fn reset(&mut self) -> Result<(), TestStandResetError> {
+        self.assistant.reset()
+            .map_err(|err| TestStandResetError::Assistant(err))?;
+
+        if let Err(err) = self.target.reset() {
+            let timeout = self.target.timeouts().boot();
+
+            self.assistant.reset_target()
+                .map_err(|err| TestStandResetError::Assistant(err))?;
+            self.target.wait_for_boot(timeout)
+                .map_err(|boot| TestStandResetError::Target(err, boot))?;
+        }
+
+        if let Ok(serial) = &mut self.serial {
+            serial.reset()
+                .map_err(|err| TestStandResetError::Serial(err))?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Drop for TestStand {
+    fn drop(&mut self) {
+        // We're probably dropped at the end of a test case, which might have
+        // failed and left the test stand in an unknown state. Let's clean up
+        // after it. We can't return an error from here, but that's fine: The
+        // next test case resets the test stand again, and will report any
+        // error there.
+        let _ = self.reset();
     }
 }
 
@@ -45,6 +118,8 @@ impl TestStand {
 pub enum TestStandInitError {
     Inner(host_lib::test_stand::TestStandInitError),
     NotConfigured(NotConfiguredError),
+    Reset(TestStandResetError),
+    Handshake(TargetHandshakeError),
 }
 
 impl From<NotConfiguredError> for TestStandInitError {
@@ -52,3 +127,11 @@ impl From<NotConfiguredError> for TestStandInitError {
         Self::NotConfigured(err)
     }
 }
+
+
+#[derive(Debug)]
+pub enum TestStandResetError {
+    Assistant(AssistantError),
+    Serial(SerialError),
+    Target(TargetResetError, TargetWaitForBootError),
+}
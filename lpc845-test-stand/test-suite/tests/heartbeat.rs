@@ -0,0 +1,35 @@
+//! Test Suite for the heartbeat of the test nodes
+//!
+//! This test suite communicates with hardware. See top-level README.md for
+//! wiring instructions.
+
+
+use std::{
+    thread::sleep,
+    time::Duration,
+};
+
+use lpc845_test_suite::{
+    Result,
+    TestStand,
+};
+
+
+#[test]
+fn it_should_receive_heartbeats_from_both_nodes() -> Result {
+    let mut test_stand = TestStand::new()?;
+
+    // Give both nodes time to send a few heartbeats. They're received in the
+    // background, while we wait for the replies to the following requests.
+    sleep(Duration::from_millis(300));
+    test_stand.target.pin_is_high()?;
+    test_stand.assistant.pin_is_high()?;
+
+    let (_, target)    = test_stand.target.last_heartbeat().unwrap();
+    let (_, assistant) = test_stand.assistant.last_heartbeat().unwrap();
+
+    assert!(target.uptime_ms > 0);
+    assert!(assistant.uptime_ms > 0);
+
+    Ok(())
+}
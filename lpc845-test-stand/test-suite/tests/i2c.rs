@@ -4,11 +4,19 @@
 //! wiring instructions.
 
 
-use std::time::Duration;
-
+use host_lib::{
+    assistant::AssistantError,
+    i2c::TransactionError,
+};
+use lpc845_messages::{
+    ASSISTANT_I2C_ADDRESS,
+    DmaMode,
+    i2c,
+};
 use lpc845_test_suite::{
     Result,
     TestStand,
+    target::TargetI2cError,
 };
 
 
@@ -17,10 +25,21 @@ fn it_should_start_a_transaction() -> Result {
     let mut test_stand = TestStand::new()?;
 
     let data = 0x22;
-    let timeout = Duration::from_millis(50);
-    let reply = test_stand.target.start_i2c_transaction(data, timeout)?;
+    let timeout = test_stand.target.timeouts().reply();
+    test_stand.target.i2c_write(
+        DmaMode::Regular,
+        ASSISTANT_I2C_ADDRESS,
+        &[data],
+        timeout,
+    )?;
+    let reply = test_stand.target.i2c_read(
+        DmaMode::Regular,
+        ASSISTANT_I2C_ADDRESS,
+        1,
+        timeout,
+    )?;
 
-    assert_eq!(reply, data << 1);
+    assert_eq!(reply, [data << 1]);
 
     Ok(())
 }
@@ -30,10 +49,213 @@ fn it_should_start_a_transaction_using_dma() -> Result {
     let mut test_stand = TestStand::new()?;
 
     let data = 0x22;
-    let timeout = Duration::from_millis(50);
-    let reply = test_stand.target.start_i2c_transaction_dma(data, timeout)?;
+    let timeout = test_stand.target.timeouts().reply();
+    test_stand.target.i2c_write(
+        DmaMode::Dma,
+        ASSISTANT_I2C_ADDRESS,
+        &[data],
+        timeout,
+    )?;
+    let reply = test_stand.target.i2c_read(
+        DmaMode::Dma,
+        ASSISTANT_I2C_ADDRESS,
+        1,
+        timeout,
+    )?;
+
+    assert_eq!(reply, [data << 1]);
+
+    Ok(())
+}
+
+#[test]
+fn it_should_write_then_read_using_a_repeated_start() -> Result {
+    let mut test_stand = TestStand::new()?;
+
+    let data = 0x22;
+    let timeout = test_stand.target.timeouts().reply();
+    let reply = test_stand.target.i2c_write_read(
+        DmaMode::Regular,
+        ASSISTANT_I2C_ADDRESS,
+        &[data],
+        1,
+        timeout,
+    )?;
+
+    assert_eq!(reply, [data << 1]);
+
+    Ok(())
+}
+
+#[test]
+fn it_should_report_an_address_nack() -> Result {
+    let mut test_stand = TestStand::new()?;
+
+    // Nothing on the bus responds to this address.
+    let address = ASSISTANT_I2C_ADDRESS + 1;
+
+    let timeout = test_stand.target.timeouts().reply();
+    let result = test_stand.target.i2c_write(
+        DmaMode::Regular,
+        address,
+        &[0x22],
+        timeout,
+    );
+
+    assert!(matches!(
+        result,
+        Err(TargetI2cError(TransactionError::I2c(i2c::Error::AddressNack)))
+    ));
+
+    Ok(())
+}
+
+#[test]
+fn it_should_read_registers_of_an_emulated_device() -> Result {
+    let mut test_stand = TestStand::new()?;
+
+    let address = 0x50;
+    test_stand.assistant.emulate_i2c_device(i2c::Device {
+        address,
+        registers:      &[0x11, 0x22, 0x33, 0x44],
+        read_only:      &[],
+        auto_increment: true,
+        fault:          None,
+    })?;
+
+    let timeout = test_stand.target.timeouts().reply();
+    let reply = test_stand.target.i2c_write_read(
+        DmaMode::Regular,
+        address,
+        &[1],
+        2,
+        timeout,
+    )?;
+
+    assert_eq!(reply, [0x22, 0x33]);
+
+    Ok(())
+}
+
+#[test]
+fn it_should_write_registers_of_an_emulated_device() -> Result {
+    let mut test_stand = TestStand::new()?;
+
+    let address = 0x50;
+    test_stand.assistant.emulate_i2c_device(i2c::Device {
+        address,
+        registers:      &[0x11, 0x22, 0x33, 0x44],
+        read_only:      &[2],
+        auto_increment: true,
+        fault:          None,
+    })?;
+
+    let timeout = test_stand.target.timeouts().reply();
+    test_stand.target.i2c_write(
+        DmaMode::Regular,
+        address,
+        &[1, 0xaa, 0xbb, 0xcc],
+        timeout,
+    )?;
+
+    // Register 2 is read-only, so the write to it is ignored.
+    let registers = test_stand.assistant.read_i2c_registers()?;
+    assert_eq!(registers, [0x11, 0xaa, 0x33, 0xcc]);
+
+    Ok(())
+}
+
+#[test]
+fn it_should_report_a_data_nack() -> Result {
+    let mut test_stand = TestStand::new()?;
+
+    let address = 0x50;
+    test_stand.assistant.emulate_i2c_device(i2c::Device {
+        address,
+        registers:      &[0x11, 0x22, 0x33, 0x44],
+        read_only:      &[],
+        auto_increment: true,
+        fault:          Some(i2c::Fault::NackByte(1)),
+    })?;
+
+    let timeout = test_stand.target.timeouts().reply();
+    let result = test_stand.target.i2c_write(
+        DmaMode::Regular,
+        address,
+        &[0, 0xaa, 0xbb],
+        timeout,
+    );
+
+    assert!(matches!(
+        result,
+        Err(TargetI2cError(TransactionError::I2c(i2c::Error::DataNack)))
+    ));
+
+    Ok(())
+}
+
+#[test]
+fn it_should_tolerate_clock_stretching() -> Result {
+    let mut test_stand = TestStand::new()?;
+
+    let address = 0x50;
+    test_stand.assistant.emulate_i2c_device(i2c::Device {
+        address,
+        registers:      &[0x11, 0x22, 0x33, 0x44],
+        read_only:      &[],
+        auto_increment: true,
+        fault:          Some(i2c::Fault::StretchClock(500)),
+    })?;
+
+    let timeout = test_stand.target.timeouts().reply();
+    let reply = test_stand.target.i2c_write_read(
+        DmaMode::Regular,
+        address,
+        &[0],
+        4,
+        timeout,
+    )?;
+
+    assert_eq!(reply, [0x11, 0x22, 0x33, 0x44]);
+
+    Ok(())
+}
+
+#[test]
+fn it_should_act_as_a_slave() -> Result {
+    let mut test_stand = TestStand::new()?;
+
+    let address = 0x50;
+    test_stand.target.emulate_i2c_device(i2c::Device {
+        address,
+        registers:      &[0x11, 0x22, 0x33, 0x44],
+        read_only:      &[],
+        auto_increment: true,
+        fault:          None,
+    })?;
+
+    let reply = test_stand.assistant.i2c_write_read(address, &[1], 2)?;
+    assert_eq!(reply, [0x22, 0x33]);
+
+    test_stand.assistant.i2c_write(address, &[2, 0xaa])?;
+    let registers = test_stand.target.read_i2c_registers()?;
+    assert_eq!(registers, [0x11, 0x22, 0xaa, 0x44]);
+
+    Ok(())
+}
+
+#[test]
+fn it_should_not_respond_as_a_slave_without_an_emulated_device() -> Result {
+    let mut test_stand = TestStand::new()?;
+
+    let result = test_stand.assistant.i2c_read(0x50, 1);
 
-    assert_eq!(reply, data << 1);
+    assert!(matches!(
+        result,
+        Err(AssistantError::I2cTransaction(
+            TransactionError::I2c(i2c::Error::AddressNack)
+        ))
+    ));
 
     Ok(())
 }
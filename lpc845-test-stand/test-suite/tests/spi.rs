@@ -4,11 +4,15 @@
 //! wiring instructions.
 
 
-use std::time::Duration;
-
+use host_lib::spi::TransferError;
+use lpc845_messages::{
+    DmaMode,
+    spi,
+};
 use lpc845_test_suite::{
     Result,
     TestStand,
+    target::TargetSpiError,
 };
 
 
@@ -17,10 +21,17 @@ fn it_should_start_a_transaction() -> Result {
     let mut test_stand = TestStand::new()?;
 
     let data = 0x22;
-    let timeout = Duration::from_millis(50);
-    let reply = test_stand.target.start_spi_transaction(data, timeout)?;
+    let timeout = test_stand.target.timeouts().reply();
+    let reply = test_stand.target.spi_transfer(
+        DmaMode::Regular,
+        spi::Config::default(),
+        spi::ChipSelect::Transfer,
+        &[data, 0xff],
+        timeout,
+    )?;
 
-    assert_eq!(reply, data << 1);
+    // The assistant replies to each frame during the next one.
+    assert_eq!(reply[1], data << 1);
 
     Ok(())
 }
@@ -30,10 +41,287 @@ fn it_should_start_a_transaction_using_dma() -> Result {
     let mut test_stand = TestStand::new()?;
 
     let data = 0x22;
-    let timeout = Duration::from_millis(50);
-    let reply = test_stand.target.start_spi_transaction_dma(data, timeout)?;
+    let timeout = test_stand.target.timeouts().reply();
+    let reply = test_stand.target.spi_transfer(
+        DmaMode::Dma,
+        spi::Config::default(),
+        spi::ChipSelect::Transfer,
+        &[data, 0xff],
+        timeout,
+    )?;
+
+    assert_eq!(reply[1], data << 1);
+
+    Ok(())
+}
+
+#[test]
+fn it_should_transfer_multiple_frames() -> Result {
+    let mut test_stand = TestStand::new()?;
+
+    let data = [0x01, 0x02, 0x03, 0x04];
+    let timeout = test_stand.target.timeouts().reply();
+    let reply = test_stand.target.spi_transfer(
+        DmaMode::Regular,
+        spi::Config::default(),
+        spi::ChipSelect::Transfer,
+        &data,
+        timeout,
+    )?;
+
+    assert_eq!(reply[1..], [0x02, 0x04, 0x06]);
+
+    Ok(())
+}
+
+#[test]
+fn it_should_transfer_in_every_mode() -> Result {
+    let mut test_stand = TestStand::new()?;
+
+    let modes = [spi::MODE_0, spi::MODE_1, spi::MODE_2, spi::MODE_3];
+
+    for &mode in &modes {
+        let config = spi::Config {
+            mode,
+            .. spi::Config::default()
+        };
+        test_stand.assistant.configure_spi(config)?;
+
+        let data = 0x22;
+        let timeout = test_stand.target.timeouts().reply();
+        let reply = test_stand.target.spi_transfer(
+            DmaMode::Regular,
+            config,
+            spi::ChipSelect::Transfer,
+            &[data, 0xff],
+            timeout,
+        )?;
+
+        assert_eq!(reply[1], data << 1, "{:?}", mode);
+    }
+
+    Ok(())
+}
+
+#[test]
+fn it_should_transfer_lsb_first() -> Result {
+    let mut test_stand = TestStand::new()?;
+
+    let config = spi::Config {
+        bit_order: spi::BitOrder::LsbFirst,
+        .. spi::Config::default()
+    };
+    test_stand.assistant.configure_spi(config)?;
+
+    let data = 0x22;
+    let timeout = test_stand.target.timeouts().reply();
+    let reply = test_stand.target.spi_transfer(
+        DmaMode::Regular,
+        config,
+        spi::ChipSelect::Transfer,
+        &[data, 0xff],
+        timeout,
+    )?;
+
+    assert_eq!(reply[1], data << 1);
+
+    Ok(())
+}
+
+#[test]
+fn it_should_transfer_16_bit_frames() -> Result {
+    let mut test_stand = TestStand::new()?;
+
+    let config = spi::Config {
+        frame_size: 16,
+        .. spi::Config::default()
+    };
+    test_stand.assistant.configure_spi(config)?;
 
+    let data: u16 = 0x1122;
+    let timeout = test_stand.target.timeouts().reply();
+    let reply = test_stand.target.spi_transfer(
+        DmaMode::Regular,
+        config,
+        spi::ChipSelect::Transfer,
+        &[data as u8, (data >> 8) as u8, 0xff, 0xff],
+        timeout,
+    )?;
+
+    let reply = reply[2] as u16 | (reply[3] as u16) << 8;
     assert_eq!(reply, data << 1);
 
     Ok(())
 }
+
+#[test]
+fn it_should_assert_chip_select_for_each_frame() -> Result {
+    let mut test_stand = TestStand::new()?;
+
+    let data = 0x22;
+    let timeout = test_stand.target.timeouts().reply();
+    let reply = test_stand.target.spi_transfer(
+        DmaMode::Regular,
+        spi::Config::default(),
+        spi::ChipSelect::Frame,
+        &[data, 0xff],
+        timeout,
+    )?;
+
+    assert_eq!(reply[1], data << 1);
+
+    Ok(())
+}
+
+#[test]
+fn it_should_reject_per_frame_chip_select_using_dma() -> Result {
+    let mut test_stand = TestStand::new()?;
+
+    let timeout = test_stand.target.timeouts().reply();
+    let result = test_stand.target.spi_transfer(
+        DmaMode::Dma,
+        spi::Config::default(),
+        spi::ChipSelect::Frame,
+        &[0x22, 0xff],
+        timeout,
+    );
+
+    assert!(matches!(
+        result,
+        Err(TargetSpiError(TransferError::Spi(spi::Error::UnsupportedConfig)))
+    ));
+
+    Ok(())
+}
+
+#[test]
+fn it_should_receive_responses_from_an_emulated_device() -> Result {
+    let mut test_stand = TestStand::new()?;
+
+    test_stand.assistant.emulate_spi_device(
+        spi::Device::Responses(&[0x11, 0x22, 0x33]),
+    )?;
+
+    let timeout = test_stand.target.timeouts().reply();
+    let reply = test_stand.target.spi_transfer(
+        DmaMode::Regular,
+        spi::Config::default(),
+        spi::ChipSelect::Transfer,
+        &[0x01, 0x02, 0x03],
+        timeout,
+    )?;
+
+    assert_eq!(reply, [0x11, 0x22, 0x33]);
+
+    Ok(())
+}
+
+#[test]
+fn it_should_access_registers_of_an_emulated_device() -> Result {
+    let mut test_stand = TestStand::new()?;
+
+    let read  = 0x03;
+    let write = 0x02;
+
+    test_stand.assistant.emulate_spi_device(spi::Device::Registers {
+        registers: &[0x11, 0x22, 0x33, 0x44],
+        read,
+        write,
+    })?;
+
+    let timeout = test_stand.target.timeouts().reply();
+    test_stand.target.spi_transfer(
+        DmaMode::Regular,
+        spi::Config::default(),
+        spi::ChipSelect::Transfer,
+        &[write, 1, 0xaa, 0xbb],
+        timeout,
+    )?;
+    let reply = test_stand.target.spi_transfer(
+        DmaMode::Regular,
+        spi::Config::default(),
+        spi::ChipSelect::Transfer,
+        &[read, 0, 0xff, 0xff, 0xff, 0xff],
+        timeout,
+    )?;
+
+    // The device replies to each frame during the next one.
+    assert_eq!(reply[2..], [0x11, 0xaa, 0xbb, 0x44]);
+
+    Ok(())
+}
+
+#[test]
+fn it_should_log_received_frames_per_transfer() -> Result {
+    let mut test_stand = TestStand::new()?;
+
+    let timeout = test_stand.target.timeouts().reply();
+    test_stand.target.spi_transfer(
+        DmaMode::Regular,
+        spi::Config::default(),
+        spi::ChipSelect::Transfer,
+        &[0x01, 0x02],
+        timeout,
+    )?;
+    test_stand.target.spi_transfer(
+        DmaMode::Regular,
+        spi::Config::default(),
+        spi::ChipSelect::Frame,
+        &[0x03, 0x04],
+        timeout,
+    )?;
+
+    let log = test_stand.assistant.read_spi_log()?;
+
+    assert_eq!(log.transfers, [&[0x01, 0x02][..], &[0x03], &[0x04]]);
+    assert!(!log.overflowed);
+
+    Ok(())
+}
+
+#[test]
+fn it_should_act_as_a_slave() -> Result {
+    let mut test_stand = TestStand::new()?;
+
+    test_stand.target.emulate_spi_device(
+        spi::Device::Responses(&[0x11, 0x22, 0x33]),
+    )?;
+
+    let reply = test_stand.assistant.spi_transfer(
+        spi::Config::default(),
+        spi::ChipSelect::Transfer,
+        &[0x01, 0x02, 0x03],
+    )?;
+    assert_eq!(reply, [0x11, 0x22, 0x33]);
+
+    let log = test_stand.target.read_spi_log()?;
+    assert_eq!(log.transfers, [&[0x01, 0x02, 0x03][..]]);
+
+    Ok(())
+}
+
+#[test]
+fn it_should_return_to_master_mode_after_acting_as_a_slave() -> Result {
+    let mut test_stand = TestStand::new()?;
+
+    test_stand.target.configure_spi_slave(spi::Config::default())?;
+    let reply = test_stand.assistant.spi_transfer(
+        spi::Config::default(),
+        spi::ChipSelect::Transfer,
+        &[0x01],
+    )?;
+    assert_eq!(reply, [0xff]);
+
+    let data = 0x22;
+    let timeout = test_stand.target.timeouts().reply();
+    let reply = test_stand.target.spi_transfer(
+        DmaMode::Regular,
+        spi::Config::default(),
+        spi::ChipSelect::Transfer,
+        &[data, 0xff],
+        timeout,
+    )?;
+    assert_eq!(reply[1], data << 1);
+
+    Ok(())
+}
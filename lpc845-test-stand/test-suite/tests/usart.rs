@@ -4,14 +4,36 @@
 //! wiring instructions.
 
 
-use std::time::Duration;
-
+use lpc845_messages::{
+    UsartMode,
+    usart::{
+        Configure,
+        DataBits,
+        Fault,
+        LineCondition,
+        MeasureTiming,
+        Parity,
+        StopBits,
+    },
+};
 use lpc845_test_suite::{
     Result,
     TestStand,
 };
 
 
+/// The baud rates that the tests below cycle through
+const BAUD_RATES: &[u32] = &[
+    9600,
+    19200,
+    57600,
+    115200,
+    230400,
+    460800,
+    921600,
+];
+
+
 #[test]
 fn it_should_send_messages() -> Result {
     let mut test_stand = TestStand::new()?;
@@ -19,7 +41,7 @@ fn it_should_send_messages() -> Result {
     let message = b"Hello, world!";
     test_stand.target.send_usart(message)?;
 
-    let timeout  = Duration::from_millis(50);
+    let timeout  = test_stand.assistant.timeouts().usart();
     let received = test_stand.assistant
         .receive_from_target_usart(message, timeout)?;
 
@@ -34,7 +56,7 @@ fn it_should_receive_messages() -> Result {
     let message = b"Hello, world!";
     test_stand.assistant.send_to_target_usart(message)?;
 
-    let timeout  = Duration::from_millis(50);
+    let timeout  = test_stand.target.timeouts().usart();
     let received = test_stand.target.wait_for_usart_rx(message, timeout)?;
 
     assert_eq!(received, message);
@@ -48,7 +70,7 @@ fn it_should_send_messages_using_dma() -> Result {
     let message = b"Hello, world!";
     test_stand.target.send_usart_dma(message)?;
 
-    let timeout  = Duration::from_millis(50);
+    let timeout  = test_stand.assistant.timeouts().usart();
     let received = test_stand.assistant
         .receive_from_target_usart(message, timeout)?;
 
@@ -63,7 +85,7 @@ fn it_should_receive_messages_via_dma() -> Result {
     let message = b"Hello, world!";
     test_stand.assistant.send_to_target_usart_dma(message)?;
 
-    let timeout  = Duration::from_millis(50);
+    let timeout  = test_stand.target.timeouts().usart();
     let received = test_stand.target.wait_for_usart_rx_dma(message, timeout)?;
 
     assert_eq!(received, message);
@@ -81,12 +103,12 @@ fn it_should_send_using_flow_control() -> Result {
 
     test_stand.assistant.wait_for_rts()?;
 
-    let timeout = Duration::from_millis(50);
+    let timeout = test_stand.assistant.timeouts().usart();
     test_stand.assistant.expect_nothing_from_target(timeout)?;
 
     test_stand.assistant.enable_cts()?;
 
-    let timeout = Duration::from_millis(50);
+    let timeout = test_stand.assistant.timeouts().usart();
     let received = test_stand.assistant
         .receive_from_target_usart(message, timeout)?;
 
@@ -101,7 +123,7 @@ fn it_should_send_in_sync_mode() -> Result {
     let message = b"Hello, world!";
     test_stand.target.send_usart_sync(message)?;
 
-    let timeout  = Duration::from_millis(50);
+    let timeout  = test_stand.assistant.timeouts().usart();
     let received = test_stand.assistant
         .receive_from_target_usart_sync(message, timeout)?;
 
@@ -116,7 +138,7 @@ fn it_should_receive_in_sync_mode() -> Result {
     let message = b"Hello, world!";
     test_stand.assistant.send_to_target_usart_sync(message)?;
 
-    let timeout  = Duration::from_millis(50);
+    let timeout  = test_stand.target.timeouts().usart();
     let received = test_stand.target.wait_for_usart_rx_sync(message, timeout)?;
 
     assert_eq!(received, message);
@@ -144,9 +166,401 @@ fn it_should_ignore_received_data_until_an_address_is_matched() -> Result {
     test_stand.assistant.send_to_target_usart(&[address | 0x80])?;
     test_stand.assistant.send_to_target_usart(message)?;
 
-    let timeout = Duration::from_millis(50);
+    let timeout = test_stand.target.timeouts().usart();
     let received = test_stand.target.wait_for_usart_rx(message, timeout)?;
 
     assert_eq!(received, message);
     Ok(())
 }
+
+#[test]
+fn it_should_stop_sending_when_stalled_via_flow_control() -> Result {
+    let mut test_stand = TestStand::new()?;
+
+    test_stand.assistant.stall_target_usart(5)?;
+
+    let message = b"Hello, world!";
+    test_stand.target.send_usart_with_flow_control(message)?;
+
+    let timeout  = test_stand.assistant.timeouts().usart();
+    let received = test_stand.assistant
+        .receive_from_target_usart(&message[..5], timeout)?;
+    assert_eq!(received, &message[..5]);
+
+    test_stand.assistant.expect_nothing_from_target(timeout)?;
+    test_stand.assistant.release_target_usart()?;
+
+    let received = test_stand.assistant
+        .receive_from_target_usart(&message[5..], timeout)?;
+    assert_eq!(received, &message[5..]);
+
+    Ok(())
+}
+
+#[test]
+fn it_should_stall_the_sender_via_flow_control() -> Result {
+    let mut test_stand = TestStand::new()?;
+
+    test_stand.target.stall_usart(5)?;
+
+    let message = b"Hello, world!";
+    test_stand.assistant.send_to_target_usart_with_flow_control(message)?;
+
+    let timeout  = test_stand.target.timeouts().usart();
+    let received = test_stand.target
+        .wait_for_usart_rx(&message[..5], timeout)?;
+    assert_eq!(received, &message[..5]);
+
+    // The target's receive register is full, so it must have deasserted RTS.
+    assert!(!test_stand.assistant.wait_for_rts()?);
+
+    test_stand.target.release_usart()?;
+
+    let received = test_stand.target
+        .wait_for_usart_rx(&message[5..], timeout)?;
+    assert_eq!(received, &message[5..]);
+
+    // With all data received, the target is ready for more.
+    assert!(test_stand.assistant.wait_for_rts()?);
+
+    Ok(())
+}
+
+#[test]
+fn it_should_report_framing_errors() -> Result {
+    let mut test_stand = TestStand::new()?;
+
+    test_stand.assistant.inject_usart_fault(Fault::Framing, &[0x55])?;
+
+    let timeout = test_stand.target.timeouts().usart();
+    test_stand.target
+        .wait_for_usart_line_event(LineCondition::FramingError, timeout)?;
+
+    Ok(())
+}
+
+#[test]
+fn it_should_report_breaks() -> Result {
+    let mut test_stand = TestStand::new()?;
+
+    test_stand.assistant.inject_usart_fault(Fault::Break, &[0x55])?;
+
+    let timeout = test_stand.target.timeouts().usart();
+    test_stand.target
+        .wait_for_usart_line_event(LineCondition::Break, timeout)?;
+
+    Ok(())
+}
+
+#[test]
+fn it_should_report_errors_on_baud_rate_mismatch() -> Result {
+    let mut test_stand = TestStand::new()?;
+
+    test_stand.assistant.inject_usart_fault(Fault::Baud(9600), &[0x00])?;
+
+    let timeout = test_stand.target.timeouts().usart();
+    test_stand.target
+        .wait_for_usart_line_event(LineCondition::FramingError, timeout)?;
+
+    Ok(())
+}
+
+#[test]
+fn it_should_report_parity_errors() -> Result {
+    let mut test_stand = TestStand::new()?;
+
+    let config = Configure {
+        parity: Parity::Even,
+        .. Configure::new(UsartMode::Regular, 115200)
+    };
+    configure(&mut test_stand, config)?;
+
+    test_stand.assistant.inject_usart_fault(Fault::Parity, &[0x55])?;
+
+    let timeout = test_stand.target.timeouts().usart();
+    test_stand.target
+        .wait_for_usart_line_event(LineCondition::ParityError, timeout)?;
+
+    Ok(())
+}
+
+#[test]
+fn it_should_send_messages_at_various_baud_rates() -> Result {
+    let mut test_stand = TestStand::new()?;
+
+    for &baud in BAUD_RATES {
+        configure(&mut test_stand, Configure::new(UsartMode::Regular, baud))?;
+
+        let message = b"Hello, world!";
+        test_stand.target.send_usart(message)?;
+
+        let timeout  = test_stand.assistant.timeouts().usart();
+        let received = test_stand.assistant
+            .receive_from_target_usart(message, timeout)?;
+
+        assert_eq!(received, message, "baud rate: {}", baud);
+    }
+
+    Ok(())
+}
+
+#[test]
+fn it_should_receive_messages_at_various_baud_rates() -> Result {
+    let mut test_stand = TestStand::new()?;
+
+    for &baud in BAUD_RATES {
+        configure(&mut test_stand, Configure::new(UsartMode::Regular, baud))?;
+
+        let message = b"Hello, world!";
+        test_stand.assistant.send_to_target_usart(message)?;
+
+        let timeout  = test_stand.target.timeouts().usart();
+        let received = test_stand.target.wait_for_usart_rx(message, timeout)?;
+
+        assert_eq!(received, message, "baud rate: {}", baud);
+    }
+
+    Ok(())
+}
+
+#[test]
+fn it_should_send_messages_using_dma_at_various_baud_rates() -> Result {
+    let mut test_stand = TestStand::new()?;
+
+    for &baud in BAUD_RATES {
+        configure(&mut test_stand, Configure::new(UsartMode::Regular, baud))?;
+
+        let message = b"Hello, world!";
+        test_stand.target.send_usart_dma(message)?;
+
+        let timeout  = test_stand.assistant.timeouts().usart();
+        let received = test_stand.assistant
+            .receive_from_target_usart(message, timeout)?;
+
+        assert_eq!(received, message, "baud rate: {}", baud);
+    }
+
+    Ok(())
+}
+
+#[test]
+fn it_should_receive_messages_via_dma_at_various_baud_rates() -> Result {
+    let mut test_stand = TestStand::new()?;
+
+    for &baud in BAUD_RATES {
+        configure(&mut test_stand, Configure::new(UsartMode::Dma, baud))?;
+
+        let message = b"Hello, world!";
+        test_stand.assistant.send_to_target_usart_dma(message)?;
+
+        let timeout  = test_stand.target.timeouts().usart();
+        let received = test_stand.target
+            .wait_for_usart_rx_dma(message, timeout)?;
+
+        assert_eq!(received, message, "baud rate: {}", baud);
+    }
+
+    Ok(())
+}
+
+#[test]
+fn it_should_send_with_accurate_timing() -> Result {
+    let mut test_stand = TestStand::new()?;
+
+    let params = MeasureTiming::new(UsartMode::Regular, 115200);
+    send_and_measure_timing(&mut test_stand, params)
+}
+
+#[test]
+fn it_should_send_with_accurate_timing_at_various_baud_rates() -> Result {
+    let mut test_stand = TestStand::new()?;
+
+    // The assistant can't capture edges reliably at higher baud rates.
+    for &baud in BAUD_RATES.iter().filter(|&&baud| baud <= 115200) {
+        configure(&mut test_stand, Configure::new(UsartMode::Regular, baud))?;
+
+        let params = MeasureTiming::new(UsartMode::Regular, baud);
+        send_and_measure_timing(&mut test_stand, params)?;
+    }
+
+    Ok(())
+}
+
+#[test]
+fn it_should_reject_timing_measurements_of_unsupported_usarts() -> Result {
+    let mut test_stand = TestStand::new()?;
+
+    let params = MeasureTiming::new(UsartMode::Dma, 115200);
+    assert!(
+        test_stand.assistant.measure_target_usart_timing(params).is_err()
+    );
+
+    Ok(())
+}
+
+#[test]
+fn it_should_use_the_configured_frame_format() -> Result {
+    let mut test_stand = TestStand::new()?;
+
+    let config = Configure {
+        data_bits: DataBits::Seven,
+        parity:    Parity::Odd,
+        stop_bits: StopBits::Two,
+        .. Configure::new(UsartMode::Regular, 57600)
+    };
+    configure(&mut test_stand, config)?;
+
+    // Only ASCII, as we're sending 7 data bits.
+    let message = b"Hello, world!";
+    test_stand.assistant.send_to_target_usart(message)?;
+
+    let timeout  = test_stand.target.timeouts().usart();
+    let received = test_stand.target.wait_for_usart_rx(message, timeout)?;
+
+    assert_eq!(received, message);
+    Ok(())
+}
+
+#[test]
+#[ignore = "requires a USB/serial converter"]
+fn it_should_send_messages_to_the_serial_converter() -> Result {
+    let mut test_stand = TestStand::new()?;
+
+    let message = b"Hello, world!";
+    test_stand.target.send_usart(message)?;
+
+    let serial   = test_stand.serial()?;
+    let timeout  = serial.timeouts().usart();
+    let received = serial.receive(message.len(), timeout)?;
+
+    assert_eq!(received, message);
+    Ok(())
+}
+
+#[test]
+#[ignore = "requires a USB/serial converter"]
+fn it_should_send_messages_to_the_serial_converter_at_various_baud_rates()
+    -> Result
+{
+    let mut test_stand = TestStand::new()?;
+
+    for &baud in BAUD_RATES {
+        let config = Configure::new(UsartMode::Regular, baud);
+        configure(&mut test_stand, config)?;
+        test_stand.serial()?.configure(&config)?;
+
+        let message = b"Hello, world!";
+        test_stand.target.send_usart(message)?;
+
+        let serial   = test_stand.serial()?;
+        let timeout  = serial.timeouts().usart();
+        let received = serial.receive(message.len(), timeout)?;
+
+        assert_eq!(received, message, "baud rate: {}", baud);
+    }
+
+    Ok(())
+}
+
+#[test]
+#[ignore = "requires a USB/serial converter"]
+fn it_should_send_to_the_serial_converter_in_the_configured_frame_format()
+    -> Result
+{
+    let mut test_stand = TestStand::new()?;
+
+    let config = Configure {
+        data_bits: DataBits::Seven,
+        parity:    Parity::Even,
+        stop_bits: StopBits::Two,
+        .. Configure::new(UsartMode::Regular, 57600)
+    };
+    configure(&mut test_stand, config)?;
+    test_stand.serial()?.configure(&config)?;
+
+    // Only ASCII, as we're sending 7 data bits.
+    let message = b"Hello, world!";
+    test_stand.target.send_usart(message)?;
+
+    let serial   = test_stand.serial()?;
+    let timeout  = serial.timeouts().usart();
+    let received = serial.receive(message.len(), timeout)?;
+
+    assert_eq!(received, message);
+    Ok(())
+}
+
+#[test]
+fn it_should_reject_unsupported_baud_rates() -> Result {
+    let mut test_stand = TestStand::new()?;
+
+    let config = Configure::new(UsartMode::Regular, 3_000_000);
+    assert!(test_stand.target.configure_usart(config).is_err());
+    assert!(test_stand.assistant.configure_usart(config).is_err());
+
+    Ok(())
+}
+
+
+/// Configures the USART on both the target and the assistant
+///
+/// Both derive their baud rate from the same clock, so they should end up with
+/// the same one. It must be close enough to the requested one to communicate
+/// with other devices.
+fn configure(test_stand: &mut TestStand, config: Configure<UsartMode>)
+    -> Result
+{
+    let target    = test_stand.target.configure_usart(config)?;
+    let assistant = test_stand.assistant.configure_usart(config)?;
+
+    assert_eq!(target, assistant);
+
+    let deviation = (f64::from(target) - f64::from(config.baud)).abs();
+    assert!(
+        deviation / f64::from(config.baud) <= 0.02,
+        "requested {} baud, got {}",
+        config.baud,
+        target,
+    );
+
+    Ok(())
+}
+
+/// Has the target send a message, while the assistant measures its timing
+///
+/// The sender's bit time must be within 1% of nominal. That's stricter than
+/// what receivers tolerate, but a miscalculated clock divider would still be
+/// caught, long before it causes communication errors.
+fn send_and_measure_timing(
+    test_stand: &mut TestStand,
+    params:     MeasureTiming<UsartMode>,
+)
+    -> Result
+{
+    test_stand.assistant.measure_target_usart_timing(params)?;
+
+    let message = b"Hello, world!";
+    test_stand.target.send_usart(message)?;
+
+    let timeout = test_stand.assistant.timeouts().usart();
+    test_stand.assistant.receive_from_target_usart(message, timeout)?;
+
+    let timing = test_stand.assistant.read_target_usart_timing()?;
+
+    assert_eq!(timing.frames, message.len() as u32, "{:?}", timing);
+    assert_eq!(timing.missed_edges, 0, "{:?}", timing);
+
+    let deviation_ppm = timing.deviation_ppm.unwrap();
+    assert!(
+        deviation_ppm.abs() <= 10_000,
+        "baud rate: {}, timing: {:?}",
+        params.baud,
+        timing,
+    );
+
+    // The target sends the message in one go, so the line shouldn't go idle
+    // between frames.
+    assert_eq!(timing.idle_periods, 0, "{:?}", timing);
+
+    Ok(())
+}
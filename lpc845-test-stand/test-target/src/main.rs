@@ -8,13 +8,16 @@
 #![no_std]
 
 
-extern crate panic_rtt_target;
-
-
-use core::marker::PhantomData;
+use core::{
+    convert::TryFrom,
+    marker::PhantomData,
+};
 
 use heapless::{
-    consts::U32,
+    consts::{
+        U8,
+        U32,
+    },
     spsc,
 };
 use lpc8xx_hal::{
@@ -38,11 +41,16 @@ use lpc8xx_hal::{
     },
     i2c,
     init_state::Enabled,
+    mrt::{
+        self,
+        MRT0,
+    },
     nb::{
         self,
         block,
     },
     pac::{
+        self,
         I2C0,
         SPI0,
         USART0,
@@ -90,25 +98,77 @@ use lpc8xx_hal::{
     },
 };
 use rtt_target::rprintln;
+use void::ResultVoidExt;
 
 #[cfg(feature = "sleep")]
 use lpc8xx_hal::cortex_m::asm;
 
-use firmware_lib::usart::{
-    RxIdle,
-    RxInt,
-    Tx,
-    Usart,
+use firmware_lib::{
+    heartbeat::{
+        self,
+        Ticker,
+    },
+    dispatch::Dispatcher,
+    i2c::Device as I2cDevice,
+    lpc8xx::{
+        self,
+        RxInt,
+        Tx,
+    },
+    panic,
+    spi::{
+        Device as SpiDevice,
+        Log as SpiLog,
+    },
+    usart::{
+        RxIdle,
+        Usart,
+        rx::{
+            ProcessError,
+            ReceiveError,
+        },
+    },
 };
 use lpc845_messages::{
     DmaMode,
+    Heartbeat,
     HostToTarget,
+    Identify,
+    Node,
+    Reset,
+    ResetCause,
+    SendUsart,
+    StartTimerInterrupt,
+    StopTimerInterrupt,
     TargetToHost,
     UsartMode,
+    WaitForAddress,
+    i2c as protocol_i2c,
     pin,
+    rpc,
+    spi as protocol_spi,
+    usart::{
+        self as protocol_usart,
+        LineCondition,
+    },
 };
 
 
+/// The address of the I2C slave, before it is told to emulate a device
+///
+/// `lpc8xx-hal` requires an address to enable slave mode. This one is reserved
+/// by the I2C specification, and the slave doesn't acknowledge it anyway.
+const I2C_SLAVE_ADDRESS: u8 = 0x7f;
+
+/// The frequency of the clock that drives the USARTs, in Hz
+///
+/// The FRG divides the 12 MHz FRO by `1 + 22 / 256`. See `init`.
+const USART_CLOCK_HZ: u32 = 11_050_359;
+
+/// The baud rate the USARTs under test use, until reconfigured
+const USART_BAUD: u32 = 115_200;
+
+
 #[rtic::app(device = lpc8xx_hal::pac)]
 const APP: () = {
     struct Resources {
@@ -125,6 +185,9 @@ const APP: () = {
         usart_rts_pin: Option<Pin<PIO0_9, pins::state::Swm<(), ()>>>,
         usart_cts:     Option<swm::Function<U1_CTS, Assigned<PIO0_8>>>,
 
+        usart_line_prod: spsc::Producer<'static, LineCondition, U8>,
+        usart_line_cons: spsc::Consumer<'static, LineCondition, U8>,
+
         usart_sync_rx_int:  RxInt<'static, USART3, SyncMode>,
         usart_sync_rx_idle: RxIdle<'static>,
         usart_sync_tx:      Tx<USART3, SyncMode>,
@@ -139,12 +202,22 @@ const APP: () = {
         i2c:     Option<i2c::Master<I2C0, Enabled<PhantomData<IOSC>>, Enabled>>,
         i2c_dma: Option<dma::Channel<dma::Channel15, Enabled>>,
 
+        i2c_slave:  i2c::Slave<I2C0, Enabled<PhantomData<IOSC>>, Enabled>,
+        i2c_device: Option<I2cDevice>,
+
         spi:        Option<SPI<SPI0, Enabled<spi::Master>>>,
         ssel:       GpioPin<PIO0_19, Output>,
         spi_rx_dma: Option<dma::Channel<dma::Channel10, Enabled>>,
         spi_tx_dma: Option<dma::Channel<dma::Channel11, Enabled>>,
+        spi_device: Option<SpiDevice>,
+        spi_log:    SpiLog,
 
         usart_dma_tx_channel: Option<dma::Channel<dma::Channel3, Enabled>>,
+        usart_dma_tx: usart::Tx<
+            USART2,
+            usart::state::Enabled<u8, AsyncMode>,
+            usart::state::NoThrottle,
+        >,
         usart_dma_rx_transfer: Option<
             dma::Transfer<
                 Started,
@@ -156,6 +229,9 @@ const APP: () = {
 
         dma_rx_prod: spsc::Producer<'static, u8, U32>,
         dma_rx_cons: spsc::Consumer<'static, u8, U32>,
+
+        heartbeat_timer: mrt::Channel<MRT0>,
+        ticker:          Ticker,
     }
 
     #[init]
@@ -173,9 +249,15 @@ const APP: () = {
             spsc::Queue(heapless::i::Queue::new());
         static mut DMA_BUFFER: [u8; 13] = [0; 13];
 
+        static mut LINE_QUEUE: spsc::Queue<LineCondition, U8> =
+            spsc::Queue(heapless::i::Queue::new());
+
         rtt_target::rtt_init_print!();
         rprintln!("Starting target.");
 
+        let reset_cause = reset_cause();
+        rprintln!("Reset cause: {:?}", reset_cause);
+
         // Get access to the device's peripherals. This can't panic, since this
         // is the only place in this program where we call this method.
         let p = Peripherals::take().unwrap_or_else(|| unreachable!());
@@ -262,7 +344,8 @@ const APP: () = {
             &mut swm_handle,
         );
 
-        // Use USART1 as the test subject.
+        // Use USART1 as the test subject. Besides received data, we're
+        // interested in breaks, to report them to the host.
         let mut usart = p.USART1.enable_async(
             &clock_config,
             &mut syscon.handle,
@@ -271,7 +354,8 @@ const APP: () = {
             usart::Settings::default(),
         );
         usart.enable_interrupts(usart::Interrupts {
-            RXRDY: true,
+            RXRDY:      true,
+            DELTARXBRK: true,
             .. usart::Interrupts::default()
         });
 
@@ -322,10 +406,15 @@ const APP: () = {
             usart::Settings::default(),
         );
 
-        let (host_rx_int,  host_rx_idle,  host_tx)  = HOST.init(host);
-        let (usart_rx_int, usart_rx_idle, usart_tx) = USART.init(usart);
+        let (host_rx_int, host_rx_idle, mut host_tx) =
+            HOST.init(host.rx, host.tx);
+        let (usart_rx_int, usart_rx_idle, usart_tx) =
+            USART.init(usart.rx, usart.tx);
         let (usart_sync_rx_int, usart_sync_rx_idle, usart_sync_tx) =
-            USART_SYNC.init(usart_sync);
+            USART_SYNC.init(usart_sync.rx, usart_sync.tx);
+
+        // From now on, report panics to the host, not just via RTT.
+        panic::report_to(lpc8xx::write_raw::<USART0>);
 
         let (i2c0_sda, _) = swm
             .fixed_functions
@@ -336,7 +425,9 @@ const APP: () = {
             .i2c0_scl
             .assign(p.pins.pio0_10.into_swm_pin(), &mut swm_handle);
 
-        let i2c = p.I2C0
+        // The slave only acknowledges its address, once it has been told to
+        // emulate a device.
+        let mut i2c = p.I2C0
             .enable(
                 &syscon.iosc,
                 i2c0_scl,
@@ -345,7 +436,15 @@ const APP: () = {
             )
             .enable_master_mode(
                 &i2c::Clock::new_400khz(),
-            );
+            )
+            .enable_slave_mode(
+                I2C_SLAVE_ADDRESS,
+            )
+            .expect("Not using a valid address");
+        i2c.enable_interrupts(i2c::Interrupts {
+            slave_pending: true,
+            .. i2c::Interrupts::default()
+        });
 
         let (spi0_sck, _) = swm
             .movable_functions
@@ -383,6 +482,29 @@ const APP: () = {
         let usart_dma_rx_transfer =  usart_dma_rx_transfer.start();
 
         let (dma_rx_prod, dma_rx_cons) = DMA_QUEUE.split();
+        let (usart_line_prod, usart_line_cons) = LINE_QUEUE.split();
+
+        // Use one of the MRT channels to keep track of time for the heartbeat.
+        // SysTick is already taken by the timer interrupt test. The MRT runs at
+        // the system frequency of 12 MHz, so 1 ms are 12000 timer ticks.
+        let timers = p.MRT0.split(&mut syscon.handle);
+        let mut heartbeat_timer = timers.mrt0;
+        heartbeat_timer.start(
+            mrt::Ticks::try_from(heartbeat::PERIOD_MS * 12_000).unwrap()
+        );
+
+        // The HAL doesn't support MRT interrupts, so we need to enable the
+        // interrupt directly. This is sound, as we only modify the control
+        // register of the channel we own.
+        let mrt = unsafe { &*pac::MRT0::ptr() };
+        mrt.channel[0].ctrl.modify(|_, w| w.inten().enabled());
+
+        // Let the test suite know that we're ready. It might be waiting for
+        // this, if it has just reset us.
+        let mut buf = [0; 8];
+        host_tx
+            .send_notification(TargetToHost::Booted { reset_cause }, &mut buf)
+            .unwrap();
 
         init::LateResources {
             swm: Some(swm_handle),
@@ -398,6 +520,9 @@ const APP: () = {
             usart_rts_pin: Some(p.pins.pio0_9.into_swm_pin()),
             usart_cts: Some(u1_cts),
 
+            usart_line_prod,
+            usart_line_cons,
+
             usart_sync_rx_int,
             usart_sync_rx_idle,
             usart_sync_tx,
@@ -412,44 +537,70 @@ const APP: () = {
             i2c:     Some(i2c.master),
             i2c_dma: Some(dma.channels.channel15),
 
+            i2c_slave:  i2c.slave,
+            i2c_device: None,
+
             spi: Some(spi),
             ssel,
             spi_rx_dma: Some(dma.channels.channel10),
             spi_tx_dma: Some(dma.channels.channel11),
+            spi_device: None,
+            spi_log:    SpiLog::default(),
 
             usart_dma_tx_channel:  Some(dma.channels.channel3),
+            usart_dma_tx:          usart2.tx,
             usart_dma_rx_transfer: Some(usart_dma_rx_transfer),
 
             dma_rx_prod,
             dma_rx_cons,
+
+            heartbeat_timer,
+            ticker: Ticker::new(),
         }
     }
 
     #[idle(resources = [
         swm,
-        host_rx_idle, host_tx,
+        host_rx_int, host_rx_idle, host_tx,
         usart_rx_int, usart_rx_idle, usart_tx,
-        usart_rts, usart_rts_pin, usart_cts,
-        usart_sync_rx_idle, usart_sync_tx,
+        usart_rts, usart_rts_pin, usart_cts, usart_line_cons,
+        usart_sync_rx_int, usart_sync_rx_idle, usart_sync_tx,
         green,
         red,
         systick,
         i2c,
         i2c_dma,
+        i2c_slave,
+        i2c_device,
         spi,
         ssel,
         spi_rx_dma,
         spi_tx_dma,
+        spi_device,
+        spi_log,
         usart_dma_tx_channel,
+        usart_dma_tx,
         dma_rx_cons,
+        ticker,
+        blue,
     ])]
     fn idle(cx: idle::Context) -> ! {
+        // DMA transfers require `'static` buffers. RTIC hands these out as
+        // `&'static mut`, as `idle` only runs once. The replies to I2C and SPI
+        // transactions borrow the data that was read, so each handler needs a
+        // buffer of its own, as all replies share the same lifetime.
+        static mut I2C_WRITE:      [u8; 32] = [0; 32];
+        static mut I2C_READ:       [u8; 32] = [0; 32];
+        static mut I2C_WRITE_READ: [u8; 32] = [0; 32];
+        static mut SPI_TRANSFER:   [u8; 32] = [0; 32];
+
         let swm            = cx.resources.swm;
         let usart_rx       = cx.resources.usart_rx_idle;
         let usart_tx       = cx.resources.usart_tx;
         let usart_rts      = cx.resources.usart_rts;
         let usart_rts_pin  = cx.resources.usart_rts_pin;
         let usart_cts      = cx.resources.usart_cts;
+        let usart_line     = cx.resources.usart_line_cons;
         let usart_sync_rx  = cx.resources.usart_sync_rx_idle;
         let usart_sync_tx  = cx.resources.usart_sync_tx;
         let host_rx        = cx.resources.host_rx_idle;
@@ -459,22 +610,33 @@ const APP: () = {
         let systick        = cx.resources.systick;
         let i2c            = cx.resources.i2c;
         let i2c_dma        = cx.resources.i2c_dma;
-        let spi            = cx.resources.spi;
         let ssel           = cx.resources.ssel;
         let spi_rx_dma     = cx.resources.spi_rx_dma;
         let spi_tx_dma     = cx.resources.spi_tx_dma;
         let usart_dma_chan = cx.resources.usart_dma_tx_channel;
+        let usart_dma_tx   = cx.resources.usart_dma_tx;
         let usart_dma_cons = cx.resources.dma_rx_cons;
 
-        let mut usart_rx_int = cx.resources.usart_rx_int;
-
-        let mut buf = [0; 256];
+        let mut host_rx_int       = cx.resources.host_rx_int;
+        let mut usart_rx_int      = cx.resources.usart_rx_int;
+        let mut usart_sync_rx_int = cx.resources.usart_sync_rx_int;
+        let mut ticker            = cx.resources.ticker;
+        let mut blue              = cx.resources.blue;
+        let mut i2c_slave         = cx.resources.i2c_slave;
+        let mut i2c_device        = cx.resources.i2c_device;
+        let mut spi               = cx.resources.spi;
+        let mut spi_device        = cx.resources.spi_device;
+        let mut spi_log           = cx.resources.spi_log;
+
+        let mut buf           = [0; 256];
+        let mut i2c_registers = [0; 128];
+        let mut spi_transfers = [0; 128];
 
         loop {
             usart_rx
                 .process_raw(|data| {
-                    host_tx.send_message(
-                        &TargetToHost::UsartReceive {
+                    host_tx.send_notification(
+                        TargetToHost::UsartReceive {
                             mode: UsartMode::Regular,
                             data,
                         },
@@ -484,8 +646,8 @@ const APP: () = {
                 .expect("Error processing USART data");
             usart_sync_rx
                 .process_raw(|data| {
-                    host_tx.send_message(
-                        &TargetToHost::UsartReceive {
+                    host_tx.send_notification(
+                        TargetToHost::UsartReceive {
                             mode: UsartMode::Sync,
                             data,
                         },
@@ -496,8 +658,8 @@ const APP: () = {
 
             while let Some(b) = usart_dma_cons.dequeue() {
                 host_tx
-                    .send_message(
-                        &TargetToHost::UsartReceive {
+                    .send_notification(
+                        TargetToHost::UsartReceive {
                             mode: UsartMode::Dma,
                             data: &[b],
                         },
@@ -506,142 +668,194 @@ const APP: () = {
                     .unwrap();
             }
 
-            host_rx
-                .process_message(|message| {
-                    // We're working around two problems here:
-                    // 1. We only have a mutable reference to resources we need
-                    //    to own. Unfortunately RTIC doesn't allow us to move
-                    //    stuff into `idle`, so we need to use the `take`/
-                    //    `unwrap` trick to actually move them in here.
-                    // 2. Usually we can move things out of variables and back
-                    //    into them. As long as the compiler understands that
-                    //    we've replaced what we moved out, it won't be a
-                    //    problem. The closure prevents that understanding, thus
-                    //    necessitating this little dance with the local
-                    //    variables.
-                    let mut swm_local = swm.take().unwrap();
-                    let mut usart_tx_local = usart_tx.take().unwrap();
-                    let mut usart_rts_local = usart_rts.take().unwrap();
-                    let mut usart_rts_pin_local = usart_rts_pin.take().unwrap();
-                    let mut usart_cts_local = usart_cts.take().unwrap();
-                    let mut usart_dma_chan_local =
-                        usart_dma_chan.take().unwrap();
-                    let mut i2c_local = i2c.take().unwrap();
-                    let mut i2c_dma_local = i2c_dma.take().unwrap();
-                    let mut spi_local = spi.take().unwrap();
-                    let mut spi_rx_dma_local = spi_rx_dma.take().unwrap();
-                    let mut spi_tx_dma_local = spi_tx_dma.take().unwrap();
-
-                    let result = match message {
-                        HostToTarget::SendUsart {
-                            mode: UsartMode::Regular,
-                            data,
-                        } => {
-                            usart_tx_local.send_raw(data)
-                        }
-                        HostToTarget::SendUsart {
-                            mode: UsartMode::Dma,
-                            data,
-                        } => {
-                            static mut DMA_BUFFER: [u8; 16] = [0; 16];
-
-                            {
-                                // This is sound, as we know this closure is
-                                // only ever executed once at a time, and the
-                                // mutable reference is dropped at the end of
-                                // this block.
-                                let dma_buffer = unsafe {
-                                    &mut DMA_BUFFER
-                                };
-
-                                dma_buffer[..data.len()].copy_from_slice(data);
+            while let Some(condition) = usart_line.dequeue() {
+                host_tx
+                    .send_notification(
+                        TargetToHost::UsartLineEvent(
+                            protocol_usart::LineEvent {
+                                usart: UsartMode::Regular,
+                                condition,
                             }
+                        ),
+                        &mut buf,
+                    )
+                    .unwrap();
+            }
 
-                            let payload = {
-                                // Sound, as we know this closure is only ever
-                                // executed once at a time, and the only other
-                                // reference has been dropped already.
-                                let dma_buffer = unsafe {
-                                    &DMA_BUFFER
-                                };
-
-                                let transfer = usart_tx_local.usart.write_all(
-                                    &dma_buffer[..data.len()],
-                                    usart_dma_chan_local,
-                                );
-                                transfer
-                                    .start()
-                                    .wait()
-                                    .unwrap()
-                            };
-
-                            usart_dma_chan_local = payload.channel;
-                            usart_tx_local.usart = payload.dest;
-
-                            Ok(())
-                        }
-                        HostToTarget::SendUsart {
-                            mode: UsartMode::FlowControl,
-                            data,
-                        } => {
-                            rprintln!("USART: Sending with flow control");
-
-                            rprintln!("USART: Enable flow control");
-                            let mut usart = usart_tx_local.usart;
-                            let (rts, rts_pin) = usart.enable_rts(
-                                usart_rts_local,
-                                usart_rts_pin_local,
-                                &mut swm_local,
-                            );
-                            let mut usart = usart.enable_cts_throttling(
-                                usart_cts_local,
-                            );
-
-                            rprintln!("USART: Writing data");
-                            usart.bwrite_all(data)
-                                .unwrap();
-
-                            rprintln!("USART: Disable flow control");
-                            let (rts, rts_pin) = usart.disable_rts(
-                                rts,
-                                rts_pin,
-                                &mut swm_local,
+            host_rx
+                .process_messages(|frame| {
+                    let request: rpc::Request<HostToTarget> = frame.decode()?;
+
+                    // Some of the handlers need to own resources that we only
+                    // have a mutable reference to. Those resources are stored
+                    // in `Option`s, so the handlers can take them out, and put
+                    // them back once they're done.
+                    Dispatcher::new(request, host_tx, &mut buf)
+                        .on(|SendUsart { mode, data }| {
+                            match mode {
+                                UsartMode::Regular => {
+                                    usart_tx.as_mut().unwrap().send_raw(data)
+                                        .void_unwrap();
+                                }
+                                UsartMode::Dma => {
+                                    static mut DMA_BUFFER: [u8; 16] = [0; 16];
+
+                                    {
+                                        // This is sound, as we know this
+                                        // closure is only ever executed once at
+                                        // a time, and the mutable reference is
+                                        // dropped at the end of this block.
+                                        let dma_buffer = unsafe {
+                                            &mut DMA_BUFFER
+                                        };
+
+                                        dma_buffer[..data.len()]
+                                            .copy_from_slice(data);
+                                    }
+
+                                    let mut tx = usart_tx.take().unwrap();
+
+                                    let payload = {
+                                        // Sound, as we know this closure is
+                                        // only ever executed once at a time,
+                                        // and the only other reference has been
+                                        // dropped already.
+                                        let dma_buffer = unsafe {
+                                            &DMA_BUFFER
+                                        };
+
+                                        let transfer = tx.usart.write_all(
+                                            &dma_buffer[..data.len()],
+                                            usart_dma_chan.take().unwrap(),
+                                        );
+                                        transfer
+                                            .start()
+                                            .wait()
+                                            .unwrap()
+                                    };
+
+                                    tx.usart = payload.dest;
+                                    *usart_tx = Some(tx);
+                                    *usart_dma_chan = Some(payload.channel);
+                                }
+                                UsartMode::FlowControl => {
+                                    rprintln!(
+                                        "USART: Sending with flow control"
+                                    );
+
+                                    let mut swm_handle = swm.take().unwrap();
+                                    let mut tx = usart_tx.take().unwrap();
+
+                                    rprintln!("USART: Enable flow control");
+                                    let mut usart = tx.usart;
+                                    let (rts, rts_pin) = usart.enable_rts(
+                                        usart_rts.take().unwrap(),
+                                        usart_rts_pin.take().unwrap(),
+                                        &mut swm_handle,
+                                    );
+                                    let mut usart = usart.enable_cts_throttling(
+                                        usart_cts.take().unwrap(),
+                                    );
+
+                                    rprintln!("USART: Writing data");
+                                    usart.bwrite_all(data)
+                                        .unwrap();
+
+                                    rprintln!("USART: Disable flow control");
+                                    let (rts, rts_pin) = usart.disable_rts(
+                                        rts,
+                                        rts_pin,
+                                        &mut swm_handle,
+                                    );
+                                    let (usart, cts) = usart
+                                        .disable_cts_throttling();
+                                    tx.usart = usart;
+
+                                    *swm           = Some(swm_handle);
+                                    *usart_tx      = Some(tx);
+                                    *usart_rts     = Some(rts);
+                                    *usart_rts_pin = Some(rts_pin);
+                                    *usart_cts     = Some(cts);
+                                }
+                                UsartMode::Sync => {
+                                    usart_sync_tx.send_raw(data)
+                                        .void_unwrap();
+                                }
+                            }
+                            None
+                        })
+                        .on(|config: protocol_usart::Configure<UsartMode>| {
+                            let result = configure_usart(
+                                usart_tx.as_mut().unwrap(),
+                                usart_dma_tx,
+                                &config,
                             );
-                            let (usart, cts) = usart
-                                .disable_cts_throttling();
-                            usart_rts_local = rts;
-                            usart_rts_pin_local = rts_pin;
-                            usart_cts_local = cts;
-                            usart_tx_local.usart = usart;
-
-                            Ok(())
-                        }
-                        HostToTarget::SendUsart {
-                            mode: UsartMode::Sync,
-                            data,
-                        } => {
-                            usart_sync_tx.send_raw(data)
-                        }
-                        HostToTarget::WaitForAddress(address) => {
+                            Some(TargetToHost::UsartConfigured(
+                                protocol_usart::Configured {
+                                    usart: config.usart,
+                                    result,
+                                }
+                            ))
+                        })
+                        .on(|stall: protocol_usart::Stall<UsartMode>| {
+                            let result = match stall.usart {
+                                UsartMode::FlowControl => {
+                                    assign_usart_rts(
+                                        true,
+                                        swm.as_mut().unwrap(),
+                                    );
+                                    usart_rx_int.lock(|rx| {
+                                        rx.stall(stall.after)
+                                    });
+                                    Ok(())
+                                }
+                                _ => {
+                                    Err(protocol_usart::Error::UnsupportedMode)
+                                }
+                            };
+                            Some(TargetToHost::UsartStalled(
+                                protocol_usart::Stalled {
+                                    usart: stall.usart,
+                                    result,
+                                }
+                            ))
+                        })
+                        .on(|release: protocol_usart::Release<UsartMode>| {
+                            let result = match release.usart {
+                                UsartMode::FlowControl => {
+                                    usart_rx_int.lock(|rx| {
+                                        lpc8xx::usart_release(rx)
+                                    });
+                                    Ok(())
+                                }
+                                _ => {
+                                    Err(protocol_usart::Error::UnsupportedMode)
+                                }
+                            };
+                            Some(TargetToHost::UsartReleased(
+                                protocol_usart::Released {
+                                    usart: release.usart,
+                                    result,
+                                }
+                            ))
+                        })
+                        .on(|WaitForAddress(address)| {
                             usart_rx_int.lock(|rx| {
                                 rx.usart.start_address_detection(address);
                                 block!(rx.usart.read())
                                     .unwrap();
                                 rx.usart.stop_address_detection();
                             });
-                            Ok(())
-                        }
-                        HostToTarget::SetPin(
-                            pin::SetLevel { level: pin::Level::High, .. }
-                        ) => {
-                            Ok(green.set_high())
-                        }
-                        HostToTarget::SetPin(
-                            pin::SetLevel { level: pin::Level::Low, .. }
-                        ) => {
-                            Ok(green.set_low())
-                        }
-                        HostToTarget::ReadPin(pin::ReadLevel { pin: () }) => {
+                            None
+                        })
+                        .on(|pin::SetLevel { level, .. }| {
+                            match level {
+                                pin::Level::High => green.set_high(),
+                                pin::Level::Low  => green.set_low(),
+                            }
+                            None
+                        })
+                        .on(|pin::ReadLevel { pin: () }| {
                             let level = match red.is_high() {
                                 true  => pin::Level::High,
                                 false => pin::Level::Low,
@@ -653,16 +867,74 @@ const APP: () = {
                                 period_ms: None,
                             };
 
-                            host_tx
-                                .send_message(
-                                    &TargetToHost::ReadPinResult(Some(result)),
-                                    &mut buf,
+                            Some(TargetToHost::ReadPinResult(Some(result)))
+                        })
+                        .on(|Reset| {
+                            rprintln!("Resetting target");
+
+                            systick.disable_interrupt();
+                            systick.disable_counter();
+
+                            green.set_high();
+                            blue.lock(|blue| blue.set_high());
+                            ssel.set_high();
+
+                            // Stop emulating any I2C device.
+                            i2c_slave.lock(|i2c| {
+                                lpc8xx::i2c_set_slave_address(
+                                    i2c,
+                                    I2C_SLAVE_ADDRESS,
+                                )
+                            })
+                            .unwrap();
+                            i2c_device.lock(|i2c_device| *i2c_device = None);
+
+                            // Stop emulating any SPI device, and return to
+                            // master mode.
+                            spi.lock(|spi| {
+                                spi_enable_master_mode(
+                                    spi.as_mut().unwrap(),
+                                    swm.as_mut().unwrap(),
+                                )
+                            });
+                            spi_device.lock(|spi_device| *spi_device = None);
+                            spi_log.lock(|log| log.clear());
+
+                            // Stop stalling the assistant's USART.
+                            assign_usart_rts(false, swm.as_mut().unwrap());
+                            usart_rx_int.lock(|rx| lpc8xx::usart_release(rx));
+
+                            // Restore the initial USART configuration.
+                            for &mode in &[UsartMode::Regular, UsartMode::Dma] {
+                                configure_usart(
+                                    usart_tx.as_mut().unwrap(),
+                                    usart_dma_tx,
+                                    &protocol_usart::Configure::new(
+                                        mode,
+                                        USART_BAUD,
+                                    ),
                                 )
                                 .unwrap();
+                            }
 
-                            Ok(())
-                        }
-                        HostToTarget::StartTimerInterrupt { period_ms } => {
+                            // Discard anything we've received but not yet
+                            // forwarded. It belongs to the previous test case.
+                            usart_rx.discard();
+                            usart_sync_rx.discard();
+                            while usart_dma_cons.dequeue().is_some() {}
+                            while usart_line.dequeue().is_some() {}
+
+                            // A partial frame in the DMA receiver's buffer
+                            // can't be discarded, as the HAL provides no way
+                            // to abort a running transfer. Test cases must
+                            // always send complete frames via DMA.
+
+                            Some(TargetToHost::ResetComplete)
+                        })
+                        .on(|Identify| {
+                            Some(TargetToHost::Identity(Node::Target))
+                        })
+                        .on(|StartTimerInterrupt { period_ms }| {
                             // By default (and we haven't changed that setting)
                             // the SysTick timer runs at half the system
                             // frequency. The system frequency runs at 12 MHz by
@@ -677,195 +949,208 @@ const APP: () = {
                             systick.enable_interrupt();
                             systick.enable_counter();
 
-                            Ok(())
-                        }
-                        HostToTarget::StopTimerInterrupt => {
+                            None
+                        })
+                        .on(|StopTimerInterrupt| {
                             systick.disable_interrupt();
                             systick.disable_counter();
 
-                            Ok(())
-                        }
-                        HostToTarget::StartI2cTransaction {
-                            mode: DmaMode::Regular,
-                            address,
-                            data,
-                        } => {
-                            rprintln!("I2C: Write");
-                            i2c_local.write(address, &[data])
-                                .unwrap();
+                            None
+                        })
+                        .on(|protocol_i2c::Write { bus, address, data }| {
+                            let result = i2c_transaction(
+                                i2c,
+                                i2c_dma,
+                                bus,
+                                address,
+                                data,
+                                0,
+                                I2C_WRITE,
+                            );
 
-                            rprintln!("I2C: Read");
-                            let mut rx_buf = [0u8; 1];
-                            i2c_local.read(address, &mut rx_buf)
-                                .unwrap();
+                            Some(TargetToHost::I2cCompleted(
+                                protocol_i2c::Completed { bus, result }
+                            ))
+                        })
+                        .on(|protocol_i2c::Read { bus, address, len }| {
+                            let result = i2c_transaction(
+                                i2c,
+                                i2c_dma,
+                                bus,
+                                address,
+                                &[],
+                                len,
+                                I2C_READ,
+                            );
 
-                            rprintln!("I2C: Done");
+                            Some(TargetToHost::I2cCompleted(
+                                protocol_i2c::Completed { bus, result }
+                            ))
+                        })
+                        .on(|I2cWriteRead { address, data, len }| {
+                            let bus    = DmaMode::Regular;
+                            let result = i2c_transaction(
+                                i2c,
+                                i2c_dma,
+                                bus,
+                                address,
+                                data,
+                                len,
+                                I2C_WRITE_READ,
+                            );
 
-                            host_tx
-                                .send_message(
-                                    &TargetToHost::I2cReply(rx_buf[0]),
-                                    &mut buf,
+                            Some(TargetToHost::I2cCompleted(
+                                protocol_i2c::Completed { bus, result }
+                            ))
+                        })
+                        .on(|transfer: protocol_spi::Transfer<_>| {
+                            let bus    = transfer.bus;
+                            let result = spi.lock(|spi| {
+                                spi_enable_master_mode(
+                                    spi.as_mut().unwrap(),
+                                    swm.as_mut().unwrap(),
+                                );
+                                spi_transfer(
+                                    spi,
+                                    spi_rx_dma,
+                                    spi_tx_dma,
+                                    ssel,
+                                    transfer,
+                                    SPI_TRANSFER,
                                 )
-                                .unwrap();
-
-                            Ok(())
-                        }
-                        HostToTarget::StartI2cTransaction {
-                            mode: DmaMode::Dma,
-                            address,
-                            data,
-                        } => {
-                            static mut TX_BUF: [u8; 1] = [0; 1];
-                            static mut RX_BUF: [u8; 1] = [0; 1];
-
-                            // Sound, as we have exclusive access to these
-                            // statics here.
-                            let tx_buf = unsafe { &mut TX_BUF };
-                            let mut rx_buf = unsafe { &mut RX_BUF[..] };
-
-
-                            tx_buf[0] = data;
-
-                            // Write data to slave
-                            let payload = i2c_local
-                                .write_all(address, tx_buf, i2c_dma_local)
-                                .unwrap()
-                                .start()
-                                .wait()
-                                .unwrap();
-
-                            i2c_dma_local = payload.channel;
-                            i2c_local = payload.dest;
-
-                            rx_buf[0] = 0;
-
-                            // Read data from slave
-                            let payload = i2c_local
-                                .read_all(address, rx_buf, i2c_dma_local)
-                                .unwrap()
-                                .start()
-                                .wait()
-                                .unwrap();
-
-                            i2c_dma_local = payload.channel;
-                            i2c_local = payload.source;
-                            rx_buf = payload.dest;
+                            });
 
-                            host_tx
-                                .send_message(
-                                    &TargetToHost::I2cReply(rx_buf[0]),
-                                    &mut buf,
-                                )
-                                .unwrap();
+                            Some(TargetToHost::SpiCompleted(
+                                protocol_spi::Completed { bus, result }
+                            ))
+                        })
+                        .on(|protocol_i2c::Emulate { bus: (), device }| {
+                            let result = I2cDevice::new(&device)
+                                .and_then(|device| {
+                                    i2c_slave.lock(|i2c| {
+                                        lpc8xx::i2c_set_slave_address(
+                                            i2c,
+                                            device.address(),
+                                        )
+                                        .map_err(|_| {
+                                            protocol_i2c::Error::Other
+                                        })?;
+                                        i2c_device.lock(|i2c_device| {
+                                            *i2c_device = Some(device);
+                                        });
+                                        Ok(())
+                                    })
+                                });
+
+                            Some(TargetToHost::I2cEmulated(
+                                protocol_i2c::Emulated { bus: (), result }
+                            ))
+                        })
+                        .on(|protocol_i2c::ReadRegisters { bus: () }| {
+                            let len = i2c_device.lock(|i2c_device| {
+                                let registers = i2c_device.as_ref()
+                                    .map(|device| device.registers())
+                                    .unwrap_or(&[]);
+                                i2c_registers[..registers.len()]
+                                    .copy_from_slice(registers);
+                                registers.len()
+                            });
 
-                            Ok(())
-                        }
-                        HostToTarget::StartSpiTransaction {
-                            mode: DmaMode::Regular,
-                            data,
-                        } => {
-                            rprintln!("SPI: Start transaction");
-                            ssel.set_low();
-
-                            // Clear receive buffer. Otherwise the following
-                            // series of operations won't work as intended.
-                            loop {
-                                if let Err(nb::Error::WouldBlock) =
-                                    spi_local.read()
-                                {
-                                    break;
+                            Some(TargetToHost::I2cRegisters(
+                                protocol_i2c::Registers {
+                                    bus:       (),
+                                    registers: &i2c_registers[..len],
                                 }
-                            }
-
-                            rprintln!("SPI: Write");
-                            block!(spi_local.send(data))
-                                .unwrap();
-                            let _ = block!(spi_local.read())
-                                .unwrap();
-
-                            rprintln!("SPI: Read");
-                            block!(spi_local.send(0xff))
-                                .unwrap();
-                            let reply = block!(spi_local.read())
-                                .unwrap();
-
-                            ssel.set_high();
-                            rprintln!("SPI: Done");
-
-                            host_tx
-                                .send_message(
-                                    &TargetToHost::SpiReply(reply),
-                                    &mut buf,
-                                )
-                                .unwrap();
-
-                            Ok(())
-                        }
-                        HostToTarget::StartSpiTransaction {
-                            mode: DmaMode::Dma,
-                            data,
-                        } => {
-                            static mut SPI_BUF: [u8; 2] = [0; 2];
-
-                            // Sound, as we have exclusive access to the static
-                            // here.
-                            let mut spi_buf = unsafe { &mut SPI_BUF[..] };
-
-                            rprintln!("SPI/DMA: Start transaction");
-                            ssel.set_low();
-
-                            spi_buf[0] = data;
-                            let payload = spi_local
-                                .transfer_all(
-                                    spi_buf,
-                                    spi_rx_dma_local,
-                                    spi_tx_dma_local,
-                                )
-                                .start()
-                                .wait();
-
-                            ssel.set_high();
-
-                            spi_local        = payload.0;
-                            spi_buf          = payload.1;
-                            spi_rx_dma_local = payload.2;
-                            spi_tx_dma_local = payload.3;
-
-                            rprintln!(
-                                "SPI/DMA: Transaction ended ({})",
-                                spi_buf[1],
-                            );
+                            ))
+                        })
+                        .on(|protocol_spi::Configure { bus: (), config }| {
+                            let result = spi.lock(|spi| {
+                                let spi = spi.as_mut().unwrap();
+                                spi_enable_slave_mode(
+                                    spi,
+                                    swm.as_mut().unwrap(),
+                                );
+                                lpc8xx::spi_configure(spi, &config)
+                            });
 
-                            host_tx
-                                .send_message(
-                                    &TargetToHost::SpiReply(spi_buf[1]),
-                                    &mut buf,
-                                )
-                                .unwrap();
+                            Some(TargetToHost::SpiConfigured(
+                                protocol_spi::Configured { bus: (), result }
+                            ))
+                        })
+                        .on(|protocol_spi::Emulate { bus: (), device }| {
+                            let result = SpiDevice::new(&device)
+                                .map(|mut device| {
+                                    spi.lock(|spi| {
+                                        let spi = spi.as_mut().unwrap();
+                                        spi_enable_slave_mode(
+                                            spi,
+                                            swm.as_mut().unwrap(),
+                                        );
+                                        lpc8xx::spi_preload(
+                                            spi,
+                                            device.preload().into(),
+                                        );
+                                        spi_device.lock(|spi_device| {
+                                            *spi_device = Some(device);
+                                        });
+                                    });
+                                });
+
+                            Some(TargetToHost::SpiEmulated(
+                                protocol_spi::Emulated { bus: (), result }
+                            ))
+                        })
+                        .on(|protocol_spi::ReadLog { bus: () }| {
+                            let (len, overflowed) = spi_log.lock(|log| {
+                                let transfers = log.transfers();
+                                spi_transfers[..transfers.len()]
+                                    .copy_from_slice(transfers);
+                                let result =
+                                    (transfers.len(), log.overflowed());
+
+                                log.clear();
+                                result
+                            });
 
-                            Ok(())
-                        }
-                        message => {
-                            panic!("Unsupported message: {:?}", message)
-                        }
-                    };
-
-                    *swm = Some(swm_local);
-                    *usart_tx = Some(usart_tx_local);
-                    *usart_rts = Some(usart_rts_local);
-                    *usart_rts_pin = Some(usart_rts_pin_local);
-                    *usart_cts = Some(usart_cts_local);
-                    *usart_dma_chan = Some(usart_dma_chan_local);
-                    *i2c = Some(i2c_local);
-                    *i2c_dma = Some(i2c_dma_local);
-                    *spi = Some(spi_local);
-                    *spi_rx_dma = Some(spi_rx_dma_local);
-                    *spi_tx_dma = Some(spi_tx_dma_local);
-
-                    result
+                            Some(TargetToHost::SpiLog(
+                                protocol_spi::Log {
+                                    bus:       (),
+                                    transfers: &spi_transfers[..len],
+                                    overflowed,
+                                }
+                            ))
+                        })
+                        .finish()
+                        .map_err(|err| ProcessError::Other(err))
                 })
-                .expect("Error processing host request");
-            host_rx.clear_buf();
+                .unwrap_or_else(|err| {
+                    // The request has been dropped. Nothing we can do about
+                    // that, except to carry on with the next one.
+                    rprintln!("Error processing host request: {:?}", err);
+                });
+
+            if let Some(uptime_ms) = ticker.lock(|ticker| ticker.due()) {
+                let rx_errors = host_rx_int.lock(|rx| rx.errors())
+                    .wrapping_add(usart_rx_int.lock(|rx| rx.errors()))
+                    .wrapping_add(usart_sync_rx_int.lock(|rx| rx.errors()));
+                let queue_high_water = host_rx.take_high_water()
+                    .max(usart_rx.take_high_water())
+                    .max(usart_sync_rx.take_high_water());
+
+                let heartbeat = Heartbeat {
+                    uptime_ms,
+                    rx_errors,
+                    queue_high_water: queue_high_water as u32,
+                    events_dropped:   0,
+                };
+
+                host_tx
+                    .send_message(
+                        &rpc::Response::heartbeat(heartbeat),
+                        &mut buf,
+                    )
+                    .expect("Error sending heartbeat");
+            }
 
             // We need this critical section to protect against a race
             // conditions with the interrupt handlers. Otherwise, the following
@@ -879,7 +1164,10 @@ const APP: () = {
             // us up before the test suite times out. But it could also lead to
             // spurious test failures.
             interrupt::free(|_| {
-                if !host_rx.can_process() && !usart_rx.can_process() {
+                if !host_rx.can_process()
+                    && !usart_rx.can_process()
+                    && !usart_line.ready()
+                {
                     // On LPC84x MCUs, debug mode is not supported when
                     // sleeping. This interferes with RTT communication. Only
                     // sleep, if the user enables this through a compile-time
@@ -897,10 +1185,31 @@ const APP: () = {
             .expect("Error receiving from USART0");
     }
 
-    #[task(binds = USART1, resources = [usart_rx_int])]
+    #[task(binds = USART1, resources = [usart_rx_int, usart_line_prod])]
     fn usart1(cx: usart1::Context) {
-        cx.resources.usart_rx_int.receive()
-            .expect("Error receiving from USART1");
+        let rx   = cx.resources.usart_rx_int;
+        let line = cx.resources.usart_line_prod;
+
+        // If the idle loop falls behind, line events are dropped. That's fine,
+        // as tests only look for the first few events after injecting a fault.
+
+        // The break flag changes state when a break starts, and again when it
+        // ends. We only report the start.
+        if rx.usart.is_flag_set(usart::Flag::DELTARXBRK)
+            && rx.usart.is_flag_set(usart::Flag::RXBRK)
+        {
+            let _ = line.enqueue(LineCondition::Break);
+        }
+
+        match lpc8xx::usart_receive(rx) {
+            Ok(()) => {}
+            Err(ReceiveError::Usart(err)) => {
+                let _ = line.enqueue(lpc8xx::usart_line_condition(&err));
+            }
+            Err(err) => {
+                panic!("Error receiving from USART1: {:?}", err);
+            }
+        }
     }
 
     #[task(binds = PIN_INT6_USART3, resources = [usart_sync_rx_int])]
@@ -914,6 +1223,14 @@ const APP: () = {
         cx.resources.blue.toggle();
     }
 
+    #[task(binds = MRT0, resources = [heartbeat_timer, ticker])]
+    fn mrt0(cx: mrt0::Context) {
+        // Clear the interrupt flag.
+        let _ = cx.resources.heartbeat_timer.wait();
+
+        cx.resources.ticker.tick();
+    }
+
     #[task(binds = PIN_INT0, resources = [red_int])]
     fn pinint0(context: pinint0::Context) {
         let red_int = context.resources.red_int;
@@ -954,4 +1271,490 @@ const APP: () = {
         transfer_ready.set_a_when_complete();
         *transfer = Some(transfer_ready.start());
     }
+
+    #[task(binds = I2C0, resources = [i2c_slave, i2c_device])]
+    fn i2c0(context: i2c0::Context) {
+        let device = context.resources.i2c_device;
+
+        match context.resources.i2c_slave.wait() {
+            Ok(i2c::slave::State::AddressMatched(i2c)) => {
+                // Without an emulated device, nobody is home.
+                match device {
+                    Some(device) => {
+                        device.start();
+                        i2c.ack().unwrap();
+                    }
+                    None => {
+                        i2c.nack().unwrap();
+                    }
+                }
+            }
+            Ok(i2c::slave::State::RxReady(i2c)) => {
+                let data = i2c.read().unwrap();
+
+                match device {
+                    Some(device) => {
+                        stretch_clock(device);
+
+                        if device.write(data) {
+                            i2c.ack().unwrap();
+                        }
+                        else {
+                            i2c.nack().unwrap();
+                        }
+                    }
+                    None => {
+                        i2c.nack().unwrap();
+                    }
+                }
+            }
+            Ok(i2c::slave::State::TxReady(i2c)) => {
+                let data = match device {
+                    Some(device) => {
+                        stretch_clock(device);
+                        device.read()
+                    }
+                    None => {
+                        0xff
+                    }
+                };
+                i2c.transmit(data).unwrap();
+            }
+            Err(nb::Error::WouldBlock) => {
+                // I2C not ready; nothing to do
+            }
+            Err(err) => {
+                panic!("I2C error: {:?}", err);
+            }
+        }
+    }
+
+    #[task(binds = SPI0, resources = [spi, spi_device, spi_log])]
+    fn spi0(context: spi0::Context) {
+        static mut ACTIVE: bool = false;
+
+        let device = context.resources.spi_device;
+        let log    = context.resources.spi_log;
+
+        // The SPI interrupts are only enabled in slave mode, and only master
+        // mode uses DMA, which takes the peripheral out of its `Option`.
+        let spi = context.resources.spi.as_mut().unwrap();
+
+        if spi.is_slave_select_asserted() {
+            *ACTIVE = true;
+
+            if let Some(device) = device {
+                device.start();
+            }
+        }
+        if *ACTIVE {
+            if let Some(data) = lpc8xx::spi_receive(spi) {
+                log.push(data as u8);
+
+                // Without an emulated device, the slave replies with `0xff`.
+                let reply = match device {
+                    Some(device) => device.receive(data as u8).into(),
+                    None         => 0xff,
+                };
+                lpc8xx::spi_transmit(spi, reply);
+            }
+        }
+        if spi.is_slave_select_deasserted() {
+            *ACTIVE = false;
+            log.end();
+        }
+    }
 };
+
+
+/// Executes an I2C transaction that the host requested
+///
+/// Writes `data`, then reads `len` bytes into `buf`, skipping either if it's
+/// empty. Using DMA, the read follows the write after a stop condition, as
+/// `lpc8xx-hal` can't use DMA with a repeated start. `buf` also holds the data
+/// while it is written via DMA.
+///
+/// Returns the data that was read.
+fn i2c_transaction<'b>(
+    i2c:     &mut Option<
+        i2c::Master<I2C0, Enabled<PhantomData<IOSC>>, Enabled>
+    >,
+    i2c_dma: &mut Option<dma::Channel<dma::Channel15, Enabled>>,
+    mode:    DmaMode,
+    address: u8,
+    data:    &[u8],
+    len:     u16,
+    buf:     &'b mut [u8],
+)
+    -> Result<&'b [u8], protocol_i2c::Error>
+{
+    let len = len as usize;
+    if data.len() > buf.len() || len > buf.len() {
+        return Err(protocol_i2c::Error::TooLong);
+    }
+
+    match mode {
+        DmaMode::Regular => {
+            let master = i2c.as_mut().unwrap();
+            let buf    = &mut buf[..len];
+            lpc8xx::i2c_transaction(master, address, data, buf)?;
+
+            Ok(buf)
+        }
+        DmaMode::Dma => {
+            // The DMA API consumes the master and the channel, and doesn't
+            // return them, if addressing the slave fails. There's no way to
+            // get them back after that, so all we can do is fail every DMA
+            // transaction from then on.
+            if i2c.is_none() || i2c_dma.is_none() {
+                return Err(protocol_i2c::Error::Other);
+            }
+            let mut master  = i2c.take().unwrap();
+            let mut channel = i2c_dma.take().unwrap();
+
+            if !data.is_empty() {
+                buf[..data.len()].copy_from_slice(data);
+
+                // Sound, as we wait for the transfer to complete right away.
+                let tx_buf = unsafe { dma_buffer(&mut buf[..data.len()]) };
+
+                let transfer = master
+                    .write_all(address, tx_buf, channel)
+                    .map_err(lpc8xx::handle_i2c_error::<I2C0>)?;
+
+                match transfer.start().wait() {
+                    Ok(payload) => {
+                        master  = payload.dest;
+                        channel = payload.channel;
+                    }
+                    Err((dma::transfer::Error::Dest(err), payload)) => {
+                        *i2c     = Some(payload.dest);
+                        *i2c_dma = Some(payload.channel);
+
+                        return Err(lpc8xx::handle_i2c_error::<I2C0>(err));
+                    }
+                    Err((dma::transfer::Error::Source(void), _)) => {
+                        match void {}
+                    }
+                }
+            }
+            if len > 0 {
+                // Sound, as we wait for the transfer to complete right away.
+                let rx_buf = unsafe { dma_buffer(&mut buf[..len]) };
+
+                let transfer = master
+                    .read_all(address, rx_buf, channel)
+                    .map_err(lpc8xx::handle_i2c_error::<I2C0>)?;
+
+                match transfer.start().wait() {
+                    Ok(payload) => {
+                        master  = payload.source;
+                        channel = payload.channel;
+                    }
+                    Err((dma::transfer::Error::Source(err), payload)) => {
+                        *i2c     = Some(payload.source);
+                        *i2c_dma = Some(payload.channel);
+
+                        return Err(lpc8xx::handle_i2c_error::<I2C0>(err));
+                    }
+                    Err((dma::transfer::Error::Dest(void), _)) => {
+                        match void {}
+                    }
+                }
+            }
+
+            *i2c     = Some(master);
+            *i2c_dma = Some(channel);
+
+            Ok(&buf[..len])
+        }
+    }
+}
+
+/// Executes an SPI transfer that the host requested
+///
+/// Returns the frames received from the slave, which are stored in `buf`.
+fn spi_transfer<'b>(
+    spi:        &mut Option<SPI<SPI0, Enabled<spi::Master>>>,
+    spi_rx_dma: &mut Option<dma::Channel<dma::Channel10, Enabled>>,
+    spi_tx_dma: &mut Option<dma::Channel<dma::Channel11, Enabled>>,
+    ssel:       &mut GpioPin<PIO0_19, Output>,
+    transfer:   protocol_spi::Transfer<DmaMode>,
+    buf:        &'b mut [u8],
+)
+    -> Result<&'b [u8], protocol_spi::Error>
+{
+    use protocol_spi::ChipSelect;
+
+    let protocol_spi::Transfer { bus, config, chip_select, data } = transfer;
+
+    if data.len() > buf.len() {
+        return Err(protocol_spi::Error::TooLong);
+    }
+    let buf = &mut buf[..data.len()];
+    buf.copy_from_slice(data);
+
+    lpc8xx::spi_configure(spi.as_mut().unwrap(), &config)?;
+
+    match bus {
+        DmaMode::Regular => {
+            let spi       = spi.as_mut().unwrap();
+            let frame_len = if config.frame_size > 8 { 2 } else { 1 };
+
+            if chip_select == ChipSelect::Frame {
+                for frame in buf.chunks_mut(frame_len) {
+                    ssel.set_low();
+                    lpc8xx::spi_transfer(spi, config.frame_size, frame);
+                    ssel.set_high();
+                }
+            }
+            else {
+                ssel.set_low();
+                lpc8xx::spi_transfer(spi, config.frame_size, buf);
+                if chip_select == ChipSelect::Transfer {
+                    ssel.set_high();
+                }
+            }
+
+            Ok(buf)
+        }
+        DmaMode::Dma => {
+            // DMA writes one byte per frame, and can't toggle chip select
+            // between frames.
+            if config.frame_size > 8 || chip_select == ChipSelect::Frame {
+                return Err(protocol_spi::Error::UnsupportedConfig);
+            }
+            // `transfer_all` panics on an empty buffer.
+            if buf.is_empty() {
+                return Ok(buf);
+            }
+
+            // Sound, as we wait for the transfer to complete right away.
+            let dma_buf = unsafe { dma_buffer(buf) };
+
+            ssel.set_low();
+
+            let payload = spi.take().unwrap()
+                .transfer_all(
+                    dma_buf,
+                    spi_rx_dma.take().unwrap(),
+                    spi_tx_dma.take().unwrap(),
+                )
+                .start()
+                .wait();
+
+            if chip_select == ChipSelect::Transfer {
+                ssel.set_high();
+            }
+
+            *spi        = Some(payload.0);
+            *spi_rx_dma = Some(payload.2);
+            *spi_tx_dma = Some(payload.3);
+
+            Ok(buf)
+        }
+    }
+}
+
+/// Makes a buffer available to the DMA API of `lpc8xx-hal`
+///
+/// The DMA API requires `'static` buffers, as it can't know whether a transfer
+/// is waited for. The buffers used here are `'static`, but their sub-slices,
+/// which are what gets transferred, can't be.
+///
+/// # Safety
+///
+/// The caller must wait for the transfer to complete, and must not use the
+/// returned reference, including what the transfer hands back, afterwards.
+unsafe fn dma_buffer(buf: &mut [u8]) -> &'static mut [u8] {
+    &mut *(buf as *mut [u8])
+}
+
+/// Configures a USART under test, as the host requested
+///
+/// Only the USARTs used by the regular and DMA modes can be configured. The
+/// flow control mode uses the same USART as the regular mode. Returns the baud
+/// rate that was actually configured.
+fn configure_usart(
+    usart_tx:     &mut Tx<USART1, AsyncMode>,
+    usart_dma_tx: &mut usart::Tx<
+        USART2,
+        usart::state::Enabled<u8, AsyncMode>,
+        usart::state::NoThrottle,
+    >,
+    config:       &protocol_usart::Configure<UsartMode>,
+)
+    -> Result<u32, protocol_usart::Error>
+{
+    match config.usart {
+        UsartMode::Regular => {
+            lpc8xx::usart_configure(
+                &mut usart_tx.usart,
+                config,
+                USART_CLOCK_HZ,
+            )
+        }
+        UsartMode::Dma => {
+            lpc8xx::usart_configure(usart_dma_tx, config, USART_CLOCK_HZ)
+        }
+        UsartMode::FlowControl | UsartMode::Sync => {
+            Err(protocol_usart::Error::UnsupportedConfig)
+        }
+    }
+}
+
+/// Assigns the RTS function of USART1 to PIO0_9, or unassigns it
+///
+/// `lpc8xx-hal` models RTS as part of the transmitter, which only drives it
+/// while sending with flow control. A stalled receiver needs to drive it too.
+fn assign_usart_rts(assign: bool, _swm: &mut swm::Handle) {
+    // Sound, as we have exclusive access to the switch matrix through `_swm`.
+    // While sending with flow control, `lpc8xx-hal` assigns the same pin.
+    let swm = unsafe { &*pac::SWM0::ptr() };
+
+    let pin = if assign { 9 } else { 0xff };
+    swm.pinassign1.modify(|_, w| unsafe { w.u1_rts_o().bits(pin) });
+}
+
+/// Switches SPI0 into slave mode, if it isn't already
+///
+/// Routes the slave select input to the pin that is otherwise driven as a
+/// GPIO output in master mode, and enables the interrupts the slave is handled
+/// in.
+fn spi_enable_slave_mode(
+    spi:  &mut SPI<SPI0, Enabled<spi::Master>>,
+    _swm: &mut swm::Handle,
+)
+{
+    // Sound, as we have exclusive access to the switch matrix through `_swm`,
+    // and `lpc8xx-hal` doesn't know about the SPI0_SSEL0 assignment anyway.
+    let swm = unsafe { &*pac::SWM0::ptr() };
+    if swm.pinassign4.read().spi0_ssel0_io().bits() == 19 {
+        return;
+    }
+
+    // PIO0_19 is assigned to SPI0_SSEL0, overriding its GPIO function.
+    swm.pinassign4.modify(|_, w| unsafe { w.spi0_ssel0_io().bits(19) });
+
+    lpc8xx::spi_enable_slave_mode(spi);
+    lpc8xx::spi_preload(spi, 0xffff);
+    spi.enable_interrupts(spi::Interrupts {
+        rx_ready: true,
+        slave_select_asserted: true,
+        slave_select_deasserted: true,
+        .. Default::default()
+    });
+}
+
+/// Switches SPI0 back into master mode, if it isn't already
+///
+/// Reverses [`spi_enable_slave_mode`], and restores the SPI clock configured
+/// during initialization.
+///
+/// [`spi_enable_slave_mode`]: fn.spi_enable_slave_mode.html
+fn spi_enable_master_mode(
+    spi:  &mut SPI<SPI0, Enabled<spi::Master>>,
+    _swm: &mut swm::Handle,
+)
+{
+    // Sound, as we have exclusive access to the switch matrix through `_swm`,
+    // and `lpc8xx-hal` doesn't know about the SPI0_SSEL0 assignment anyway.
+    let swm = unsafe { &*pac::SWM0::ptr() };
+    if swm.pinassign4.read().spi0_ssel0_io().bits() != 19 {
+        return;
+    }
+
+    spi.disable_interrupts(spi::Interrupts {
+        rx_ready: true,
+        slave_select_asserted: true,
+        slave_select_deasserted: true,
+        .. Default::default()
+    });
+    lpc8xx::spi_enable_master_mode(spi, 0x0fff);
+
+    // Unassigning the pin hands it back to GPIO.
+    swm.pinassign4.modify(|_, w| unsafe { w.spi0_ssel0_io().bits(0xff) });
+}
+
+/// Hold the I2C clock low, if the emulated device is configured to do that
+///
+/// The slave holds the clock low until it has been serviced, so all we need to
+/// do is wait. The system clock runs at 12 MHz, so 1 µs is 12 cycles.
+fn stretch_clock(device: &I2cDevice) {
+    let us = device.clock_stretch_us();
+    if us > 0 {
+        lpc8xx_hal::cortex_m::asm::delay(us.saturating_mul(12));
+    }
+}
+
+/// An I2C write-read transaction that doesn't use DMA
+///
+/// `lpc8xx-hal` can't use DMA with a repeated start. Requests for a DMA
+/// write-read are left to the dispatcher, which reports them as unsupported.
+struct I2cWriteRead<'r> {
+    address: u8,
+    data:    &'r [u8],
+    len:     u16,
+}
+
+impl<'r> TryFrom<HostToTarget<'r>> for I2cWriteRead<'r> {
+    type Error = HostToTarget<'r>;
+
+    fn try_from(message: HostToTarget<'r>) -> Result<Self, Self::Error> {
+        match protocol_i2c::WriteRead::try_from(message)? {
+            protocol_i2c::WriteRead {
+                bus: DmaMode::Regular,
+                address,
+                data,
+                len,
+            } => {
+                Ok(Self { address, data, len })
+            }
+            write_read => {
+                Err(HostToTarget::I2cWriteRead(write_read))
+            }
+        }
+    }
+}
+
+
+/// Determine the cause of the last reset
+///
+/// Clears the reset status afterwards, so the next reset is reported correctly.
+fn reset_cause() -> ResetCause {
+    // Sound, as the HAL doesn't use the reset status register, and we're only
+    // accessing it here, during initialization.
+    let syscon = unsafe { &*pac::SYSCON::ptr() };
+
+    let status = syscon.sysrststat.read();
+    let cause = if status.por().bit_is_set() {
+        ResetCause::PowerOn
+    }
+    else if status.bod().bit_is_set() {
+        ResetCause::Brownout
+    }
+    else if status.wdt().bit_is_set() {
+        ResetCause::Watchdog
+    }
+    else if status.sysrst().bit_is_set() {
+        ResetCause::Software
+    }
+    else if status.extrst().bit_is_set() {
+        ResetCause::External
+    }
+    else {
+        ResetCause::Unknown
+    };
+
+    // The status bits are cleared by writing ones to them.
+    syscon.sysrststat.write(|w| {
+        w
+            .por().set_bit()
+            .extrst().set_bit()
+            .wdt().set_bit()
+            .bod().set_bit()
+            .sysrst().set_bit()
+    });
+
+    cause
+}
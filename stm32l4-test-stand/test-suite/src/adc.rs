@@ -0,0 +1,83 @@
+//! Evaluation of the target's ADC measurements
+
+
+use host_lib::config::AdcTolerances;
+
+
+/// The highest raw value of the target's 12-bit ADC
+pub const MAX_VALUE: u16 = 4095;
+
+/// The reference voltage of the target's ADC, in millivolts
+pub const VREF_MV: u16 = 3300;
+
+
+/// Converts a raw ADC value into the voltage it represents, in millivolts
+pub fn millivolts(value: u16) -> f64 {
+    f64::from(value) * f64::from(VREF_MV) / f64::from(MAX_VALUE)
+}
+
+
+/// The errors of a series of ADC measurements
+///
+/// Determined by fitting a line through the measured voltages, as a function
+/// of the applied voltages.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Linearity {
+    /// The measured voltage at 0 V, according to the best-fit line
+    pub offset_mv: f64,
+
+    /// The deviation of the best-fit line's slope from 1, in percent
+    pub gain_error_percent: f64,
+
+    /// The largest deviation of a single measurement from the best-fit line
+    pub nonlinearity_mv: f64,
+}
+
+impl Linearity {
+    /// Fit a line through a series of measurements
+    ///
+    /// Each sample consists of the applied voltage in millivolts, and the raw
+    /// value that the ADC measured. Requires at least two samples with
+    /// different voltages.
+    pub fn fit(samples: &[(u16, u16)]) -> Self {
+        let points: Vec<_> = samples
+            .iter()
+            .map(|&(applied, value)| (f64::from(applied), millivolts(value)))
+            .collect();
+
+        let n      = points.len() as f64;
+        let mean_x = points.iter().map(|&(x, _)| x).sum::<f64>() / n;
+        let mean_y = points.iter().map(|&(_, y)| y).sum::<f64>() / n;
+
+        let covariance: f64 = points
+            .iter()
+            .map(|&(x, y)| (x - mean_x) * (y - mean_y))
+            .sum();
+        let variance: f64 = points
+            .iter()
+            .map(|&(x, _)| (x - mean_x).powi(2))
+            .sum();
+
+        let slope     = covariance / variance;
+        let intercept = mean_y - slope * mean_x;
+
+        let nonlinearity_mv = points
+            .iter()
+            .map(|&(x, y)| (y - (slope * x + intercept)).abs())
+            .fold(0.0, f64::max);
+
+        Self {
+            offset_mv:          intercept,
+            gain_error_percent: (slope - 1.0) * 100.0,
+            nonlinearity_mv,
+        }
+    }
+
+    /// Indicates whether all errors are within the given tolerances
+    pub fn is_within(&self, tolerances: &AdcTolerances) -> bool {
+        self.offset_mv.abs() <= tolerances.offset_mv
+            && self.gain_error_percent.abs() <= tolerances.gain_error_percent
+            && self.nonlinearity_mv <= tolerances.nonlinearity_mv
+    }
+}
+
@@ -15,6 +15,7 @@ use crate::{
         TargetStartTimerInterruptError,
         TargetUsartSendError,
         TargetUsartWaitError,
+        TargetWaitForBootError,
     },
     test_stand::TestStandInitError,
 };
@@ -38,6 +39,7 @@ pub enum Error {
     TargetStartTimerInterrupt(TargetStartTimerInterruptError),
     TargetUsartSend(TargetUsartSendError),
     TargetUsartWait(TargetUsartWaitError),
+    TargetWaitForBoot(TargetWaitForBootError),
     TestStandInit(TestStandInitError),
 }
 
@@ -107,6 +109,12 @@ impl From<TargetUsartWaitError> for Error {
     }
 }
 
+impl From<TargetWaitForBootError> for Error {
+    fn from(err: TargetWaitForBootError) -> Self {
+        Self::TargetWaitForBoot(err)
+    }
+}
+
 impl From<TestStandInitError> for Error {
     fn from(err: TestStandInitError) -> Self {
         Self::TestStandInit(err)
@@ -1,6 +1,7 @@
 //! The library code that supports this test suite
 
 
+pub mod adc;
 pub mod error;
 pub mod target;
 pub mod test_stand;
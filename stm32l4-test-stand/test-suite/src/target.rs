@@ -7,22 +7,37 @@ use std::{
 };
 
 use host_lib::{
+    config::Timeouts,
     conn::{
         Conn,
+        ConnClearError,
         ConnReceiveError,
         ConnSendError,
     },
+    i2c::{
+        I2c,
+        TransactionError,
+    },
     pin::{
         Pin,
         ReadLevelError,
     },
+    spi::{
+        Spi,
+        TransferError,
+    },
 };
 use lpc845_messages::{
+    AdcInput,
     DmaMode,
+    Heartbeat,
     HostToTarget,
+    Node,
+    ResetCause,
     TargetToHost,
     UsartMode,
     pin,
+    spi,
 };
 
 
@@ -40,6 +55,118 @@ impl Target {
         }
     }
 
+    /// The default timeouts for communicating with the target
+    pub fn timeouts(&self) -> &Timeouts {
+        self.conn.timeouts()
+    }
+
+    /// The last heartbeat received from the target, and when it arrived
+    ///
+    /// Heartbeats are only received while waiting for other messages from the
+    /// target.
+    pub fn last_heartbeat(&self) -> Option<(Instant, Heartbeat)> {
+        self.conn.last_heartbeat()
+    }
+
+    /// Confirm that the connection actually leads to the test target
+    ///
+    /// Asks the node to identify itself and returns an error, if it doesn't
+    /// reply as the target within the given timeout.
+    pub fn handshake(&mut self, timeout: Duration)
+        -> Result<(), TargetHandshakeError>
+    {
+        let id = self.conn
+            .send(&HostToTarget::Identify)
+            .map_err(|err| TargetHandshakeError::Send(err))?;
+
+        let mut tmp = Vec::new();
+        let message = self.conn
+            .receive_reply::<TargetToHost>(id, timeout, &mut tmp)
+            .map_err(|err| TargetHandshakeError::Receive(err))?;
+
+        match message {
+            TargetToHost::Identity(Node::Target) => {
+                Ok(())
+            }
+            TargetToHost::Identity(node) => {
+                Err(TargetHandshakeError::WrongNode(node))
+            }
+            message => {
+                Err(
+                    TargetHandshakeError::UnexpectedMessage(
+                        format!("{:?}", message)
+                    )
+                )
+            }
+        }
+    }
+
+    /// Reset the target to its initial state
+    ///
+    /// Discards any data from the target that hasn't been processed yet, then
+    /// instructs the target to reset all its pins, peripherals, and buffers.
+    /// Waits until the target confirms the reset.
+    pub fn reset(&mut self) -> Result<(), TargetResetError> {
+        let timeout = self.conn.timeouts().reply();
+
+        self.conn
+            .clear_input()
+            .map_err(|err| TargetResetError::Clear(err))?;
+        let id = self.conn
+            .send(&HostToTarget::Reset)
+            .map_err(|err| TargetResetError::Send(err))?;
+        self.conn.restart_heartbeat();
+
+        let start = Instant::now();
+
+        loop {
+            if start.elapsed() > timeout {
+                return Err(TargetResetError::Timeout);
+            }
+
+            let mut tmp = Vec::new();
+            let message = self.conn
+                .receive_reply::<TargetToHost>(id, timeout, &mut tmp)
+                .map_err(|err| TargetResetError::Receive(err))?;
+
+            // Anything the target sent before the reset is left over from
+            // whatever happened before. We can safely ignore it.
+            if let TargetToHost::ResetComplete = message {
+                return Ok(());
+            }
+        }
+    }
+
+    /// Wait for the target to boot
+    ///
+    /// Call this after resetting the target in hardware, for example using
+    /// `Assistant::reset_target`. Returns the cause of the reset, as reported
+    /// by the target.
+    pub fn wait_for_boot(&mut self, timeout: Duration)
+        -> Result<ResetCause, TargetWaitForBootError>
+    {
+        self.conn.restart_heartbeat();
+
+        let start = Instant::now();
+
+        loop {
+            if start.elapsed() > timeout {
+                return Err(TargetWaitForBootError::Timeout);
+            }
+
+            let mut tmp = Vec::new();
+            let message = self.conn
+                .receive::<TargetToHost>(timeout, &mut tmp)
+                .map_err(|err| TargetWaitForBootError::Receive(err))?;
+
+            // Anything the target sent before it was reset is of no interest
+            // to us.
+            if let TargetToHost::Booted { reset_cause } = message {
+                return Ok(reset_cause);
+            }
+        }
+    }
+
     /// Instruct the target to set a GPIO pin high
     pub fn set_pin_high(&mut self) -> Result<(), TargetSetPinHighError> {
         self.pin
@@ -65,8 +192,9 @@ impl Target {
     /// Uses `pin_state` internally.
     pub fn pin_is_high(&mut self) -> Result<bool, TargetPinReadError> {
         let pin_state = self.pin.read_level::<HostToTarget, TargetToHost>(
-            Duration::from_millis(10),
+            self.conn.timeouts().read(),
             &mut self.conn,
+            &mut Vec::new(),
         )?;
         Ok(pin_state.0 == pin::Level::High)
     }
@@ -76,8 +204,9 @@ impl Target {
     /// Uses `pin_state` internally.
     pub fn pin_is_low(&mut self) -> Result<bool, TargetPinReadError> {
         let pin_state = self.pin.read_level::<HostToTarget, TargetToHost>(
-            Duration::from_millis(10),
+            self.conn.timeouts().read(),
             &mut self.conn,
+            &mut Vec::new(),
         )?;
         Ok(pin_state.0 == pin::Level::Low)
     }
@@ -88,7 +217,9 @@ impl Target {
     {
         self.conn
             .send(&HostToTarget::SendUsart { mode: UsartMode::Regular, data })
-            .map_err(|err| TargetUsartSendError(err))
+            .map_err(|err| TargetUsartSendError(err))?;
+
+        Ok(())
     }
 
     /// Instruct the target to send this message via USART using DMA
@@ -97,7 +228,9 @@ impl Target {
     {
         self.conn
             .send(&HostToTarget::SendUsart { mode: UsartMode::Dma, data })
-            .map_err(|err| TargetUsartSendError(err))
+            .map_err(|err| TargetUsartSendError(err))?;
+
+        Ok(())
     }
 
     /// Instruct the target to send this message via USART using DMA
@@ -109,7 +242,9 @@ impl Target {
                 mode: UsartMode::FlowControl,
                 data,
             })
-            .map_err(|err| TargetUsartSendError(err))
+            .map_err(|err| TargetUsartSendError(err))?;
+
+        Ok(())
     }
 
     /// Wait to receive the provided data via USART
@@ -172,19 +307,23 @@ impl Target {
         }
     }
 
-    pub fn read_adc(&mut self) -> Result<u16, ReadAdcError> {
-        let timeout = Duration::from_millis(10);
+    /// Read a raw value from one of the target's analog inputs
+    pub fn read_adc(&mut self, input: AdcInput)
+        -> Result<u16, ReadAdcError>
+    {
+        let timeout = self.conn.timeouts().read();
 
         // Wait for a bit, to give whatever event is expected to change the
         // level some time to happen.
         sleep(timeout);
 
-        self.conn
-            .send(&HostToTarget::ReadAdc)
+        let id = self.conn
+            .send(&HostToTarget::ReadAdc { input })
             .map_err(|err| ReadAdcError::Send(err))?;
 
         let mut buf = Vec::new();
-        let reply = self.conn.receive::<TargetToHost>(timeout, &mut buf)
+        let reply = self.conn
+            .receive_reply::<TargetToHost>(id, timeout, &mut buf)
             .map_err(|err| ReadAdcError::Receive(err))?;
 
         match reply {
@@ -201,74 +340,82 @@ impl Target {
         }
     }
 
-    /// Start an I2C transaction
-    ///
-    /// Sends the provided `data` and returns the reply.
-    pub fn start_i2c_transaction(&mut self, data: u8, timeout: Duration)
-        -> Result<u8, TargetI2cError>
+    /// Write to an I2C slave
+    pub fn i2c_write(&mut self, address: u8, data: &[u8], timeout: Duration)
+        -> Result<(), TargetI2cError>
     {
-        let address = 0x48;
-
-        self.conn
-            .send(
-                &HostToTarget::StartI2cTransaction {
-                    mode: DmaMode::Regular,
-                    address,
-                    data,
-                }
+        I2c::new(DmaMode::Regular)
+            .write::<HostToTarget, TargetToHost>(
+                address,
+                data,
+                timeout,
+                &mut self.conn,
+                &mut Vec::new(),
             )
-            .map_err(|err| TargetI2cError::Send(err))?;
-
-        let mut tmp = Vec::new();
-        let message = self.conn
-            .receive::<TargetToHost>(timeout, &mut tmp)
-            .map_err(|err| TargetI2cError::Receive(err))?;
-
-        match message {
-            TargetToHost::I2cReply(reply) => {
-                Ok(reply)
-            }
-            message => {
-                Err(
-                    TargetI2cError::UnexpectedMessage(
-                        format!("{:?}", message)
-                    )
-                )
-            }
-        }
+            .map_err(|err| TargetI2cError(err))
     }
 
-    /// Start an SPI transaction
+    /// Read from an I2C slave
     ///
-    /// Sends the provided `data` and returns the reply.
-    pub fn start_spi_transaction(&mut self, data: u8, timeout: Duration)
-        -> Result<u8, TargetSpiError>
+    /// Returns the data that was read.
+    pub fn i2c_read(&mut self, address: u8, len: u16, timeout: Duration)
+        -> Result<Vec<u8>, TargetI2cError>
     {
-        self.conn
-            .send(
-                &HostToTarget::StartSpiTransaction {
-                    mode: DmaMode::Regular,
-                    data,
-                }
+        I2c::new(DmaMode::Regular)
+            .read::<HostToTarget, TargetToHost>(
+                address,
+                len,
+                timeout,
+                &mut self.conn,
+                &mut Vec::new(),
             )
-            .map_err(|err| TargetSpiError::Send(err))?;
+            .map_err(|err| TargetI2cError(err))
+    }
 
-        let mut tmp = Vec::new();
-        let message = self.conn.receive::<TargetToHost>(timeout, &mut tmp)
-            .map_err(|err| TargetSpiError::Receive(err))?;
+    /// Write to, then read from an I2C slave, using a repeated start
+    ///
+    /// Returns the data that was read.
+    pub fn i2c_write_read(&mut self,
+        address: u8,
+        data:    &[u8],
+        len:     u16,
+        timeout: Duration,
+    )
+        -> Result<Vec<u8>, TargetI2cError>
+    {
+        I2c::new(DmaMode::Regular)
+            .write_read::<HostToTarget, TargetToHost>(
+                address,
+                data,
+                len,
+                timeout,
+                &mut self.conn,
+                &mut Vec::new(),
+            )
+            .map_err(|err| TargetI2cError(err))
+    }
 
-        match message {
-            TargetToHost::SpiReply(reply) => {
-                Ok(reply)
-            }
-            message => {
-                Err(
-                    TargetSpiError::UnexpectedMessage(
-                        format!("{:?}", message)
-                    )
-                )
-            }
-        }
+    /// Transfer frames to and from an SPI slave
+    ///
+    /// Returns the frames received from the slave.
+    pub fn spi_transfer(&mut self,
+        config:      spi::Config,
+        chip_select: spi::ChipSelect,
+        data:        &[u8],
+        timeout:     Duration,
+    )
+        -> Result<Vec<u8>, TargetSpiError>
+    {
+        Spi::new(DmaMode::Regular)
+            .transfer::<HostToTarget, TargetToHost>(
+                config,
+                chip_select,
+                data,
+                timeout,
+                &mut self.conn,
+                &mut Vec::new(),
+            )
+            .map_err(|err| TargetSpiError(err))
     }
 
     /// Start a timer interrupt with the given period in milliseconds
@@ -303,7 +450,7 @@ pub struct TimerInterrupt<'r>(&'r mut Target);
 impl Drop for TimerInterrupt<'_> {
     fn drop(&mut self) {
         (self.0).conn.send(&HostToTarget::StopTimerInterrupt)
-            .unwrap()
+            .unwrap();
     }
 }
 
@@ -315,19 +462,44 @@ pub struct PwmSignal<'r>(&'r mut Target);
 impl Drop for PwmSignal<'_> {
     fn drop(&mut self) {
         (self.0).conn.send(&HostToTarget::StopPwmSignal)
-            .unwrap()
+            .unwrap();
     }
 }
 
 
 #[derive(Debug)]
-pub struct TargetSetPinHighError(ConnSendError);
+pub enum TargetHandshakeError {
+    Send(ConnSendError),
+    Receive(ConnReceiveError),
+
+    /// A different kind of node replied to the handshake
+    WrongNode(Node),
+
+    UnexpectedMessage(String),
+}
 
 #[derive(Debug)]
-pub struct TargetSetPinLowError(ConnSendError);
+pub enum TargetResetError {
+    Clear(ConnClearError),
+    Send(ConnSendError),
+    Receive(ConnReceiveError),
+    Timeout,
+}
 
 #[derive(Debug)]
-pub struct TargetPinReadError(ReadLevelError);
+pub enum TargetWaitForBootError {
+    Receive(ConnReceiveError),
+    Timeout,
+}
+
+#[derive(Debug)]
+pub struct TargetSetPinHighError(pub ConnSendError);
+
+#[derive(Debug)]
+pub struct TargetSetPinLowError(pub ConnSendError);
+
+#[derive(Debug)]
+pub struct TargetPinReadError(pub ReadLevelError);
 
 impl From<ReadLevelError> for TargetPinReadError {
     fn from(err: ReadLevelError) -> Self {
@@ -337,7 +509,7 @@ impl From<ReadLevelError> for TargetPinReadError {
 
 
 #[derive(Debug)]
-pub struct TargetUsartSendError(ConnSendError);
+pub struct TargetUsartSendError(pub ConnSendError);
 
 #[derive(Debug)]
 pub enum TargetUsartWaitError {
@@ -354,21 +526,13 @@ pub enum ReadAdcError {
 }
 
 #[derive(Debug)]
-pub enum TargetI2cError {
-    Send(ConnSendError),
-    Receive(ConnReceiveError),
-    UnexpectedMessage(String),
-}
+pub struct TargetI2cError(pub TransactionError);
 
 #[derive(Debug)]
-pub enum TargetSpiError {
-    Send(ConnSendError),
-    Receive(ConnReceiveError),
-    UnexpectedMessage(String),
-}
+pub struct TargetSpiError(pub TransferError);
 
 #[derive(Debug)]
-pub struct TargetStartTimerInterruptError(ConnSendError);
+pub struct TargetStartTimerInterruptError(pub ConnSendError);
 
 #[derive(Debug)]
-pub struct TargetStartPwmSignalError(ConnSendError);
+pub struct TargetStartPwmSignalError(pub ConnSendError);
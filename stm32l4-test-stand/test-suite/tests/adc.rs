@@ -1,9 +1,11 @@
 //! Test Suite for the ADC API in STM32L4xx HAL
 
 
+use lpc845_messages::AdcInput;
 use stm32l4_test_suite::{
     Result,
     TestStand,
+    adc::Linearity,
 };
 
 
@@ -12,14 +14,40 @@ fn it_should_read_adc_values() -> Result {
     let mut test_stand = TestStand::new()?;
 
     test_stand.assistant.set_pin_5_low()?;
-    let value = test_stand.target.read_adc()?;
+    let value = test_stand.target.read_adc(AdcInput::Pin5)?;
     println!("value (low): {}", value);
     assert!(value < 16);
 
     test_stand.assistant.set_pin_5_high()?;
-    let value = test_stand.target.read_adc()?;
+    let value = test_stand.target.read_adc(AdcInput::Pin5)?;
     println!("value (high): {}", value);
     assert!(value > 2u16.pow(12) - 128);
 
     Ok(())
 }
+
+#[test]
+fn it_should_convert_voltages_linearly() -> Result {
+    let mut test_stand = TestStand::new()?;
+
+    // Stay clear of the supply rails, where neither the assistant's DAC nor
+    // the target's ADC are linear.
+    let mut samples = Vec::new();
+    for millivolts in (100..=3200).step_by(100) {
+        let applied = test_stand.assistant.set_analog(millivolts)?;
+        let value   = test_stand.target.read_adc(AdcInput::Analog)?;
+        println!("{} mV: {}", applied, value);
+
+        samples.push((applied, value));
+    }
+
+    let linearity = Linearity::fit(&samples);
+    assert!(
+        linearity.is_within(&test_stand.adc_tolerances),
+        "linearity: {:?}, tolerances: {:?}",
+        linearity,
+        test_stand.adc_tolerances,
+    );
+
+    Ok(())
+}
@@ -4,11 +4,15 @@
 //! wiring instructions.
 
 
-use std::time::Duration;
-
+use host_lib::i2c::TransactionError;
+use lpc845_messages::{
+    ASSISTANT_I2C_ADDRESS,
+    i2c,
+};
 use stm32l4_test_suite::{
     Result,
     TestStand,
+    target::TargetI2cError,
 };
 
 
@@ -17,10 +21,68 @@ fn it_should_start_a_transaction() -> Result {
     let mut test_stand = TestStand::new()?;
 
     let data = 0x22;
-    let timeout = Duration::from_millis(50);
-    let reply = test_stand.target.start_i2c_transaction(data, timeout)?;
+    let timeout = test_stand.target.timeouts().reply();
+    test_stand.target.i2c_write(ASSISTANT_I2C_ADDRESS, &[data], timeout)?;
+    let reply = test_stand.target.i2c_read(ASSISTANT_I2C_ADDRESS, 1, timeout)?;
+
+    assert_eq!(reply, [data << 1]);
+
+    Ok(())
+}
+
+#[test]
+fn it_should_write_then_read_using_a_repeated_start() -> Result {
+    let mut test_stand = TestStand::new()?;
+
+    let data = 0x22;
+    let timeout = test_stand.target.timeouts().reply();
+    let reply = test_stand.target.i2c_write_read(
+        ASSISTANT_I2C_ADDRESS,
+        &[data],
+        1,
+        timeout,
+    )?;
+
+    assert_eq!(reply, [data << 1]);
+
+    Ok(())
+}
+
+#[test]
+fn it_should_report_a_nack() -> Result {
+    let mut test_stand = TestStand::new()?;
+
+    // Nothing on the bus responds to this address.
+    let address = ASSISTANT_I2C_ADDRESS + 1;
+
+    let timeout = test_stand.target.timeouts().reply();
+    let result = test_stand.target.i2c_write(address, &[0x22], timeout);
+
+    assert!(matches!(
+        result,
+        Err(TargetI2cError(TransactionError::I2c(i2c::Error::AddressNack)))
+    ));
+
+    Ok(())
+}
+
+#[test]
+fn it_should_read_registers_of_an_emulated_device() -> Result {
+    let mut test_stand = TestStand::new()?;
+
+    let address = 0x50;
+    test_stand.assistant.emulate_i2c_device(i2c::Device {
+        address,
+        registers:      &[0x11, 0x22, 0x33, 0x44],
+        read_only:      &[],
+        auto_increment: true,
+        fault:          None,
+    })?;
+
+    let timeout = test_stand.target.timeouts().reply();
+    let reply = test_stand.target.i2c_write_read(address, &[1], 2, timeout)?;
 
-    assert_eq!(reply, data << 1);
+    assert_eq!(reply, [0x22, 0x33]);
 
     Ok(())
 }
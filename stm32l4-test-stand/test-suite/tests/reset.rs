@@ -0,0 +1,42 @@
+//! Test Suite for resetting the target
+//!
+//! This test suite communicates with hardware. See top-level README.md for
+//! wiring instructions.
+
+
+use lpc845_messages::ResetCause;
+
+use stm32l4_test_suite::{
+    Result,
+    TestStand,
+};
+
+
+#[test]
+fn it_should_boot_after_external_reset() -> Result {
+    let mut test_stand = TestStand::new()?;
+
+    let timeout = test_stand.target.timeouts().boot();
+
+    test_stand.assistant.reset_target()?;
+    let reset_cause = test_stand.target.wait_for_boot(timeout)?;
+
+    assert_eq!(reset_cause, ResetCause::External);
+
+    Ok(())
+}
+
+#[test]
+fn it_should_stay_in_reset_while_held() -> Result {
+    let mut test_stand = TestStand::new()?;
+
+    let timeout = test_stand.target.timeouts().boot();
+
+    test_stand.assistant.hold_target_in_reset()?;
+    assert!(test_stand.target.wait_for_boot(timeout).is_err());
+
+    test_stand.assistant.release_target_reset()?;
+    test_stand.target.wait_for_boot(timeout)?;
+
+    Ok(())
+}
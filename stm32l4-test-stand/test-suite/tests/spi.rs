@@ -4,11 +4,12 @@
 //! wiring instructions.
 
 
-use std::time::Duration;
-
+use host_lib::spi::TransferError;
+use lpc845_messages::spi;
 use stm32l4_test_suite::{
     Result,
     TestStand,
+    target::TargetSpiError,
 };
 
 
@@ -17,10 +18,117 @@ fn it_should_start_a_transaction() -> Result {
     let mut test_stand = TestStand::new()?;
 
     let data = 0x22;
-    let timeout = Duration::from_millis(50);
-    let reply = test_stand.target.start_spi_transaction(data, timeout)?;
+    let timeout = test_stand.target.timeouts().reply();
+    let reply = test_stand.target.spi_transfer(
+        spi::Config::default(),
+        spi::ChipSelect::Transfer,
+        &[data, 0xff],
+        timeout,
+    )?;
+
+    // The assistant replies to each frame during the next one.
+    assert_eq!(reply[1], data << 1);
+
+    Ok(())
+}
+
+#[test]
+fn it_should_transfer_in_every_mode() -> Result {
+    let mut test_stand = TestStand::new()?;
+
+    let modes = [spi::MODE_0, spi::MODE_1, spi::MODE_2, spi::MODE_3];
+
+    for &mode in &modes {
+        let config = spi::Config {
+            mode,
+            .. spi::Config::default()
+        };
+        test_stand.assistant.configure_spi(config)?;
+
+        let data = 0x22;
+        let timeout = test_stand.target.timeouts().reply();
+        let reply = test_stand.target.spi_transfer(
+            config,
+            spi::ChipSelect::Transfer,
+            &[data, 0xff],
+            timeout,
+        )?;
+
+        assert_eq!(reply[1], data << 1, "{:?}", mode);
+    }
+
+    Ok(())
+}
+
+#[test]
+fn it_should_transfer_lsb_first() -> Result {
+    let mut test_stand = TestStand::new()?;
+
+    let config = spi::Config {
+        bit_order: spi::BitOrder::LsbFirst,
+        .. spi::Config::default()
+    };
+    test_stand.assistant.configure_spi(config)?;
+
+    let data = 0x22;
+    let timeout = test_stand.target.timeouts().reply();
+    let reply = test_stand.target.spi_transfer(
+        config,
+        spi::ChipSelect::Transfer,
+        &[data, 0xff],
+        timeout,
+    )?;
+
+    assert_eq!(reply[1], data << 1);
+
+    Ok(())
+}
+
+#[test]
+fn it_should_reject_frames_larger_than_8_bits() -> Result {
+    let mut test_stand = TestStand::new()?;
+
+    let config = spi::Config {
+        frame_size: 16,
+        .. spi::Config::default()
+    };
+
+    let timeout = test_stand.target.timeouts().reply();
+    let result = test_stand.target.spi_transfer(
+        config,
+        spi::ChipSelect::Transfer,
+        &[0x22, 0x11],
+        timeout,
+    );
+
+    assert!(matches!(
+        result,
+        Err(TargetSpiError(TransferError::Spi(spi::Error::UnsupportedConfig)))
+    ));
+
+    Ok(())
+}
+
+#[test]
+fn it_should_receive_responses_from_an_emulated_device() -> Result {
+    let mut test_stand = TestStand::new()?;
+
+    test_stand.assistant.emulate_spi_device(
+        spi::Device::Responses(&[0x11, 0x22, 0x33]),
+    )?;
+
+    let timeout = test_stand.target.timeouts().reply();
+    let reply = test_stand.target.spi_transfer(
+        spi::Config::default(),
+        spi::ChipSelect::Transfer,
+        &[0x01, 0x02, 0x03],
+        timeout,
+    )?;
+
+    assert_eq!(reply, [0x11, 0x22, 0x33]);
 
-    assert_eq!(reply, data << 1);
+    let log = test_stand.assistant.read_spi_log()?;
+    assert_eq!(log.transfers, [[0x01, 0x02, 0x03]]);
 
     Ok(())
 }
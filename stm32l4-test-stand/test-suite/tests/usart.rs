@@ -1,8 +1,6 @@
 //! Test Suite for the USART API in STM32L4xx HAL
 
 
-use std::time::Duration;
-
 use stm32l4_test_suite::{
     Result,
     TestStand,
@@ -16,7 +14,7 @@ fn it_should_send_messages() -> Result {
     let message = b"Hello, world!";
     test_stand.target.send_usart(message)?;
 
-    let timeout  = Duration::from_millis(50);
+    let timeout  = test_stand.assistant.timeouts().usart();
     let received = test_stand.assistant
         .receive_from_target_usart(message, timeout)?;
 
@@ -31,7 +29,7 @@ fn it_should_receive_messages() -> Result {
     let message = b"Hello, world!";
     test_stand.assistant.send_to_target_usart(message)?;
 
-    let timeout  = Duration::from_millis(50);
+    let timeout  = test_stand.target.timeouts().usart();
     let received = test_stand.target.wait_for_usart_rx(message, timeout)?;
 
     assert_eq!(received, message);
@@ -45,7 +43,7 @@ fn it_should_send_messages_using_dma() -> Result {
     let message = b"Hello, world!";
     test_stand.target.send_usart_dma(message)?;
 
-    let timeout  = Duration::from_millis(50);
+    let timeout  = test_stand.assistant.timeouts().usart();
     let received = test_stand.assistant
         .receive_from_target_usart(message, timeout)?;
 
@@ -60,7 +58,7 @@ fn it_should_receive_messages_via_dma() -> Result {
     let message = b"Hello, world!";
     test_stand.assistant.send_to_target_usart_dma(message)?;
 
-    let timeout  = Duration::from_millis(50);
+    let timeout  = test_stand.target.timeouts().usart();
     let received = test_stand.target.wait_for_usart_rx_dma(message, timeout)?;
 
     assert_eq!(received, message);
@@ -78,12 +76,12 @@ fn it_should_send_using_flow_control() -> Result {
 
     test_stand.assistant.wait_for_rts()?;
 
-    let timeout = Duration::from_millis(50);
+    let timeout = test_stand.assistant.timeouts().usart();
     test_stand.assistant.expect_nothing_from_target(timeout)?;
 
     test_stand.assistant.enable_cts()?;
 
-    let timeout = Duration::from_millis(50);
+    let timeout = test_stand.assistant.timeouts().usart();
     let received = test_stand.assistant
         .receive_from_target_usart(message, timeout)?;
 
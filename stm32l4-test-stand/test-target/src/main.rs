@@ -5,8 +5,7 @@
 #![no_std]
 
 
-extern crate panic_rtt_target;
-
+use core::convert::TryFrom;
 
 use cortex_m::peripheral::{
     SYST,
@@ -15,7 +14,6 @@ use cortex_m::peripheral::{
 use embedded_hal::spi;
 use heapless::{
     pool,
-    Vec,
     consts::U256,
     pool::singleton::{
         Box,
@@ -55,15 +53,20 @@ use stm32l4xx_hal::{
         PC0,
         PC1,
         PC2,
+        PC3,
         PC7,
         PushPull,
     },
-    i2c::I2c,
+    i2c::{
+        self,
+        I2c,
+    },
     pac::{
         self,
         I2C1,
         SPI2,
         TIM1,
+        TIM7,
         USART1,
         USART2,
         USART3,
@@ -78,14 +81,49 @@ use stm32l4xx_hal::{
         Serial,
     },
     spi::Spi,
+    timer::{
+        self,
+        Timer,
+    },
 };
 
+use firmware_lib::{
+    dispatch::Dispatcher,
+    heartbeat::{
+        self,
+        Ticker,
+    },
+    panic,
+    stm32l4,
+    usart::{
+        RxIdle,
+        RxInt,
+        Tx,
+        Usart,
+        rx::ProcessError,
+    },
+};
 use lpc845_messages::{
+    AdcInput,
     DmaMode,
+    Heartbeat,
     HostToTarget,
+    Identify,
+    Node,
+    ReadAdc,
+    Reset,
+    ResetCause,
+    SendUsart,
+    StartPwmSignal,
+    StartTimerInterrupt,
+    StopPwmSignal,
+    StopTimerInterrupt,
     TargetToHost,
     UsartMode,
+    i2c as protocol_i2c,
     pin,
+    rpc,
+    spi as protocol_spi,
 };
 
 
@@ -98,17 +136,15 @@ pool!(
 #[rtic::app(device = stm32l4xx_hal::pac)]
 const APP: () = {
     struct Resources {
-        rx_main: serial::Rx<USART1>,
-        tx_main: serial::Tx<USART1>,
-        rx_host: serial::Rx<USART2>,
-        tx_host: serial::Tx<USART2>,
+        rx_int_main:  RxInt<'static, serial::Rx<USART1>>,
+        rx_idle_main: RxIdle<'static>,
+        tx_main:      Tx<serial::Tx<USART1>>,
+        rx_int_host:  RxInt<'static, serial::Rx<USART2>>,
+        rx_idle_host: RxIdle<'static>,
+        tx_host:      Tx<serial::Tx<USART2>>,
         rx_dma: serial::Rx<USART3>,
         tx_dma: serial::Tx<USART3>,
 
-        rx_prod_main: spsc::Producer<'static, u8, U256>,
-        rx_cons_main: spsc::Consumer<'static, u8, U256>,
-        rx_prod_host: spsc::Producer<'static, u8, U256>,
-        rx_cons_host: spsc::Consumer<'static, u8, U256>,
         rx_prod_dma: spsc::Producer<'static, u8, U256>,
         rx_cons_dma: spsc::Consumer<'static, u8, U256>,
 
@@ -117,6 +153,7 @@ const APP: () = {
 
         adc: ADC,
         analog: PC0<Analog>,
+        analog_dac: PC3<Analog>,
 
         gpio_out: PC1<Output<PushPull>>,
         gpio_in: PC2<Input<Floating>>,
@@ -144,14 +181,15 @@ const APP: () = {
         clocks: Clocks,
 
         pwm_signal: Pwm<TIM1, pwm::C4>,
+
+        heartbeat_timer: Timer<TIM7>,
+        ticker:          Ticker,
     }
 
     #[init]
     fn init(cx: init::Context) -> init::LateResources {
-        static mut RX_QUEUE_HOST: spsc::Queue<u8, U256> =
-            spsc::Queue(heapless::i::Queue::new());
-        static mut RX_QUEUE_MAIN: spsc::Queue<u8, U256> =
-            spsc::Queue(heapless::i::Queue::new());
+        static mut USART_MAIN: Usart = Usart::new();
+        static mut USART_HOST: Usart = Usart::new();
         static mut RX_QUEUE_DMA: spsc::Queue<u8, U256> =
             spsc::Queue(heapless::i::Queue::new());
 
@@ -165,6 +203,9 @@ const APP: () = {
         let cp = cx.core;
         let p = pac::Peripherals::take().unwrap();
 
+        let reset_cause = reset_cause(&p.RCC);
+        rprint!("reset cause: {:?}...", reset_cause);
+
         let mut rcc = p.RCC.constrain();
         let mut flash = p.FLASH.constrain();
         let mut pwr = p.PWR.constrain(&mut rcc.apb1r1);
@@ -204,6 +245,8 @@ const APP: () = {
         let rx_pin_dma = gpiob.pb11.into_af7(&mut gpiob.moder, &mut gpiob.afrh);
 
         let analog = gpioc.pc0.into_analog(&mut gpioc.moder, &mut gpioc.pupdr);
+        let analog_dac = gpioc.pc3
+            .into_analog(&mut gpioc.moder, &mut gpioc.pupdr);
 
         let gpio_out = gpioc.pc1
             .into_push_pull_output(&mut gpioc.moder, &mut gpioc.otyper);
@@ -283,10 +326,18 @@ const APP: () = {
         let (tx_main, rx_main) = usart_main.split();
         let (tx_host, rx_host) = usart_host.split();
         let (tx_dma, rx_dma) = usart_dma.split();
-        let (rx_prod_main, rx_cons_main) = RX_QUEUE_MAIN.split();
-        let (rx_prod_host, rx_cons_host) = RX_QUEUE_HOST.split();
         let (rx_prod_dma, rx_cons_dma) = RX_QUEUE_DMA.split();
 
+        // Use TIM7 to keep track of time for the heartbeat. SysTick is already
+        // taken by the timer interrupt test.
+        let mut heartbeat_timer = Timer::tim7(
+            p.TIM7,
+            (1000 / heartbeat::PERIOD_MS).hz(),
+            clocks,
+            &mut rcc.apb1r1,
+        );
+        heartbeat_timer.listen(timer::Event::TimeOut);
+
         let dma1 = p.DMA1.split(&mut rcc.ahb1);
         let dma_tx_main = tx_main.frame_sender(dma1.4);
         let dma_rx_dma = {
@@ -296,20 +347,35 @@ const APP: () = {
             rx_dma.frame_read(dma1.3, buf)
         };
 
+        let (rx_int_main, rx_idle_main, tx_main) =
+            USART_MAIN.init(rx_main, tx_main);
+        let (rx_int_host, rx_idle_host, mut tx_host) =
+            USART_HOST.init(rx_host, tx_host);
+
+        // From now on, report panics to the host, not just via RTT.
+        panic::report_to(stm32l4::write_raw::<stm32l4::Usart2>);
+
+        // Let the test suite know that we're ready. It might be waiting for
+        // this, if it has just reset us.
+        tx_host
+            .send_notification(
+                TargetToHost::Booted { reset_cause },
+                &mut [0; 256],
+            )
+            .expect("Error sending message to host");
+
         rprintln!("done.");
 
         init::LateResources {
-            rx_main,
+            rx_int_main,
+            rx_idle_main,
             tx_main,
-            rx_host,
+            rx_int_host,
+            rx_idle_host,
             tx_host,
             rx_dma,
             tx_dma,
 
-            rx_prod_main,
-            rx_cons_main,
-            rx_prod_host,
-            rx_cons_host,
             rx_prod_dma,
             rx_cons_dma,
 
@@ -318,6 +384,7 @@ const APP: () = {
 
             adc,
             analog,
+            analog_dac,
 
             gpio_out,
             gpio_in,
@@ -332,18 +399,24 @@ const APP: () = {
             clocks,
 
             pwm_signal,
+
+            heartbeat_timer,
+            ticker: Ticker::new(),
         }
     }
 
     #[idle(resources = [
-        rx_cons_main,
-        rx_cons_host,
-        rx_cons_dma,
+        rx_int_main,
+        rx_idle_main,
         tx_main,
+        rx_int_host,
+        rx_idle_host,
         tx_host,
+        rx_cons_dma,
         dma_tx_main,
         adc,
         analog,
+        analog_dac,
         gpio_out,
         gpio_in,
         i2c,
@@ -352,16 +425,20 @@ const APP: () = {
         systick,
         clocks,
         pwm_signal,
+        ticker,
+        timer_signal,
+        dma_rx_dma,
     ])]
     fn idle(cx: idle::Context) -> ! {
-        let rx_main = cx.resources.rx_cons_main;
-        let rx_host = cx.resources.rx_cons_host;
+        let rx_main = cx.resources.rx_idle_main;
+        let rx_host = cx.resources.rx_idle_host;
         let rx_dma  = cx.resources.rx_cons_dma;
         let tx_main = cx.resources.tx_main;
         let tx_host = cx.resources.tx_host;
         let dma_tx_main = cx.resources.dma_tx_main;
         let adc = cx.resources.adc;
         let analog = cx.resources.analog;
+        let analog_dac = cx.resources.analog_dac;
         let gpio_out = cx.resources.gpio_out;
         let gpio_in = cx.resources.gpio_in;
         let i2c = cx.resources.i2c;
@@ -371,237 +448,279 @@ const APP: () = {
         let clocks = cx.resources.clocks;
         let pwm_signal = cx.resources.pwm_signal;
 
-        let mut buf_main_rx: Vec<_, U256> = Vec::new();
-        let mut buf_host_rx: Vec<_, U256> = Vec::new();
+        let mut rx_int_main  = cx.resources.rx_int_main;
+        let mut rx_int_host  = cx.resources.rx_int_host;
+        let mut ticker       = cx.resources.ticker;
+        let mut timer_signal = cx.resources.timer_signal;
+        let mut dma_rx_dma   = cx.resources.dma_rx_dma;
+
+        let mut buf = [0; 256];
 
         loop {
-            handle_usart_rx(
-                rx_main,
-                tx_host,
-                UsartMode::Regular,
-                &mut buf_main_rx,
-            );
-            handle_usart_rx(
-                rx_dma,
-                tx_host,
-                UsartMode::Dma,
-                &mut buf_main_rx,
-            );
-
-            if let Some(b) = rx_host.dequeue() {
-                // Requests are COBS-encoded, so we know that `0` means we
-                // received a full frame.
-                if b != 0 {
-                    buf_host_rx.push(b).expect("Receive buffer full");
-                    continue;
-                }
-
-                let message = postcard::from_bytes_cobs(&mut buf_host_rx)
-                    .expect("Error decoding message");
-                match message {
-                    HostToTarget::SendUsart {
-                        mode: UsartMode::Regular,
-                        data,
-                    } => {
-                        tx_main.bwrite_all(data)
-                            .expect("Error writing to USART");
-                        rprintln!("Sent data from host: {:?}", data);
-                    }
-                    HostToTarget::SendUsart {
-                        mode: UsartMode::Dma,
-                        data,
-                    } => {
-                        rprint!("Sending using USART/DMA...");
-
-                        let buf = DmaPool::alloc().unwrap();
-                        let mut buf = buf.init(DMAFrame::new());
-                        buf.write_slice(data);
-
-                        dma_tx_main.send(buf).unwrap();
-
-                        loop {
-                            let buf = dma_tx_main.transfer_complete_interrupt();
-                            if let Some(buf) = buf {
-                                // Not sure why, but the buffer needs to be
-                                // dropped explicitly for its memory to be
-                                // freed.
-                                drop(buf);
-                                break;
-                            }
-                        }
-
-                        rprintln!("done.")
-                    }
-                    HostToTarget::SendUsart {
-                        mode: UsartMode::FlowControl,
-                        data,
-                    } => {
-                        // Re-using USART1 for the flow control test.
-                        // Unfortunately the STM32L433 doesn't have enough
-                        // USARTs to test this on a separate instance.
-                        tx_main.bwrite_all(data)
-                            .expect("Error writing to USART");
-
-                        rprintln!("Sent data using flow control: {:?}", data);
-                    }
-                    HostToTarget::ReadAdc => {
-                        let value = adc.read(analog).unwrap();
-
-                        let message = TargetToHost::AdcValue(value);
-
-                        let buf_host_tx: Vec<_, U256> =
-                            postcard::to_vec_cobs(&message)
-                                .expect("Error encoding message to host");
-                        tx_host.bwrite_all(buf_host_tx.as_ref())
-                            .expect("Error sending message to host");
-                    }
-                    HostToTarget::SetPin(
-                        pin::SetLevel { level, pin: () }
-                    ) => {
-                        match level {
-                            pin::Level::High => {
-                                gpio_out.set_high().unwrap();
+            rx_main
+                .process_raw(|data| {
+                    tx_host.send_notification(
+                        TargetToHost::UsartReceive {
+                            mode: UsartMode::Regular,
+                            data,
+                        },
+                        &mut buf,
+                    )
+                })
+                .expect("Error processing USART data");
+
+            while let Some(b) = rx_dma.dequeue() {
+                tx_host
+                    .send_notification(
+                        TargetToHost::UsartReceive {
+                            mode: UsartMode::Dma,
+                            data: &[b],
+                        },
+                        &mut buf,
+                    )
+                    .expect("Error sending message to host");
+            }
+
+            rx_host
+                .process_messages(|frame| {
+                    let request: rpc::Request<HostToTarget> = frame.decode()?;
+
+                    Dispatcher::new(request, tx_host, &mut buf)
+                        .on(|SendUsart { mode, data }| {
+                            match mode {
+                                UsartMode::Regular => {
+                                    tx_main.send_raw(data)
+                                        .expect("Error writing to USART");
+                                    rprintln!(
+                                        "Sent data from host: {:?}",
+                                        data,
+                                    );
+                                }
+                                UsartMode::Dma => {
+                                    rprint!("Sending using USART/DMA...");
+
+                                    let buf = DmaPool::alloc().unwrap();
+                                    let mut buf = buf.init(DMAFrame::new());
+                                    buf.write_slice(data);
+
+                                    dma_tx_main.send(buf).unwrap();
+
+                                    loop {
+                                        let buf = dma_tx_main
+                                            .transfer_complete_interrupt();
+                                        if let Some(buf) = buf {
+                                            // Not sure why, but the buffer
+                                            // needs to be dropped explicitly
+                                            // for its memory to be freed.
+                                            drop(buf);
+                                            break;
+                                        }
+                                    }
+
+                                    rprintln!("done.")
+                                }
+                                UsartMode::FlowControl => {
+                                    // Re-using USART1 for the flow control
+                                    // test. Unfortunately the STM32L433
+                                    // doesn't have enough USARTs to test this
+                                    // on a separate instance.
+                                    tx_main.send_raw(data)
+                                        .expect("Error writing to USART");
+
+                                    rprintln!(
+                                        "Sent data using flow control: {:?}",
+                                        data,
+                                    );
+                                }
+                                UsartMode::Sync => {
+                                    rprintln!("Synchronous mode not supported");
+                                }
                             }
-                            pin::Level::Low => {
-                                gpio_out.set_low().unwrap();
+                            None
+                        })
+                        .on(|ReadAdc { input }| {
+                            let value = match input {
+                                AdcInput::Pin5   => adc.read(analog),
+                                AdcInput::Analog => adc.read(analog_dac),
+                            };
+                            Some(TargetToHost::AdcValue(value.unwrap()))
+                        })
+                        .on(|pin::SetLevel { level, pin: () }| {
+                            match level {
+                                pin::Level::High => {
+                                    gpio_out.set_high().unwrap();
+                                }
+                                pin::Level::Low => {
+                                    gpio_out.set_low().unwrap();
+                                }
                             }
-                        }
-                    }
-                    HostToTarget::ReadPin(pin::ReadLevel { pin: () }) => {
-                        let level = match gpio_in.is_high().unwrap() {
-                            true  => pin::Level::High,
-                            false => pin::Level::Low,
-                        };
-
-                        let message = TargetToHost::ReadPinResult(
-                            Some(
-                                pin::ReadLevelResult {
-                                    pin: (),
-                                    level,
-                                    period_ms: None,
+                            None
+                        })
+                        .on(|pin::ReadLevel { pin: () }| {
+                            let level = match gpio_in.is_high().unwrap() {
+                                true  => pin::Level::High,
+                                false => pin::Level::Low,
+                            };
+
+                            let result = pin::ReadLevelResult {
+                                pin: (),
+                                level,
+                                period_ms: None,
+                            };
+
+                            Some(TargetToHost::ReadPinResult(Some(result)))
+                        })
+                        .on(|I2cWrite { address, data }| {
+                            let result = i2c_transaction(i2c, address, data, 0);
+
+                            Some(TargetToHost::I2cCompleted(
+                                protocol_i2c::Completed {
+                                    bus: DmaMode::Regular,
+                                    result,
+                                }
+                            ))
+                        })
+                        .on(|I2cRead { address, len }| {
+                            let result =
+                                i2c_transaction(i2c, address, &[], len);
+
+                            Some(TargetToHost::I2cCompleted(
+                                protocol_i2c::Completed {
+                                    bus: DmaMode::Regular,
+                                    result,
+                                }
+                            ))
+                        })
+                        .on(|I2cWriteRead { address, data, len }| {
+                            let result =
+                                i2c_transaction(i2c, address, data, len);
+
+                            Some(TargetToHost::I2cCompleted(
+                                protocol_i2c::Completed {
+                                    bus: DmaMode::Regular,
+                                    result,
+                                }
+                            ))
+                        })
+                        .on(|SpiTransfer { config, chip_select, data }| {
+                            let result = spi_transfer(
+                                spi,
+                                ssel,
+                                config,
+                                chip_select,
+                                data,
+                            );
+
+                            Some(TargetToHost::SpiCompleted(
+                                protocol_spi::Completed {
+                                    bus: DmaMode::Regular,
+                                    result,
                                 }
-                            )
-                        );
-
-                        let buf_host_tx: Vec<_, U256> =
-                            postcard::to_vec_cobs(&message)
-                                .expect("Error encoding message to host");
-                        tx_host.bwrite_all(buf_host_tx.as_ref())
-                            .expect("Error sending message to host");
-                    }
-                    HostToTarget::StartI2cTransaction {
-                        mode: DmaMode::Regular,
-                        address,
-                        data,
-                    } => {
-                        i2c.write(address, &[data])
-                            .unwrap();
-
-                        let mut rx_buf = [0u8; 1];
-                        i2c.read(address, &mut rx_buf)
-                            .unwrap();
-
-                        let message = TargetToHost::I2cReply(rx_buf[0]);
-
-                        let buf_host_tx: Vec<_, U256> =
-                            postcard::to_vec_cobs(&message)
-                                .expect("Error encoding message to host");
-                        tx_host.bwrite_all(buf_host_tx.as_ref())
-                            .expect("Error sending message to host");
-                    }
-                    HostToTarget::StartSpiTransaction {
-                        mode: DmaMode::Regular,
-                        data,
-                    } => {
-                        rprintln!("SPI: Set SSEL LOW");
-                        ssel.set_low().unwrap();
-
-                        let mut data = [data, 0xFF];
-                        spi.transfer(&mut data).unwrap();
-                        let reply = data[1];
-
-                        rprintln!("SPI: Set SSEL HIGH");
-                        ssel.set_high().unwrap();
-
-                        let message = TargetToHost::SpiReply(reply);
-
-                        let buf_host_tx: Vec<_, U256> =
-                            postcard::to_vec_cobs(&message)
-                                .expect("Error encoding message to host");
-                        tx_host.bwrite_all(buf_host_tx.as_ref())
-                            .expect("Error sending message to host");
-
-                        rprintln!(" done.");
-                    }
-                    HostToTarget::StartTimerInterrupt { period_ms } => {
-                        let reload = clocks.hclk().0 / 1000 * period_ms;
-                        systick.set_clock_source(SystClkSource::Core);
-                        systick.set_reload(reload);
-
-                        systick.clear_current();
-                        systick.enable_interrupt();
-                        systick.enable_counter();
-                    }
-                    HostToTarget::StopTimerInterrupt => {
-                        systick.disable_interrupt();
-                        systick.disable_counter();
-                    }
-                    HostToTarget::StartPwmSignal => {
-                        pwm_signal.set_duty(pwm_signal.get_max_duty() / 2);
-                        pwm_signal.enable();
-                    }
-                    HostToTarget::StopPwmSignal => {
-                        pwm_signal.disable();
-                    }
-                    message => {
-                        panic!("Unsupported message: {:?}", message)
-                    }
-                }
-
-                buf_host_rx.clear();
+                            ))
+                        })
+                        .on(|StartTimerInterrupt { period_ms }| {
+                            let reload = clocks.hclk().0 / 1000 * period_ms;
+                            systick.set_clock_source(SystClkSource::Core);
+                            systick.set_reload(reload);
+
+                            systick.clear_current();
+                            systick.enable_interrupt();
+                            systick.enable_counter();
+
+                            None
+                        })
+                        .on(|StopTimerInterrupt| {
+                            systick.disable_interrupt();
+                            systick.disable_counter();
+
+                            None
+                        })
+                        .on(|Reset| {
+                            rprintln!("Resetting target");
+
+                            systick.disable_interrupt();
+                            systick.disable_counter();
+                            pwm_signal.disable();
+
+                            gpio_out.set_low().unwrap();
+                            ssel.set_high().unwrap();
+                            timer_signal.lock(|pin| pin.set_low().unwrap());
+
+                            // Discard anything we've received but not yet
+                            // forwarded. It belongs to the previous test case.
+                            rx_main.discard();
+                            while rx_dma.dequeue().is_some() {}
+
+                            // The DMA receiver only forwards data on a
+                            // character match, so it might still hold part of
+                            // a message, or noise from the assistant's analog
+                            // output, which drives the same line. Restart it.
+                            dma_rx_dma.lock(|reader| {
+                                let buf = DmaPool::alloc()
+                                    .unwrap()
+                                    .init(DMAFrame::new());
+                                reader.character_match_interrupt(buf);
+                            });
+
+                            Some(TargetToHost::ResetComplete)
+                        })
+                        .on(|Identify| {
+                            Some(TargetToHost::Identity(Node::Target))
+                        })
+                        .on(|StartPwmSignal| {
+                            pwm_signal.set_duty(pwm_signal.get_max_duty() / 2);
+                            pwm_signal.enable();
+
+                            None
+                        })
+                        .on(|StopPwmSignal| {
+                            pwm_signal.disable();
+
+                            None
+                        })
+                        .finish()
+                        .map_err(|err| ProcessError::Other(err))
+                })
+                .unwrap_or_else(|err| {
+                    // The request has been dropped. Nothing we can do about
+                    // that, except to carry on with the next one.
+                    rprintln!("Error processing host request: {:?}", err);
+                });
+
+            if let Some(uptime_ms) = ticker.lock(|ticker| ticker.due()) {
+                let rx_errors = rx_int_main.lock(|rx| rx.errors())
+                    .wrapping_add(rx_int_host.lock(|rx| rx.errors()));
+                let queue_high_water = rx_main.take_high_water()
+                    .max(rx_host.take_high_water());
+
+                let heartbeat = Heartbeat {
+                    uptime_ms,
+                    rx_errors,
+                    queue_high_water: queue_high_water as u32,
+                    events_dropped:   0,
+                };
+
+                tx_host
+                    .send_message(
+                        &rpc::Response::heartbeat(heartbeat),
+                        &mut buf,
+                    )
+                    .expect("Error sending heartbeat");
             }
         }
     }
 
-    #[task(binds = USART1, resources = [rx_main, rx_prod_main])]
+    #[task(binds = USART1, resources = [rx_int_main])]
     fn usart1(cx: usart1::Context) {
-        let rx = cx.resources.rx_main;
-        let queue = cx.resources.rx_prod_main;
-
-        let b = match rx.read() {
-            Ok(b) => b,
-            Err(err) => {
-                rprintln!("Error reading from USART1: {:?}", err);
-                return;
-            }
-        };
-        match queue.enqueue(b) {
-            Ok(()) => (),
-            Err(err) => {
-                rprintln!("Error adding received byte to queue: {:?}", err);
-                return;
-            }
+        if let Err(err) = cx.resources.rx_int_main.receive() {
+            rprintln!("Error receiving from USART1: {:?}", err);
         }
     }
 
-    #[task(binds = USART2, resources = [rx_host, rx_prod_host])]
+    #[task(binds = USART2, resources = [rx_int_host])]
     fn usart2(cx: usart2::Context) {
-        let rx = cx.resources.rx_host;
-        let queue = cx.resources.rx_prod_host;
-
-        let b = match rx.read() {
-            Ok(b) => b,
-            Err(err) => {
-                rprintln!("Error reading from USART2: {:?}", err);
-                return;
-            }
-        };
-        match queue.enqueue(b) {
-            Ok(()) => (),
-            Err(err) => {
-                rprintln!("Error adding received byte to queue: {:?}", err);
-                return;
-            }
+        if let Err(err) = cx.resources.rx_int_host.receive() {
+            rprintln!("Error receiving from USART2: {:?}", err);
         }
     }
 
@@ -623,6 +742,12 @@ const APP: () = {
         }
     }
 
+    #[task(binds = TIM7, resources = [heartbeat_timer, ticker])]
+    fn tim7(cx: tim7::Context) {
+        cx.resources.heartbeat_timer.clear_interrupt(timer::Event::TimeOut);
+        cx.resources.ticker.tick();
+    }
+
     #[task(binds = SysTick, resources = [timer_signal])]
     fn syst(cx: syst::Context) {
         // cx.resources.timer_signal.toggle();
@@ -641,28 +766,250 @@ const APP: () = {
     }
 };
 
-fn handle_usart_rx(
-    queue: &mut spsc::Consumer<'static, u8, U256>,
-    tx_host: &mut serial::Tx<USART2>,
-    mode: UsartMode,
-    buf: &mut Vec<u8, U256>,
-) {
-    while let Some(b) = queue.dequeue() {
-        buf.push(b)
-            .expect("Main receive buffer full");
+/// Executes an I2C transaction that the host requested
+///
+/// Writes `data`, then reads `len` bytes, skipping either if it's empty. If
+/// both are present, the read follows the write after a repeated start.
+///
+/// Returns the data that was read.
+fn i2c_transaction(
+    i2c: &mut I2c<
+        I2C1,
+        (
+            PA9<Alternate<AF4, Output<OpenDrain>>>,
+            PA10<Alternate<AF4, Output<OpenDrain>>>
+        )
+    >,
+    address: u8,
+    data:    &[u8],
+    len:     u16,
+)
+    -> Result<&'static [u8], protocol_i2c::Error>
+{
+    static mut RX_BUF: [u8; 32] = [0; 32];
+
+    // Sound, as this function is only called from the idle loop, and the reply
+    // that borrows `RX_BUF` has been sent before it is called again.
+    let rx_buf = unsafe { &mut RX_BUF };
+
+    let len = len as usize;
+    if len > rx_buf.len() {
+        return Err(protocol_i2c::Error::TooLong);
     }
+    let rx_buf: &'static mut [u8] = &mut rx_buf[..len];
 
-    if buf.len() > 0 {
-        let message = TargetToHost::UsartReceive {
-            mode,
-            data: buf.as_ref(),
-        };
+    let result = match (data.is_empty(), rx_buf.is_empty()) {
+        (false, true) => i2c.write(address, data),
+        (true, false) => i2c.read(address, rx_buf),
+        _             => i2c.write_read(address, data, rx_buf),
+    };
+    result.map_err(|err| handle_i2c_error(err))?;
 
-        let buf_host_tx: Vec<_, U256> = postcard::to_vec_cobs(&message)
-            .expect("Error encoding message to host");
-        tx_host.bwrite_all(buf_host_tx.as_ref())
-            .expect("Error sending message to host");
+    Ok(rx_buf)
+}
 
-        buf.clear();
+/// Executes an SPI transfer that the host requested
+///
+/// Returns the frames received from the slave.
+fn spi_transfer(
+    spi: &mut Spi<
+        SPI2,
+        (
+            PB13<Alternate<AF5, Input<Floating>>>,
+            PB14<Alternate<AF5, Input<Floating>>>,
+            PB15<Alternate<AF5, Input<Floating>>>,
+        )
+    >,
+    ssel:        &mut PB1<Output<PushPull>>,
+    config:      protocol_spi::Config,
+    chip_select: protocol_spi::ChipSelect,
+    data:        &[u8],
+)
+    -> Result<&'static [u8], protocol_spi::Error>
+{
+    use protocol_spi::ChipSelect;
+
+    static mut BUF: [u8; 32] = [0; 32];
+
+    // Sound, as this function is only called from the idle loop, and the reply
+    // that borrows `BUF` has been sent before it is called again.
+    let buf = unsafe { &mut BUF };
+
+    if data.len() > buf.len() {
+        return Err(protocol_spi::Error::TooLong);
+    }
+    let buf: &'static mut [u8] = &mut buf[..data.len()];
+    buf.copy_from_slice(data);
+
+    // Sound, as we own SPI2 through `spi`, and no transfer is in progress.
+    unsafe { stm32l4::configure_spi::<stm32l4::Spi2>(&config)? };
+
+    // Frames are never larger than 8 bits, so each takes up one byte.
+    let result = if chip_select == ChipSelect::Frame {
+        buf.chunks_mut(1).try_for_each(|frame| {
+            ssel.set_low().unwrap();
+            let result = spi.transfer(frame).map(|_| ());
+            ssel.set_high().unwrap();
+            result
+        })
+    }
+    else {
+        ssel.set_low().unwrap();
+        let result = spi.transfer(buf).map(|_| ());
+        if chip_select == ChipSelect::Transfer {
+            ssel.set_high().unwrap();
+        }
+        result
+    };
+    result.map_err(|_| protocol_spi::Error::Other)?;
+
+    Ok(buf)
+}
+
+/// Converts an error from `stm32l4xx-hal` into one the host understands
+///
+/// The HAL doesn't tell us whether it was the address or the data that wasn't
+/// acknowledged. We report the address, as nothing answering to the address is
+/// by far the most common cause.
+fn handle_i2c_error(err: i2c::Error) -> protocol_i2c::Error {
+    match err {
+        i2c::Error::Nack        => protocol_i2c::Error::AddressNack,
+        i2c::Error::Arbitration => protocol_i2c::Error::ArbitrationLost,
+        _                       => protocol_i2c::Error::Other,
+    }
+}
+
+/// An I2C write that doesn't use DMA
+///
+/// This firmware doesn't support DMA for I2C. Requests for a DMA transaction
+/// are left to the dispatcher, which reports them as unsupported.
+struct I2cWrite<'r> {
+    address: u8,
+    data:    &'r [u8],
+}
+
+impl<'r> TryFrom<HostToTarget<'r>> for I2cWrite<'r> {
+    type Error = HostToTarget<'r>;
+
+    fn try_from(message: HostToTarget<'r>) -> Result<Self, Self::Error> {
+        match protocol_i2c::Write::try_from(message)? {
+            protocol_i2c::Write { bus: DmaMode::Regular, address, data } => {
+                Ok(Self { address, data })
+            }
+            write => {
+                Err(HostToTarget::I2cWrite(write))
+            }
+        }
+    }
+}
+
+/// An I2C read that doesn't use DMA
+///
+/// See [`I2cWrite`].
+struct I2cRead {
+    address: u8,
+    len:     u16,
+}
+
+impl<'r> TryFrom<HostToTarget<'r>> for I2cRead {
+    type Error = HostToTarget<'r>;
+
+    fn try_from(message: HostToTarget<'r>) -> Result<Self, Self::Error> {
+        match protocol_i2c::Read::try_from(message)? {
+            protocol_i2c::Read { bus: DmaMode::Regular, address, len } => {
+                Ok(Self { address, len })
+            }
+            read => {
+                Err(HostToTarget::I2cRead(read))
+            }
+        }
     }
 }
+
+/// An I2C write-read that doesn't use DMA
+///
+/// See [`I2cWrite`].
+struct I2cWriteRead<'r> {
+    address: u8,
+    data:    &'r [u8],
+    len:     u16,
+}
+
+impl<'r> TryFrom<HostToTarget<'r>> for I2cWriteRead<'r> {
+    type Error = HostToTarget<'r>;
+
+    fn try_from(message: HostToTarget<'r>) -> Result<Self, Self::Error> {
+        match protocol_i2c::WriteRead::try_from(message)? {
+            protocol_i2c::WriteRead {
+                bus: DmaMode::Regular,
+                address,
+                data,
+                len,
+            } => {
+                Ok(Self { address, data, len })
+            }
+            write_read => {
+                Err(HostToTarget::I2cWriteRead(write_read))
+            }
+        }
+    }
+}
+
+/// An SPI transfer that doesn't use DMA
+///
+/// See [`I2cWrite`].
+struct SpiTransfer<'r> {
+    config:      protocol_spi::Config,
+    chip_select: protocol_spi::ChipSelect,
+    data:        &'r [u8],
+}
+
+impl<'r> TryFrom<HostToTarget<'r>> for SpiTransfer<'r> {
+    type Error = HostToTarget<'r>;
+
+    fn try_from(message: HostToTarget<'r>) -> Result<Self, Self::Error> {
+        match protocol_spi::Transfer::try_from(message)? {
+            protocol_spi::Transfer {
+                bus: DmaMode::Regular,
+                config,
+                chip_select,
+                data,
+            } => {
+                Ok(Self { config, chip_select, data })
+            }
+            transfer => {
+                Err(HostToTarget::SpiTransfer(transfer))
+            }
+        }
+    }
+}
+
+/// Determine the cause of the last reset
+///
+/// Clears the reset flags afterwards, so the next reset is reported correctly.
+fn reset_cause(rcc: &pac::RCC) -> ResetCause {
+    let csr = rcc.csr.read();
+
+    // Every reset asserts the NRST pin, so the pin reset flag is set for all
+    // of them. We can only report an external reset, if no other flag is set.
+    let cause = if csr.borrstf().bit_is_set() {
+        // A brown-out reset can't be distinguished from a power-on reset.
+        ResetCause::PowerOn
+    }
+    else if csr.iwdgrstf().bit_is_set() || csr.wwdgrstf().bit_is_set() {
+        ResetCause::Watchdog
+    }
+    else if csr.sftrstf().bit_is_set() {
+        ResetCause::Software
+    }
+    else if csr.pinrstf().bit_is_set() {
+        ResetCause::External
+    }
+    else {
+        ResetCause::Unknown
+    };
+
+    rcc.csr.modify(|_, w| w.rmvf().set_bit());
+
+    cause
+}
@@ -0,0 +1,53 @@
+//! Conversion between analog voltages and converter values
+
+
+/// Describes the range and resolution of an analog converter
+///
+/// Converts between voltages and the raw values of a DAC or ADC that measures
+/// or outputs voltages between ground and a reference voltage.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Scale {
+    bits:    u8,
+    vref_mv: u16,
+}
+
+impl Scale {
+    /// Create a new instance of `Scale`
+    ///
+    /// `bits` is the resolution of the converter, `vref_mv` its reference
+    /// voltage in millivolts. The highest raw value corresponds to the
+    /// reference voltage.
+    pub const fn new(bits: u8, vref_mv: u16) -> Self {
+        Self {
+            bits,
+            vref_mv,
+        }
+    }
+
+    /// The highest raw value of the converter
+    pub fn max_value(&self) -> u16 {
+        ((1u32 << self.bits) - 1) as u16
+    }
+
+    /// Returns the raw value that is closest to a voltage
+    ///
+    /// Voltages above the reference voltage are clamped.
+    pub fn value(&self, millivolts: u16) -> u16 {
+        let millivolts = millivolts.min(self.vref_mv) as u32;
+        let max        = self.max_value() as u32;
+        let vref_mv    = self.vref_mv.max(1) as u32;
+
+        ((millivolts * max + vref_mv / 2) / vref_mv) as u16
+    }
+
+    /// Returns the voltage that a raw value represents, in millivolts
+    ///
+    /// The result is rounded to the nearest millivolt. Values above the
+    /// highest raw value are clamped.
+    pub fn millivolts(&self, value: u16) -> u16 {
+        let max   = self.max_value() as u32;
+        let value = value.min(self.max_value()) as u32;
+
+        ((value * self.vref_mv as u32 + max / 2) / max.max(1)) as u16
+    }
+}
@@ -0,0 +1,142 @@
+//! Dispatching of requests from the host to their handlers
+//!
+//! Example:
+//!
+//! ``` ignore
+//! host_rx
+//!     .process_messages(|frame| {
+//!         let request: rpc::Request<HostToAssistant> = frame.decode()?;
+//!
+//!         Dispatcher::new(request, host_tx, &mut buf)
+//!             .on(|pin::SetLevel { pin, level }| {
+//!                 // set the pin level; no reply
+//!                 None
+//!             })
+//!             .on(|Reset| {
+//!                 // reset the node
+//!                 Some(AssistantToHost::ResetComplete)
+//!             })
+//!             .finish()
+//!             .map_err(|err| ProcessError::Other(err))
+//!     })
+//! ```
+
+
+use core::{
+    convert::TryFrom,
+    marker::PhantomData,
+};
+
+use embedded_hal::blocking::serial::Write;
+use serde::Serialize;
+
+use protocol::rpc;
+
+use crate::usart::{
+    Tx,
+    tx::Error,
+};
+
+
+/// Dispatches a request to the handler registered for its kind
+///
+/// Handlers are registered using [`on`]. The handler that is registered first
+/// for the kind of the request is called, any other handlers are ignored.
+/// Replies returned by the handler are sent to the host, tagged with the ID of
+/// the request.
+///
+/// Once all handlers are registered, [`finish`] must be called. If no handler
+/// was registered for the request, the host is notified that the request is
+/// not supported.
+///
+/// [`on`]: #method.on
+/// [`finish`]: #method.finish
+#[must_use = "`finish` must be called to complete the dispatch"]
+pub struct Dispatcher<'a, W, Request, Reply>
+    where W: Write<u8>
+{
+    id:      rpc::RequestId,
+    request: Option<Request>,
+    tx:      &'a mut Tx<W>,
+    buf:     &'a mut [u8],
+    result:  Result<(), Error<W::Error>>,
+    _reply:  PhantomData<fn() -> Reply>,
+}
+
+impl<'a, W, Request, Reply> Dispatcher<'a, W, Request, Reply>
+    where
+        W:     Write<u8>,
+        Reply: Serialize,
+{
+    /// Create a dispatcher for the given request
+    ///
+    /// Replies are sent to the host through `tx`. `buf` is used to hold the
+    /// serialized replies, and must be large enough for that purpose.
+    pub fn new(
+        request: rpc::Request<Request>,
+        tx:      &'a mut Tx<W>,
+        buf:     &'a mut [u8],
+    )
+        -> Self
+    {
+        Self {
+            id:      request.id,
+            request: Some(request.message),
+            tx,
+            buf,
+            result:  Ok(()),
+            _reply:  PhantomData,
+        }
+    }
+
+    /// Register a handler for requests of kind `Kind`
+    ///
+    /// The handler is called right away, if the request is of that kind and
+    /// hasn't been handled yet. If the handler returns a reply, that is sent to
+    /// the host.
+    pub fn on<Kind>(mut self, handler: impl FnOnce(Kind) -> Option<Reply>)
+        -> Self
+        where Kind: TryFrom<Request, Error=Request>
+    {
+        let request = match self.request.take() {
+            Some(request) => request,
+            None          => return self,
+        };
+
+        match Kind::try_from(request) {
+            Ok(request) => {
+                if let Some(reply) = handler(request) {
+                    self.result = self.reply(Ok(reply));
+                }
+            }
+            Err(request) => {
+                self.request = Some(request);
+            }
+        }
+
+        self
+    }
+
+    /// Complete the dispatch
+    ///
+    /// Notifies the host, if the request wasn't handled. Returns any error that
+    /// occurred while sending a reply.
+    pub fn finish(mut self) -> Result<(), Error<W::Error>> {
+        if self.request.is_some() {
+            self.result = self.reply(Err(rpc::Error::Unsupported));
+        }
+
+        self.result
+    }
+
+    fn reply(&mut self, message: Result<Reply, rpc::Error>)
+        -> Result<(), Error<W::Error>>
+    {
+        let response = rpc::Response {
+            kind: rpc::Kind::Reply(self.id),
+            message,
+        };
+
+        self.tx.send_message(&response, self.buf)
+    }
+}
@@ -0,0 +1,55 @@
+//! Periodic status reports to the host
+
+
+/// The interval between two heartbeats, in milliseconds
+pub const PERIOD_MS: u32 = 100;
+
+
+/// Keeps track of the uptime, and of when the next heartbeat is due
+///
+/// Call [`tick`] from a timer interrupt that fires every [`PERIOD_MS`]
+/// milliseconds. Call [`due`] from the idle loop, to find out whether a
+/// heartbeat needs to be sent.
+///
+/// [`tick`]: #method.tick
+/// [`due`]: #method.due
+/// [`PERIOD_MS`]: constant.PERIOD_MS.html
+pub struct Ticker {
+    uptime_ms: u32,
+    due:       bool,
+}
+
+impl Ticker {
+    /// Create a new instance of `Ticker`
+    pub const fn new() -> Self {
+        Self {
+            uptime_ms: 0,
+            due:       false,
+        }
+    }
+
+    /// Advance the uptime by one period and mark the next heartbeat as due
+    pub fn tick(&mut self) {
+        self.uptime_ms = self.uptime_ms.wrapping_add(PERIOD_MS);
+        self.due = true;
+    }
+
+    /// Returns the uptime in milliseconds, if a heartbeat is due
+    ///
+    /// Only returns `Some` once per period, so the caller should send the
+    /// heartbeat right away.
+    pub fn due(&mut self) -> Option<u32> {
+        if !self.due {
+            return None;
+        }
+
+        self.due = false;
+        Some(self.uptime_ms)
+    }
+}
+
+impl Default for Ticker {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,138 @@
+//! Emulation of I2C slave devices
+
+
+use heapless::{
+    Vec,
+    consts::U128,
+};
+
+use protocol::i2c::{
+    self,
+    Fault,
+};
+
+
+/// An emulated I2C slave device with a register map
+///
+/// Keeps track of the device's registers and of the ongoing transaction. Call
+/// [`start`], [`write`], and [`read`] from the I2C slave's interrupt handler,
+/// as the master's requests come in.
+///
+/// See [`protocol::i2c::Device`] for how the device behaves.
+///
+/// [`start`]: #method.start
+/// [`write`]: #method.write
+/// [`read`]: #method.read
+/// [`protocol::i2c::Device`]: ../../protocol/i2c/struct.Device.html
+pub struct Device {
+    address:        u8,
+    registers:      Vec<u8, U128>,
+    read_only:      [bool; 256],
+    auto_increment: bool,
+    fault:          Option<Fault>,
+
+    selected: u8,
+    received: u16,
+}
+
+impl Device {
+    /// Create a device from the description sent by the host
+    ///
+    /// Returns [`Error::TooLong`], if the device has more than 128 registers.
+    /// The host has to be able to read all of them back in a single message.
+    ///
+    /// [`Error::TooLong`]: ../../protocol/i2c/enum.Error.html#variant.TooLong
+    pub fn new(device: &i2c::Device) -> Result<Self, i2c::Error> {
+        let mut registers = Vec::new();
+        registers.extend_from_slice(device.registers)
+            .map_err(|()| i2c::Error::TooLong)?;
+
+        let mut read_only = [false; 256];
+        for &register in device.read_only {
+            read_only[register as usize] = true;
+        }
+
+        Ok(Self {
+            address:        device.address,
+            registers,
+            read_only,
+            auto_increment: device.auto_increment,
+            fault:          device.fault,
+
+            selected: 0,
+            received: 0,
+        })
+    }
+
+    /// The 7-bit address the device responds to
+    pub fn address(&self) -> u8 {
+        self.address
+    }
+
+    /// The current contents of the device's registers
+    pub fn registers(&self) -> &[u8] {
+        &self.registers
+    }
+
+    /// How long to hold the clock low before each byte, in microseconds
+    pub fn clock_stretch_us(&self) -> u32 {
+        match self.fault {
+            Some(Fault::StretchClock(us)) => us,
+            _                             => 0,
+        }
+    }
+
+    /// Start a new transaction
+    ///
+    /// Call this when the master has addressed the device. This includes
+    /// repeated start conditions.
+    pub fn start(&mut self) {
+        self.received = 0;
+    }
+
+    /// Handle a byte that the master has written
+    ///
+    /// Returns whether the byte should be acknowledged.
+    pub fn write(&mut self, b: u8) -> bool {
+        let n = self.received;
+        self.received = self.received.saturating_add(1);
+
+        if self.fault == Some(Fault::NackByte(n)) {
+            return false;
+        }
+
+        if n == 0 {
+            self.selected = b;
+            return true;
+        }
+
+        let register = self.selected as usize;
+        if !self.read_only[register] {
+            if let Some(value) = self.registers.get_mut(register) {
+                *value = b;
+            }
+        }
+        self.advance();
+
+        true
+    }
+
+    /// Handle a byte that the master reads
+    ///
+    /// Returns the byte that should be sent.
+    pub fn read(&mut self) -> u8 {
+        let b = self.registers
+            .get(self.selected as usize)
+            .copied()
+            .unwrap_or(0xff);
+        self.advance();
+
+        b
+    }
+
+    fn advance(&mut self) {
+        if self.auto_increment {
+            self.selected = self.selected.wrapping_add(1);
+        }
+    }
+}
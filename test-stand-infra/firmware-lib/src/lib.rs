@@ -1,8 +1,26 @@
 //! Shared code for test target and assistant firmwares
+//!
+//! Most of this crate is independent of any specific HAL. Adapters for the
+//! supported microcontroller families are available behind the `lpc8xx` and
+//! `stm32l4` features.
 
 
 #![no_std]
 
 
+pub mod analog;
+pub mod dispatch;
+pub mod heartbeat;
+pub mod i2c;
 pub mod pin_interrupt;
+pub mod spi;
 pub mod usart;
+
+#[cfg(feature = "lpc8xx")]
+pub mod lpc8xx;
+
+#[cfg(feature = "stm32l4")]
+pub mod stm32l4;
+
+#[cfg(feature = "panic-handler")]
+pub mod panic;
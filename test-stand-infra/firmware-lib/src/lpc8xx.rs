@@ -0,0 +1,1012 @@
+//! Adapters for the LPC8xx family
+//!
+//! Connects the HAL-agnostic parts of this crate to `lpc8xx-hal`. Provides
+//! type aliases for the types that firmware based on that HAL is going to use.
+
+
+use heapless::{
+    ArrayLength,
+    consts::{
+        U32,
+        U64,
+        U256,
+    },
+    spsc,
+};
+use core::{
+    convert::TryInto as _,
+    marker::PhantomData,
+};
+
+use lpc8xx_hal::{
+    prelude::*,
+    i2c::{
+        self,
+        master,
+    },
+    init_state::Enabled,
+    mrt,
+    pac::CTIMER0,
+    pinint,
+    pins,
+    spi,
+    usart::{
+        self,
+        state::NoThrottle,
+    },
+};
+
+use crate::{
+    pin_interrupt::{
+        self,
+        Edges,
+        Event,
+        Stopwatch,
+    },
+    usart::{
+        rx,
+        tx::{
+            self,
+            TxInterrupt,
+        },
+    },
+};
+
+
+/// [`RxInt`] for an LPC8xx USART
+///
+/// [`RxInt`]: ../usart/rx/struct.RxInt.html
+pub type RxInt<'r, I, Mode, N = U256> =
+    rx::RxInt<'r, usart::Rx<I, usart::state::Enabled<u8, Mode>>, N>;
+
+/// [`Tx`] for an LPC8xx USART
+///
+/// [`Tx`]: ../usart/tx/struct.Tx.html
+pub type Tx<I, Mode> =
+    tx::Tx<usart::Tx<I, usart::state::Enabled<u8, Mode>, NoThrottle>>;
+
+/// [`TxInt`] for an LPC8xx USART
+///
+/// [`TxInt`]: ../usart/tx/struct.TxInt.html
+pub type TxInt<'r, I, Mode, N = U256> = tx::TxInt<
+    'r,
+    usart::Tx<I, usart::state::Enabled<u8, Mode>, NoThrottle>,
+    I,
+    N,
+>;
+
+/// [`Tx`] for an LPC8xx USART, after [`TxQueue::init`] has been called
+///
+/// [`Tx`]: ../usart/tx/struct.Tx.html
+/// [`TxQueue::init`]: ../usart/tx/struct.TxQueue.html#method.init
+pub type QueuedTx<'r, I, N = U256> = tx::Tx<tx::Queued<'r, I, N>>;
+
+/// [`Int`] for an LPC8xx pin interrupt that is timed by an MRT channel
+///
+/// [`Int`]: ../pin_interrupt/struct.Int.html
+pub type PinInt<'r, I, P, T, N = U32> = pin_interrupt::Int<
+    'r,
+    pinint::Interrupt<I, P, Enabled>,
+    mrt::Channel<T>,
+    N,
+>;
+
+
+impl<I, P> Edges for pinint::Interrupt<I, P, Enabled>
+    where
+        I: pinint::Trait,
+        P: pins::Trait,
+{
+    fn clear_rising_edge_flag(&mut self) -> bool {
+        pinint::Interrupt::clear_rising_edge_flag(self)
+    }
+
+    fn clear_falling_edge_flag(&mut self) -> bool {
+        pinint::Interrupt::clear_falling_edge_flag(self)
+    }
+}
+
+impl<I> TxInterrupt for I
+    where I: usart::Instance
+{
+    fn enable() {
+        // Sound, as the INTENSET register only affects the bits we write to.
+        let usart = unsafe { &*I::REGISTERS };
+        usart.intenset.write(|w| w.txrdyen().set_bit());
+    }
+
+    fn disable() {
+        // Sound, as the INTENCLR register only affects the bits we write to.
+        let usart = unsafe { &*I::REGISTERS };
+        usart.intenclr.write(|w| w.txrdyclr().set_bit());
+    }
+}
+
+impl<T> Stopwatch for mrt::Channel<T>
+    where T: mrt::Trait
+{
+    fn restart(&mut self) {
+        self.start(mrt::MAX_VALUE);
+    }
+
+    fn elapsed(&mut self) -> Option<u32> {
+        let timer_wrapped = self.wait().is_ok();
+        if timer_wrapped {
+            return None;
+        }
+
+        Some(mrt::MAX_VALUE.to_u32() - self.value())
+    }
+}
+
+
+
+/// Timestamps the edges on a pin using the capture channels of CTIMER0
+///
+/// Capture channel 0 must be assigned to the pin for rising edges, channel 1
+/// for falling edges. Unlike a pin interrupt, the timer captures the time of
+/// each edge in hardware, so the measured periods don't depend on interrupt
+/// latency. The interrupt handler only has to run before the next edge of the
+/// same kind.
+///
+/// The edges are sent to the idle context as pin interrupt [`Event`]s, with
+/// periods in ticks of the CTIMER0 clock.
+///
+/// [`Event`]: ../pin_interrupt/struct.Event.html
+pub struct EdgeCapture<'r, N = U64>
+    where N: ArrayLength<Event>
+{
+    ctimer:    CTIMER0,
+    queue:     spsc::Producer<'r, Event, N>,
+    last:      Option<u32>,
+    overflows: u32,
+}
+
+impl<'r, N> EdgeCapture<'r, N>
+    where N: ArrayLength<Event>
+{
+    /// Create a new instance of `EdgeCapture`
+    ///
+    /// Expects CTIMER0 to be enabled and running. Capturing doesn't start
+    /// until [`enable`] is called.
+    ///
+    /// [`enable`]: #method.enable
+    pub fn new(ctimer: CTIMER0, queue: spsc::Producer<'r, Event, N>) -> Self {
+        // Let the counter run freely, so the periods between edges can be
+        // calculated using wrapping arithmetic.
+        ctimer.mcr.reset();
+
+        Self {
+            ctimer,
+            queue,
+            last:      None,
+            overflows: 0,
+        }
+    }
+
+    /// Start capturing edges
+    ///
+    /// The first edge after this is sent without a period.
+    pub fn enable(&mut self) {
+        self.last = None;
+        self.ctimer.ccr.write(|w| {
+            w.cap0re().set_bit();
+            w.cap0i().set_bit();
+            w.cap1fe().set_bit();
+            w.cap1i().set_bit()
+        });
+    }
+
+    /// Stop capturing edges
+    pub fn disable(&mut self) {
+        self.ctimer.ccr.reset();
+        self.ctimer.ir.write(|w| {
+            w.cr0int().set_bit();
+            w.cr1int().set_bit()
+        });
+    }
+
+    /// Handles a CTIMER0 interrupt
+    ///
+    /// This should be called directly from the interrupt handler. If the queue
+    /// is full, the event is dropped and counted. See [`overflows`].
+    ///
+    /// [`overflows`]: #method.overflows
+    pub fn handle_interrupt(&mut self) {
+        let ir = self.ctimer.ir.read();
+
+        let rising = if ir.cr0int().bit_is_set() {
+            Some(self.ctimer.cr[0].read().cap().bits())
+        }
+        else {
+            None
+        };
+        let falling = if ir.cr1int().bit_is_set() {
+            Some(self.ctimer.cr[1].read().cap().bits())
+        }
+        else {
+            None
+        };
+
+        // Only clear the flags we've seen, or we might miss an edge.
+        self.ctimer.ir.write(|w| {
+            w.cr0int().bit(rising.is_some());
+            w.cr1int().bit(falling.is_some())
+        });
+
+        match (rising, falling) {
+            (Some(rising), Some(falling)) => {
+                // If the interrupt was late, we can see both edges at once.
+                // Send them in the order they occurred in.
+                let last = self.last.unwrap_or(rising.min(falling));
+                if rising.wrapping_sub(last) <= falling.wrapping_sub(last) {
+                    self.send(protocol::pin::Level::High, rising);
+                    self.send(protocol::pin::Level::Low, falling);
+                }
+                else {
+                    self.send(protocol::pin::Level::Low, falling);
+                    self.send(protocol::pin::Level::High, rising);
+                }
+            }
+            (Some(rising), None) => {
+                self.send(protocol::pin::Level::High, rising);
+            }
+            (None, Some(falling)) => {
+                self.send(protocol::pin::Level::Low, falling);
+            }
+            (None, None) => {}
+        }
+    }
+
+    /// The number of events that were dropped, because the queue was full
+    ///
+    /// Wraps around on overflow.
+    pub fn overflows(&self) -> u32 {
+        self.overflows
+    }
+
+    fn send(&mut self, level: protocol::pin::Level, time: u32) {
+        let period = self.last.map(|last| time.wrapping_sub(last));
+        self.last = Some(time);
+
+        if self.queue.enqueue(Event { level, period }).is_err() {
+            self.overflows = self.overflows.wrapping_add(1);
+        }
+    }
+}
+
+
+/// Executes an I2C transaction as master
+///
+/// Writes `data`, then reads into `buffer` after a repeated start, skipping
+/// either if it's empty. Any error is converted using [`handle_i2c_error`], so
+/// it can be reported to the host.
+///
+/// [`handle_i2c_error`]: fn.handle_i2c_error.html
+pub fn i2c_transaction<I, C>(
+    master:  &mut i2c::Master<I, Enabled<PhantomData<C>>, Enabled>,
+    address: u8,
+    data:    &[u8],
+    buffer:  &mut [u8],
+)
+    -> Result<(), protocol::i2c::Error>
+    where I: i2c::Instance
+{
+    let result = if data.is_empty() && !buffer.is_empty() {
+        master.read(address, buffer)
+    }
+    else {
+        i2c_write_read(master, address, data, buffer)
+    };
+
+    result.map_err(|err| handle_i2c_error::<I>(err))
+}
+
+/// Writes to, then reads from an I2C slave, using a repeated start
+///
+/// `lpc8xx-hal` doesn't support this kind of transaction, so this function
+/// accesses the registers directly. `master` is borrowed to make sure nothing
+/// else uses the peripheral in the meantime.
+///
+/// If `buffer` is empty, this is a plain write.
+pub fn i2c_write_read<I, C>(
+    _master: &mut i2c::Master<I, Enabled<PhantomData<C>>, Enabled>,
+    address: u8,
+    data:    &[u8],
+    buffer:  &mut [u8],
+)
+    -> Result<(), i2c::Error>
+    where I: i2c::Instance
+{
+    if address > 0x7f {
+        return Err(i2c::Error::AddressOutOfRange);
+    }
+
+    // Sound, as we have exclusive access to the master through `_master`.
+    let i2c = unsafe { &*I::REGISTERS };
+
+    wait_for_master_state::<I>(master::State::Idle)?;
+    i2c.mstdat.write(|w| unsafe { w.data().bits(address << 1) });
+    i2c.mstctl.write(|w| w.mststart().start());
+
+    for &b in data {
+        wait_for_master_state::<I>(master::State::TxReady)?;
+        i2c.mstdat.write(|w| unsafe { w.data().bits(b) });
+        i2c.mstctl.write(|w| w.mstcontinue().continue_());
+    }
+
+    wait_for_master_state::<I>(master::State::TxReady)?;
+
+    if !buffer.is_empty() {
+        // Starting again without stopping first is what makes this a repeated
+        // start.
+        i2c.mstdat.write(|w| unsafe { w.data().bits(address << 1 | 1) });
+        i2c.mstctl.write(|w| w.mststart().start());
+
+        for (i, b) in buffer.iter_mut().enumerate() {
+            if i != 0 {
+                i2c.mstctl.write(|w| w.mstcontinue().continue_());
+            }
+
+            wait_for_master_state::<I>(master::State::RxReady)?;
+            *b = i2c.mstdat.read().data().bits();
+        }
+    }
+
+    i2c.mstctl.write(|w| w.mststop().stop());
+
+    Ok(())
+}
+
+/// Changes the address that an I2C slave responds to
+///
+/// `lpc8xx-hal` only supports setting the address when enabling slave mode.
+/// This function accesses the register directly. `_slave` is borrowed to make
+/// sure nothing else uses the slave in the meantime.
+pub fn i2c_set_slave_address<I, C>(
+    _slave:  &mut i2c::Slave<I, Enabled<PhantomData<C>>, Enabled>,
+    address: u8,
+)
+    -> Result<(), i2c::Error>
+    where I: i2c::Instance
+{
+    if address > 0x7f {
+        return Err(i2c::Error::AddressOutOfRange);
+    }
+
+    // Sound, as we have exclusive access to the slave through `_slave`, and
+    // the SLVADR registers are only used by the slave.
+    let i2c = unsafe { &*I::REGISTERS };
+
+    i2c.slvadr[0].write(|w| {
+        w.sadisable().enabled();
+
+        // Sound, as we checked above that this is a 7-bit address.
+        unsafe { w.slvadr().bits(address) }
+    });
+
+    Ok(())
+}
+
+/// Converts an error from an I2C master, so it can be reported to the host
+///
+/// If the slave didn't acknowledge, the master is left waiting for software to
+/// end the transaction. This function ends it with a stop condition, so the
+/// master can be used again.
+pub fn handle_i2c_error<I>(err: i2c::Error) -> protocol::i2c::Error
+    where I: i2c::Instance
+{
+    let nack = match err {
+        i2c::Error::UnexpectedState {
+            actual: Ok(master::State::NackAddress),
+            ..
+        } => {
+            protocol::i2c::Error::AddressNack
+        }
+        i2c::Error::UnexpectedState {
+            actual: Ok(master::State::NackData),
+            ..
+        } => {
+            protocol::i2c::Error::DataNack
+        }
+        i2c::Error::MasterArbitrationLoss => {
+            return protocol::i2c::Error::ArbitrationLost;
+        }
+        _ => {
+            return protocol::i2c::Error::Other;
+        }
+    };
+
+    // Sound, as the MSTCTL register only affects the master, whose
+    // transaction has already failed.
+    let i2c = unsafe { &*I::REGISTERS };
+    i2c.mstctl.write(|w| w.mststop().stop());
+
+    nack
+}
+
+fn wait_for_master_state<I>(expected: master::State)
+    -> Result<(), i2c::Error>
+    where I: i2c::Instance
+{
+    // Sound, as we're only writing to the STAT register to clear error flags.
+    let i2c = unsafe { &*I::REGISTERS };
+
+    loop {
+        let stat = i2c.stat.read();
+
+        if stat.mstarbloss().bit_is_set() {
+            i2c.stat.write(|w| w.mstarbloss().set_bit());
+            return Err(i2c::Error::MasterArbitrationLoss);
+        }
+        if stat.mstststperr().bit_is_set() {
+            i2c.stat.write(|w| w.mstststperr().set_bit());
+            return Err(i2c::Error::MasterStartStopError);
+        }
+        if stat.mstpending().is_pending() {
+            break;
+        }
+    }
+
+    let actual = i2c.stat.read().mststate().variant().try_into();
+    if Ok(&expected) != actual.as_ref() {
+        return Err(i2c::Error::UnexpectedState { expected, actual });
+    }
+
+    Ok(())
+}
+
+
+/// Applies an SPI configuration that the host requested
+///
+/// `lpc8xx-hal` only supports configuring the mode, and only when enabling the
+/// peripheral. This function accesses the registers directly. `_spi` is
+/// borrowed to make sure nothing else uses the peripheral in the meantime.
+pub fn spi_configure<I, Mode>(
+    _spi:   &mut spi::SPI<I, Enabled<Mode>>,
+    config: &protocol::spi::Config,
+)
+    -> Result<(), protocol::spi::Error>
+    where I: spi::Instance
+{
+    use protocol::spi::{
+        BitOrder,
+        Phase,
+        Polarity,
+    };
+
+    if config.frame_size < 1 || config.frame_size > 16 {
+        return Err(protocol::spi::Error::UnsupportedConfig);
+    }
+
+    // Sound, as we have exclusive access to the peripheral through `_spi`.
+    let spi = unsafe { &*I::REGISTERS };
+
+    // The configuration must not be changed while the peripheral is enabled.
+    spi.cfg.modify(|_, w| w.enable().disabled());
+    spi.cfg.modify(|_, w| {
+        match config.mode.polarity {
+            Polarity::IdleLow  => w.cpol().low(),
+            Polarity::IdleHigh => w.cpol().high(),
+        };
+        match config.mode.phase {
+            Phase::CaptureOnFirstTransition  => w.cpha().change(),
+            Phase::CaptureOnSecondTransition => w.cpha().capture(),
+        };
+        match config.bit_order {
+            BitOrder::MsbFirst => w.lsbf().standard(),
+            BitOrder::LsbFirst => w.lsbf().reverse(),
+        };
+        w.enable().enabled()
+    });
+
+    spi.txctl.write(|w| unsafe { w.len().bits(config.frame_size - 1) });
+
+    // The frame size only takes effect once TXDAT is written. A master does
+    // that when starting a transfer anyway, but a slave needs something to
+    // reply with during the first frame.
+    if spi.cfg.read().master().is_slave_mode() {
+        spi.txdat.write(|w| unsafe { w.data().bits(0xffff) });
+    }
+
+    Ok(())
+}
+
+/// Switches an SPI peripheral into master mode
+///
+/// `lpc8xx-hal` decides between master and slave mode when enabling the
+/// peripheral. This function changes the mode at runtime, by accessing the
+/// registers directly. Afterwards, the `Mode` type parameter of `_spi` no
+/// longer reflects the actual mode, but none of the SPI functions in this
+/// module rely on it.
+///
+/// The SPI clock is the peripheral clock divided by `divval + 1`, like with
+/// `lpc8xx_hal::spi::Clock`.
+pub fn spi_enable_master_mode<I, Mode>(
+    _spi:   &mut spi::SPI<I, Enabled<Mode>>,
+    divval: u16,
+)
+    where I: spi::Instance
+{
+    // Sound, as we have exclusive access to the peripheral through `_spi`.
+    let spi = unsafe { &*I::REGISTERS };
+
+    // The mode must not be changed while the peripheral is enabled.
+    spi.cfg.modify(|_, w| w.enable().disabled());
+    spi.div.write(|w| unsafe { w.divval().bits(divval) });
+    spi.cfg.modify(|_, w| w.master().master_mode().enable().enabled());
+}
+
+/// Switches an SPI peripheral into slave mode
+///
+/// See [`spi_enable_master_mode`]. Use [`spi_preload`] afterwards, to provide
+/// a frame to send during the first frame.
+///
+/// [`spi_enable_master_mode`]: fn.spi_enable_master_mode.html
+/// [`spi_preload`]: fn.spi_preload.html
+pub fn spi_enable_slave_mode<I, Mode>(_spi: &mut spi::SPI<I, Enabled<Mode>>)
+    where I: spi::Instance
+{
+    // Sound, as we have exclusive access to the peripheral through `_spi`.
+    let spi = unsafe { &*I::REGISTERS };
+
+    // The mode must not be changed while the peripheral is enabled.
+    spi.cfg.modify(|_, w| w.enable().disabled());
+    spi.cfg.modify(|_, w| w.master().slave_mode().enable().enabled());
+}
+
+/// Transfers frames as an SPI master
+///
+/// Unlike `lpc8xx-hal`, this function supports frames larger than 8 bits. See
+/// [`protocol::spi::Transfer`] for how frames are encoded in `data`. The frames
+/// received from the slave are written back into `data`.
+///
+/// The peripheral asserts its slave select signals during the transfer, and
+/// leaves them asserted afterwards. Use [`spi_end_transfer`] to deassert them.
+///
+/// [`protocol::spi::Transfer`]: ../../protocol/spi/struct.Transfer.html
+/// [`spi_end_transfer`]: fn.spi_end_transfer.html
+pub fn spi_transfer<I, Mode>(
+    _spi:       &mut spi::SPI<I, Enabled<Mode>>,
+    frame_size: u8,
+    data:       &mut [u8],
+)
+    where I: spi::Instance
+{
+    // Sound, as we have exclusive access to the peripheral through `_spi`.
+    let spi = unsafe { &*I::REGISTERS };
+
+    // Discard anything left over from earlier transfers. Otherwise we would
+    // mistake it for the reply to the first frame.
+    while spi.stat.read().rxrdy().bit_is_set() {
+        spi.rxdat.read();
+    }
+
+    let frame_len = if frame_size > 8 { 2 } else { 1 };
+    let mask      = ((1u32 << frame_size) - 1) as u16;
+
+    for frame in data.chunks_mut(frame_len) {
+        let mut word = 0;
+        for (i, &b) in frame.iter().enumerate() {
+            word |= (b as u16) << (8 * i);
+        }
+
+        while spi.stat.read().txrdy().bit_is_clear() {}
+        spi.txdat.write(|w| unsafe { w.data().bits(word & mask) });
+
+        while spi.stat.read().rxrdy().bit_is_clear() {}
+        let word = spi.rxdat.read().rxdat().bits() & mask;
+
+        for (i, b) in frame.iter_mut().enumerate() {
+            *b = (word >> (8 * i)) as u8;
+        }
+    }
+}
+
+/// Deasserts the slave select signals of an SPI master
+///
+/// This ends the current transfer.
+pub fn spi_end_transfer<I, Mode>(_spi: &mut spi::SPI<I, Enabled<Mode>>)
+    where I: spi::Instance
+{
+    // Sound, as we have exclusive access to the peripheral through `_spi`.
+    let spi = unsafe { &*I::REGISTERS };
+
+    spi.stat.write(|w| w.endtransfer().set_bit());
+    while spi.stat.read().mstidle().bit_is_clear() {}
+}
+
+/// Receives a frame as an SPI slave
+///
+/// Unlike `lpc8xx-hal`, this function supports frames larger than 8 bits.
+/// Returns `None`, if no frame has been received.
+pub fn spi_receive<I, Mode>(_spi: &mut spi::SPI<I, Enabled<Mode>>)
+    -> Option<u16>
+    where I: spi::Instance
+{
+    // Sound, as we have exclusive access to the peripheral through `_spi`.
+    let spi = unsafe { &*I::REGISTERS };
+
+    if spi.stat.read().rxrdy().bit_is_clear() {
+        return None;
+    }
+
+    Some(spi.rxdat.read().rxdat().bits())
+}
+
+/// Writes the frame that an SPI slave sends to the master next
+///
+/// Unlike `lpc8xx-hal`, this function supports frames larger than 8 bits.
+/// Blocks until the previous frame has been sent.
+pub fn spi_transmit<I, Mode>(_spi: &mut spi::SPI<I, Enabled<Mode>>, word: u16)
+    where I: spi::Instance
+{
+    // Sound, as we have exclusive access to the peripheral through `_spi`.
+    let spi = unsafe { &*I::REGISTERS };
+
+    while spi.stat.read().txrdy().bit_is_clear() {}
+    spi.txdat.write(|w| unsafe { w.data().bits(word) });
+}
+
+/// Replaces the frame that an SPI slave sends to the master next
+///
+/// Unlike [`spi_transmit`], this function doesn't wait for the previous frame
+/// to be sent, but discards it. Must not be called during a transfer.
+///
+/// [`spi_transmit`]: fn.spi_transmit.html
+pub fn spi_preload<I, Mode>(_spi: &mut spi::SPI<I, Enabled<Mode>>, word: u16)
+    where I: spi::Instance
+{
+    // Sound, as we have exclusive access to the peripheral through `_spi`.
+    let spi = unsafe { &*I::REGISTERS };
+
+    // Disabling the peripheral resets its state, including the frame that is
+    // waiting to be sent.
+    spi.cfg.modify(|_, w| w.enable().disabled());
+    spi.cfg.modify(|_, w| w.enable().enabled());
+
+    spi.txdat.write(|w| unsafe { w.data().bits(word) });
+}
+
+
+/// Changes the baud rate and frame format of a USART, as the host requested
+///
+/// `lpc8xx-hal` only supports configuring a USART when enabling it, so this
+/// function accesses the registers directly. Going through `USART::disable`
+/// and `USART::enable_async` isn't possible here: both require the complete
+/// `USART`, which no longer exists once it has been split into `Rx` and `Tx`.
+/// `Clock::new_with_baudrate` wouldn't help either, as it assumes the 12 MHz
+/// IOSC and panics on baud rates it can't reach, both of which the host can
+/// request.
+///
+/// `_tx` is borrowed to make sure nothing else sends in the meantime. The
+/// receiver shares the configuration, so data that it is receiving while this
+/// function runs might get lost.
+///
+/// The USART's clock is left as it is, as it is usually shared with other
+/// USARTs. `clock_hz` is its frequency. The baud rate is derived from it using
+/// the USART's own divider and oversampling ratio. Returns the baud rate that
+/// was actually configured, which might deviate slightly from the requested
+/// one.
+pub fn usart_configure<I, Mode, Throttle, Id>(
+    _tx:      &mut usart::Tx<I, usart::state::Enabled<u8, Mode>, Throttle>,
+    config:   &protocol::usart::Configure<Id>,
+    clock_hz: u32,
+)
+    -> Result<u32, protocol::usart::Error>
+    where I: usart::Instance
+{
+    use protocol::usart::{
+        DataBits,
+        Error,
+        FlowControl,
+        Parity,
+        StopBits,
+    };
+
+    if config.flow_control != FlowControl::None {
+        return Err(Error::UnsupportedConfig);
+    }
+
+    let (osr, divider) = usart_divider(clock_hz, config.baud)
+        .ok_or(Error::UnsupportedBaud)?;
+
+    // Sound, as we have exclusive access to the transmitter through `_tx`, and
+    // only change the configuration while it is idle.
+    let usart = unsafe { &*I::REGISTERS };
+
+    while usart.stat.read().txidle().bit_is_clear() {}
+
+    usart_reconfigure::<I>(|| {
+        usart.osr.write(|w| unsafe { w.osrval().bits((osr - 1) as u8) });
+        usart.brg.write(|w| unsafe { w.brgval().bits((divider - 1) as u16) });
+        usart.cfg.modify(|_, w| {
+            let w = match config.data_bits {
+                DataBits::Seven => w.datalen().bit_7(),
+                DataBits::Eight => w.datalen().bit_8(),
+                DataBits::Nine  => w.datalen().bit_9(),
+            };
+            let w = match config.parity {
+                Parity::None => w.paritysel().no_parity(),
+                Parity::Even => w.paritysel().even_parity(),
+                Parity::Odd  => w.paritysel().odd_parity(),
+            };
+            match config.stop_bits {
+                StopBits::One => w.stoplen().bit_1(),
+                StopBits::Two => w.stoplen().bits_2(),
+            }
+        });
+    });
+
+    Ok(clock_hz / (osr * divider))
+}
+
+/// Finds the oversampling ratio and divider that best approximate `baud`
+///
+/// Returns `None`, if the best approximation still deviates by more than 2%,
+/// which is more than a receiver can be expected to tolerate.
+fn usart_divider(clock_hz: u32, baud: u32) -> Option<(u32, u32)> {
+    let mut best: Option<(u32, u32, u32)> = None;
+
+    // Higher oversampling ratios make reception more robust, so we try them
+    // first, and only use a lower one if it is strictly more accurate.
+    for osr in (5..=16).rev() {
+        let cycles = match baud.checked_mul(osr) {
+            Some(cycles) if cycles > 0 => cycles,
+            _                          => continue,
+        };
+
+        let round   = u32::from(clock_hz % cycles >= cycles - cycles / 2);
+        let divider = (clock_hz / cycles + round).clamp(1, 0x10000);
+        let actual  = clock_hz / (osr * divider);
+        let error   = actual.max(baud) - actual.min(baud);
+
+        match best {
+            Some((_, _, best_error)) if best_error <= error => {}
+            _ => best = Some((osr, divider, error)),
+        }
+    }
+
+    best.filter(|&(_, _, error)| u64::from(error) * 50 <= u64::from(baud))
+        .map(|(osr, divider, _)| (osr, divider))
+}
+
+/// Sends data via a USART, injecting a fault that the host requested
+///
+/// `lpc8xx-hal` always sends well-formed frames, so this function accesses the
+/// registers directly. `_tx` is borrowed to make sure nothing else sends in the
+/// meantime. Any configuration that is changed to inject the fault is restored
+/// before returning.
+///
+/// `clock_hz` is the frequency of the USART's clock. It is required to inject
+/// [`Fault::Baud`].
+///
+/// [`Fault::Baud`]: ../../protocol/usart/enum.Fault.html#variant.Baud
+pub fn usart_inject<I, Mode, Throttle>(
+    _tx:      &mut usart::Tx<I, usart::state::Enabled<u8, Mode>, Throttle>,
+    fault:    protocol::usart::Fault,
+    data:     &[u8],
+    clock_hz: u32,
+)
+    -> Result<(), protocol::usart::Error>
+    where I: usart::Instance
+{
+    use protocol::usart::{
+        Error,
+        Fault,
+    };
+
+    // Sound, as we have exclusive access to the transmitter through `_tx`. We
+    // only change the configuration while the transmitter is idle, and restore
+    // it before returning.
+    let usart = unsafe { &*I::REGISTERS };
+
+    while usart.stat.read().txidle().bit_is_clear() {}
+
+    let cfg = usart.cfg.read();
+    let brg = usart.brg.read().bits();
+
+    // Each bit takes this many USART clock cycles.
+    let bit_cycles = (u32::from(usart.brg.read().brgval().bits()) + 1)
+        * (u32::from(usart.osr.read().osrval().bits()) + 1);
+
+    match fault {
+        Fault::Parity => {
+            if cfg.paritysel().is_no_parity() {
+                return Err(Error::UnsupportedFault);
+            }
+            let even = cfg.paritysel().is_even_parity();
+
+            usart_reconfigure::<I>(|| {
+                usart.cfg.modify(|_, w| match even {
+                    true  => w.paritysel().odd_parity(),
+                    false => w.paritysel().even_parity(),
+                });
+            });
+        }
+        Fault::Framing => {
+            if !cfg.paritysel().is_no_parity() || cfg.datalen().is_bit_9() {
+                return Err(Error::UnsupportedFault);
+            }
+            let seven = cfg.datalen().is_bit_7();
+
+            // Send one additional data bit, which we leave low. The receiver
+            // expects the stop bit in its place.
+            usart_reconfigure::<I>(|| {
+                usart.cfg.modify(|_, w| match seven {
+                    true  => w.datalen().bit_8(),
+                    false => w.datalen().bit_9(),
+                });
+            });
+        }
+        Fault::Break => {
+            // The receiver detects a break after 16 bit times. Assuming the
+            // USART clock runs at no less than half the system clock, this
+            // holds the line low for at least that long.
+            usart.ctl.modify(|_, w| w.txdis().set_bit());
+            while usart.stat.read().txdisstat().bit_is_clear() {}
+            usart.ctl.modify(|_, w| w.txbrken().set_bit());
+            lpc8xx_hal::cortex_m::asm::delay(bit_cycles.saturating_mul(32));
+            usart.ctl.modify(|_, w| w.txbrken().clear_bit());
+            usart.ctl.modify(|_, w| w.txdis().clear_bit());
+        }
+        Fault::Baud(baud) => {
+            let osr = u32::from(usart.osr.read().osrval().bits()) + 1;
+            let divider = baud.checked_mul(osr)
+                .and_then(|divisor| clock_hz.checked_div(divisor))
+                .filter(|&divider| divider > 0 && divider <= 0x10000)
+                .ok_or(Error::UnsupportedBaud)?;
+
+            usart_reconfigure::<I>(|| {
+                usart.brg.write(|w| unsafe {
+                    w.brgval().bits((divider - 1) as u16)
+                });
+            });
+        }
+    }
+
+    for &b in data {
+        while usart.stat.read().txrdy().bit_is_clear() {}
+        usart.txdat.write(|w| unsafe { w.txdat().bits(b.into()) });
+    }
+    while usart.stat.read().txidle().bit_is_clear() {}
+
+    usart_reconfigure::<I>(|| {
+        usart.brg.write(|w| unsafe { w.bits(brg) });
+        usart.cfg.write(|w| unsafe { w.bits(cfg.bits()) });
+    });
+
+    Ok(())
+}
+
+/// Sends queued data via a USART, throttled by its CTS signal
+///
+/// Doesn't block. Sends as much of the queued data as the USART accepts right
+/// away, and leaves the rest in the queue for the next call. This function
+/// needs to be called repeatedly, until the queue is empty.
+///
+/// `lpc8xx-hal` only enables CTS throttling, if the CTS function has been
+/// assigned through its switch matrix API. This function accesses the registers
+/// directly instead, so the caller can assign CTS to a pin that is used as an
+/// input elsewhere. Throttling is enabled while data is being sent.
+pub fn usart_send_throttled<I, Mode, Throttle, N>(
+    tx:    &mut usart::Tx<I, usart::state::Enabled<u8, Mode>, Throttle>,
+    queue: &mut spsc::Queue<u8, N>,
+)
+    where
+        I: usart::Instance,
+        N: ArrayLength<u8>,
+{
+    // Sound, as we have exclusive access to the transmitter through `tx`, and
+    // `lpc8xx-hal` doesn't touch CTSEN, unless CTS throttling is enabled
+    // through its API.
+    let usart = unsafe { &*I::REGISTERS };
+
+    if queue.is_empty() {
+        if usart.stat.read().txidle().bit_is_set() {
+            usart.cfg.modify(|_, w| w.ctsen().disabled());
+        }
+        return;
+    }
+
+    usart.cfg.modify(|_, w| w.ctsen().enabled());
+
+    while let Some(&b) = queue.peek() {
+        match tx.write(b) {
+            Ok(()) => {
+                queue.dequeue();
+            }
+            Err(nb::Error::WouldBlock) => {
+                break;
+            }
+            Err(nb::Error::Other(err)) => {
+                match err {}
+            }
+        }
+    }
+}
+
+/// Receives data from a USART in its interrupt handler, observing stalls
+///
+/// Calls [`RxInt::receive`], then disables the receive interrupt, if the
+/// receiver has been stalled. The data that is left in the USART would keep
+/// triggering the interrupt otherwise. See [`usart_release`].
+///
+/// [`RxInt::receive`]: ../usart/rx/struct.RxInt.html#method.receive
+/// [`usart_release`]: fn.usart_release.html
+pub fn usart_receive<I, Mode>(rx: &mut RxInt<I, Mode>)
+    -> Result<(), rx::ReceiveError<usart::Error<u8>>>
+    where I: usart::Instance
+{
+    let result = rx.receive();
+
+    if rx.is_stalled() {
+        rx.usart.disable_interrupts(usart::Interrupts {
+            RXRDY: true,
+            .. usart::Interrupts::default()
+        });
+    }
+
+    result
+}
+
+/// Resumes receiving from a USART that has been stalled
+///
+/// Reverses what [`usart_receive`] did, once the receiver was stalled.
+///
+/// [`usart_receive`]: fn.usart_receive.html
+pub fn usart_release<I, Mode>(rx: &mut RxInt<I, Mode>)
+    where I: usart::Instance
+{
+    rx.release();
+    rx.usart.enable_interrupts(usart::Interrupts {
+        RXRDY: true,
+        .. usart::Interrupts::default()
+    });
+}
+
+/// Runs `f` to change the configuration of USART instance `I`, while disabled
+fn usart_reconfigure<I>(f: impl FnOnce())
+    where I: usart::Instance
+{
+    // Sound, as we only touch the enable bit, and the caller has exclusive
+    // access to the USART.
+    let usart = unsafe { &*I::REGISTERS };
+
+    usart.cfg.modify(|_, w| w.enable().disabled());
+    f();
+    usart.cfg.modify(|_, w| w.enable().enabled());
+}
+
+/// Converts an error from a USART receiver, so it can be reported to the host
+pub fn usart_line_condition(err: &usart::Error<u8>)
+    -> protocol::usart::LineCondition
+{
+    use protocol::usart::LineCondition;
+
+    match err {
+        usart::Error::Framing(_) => LineCondition::FramingError,
+        usart::Error::Noise(_)   => LineCondition::Noise,
+        usart::Error::Overrun(_) => LineCondition::Overrun,
+        usart::Error::Parity(_)  => LineCondition::ParityError,
+    }
+}
+
+/// Writes a byte to USART instance `I`, for use by the panic handler
+///
+/// See [`WriteRaw`].
+///
+/// # Safety
+///
+/// Must only be called, if nothing else is using the USART anymore.
+///
+/// [`WriteRaw`]: ../panic/type.WriteRaw.html
+#[cfg(feature = "panic-handler")]
+pub unsafe fn write_raw<I>(b: u8)
+    where I: usart::Instance
+{
+    let usart = &*I::REGISTERS;
+
+    while usart.stat.read().txrdy().bit_is_clear() {}
+    usart.txdat.write(|w| w.txdat().bits(b.into()));
+}
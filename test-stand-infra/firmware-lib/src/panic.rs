@@ -0,0 +1,149 @@
+//! Panic handler that reports panics to the host
+//!
+//! Prints the panic via RTT, exactly like `panic-rtt-target` does. In addition,
+//! if [`report_to`] has been called, the panic is sent to the host as a
+//! message, so the test suite can report it as the cause of a test failure.
+//!
+//! [`report_to`]: fn.report_to.html
+
+
+use core::{
+    fmt::Write as _,
+    panic::PanicInfo,
+    sync::atomic::{
+        Ordering::SeqCst,
+        compiler_fence,
+    },
+};
+
+use cortex_m::interrupt;
+use heapless::{
+    String,
+    consts::U128,
+};
+use rtt_target::{
+    ChannelMode,
+    UpChannel,
+};
+
+use protocol::{
+    Location,
+    Panic,
+    rpc,
+};
+
+
+/// Writes a byte to the USART that is connected to the host
+///
+/// Must block until the byte has been written. As the USART's driver belongs to
+/// someone else, this needs to access the peripheral directly.
+///
+/// # Safety
+///
+/// Must only be called, if nothing else is using the USART anymore. The panic
+/// handler guarantees this.
+pub type WriteRaw = unsafe fn(u8);
+
+
+static mut HOST: Option<WriteRaw> = None;
+
+
+/// Report panics to the host via the USART that `write` writes to
+///
+/// Should be called once during initialization. Panics that occur before this
+/// is called are only printed via RTT. The HAL adapters in this crate provide
+/// suitable `write` functions.
+pub fn report_to(write: WriteRaw) {
+    interrupt::free(|_| {
+        // Sound, as we're in a critical section, and the panic handler, the
+        // only other user of `HOST`, can't run at the same time.
+        unsafe {
+            HOST = Some(write);
+        }
+    })
+}
+
+
+#[inline(never)]
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    interrupt::disable();
+
+    if let Some(mut channel) = unsafe { UpChannel::conjure(0) } {
+        channel.set_mode(ChannelMode::BlockIfFull);
+
+        writeln!(channel, "{}", info).ok();
+    }
+
+    // Sound, as interrupts are disabled, and we're never returning from here.
+    // Nothing else can access `HOST` anymore.
+    if let Some(write) = unsafe { HOST } {
+        report(write, info);
+    }
+
+    loop {
+        compiler_fence(SeqCst);
+    }
+}
+
+fn report(write: WriteRaw, info: &PanicInfo) {
+    // If the message doesn't fit, whatever fits is sent.
+    let mut message = String::<U128>::new();
+    let _ = write!(message, "{}", info.message());
+
+    // The paths of files in dependencies can be too long to fit into the
+    // buffer, together with the message. Fall back to the end of the path,
+    // which still names the file, then to leaving out the location.
+    let location = info.location();
+    let files = [
+        location.map(|location| location.file()),
+        location.map(|location| path_tail(location.file(), 64)),
+        None,
+    ];
+
+    let mut buf = [0; 256];
+    let mut len = None;
+    for &file in &files {
+        let panic = Panic {
+            location: location.zip(file).map(|(location, file)| {
+                Location {
+                    file,
+                    line:   location.line(),
+                    column: location.column(),
+                }
+            }),
+            message: &message,
+        };
+
+        let response = rpc::Response::panic(panic);
+        if let Ok(data) = postcard::to_slice_cobs(&response, &mut buf) {
+            len = Some(data.len());
+            break;
+        }
+    }
+    let data = match len {
+        Some(len) => &buf[..len],
+        None      => return,
+    };
+
+    // We can't use the USART API here, as the `Tx` instance belongs to someone
+    // else. Sound, as that someone is never going to run again.
+    //
+    // The panic might have interrupted a message half-way. Send a delimiter
+    // first, so the host drops whatever it received of that message, and
+    // receives the panic as a frame of its own.
+    unsafe { write(0) }
+    for &b in data.iter() {
+        unsafe { write(b) }
+    }
+}
+
+/// Returns the end of `path`, which is at most `max` bytes long
+fn path_tail(path: &str, max: usize) -> &str {
+    let mut start = path.len().saturating_sub(max);
+    while !path.is_char_boundary(start) {
+        start += 1;
+    }
+
+    &path[start..]
+}
@@ -1,7 +1,14 @@
 //! Convenient pin interrupt API
+//!
+//! Works with any pin interrupt that implements [`Edges`], and any timer that
+//! implements [`Stopwatch`].
+//!
+//! [`Edges`]: trait.Edges.html
+//! [`Stopwatch`]: trait.Stopwatch.html
 
 
 use heapless::{
+    ArrayLength,
     consts::U32,
     spsc::{
         Consumer,
@@ -9,22 +16,23 @@ use heapless::{
         Queue,
     },
 };
-use lpc8xx_hal::{
-    prelude::*,
-    gpio,
-    init_state::Enabled,
-    mrt,
-    pinint,
-    pins,
-};
+
+use protocol::pin::Level;
 
 
 /// Represents a pin interrupt
-pub struct PinInterrupt {
-    queue: Queue<Event, QueueCap>,
+///
+/// `N` is the capacity of the queue that carries events from the interrupt
+/// context to the idle context.
+pub struct PinInterrupt<N = U32>
+    where N: ArrayLength<Event>
+{
+    queue: Queue<Event, N>,
 }
 
-impl PinInterrupt {
+impl<N> PinInterrupt<N>
+    where N: ArrayLength<Event>
+{
     /// Create a new instance of `PinInterrupt`
     ///
     /// Can be called in a const context, which means it can be used to
@@ -49,15 +57,18 @@ impl PinInterrupt {
     ///
     /// [`Int`]: struct.Int.html
     /// [`Idle`]: struct.Idle.html
-    pub fn init<I, P, T: mrt::Trait>(&mut self,
-        interrupt: pinint::Interrupt<I, P, Enabled>,
-        timer:     mrt::Channel<T>,
-    )
-        -> (Int<I, P, T>, Idle)
+    pub fn init<E, T>(&mut self, interrupt: E, timer: T)
+        -> (Int<'_, E, T, N>, Idle<'_, N>)
     {
         let (prod, cons) = self.queue.split();
 
-        let int  = Int { int: interrupt, queue: prod, timer, measuring: false };
+        let int = Int {
+            int:       interrupt,
+            queue:     prod,
+            timer,
+            measuring: false,
+            overflows: 0,
+        };
         let idle = Idle { queue: cons };
 
         (int, idle)
@@ -71,18 +82,21 @@ impl PinInterrupt {
 /// The `Int` instance can then be moved into the interrupt handler.
 ///
 /// [`PinInterrupt::init`]: struct.PinInterrupt.html#method.init
-pub struct Int<'r, I, P, T: mrt::Trait> {
-    int:       pinint::Interrupt<I, P, Enabled>,
-    queue:     Producer<'r, Event, QueueCap>,
-    timer:     mrt::Channel<T>,
+pub struct Int<'r, E, T, N = U32>
+    where N: ArrayLength<Event>
+{
+    int:       E,
+    queue:     Producer<'r, Event, N>,
+    timer:     T,
     measuring: bool,
+    overflows: u32,
 }
 
-impl<I, P, T> Int<'_, I, P, T>
+impl<E, T, N> Int<'_, E, T, N>
     where
-        I: pinint::Trait,
-        P: pins::Trait,
-        T: mrt::Trait,
+        E: Edges,
+        T: Stopwatch,
+        N: ArrayLength<Event>,
 {
     /// Handles a pin interrupts
     ///
@@ -90,29 +104,49 @@ impl<I, P, T> Int<'_, I, P, T>
     /// whether this interrupt was triggered by a rising or falling edge, and
     /// will send the respective event to the corresponding [`Idle`] instance.
     ///
+    /// If the queue is full, the event is dropped and counted. See
+    /// [`overflows`].
+    ///
     /// [`Idle`]: struct.Idle.html
+    /// [`overflows`]: #method.overflows
     pub fn handle_interrupt(&mut self) {
         let mut period = None;
 
         if self.measuring {
-            let timer_wrapped = self.timer.wait().is_ok();
-            if !timer_wrapped {
-                period = Some(mrt::MAX_VALUE.to_u32() - self.timer.value());
-            }
+            period = self.timer.elapsed();
         }
 
-        self.timer.start(mrt::MAX_VALUE);
+        self.timer.restart();
         self.measuring = true;
 
         if self.int.clear_rising_edge_flag() {
-            let event = Event { level: gpio::Level::High, period };
-            self.queue.enqueue(event).unwrap();
+            self.send(Event { level: Level::High, period });
         }
         if self.int.clear_falling_edge_flag() {
-            let event = Event { level: gpio::Level::Low, period };
-            self.queue.enqueue(event).unwrap();
+            self.send(Event { level: Level::Low, period });
+        }
+    }
+
+    /// The number of events that were dropped, because the queue was full
+    ///
+    /// Wraps around on overflow.
+    pub fn overflows(&self) -> u32 {
+        self.overflows
+    }
+
+    fn send(&mut self, event: Event) {
+        if self.queue.enqueue(event).is_err() {
+            self.overflows = self.overflows.wrapping_add(1);
         }
     }
+
+    /// Forget the previous edge
+    ///
+    /// The next edge won't report a period, as the time since an edge that
+    /// happened before the reset is meaningless.
+    pub fn reset(&mut self) {
+        self.measuring = false;
+    }
 }
 
 
@@ -125,11 +159,15 @@ impl<I, P, T> Int<'_, I, P, T>
 ///
 /// [`PinInterrupt::init`]: struct.PinInterrupt.html#method.init
 /// [`Int`]: struct.Int.html
-pub struct Idle<'r> {
-    queue: Consumer<'r, Event, QueueCap>,
+pub struct Idle<'r, N = U32>
+    where N: ArrayLength<Event>
+{
+    queue: Consumer<'r, Event, N>,
 }
 
-impl Idle<'_> {
+impl<N> Idle<'_, N>
+    where N: ArrayLength<Event>
+{
     /// Returns the next pin interrupt event, if available
     pub fn next(&mut self) -> Option<Event> {
         self.queue.dequeue()
@@ -139,6 +177,11 @@ impl Idle<'_> {
     pub fn is_ready(&self) -> bool {
         self.queue.ready()
     }
+
+    /// Discard all pin interrupt events that haven't been processed yet
+    pub fn discard(&mut self) {
+        while self.queue.dequeue().is_some() {}
+    }
 }
 
 
@@ -146,14 +189,38 @@ impl Idle<'_> {
 #[derive(Debug)]
 pub struct Event {
     /// The level of the pin after this event
-    pub level: gpio::Level,
+    pub level: Level,
 
     /// The period measured since the last event, if available
+    ///
+    /// This value is in ticks of the [`Stopwatch`] that measured it.
+    ///
+    /// [`Stopwatch`]: trait.Stopwatch.html
     pub period: Option<u32>,
 }
 
 
-// It would be nice to make the queue capacity configurable, but that would
-// require a generic with trait bound on all the structs. As of this writing,
-// `const fn`s with trait bounds are unstable, so we can't do it yet.
-type QueueCap = U32;
+/// A pin interrupt that can detect rising and falling edges
+pub trait Edges {
+    /// Clear the rising edge flag
+    ///
+    /// Returns whether the flag was set.
+    fn clear_rising_edge_flag(&mut self) -> bool;
+
+    /// Clear the falling edge flag
+    ///
+    /// Returns whether the flag was set.
+    fn clear_falling_edge_flag(&mut self) -> bool;
+}
+
+/// A timer that measures the time between pin interrupts
+pub trait Stopwatch {
+    /// Start measuring from zero
+    fn restart(&mut self);
+
+    /// The number of ticks since the last restart
+    ///
+    /// Returns `None`, if the timer has overflowed since then.
+    fn elapsed(&mut self) -> Option<u32>;
+}
+
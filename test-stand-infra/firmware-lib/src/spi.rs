@@ -0,0 +1,246 @@
+//! Emulation of SPI slave devices
+
+
+use heapless::{
+    Vec,
+    consts::U128,
+};
+
+use protocol::spi;
+
+
+/// An emulated SPI slave device
+///
+/// Call [`start`] and [`receive`] from the SPI slave's interrupt handler, as
+/// the master's frames come in.
+///
+/// See [`protocol::spi::Device`] for how the device behaves.
+///
+/// [`start`]: #method.start
+/// [`receive`]: #method.receive
+/// [`protocol::spi::Device`]: ../../protocol/spi/enum.Device.html
+pub struct Device {
+    model: Model,
+}
+
+impl Device {
+    /// Create a device from the description sent by the host
+    ///
+    /// Returns [`Error::TooLong`], if the device has more than 128 responses
+    /// or registers.
+    ///
+    /// [`Error::TooLong`]: ../../protocol/spi/enum.Error.html#variant.TooLong
+    pub fn new(device: &spi::Device) -> Result<Self, spi::Error> {
+        let model = match *device {
+            spi::Device::Responses(data) => {
+                let mut responses = Vec::new();
+                responses.extend_from_slice(data)
+                    .map_err(|()| spi::Error::TooLong)?;
+
+                Model::Responses {
+                    responses,
+                    next: 0,
+                }
+            }
+            spi::Device::Registers { registers: data, read, write } => {
+                let mut registers = Vec::new();
+                registers.extend_from_slice(data)
+                    .map_err(|()| spi::Error::TooLong)?;
+
+                Model::Registers {
+                    registers,
+                    read,
+                    write,
+                    state: State::Command,
+                }
+            }
+        };
+
+        Ok(Self { model })
+    }
+
+    /// The frame to send before the first frame is received
+    ///
+    /// Call this once, after the device has been created.
+    pub fn preload(&mut self) -> u8 {
+        match &mut self.model {
+            Model::Responses { responses, next } => {
+                next_response(responses, next)
+            }
+            Model::Registers { .. } => {
+                0xff
+            }
+        }
+    }
+
+    /// Start a new transfer
+    ///
+    /// Call this when the master has asserted chip select.
+    pub fn start(&mut self) {
+        if let Model::Registers { state, .. } = &mut self.model {
+            *state = State::Command;
+        }
+    }
+
+    /// Handle a frame that the master has sent
+    ///
+    /// Returns the frame that should be sent during the next one.
+    pub fn receive(&mut self, frame: u8) -> u8 {
+        match &mut self.model {
+            Model::Responses { responses, next } => {
+                next_response(responses, next)
+            }
+            Model::Registers { registers, read, write, state } => {
+                match *state {
+                    State::Command if frame == *read => {
+                        *state = State::Select(Command::Read);
+                        0xff
+                    }
+                    State::Command if frame == *write => {
+                        *state = State::Select(Command::Write);
+                        0xff
+                    }
+                    State::Command | State::Ignore => {
+                        *state = State::Ignore;
+                        0xff
+                    }
+                    State::Select(Command::Read) => {
+                        *state = State::Read(frame.wrapping_add(1));
+                        register(registers, frame)
+                    }
+                    State::Select(Command::Write) => {
+                        *state = State::Write(frame);
+                        0xff
+                    }
+                    State::Read(selected) => {
+                        *state = State::Read(selected.wrapping_add(1));
+                        register(registers, selected)
+                    }
+                    State::Write(selected) => {
+                        if let Some(value) =
+                            registers.get_mut(selected as usize)
+                        {
+                            *value = frame;
+                        }
+                        *state = State::Write(selected.wrapping_add(1));
+                        0xff
+                    }
+                }
+            }
+        }
+    }
+}
+
+
+/// The frames an SPI slave has received, grouped by transfer
+///
+/// See [`protocol::spi::Log`] for how the log is encoded.
+///
+/// [`protocol::spi::Log`]: ../../protocol/spi/struct.Log.html
+#[derive(Default)]
+pub struct Log {
+    transfers:  Vec<u8, U128>,
+    current:    Option<usize>,
+    overflowed: bool,
+}
+
+impl Log {
+    /// Add a frame to the current transfer
+    ///
+    /// Starts a new transfer, if none is in progress.
+    pub fn push(&mut self, frame: u8) {
+        let current = match self.current {
+            Some(current) => {
+                current
+            }
+            None => {
+                // Only start a transfer, if its first frame fits too.
+                if self.transfers.capacity() - self.transfers.len() < 2 {
+                    self.overflowed = true;
+                    return;
+                }
+
+                let current = self.transfers.len();
+                // Can't fail, as we checked the capacity above.
+                let _ = self.transfers.push(0);
+                self.current = Some(current);
+
+                current
+            }
+        };
+
+        match self.transfers.push(frame) {
+            Ok(()) => self.transfers[current] += 1,
+            Err(_) => self.overflowed = true,
+        }
+    }
+
+    /// End the current transfer
+    ///
+    /// Call this when the master has deasserted chip select.
+    pub fn end(&mut self) {
+        self.current = None;
+    }
+
+    /// The encoded transfers
+    pub fn transfers(&self) -> &[u8] {
+        &self.transfers
+    }
+
+    /// Whether frames were dropped, because the log was full
+    pub fn overflowed(&self) -> bool {
+        self.overflowed
+    }
+
+    /// Remove all transfers from the log
+    ///
+    /// If a transfer is in progress, its remaining frames are logged as a new
+    /// transfer.
+    pub fn clear(&mut self) {
+        // Not using `Vec::clear`, which violates the precondition of an unsafe
+        // method in `heapless` 0.5.
+        self.transfers  = Vec::new();
+        self.current    = None;
+        self.overflowed = false;
+    }
+}
+
+
+enum Model {
+    Responses {
+        responses: Vec<u8, U128>,
+        next:      usize,
+    },
+    Registers {
+        registers: Vec<u8, U128>,
+        read:      u8,
+        write:     u8,
+        state:     State,
+    },
+}
+
+#[derive(Clone, Copy)]
+enum State {
+    Command,
+    Select(Command),
+    Read(u8),
+    Write(u8),
+    Ignore,
+}
+
+#[derive(Clone, Copy)]
+enum Command {
+    Read,
+    Write,
+}
+
+
+fn next_response(responses: &[u8], next: &mut usize) -> u8 {
+    let response = responses.get(*next).copied().unwrap_or(0xff);
+    *next = next.saturating_add(1);
+    response
+}
+
+fn register(registers: &[u8], selected: u8) -> u8 {
+    registers.get(selected as usize).copied().unwrap_or(0xff)
+}
@@ -0,0 +1,141 @@
+//! Adapters for the STM32L4 family
+//!
+//! The USARTs provided by `stm32l4xx-hal` implement the `embedded-hal` traits,
+//! so they can be used with the [`usart`] module directly. This module
+//! provides what's needed on top of that, without depending on the HAL.
+//!
+//! [`usart`]: ../usart/index.html
+
+
+use core::ptr;
+
+
+/// An STM32L4 USART instance
+pub trait Instance {
+    /// The base address of the instance's registers
+    const BASE: usize;
+}
+
+/// Represents USART1
+pub struct Usart1;
+
+impl Instance for Usart1 {
+    const BASE: usize = 0x4001_3800;
+}
+
+/// Represents USART2
+pub struct Usart2;
+
+impl Instance for Usart2 {
+    const BASE: usize = 0x4000_4400;
+}
+
+/// Represents USART3
+pub struct Usart3;
+
+impl Instance for Usart3 {
+    const BASE: usize = 0x4000_4800;
+}
+
+
+/// An STM32L4 SPI instance
+pub trait SpiInstance {
+    /// The base address of the instance's registers
+    const BASE: usize;
+}
+
+/// Represents SPI2
+pub struct Spi2;
+
+impl SpiInstance for Spi2 {
+    const BASE: usize = 0x4000_3800;
+}
+
+
+/// Applies an SPI configuration that the host requested
+///
+/// `stm32l4xx-hal` only supports configuring the mode, and only when creating
+/// the SPI. Frames are limited to 4 to 8 bits, as the HAL accesses the data
+/// register 8 bits at a time.
+///
+/// # Safety
+///
+/// The caller must own SPI instance `I`, and no transfer must be in progress.
+pub unsafe fn configure_spi<I>(config: &protocol::spi::Config)
+    -> Result<(), protocol::spi::Error>
+    where I: SpiInstance
+{
+    use protocol::spi::{
+        BitOrder,
+        Phase,
+        Polarity,
+    };
+
+    // Register offsets and flags, as documented in the reference manual
+    // (RM0394).
+    const CR1:          usize = 0x00;
+    const CR2:          usize = 0x04;
+    const CR1_CPHA:     u32   = 0x1 << 0;
+    const CR1_CPOL:     u32   = 0x1 << 1;
+    const CR1_SPE:      u32   = 0x1 << 6;
+    const CR1_LSBFIRST: u32   = 0x1 << 7;
+    const CR2_DS_SHIFT: u32   = 8;
+    const CR2_DS_MASK:  u32   = 0xf << CR2_DS_SHIFT;
+
+    if config.frame_size < 4 || config.frame_size > 8 {
+        return Err(protocol::spi::Error::UnsupportedConfig);
+    }
+
+    let cr1 = (I::BASE + CR1) as *mut u32;
+    let cr2 = (I::BASE + CR2) as *mut u32;
+
+    // The configuration must not be changed while the peripheral is enabled.
+    let mut bits = ptr::read_volatile(cr1) & !CR1_SPE;
+    ptr::write_volatile(cr1, bits);
+
+    bits &= !(CR1_CPHA | CR1_CPOL | CR1_LSBFIRST);
+    if config.mode.phase == Phase::CaptureOnSecondTransition {
+        bits |= CR1_CPHA;
+    }
+    if config.mode.polarity == Polarity::IdleHigh {
+        bits |= CR1_CPOL;
+    }
+    if config.bit_order == BitOrder::LsbFirst {
+        bits |= CR1_LSBFIRST;
+    }
+    ptr::write_volatile(cr1, bits);
+
+    let ds = (config.frame_size as u32 - 1) << CR2_DS_SHIFT;
+    ptr::write_volatile(cr2, (ptr::read_volatile(cr2) & !CR2_DS_MASK) | ds);
+
+    ptr::write_volatile(cr1, bits | CR1_SPE);
+
+    Ok(())
+}
+
+
+/// Writes a byte to USART instance `I`, for use by the panic handler
+///
+/// See [`WriteRaw`].
+///
+/// # Safety
+///
+/// Must only be called, if nothing else is using the USART anymore.
+///
+/// [`WriteRaw`]: ../panic/type.WriteRaw.html
+#[cfg(feature = "panic-handler")]
+pub unsafe fn write_raw<I>(b: u8)
+    where I: Instance
+{
+    // Register offsets and flags, as documented in the reference manual
+    // (RM0394).
+    const ISR:     usize = 0x1c;
+    const TDR:     usize = 0x28;
+    const ISR_TXE: u32   = 0x1 << 7;
+
+    let isr = (I::BASE + ISR) as *const u32;
+    let tdr = (I::BASE + TDR) as *mut u32;
+
+    while ptr::read_volatile(isr) & ISR_TXE == 0 {}
+    ptr::write_volatile(tdr, b.into());
+}
@@ -1,7 +1,10 @@
 //! Interrupt-enabled USART API
+//!
+//! Works with any USART that implements the serial traits from `embedded-hal`.
 
 
 pub mod rx;
+pub mod timing;
 pub mod tx;
 
 
@@ -10,19 +13,20 @@ pub use self::{
         RxIdle,
         RxInt,
     },
-    tx::Tx,
+    tx::{
+        Tx,
+        TxInt,
+        TxQueue,
+    },
 };
 
 
 use heapless::{
+    ArrayLength,
     Vec,
     consts::U256,
     spsc,
 };
-use lpc8xx_hal::{
-    USART,
-    usart::state::Enabled,
-};
 
 
 /// Interrupt-enabled USART wrapper
@@ -35,14 +39,21 @@ use lpc8xx_hal::{
 /// - [`RxIdle`], which can be used to process the received data somewhere else.
 /// - [`Tx`], which can be used to send data.
 ///
+/// `N` is the capacity of the receive queue, as well as the buffer that
+/// [`RxIdle`] uses to process received data.
+///
 /// [`RxInt`]: rx/struct.RxInt.html
 /// [`RxIdle`]: rx/struct.RxIdle.html
 /// [`Tx`]: tx/struct.Tx.html
-pub struct Usart {
-    queue: spsc::Queue<u8, QueueCap>,
+pub struct Usart<N = U256>
+    where N: ArrayLength<u8>
+{
+    queue: spsc::Queue<u8, N>,
 }
 
-impl Usart {
+impl<N> Usart<N>
+    where N: ArrayLength<u8>
+{
     /// Creates a new instance of `Usart`
     pub const fn new() -> Self {
         Self {
@@ -52,35 +63,35 @@ impl Usart {
 
     /// Initialize the USART
     ///
-    /// Returns the three parts - [`RxInt`], [`RxIdle`], and [`Tx`] - which can
-    /// then be moved into different contexts.
+    /// Accepts the receiving and sending halves of the USART. Returns the three
+    /// parts - [`RxInt`], [`RxIdle`], and [`Tx`] - which can then be moved into
+    /// different contexts.
     ///
     /// [`RxInt`]: rx/struct.RxInt.html
     /// [`RxIdle`]: rx/struct.RxIdle.html
     /// [`Tx`]: tx/struct.Tx.html
-    pub fn init<I, Mode>(&mut self, usart: USART<I, Enabled<u8, Mode>>)
-        -> (RxInt<I, Mode>, RxIdle, Tx<I, Mode>)
+    pub fn init<R, W>(&mut self, rx: R, tx: W)
+        -> (RxInt<'_, R, N>, RxIdle<'_, N>, Tx<W>)
     {
         let (prod, cons) = self.queue.split();
 
         let rx_int = RxInt {
-            usart: usart.rx,
-            queue: prod,
+            usart:  rx,
+            queue:  prod,
+            errors: 0,
+            stall:  None,
         };
         let rx_idle = RxIdle {
-            queue: cons,
-            buf:   Vec::new(),
+            queue:      cons,
+            buf:        Vec::new(),
+            high_water: 0,
+            dropping:   false,
         };
         let tx = Tx {
-            usart: usart.tx,
+            usart: tx,
         };
 
         (rx_int, rx_idle, tx)
     }
 }
 
-
-// It would be nice to make the queue capacity configurable, but that would
-// require a generic with trait bound on all the structs. As of this writing,
-// `const fn`s with trait bounds are unstable, so we can't do it yet.
-type QueueCap = U256;
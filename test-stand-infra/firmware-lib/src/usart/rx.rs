@@ -1,35 +1,35 @@
 //! Receiving part of the interrupt-enabled USART API
 
 
+use embedded_hal::serial;
 use heapless::{
+    ArrayLength,
     Vec,
+    consts::U256,
     spsc,
 };
-use lpc8xx_hal::{
-    prelude::*,
-    usart::{
-        self,
-        state::Enabled,
-    },
-};
 use serde::Deserialize;
 
-use super::QueueCap;
-
 
 /// API for receiving data from a USART instance in an interrupt handler
 ///
 /// You can get an instance of this struct by calling [`Usart::init`].
 ///
 /// [`Usart::init`]: ../struct.Usart.html#method.init
-pub struct RxInt<'r, I, Mode> {
-    pub usart: usart::Rx<I, Enabled<u8, Mode>>,
-    pub queue: spsc::Producer<'r, u8, QueueCap>,
+pub struct RxInt<'r, R, N = U256>
+    where N: ArrayLength<u8>
+{
+    pub usart: R,
+    pub queue: spsc::Producer<'r, u8, N>,
+
+    pub(crate) errors: u32,
+    pub(crate) stall:  Option<u32>,
 }
 
-impl<I, Mode> RxInt<'_, I, Mode>
+impl<R, N> RxInt<'_, R, N>
     where
-        I: usart::Instance,
+        R: serial::Read<u8>,
+        N: ArrayLength<u8>,
 {
     /// Receive available data
     ///
@@ -39,12 +39,28 @@ impl<I, Mode> RxInt<'_, I, Mode>
     /// time pressure.
     ///
     /// [`RxIdle`]: struct.RxIdle.html
-    pub fn receive(&mut self) -> Result<(), ReceiveError> {
+    pub fn receive(&mut self) -> Result<(), ReceiveError<R::Error>> {
+        let result = self.receive_inner();
+        if result.is_err() {
+            self.errors = self.errors.wrapping_add(1);
+        }
+        result
+    }
+
+    fn receive_inner(&mut self) -> Result<(), ReceiveError<R::Error>> {
         loop {
+            if self.is_stalled() {
+                return Ok(());
+            }
+
             match self.usart.read() {
                 Ok(b) => {
                     self.queue.enqueue(b)
                         .map_err(|_| ReceiveError::QueueFull)?;
+
+                    if let Some(remaining) = &mut self.stall {
+                        *remaining -= 1;
+                    }
                 }
                 Err(nb::Error::WouldBlock) => {
                     return Ok(());
@@ -55,6 +71,36 @@ impl<I, Mode> RxInt<'_, I, Mode>
             }
         }
     }
+
+    /// The number of errors that `receive` has returned so far
+    pub fn errors(&self) -> u32 {
+        self.errors
+    }
+
+    /// Stop receiving, once `after` more bytes have been received
+    ///
+    /// Any data that arrives after that is left in the USART, until
+    /// [`release`] is called. Depending on the USART, this might stall the
+    /// sender via flow control, or lead to overrun errors.
+    ///
+    /// [`release`]: #method.release
+    pub fn stall(&mut self, after: u32) {
+        self.stall = Some(after);
+    }
+
+    /// Resume receiving after a call to [`stall`]
+    ///
+    /// [`stall`]: #method.stall
+    pub fn release(&mut self) {
+        self.stall = None;
+    }
+
+    /// Indicates whether receiving has stopped, due to a call to [`stall`]
+    ///
+    /// [`stall`]: #method.stall
+    pub fn is_stalled(&self) -> bool {
+        self.stall == Some(0)
+    }
 }
 
 
@@ -66,100 +112,167 @@ impl<I, Mode> RxInt<'_, I, Mode>
 /// You can get an instance of this struct by calling [`Usart::init`].
 ///
 /// [`Usart::init`]: ../struct.Usart.html#method.init
-pub struct RxIdle<'r> {
-    pub queue: spsc::Consumer<'r, u8, QueueCap>,
-    pub buf:   Vec<u8, QueueCap>,
+pub struct RxIdle<'r, N = U256>
+    where N: ArrayLength<u8>
+{
+    pub queue: spsc::Consumer<'r, u8, N>,
+    pub buf:   Vec<u8, N>,
+
+    pub(crate) high_water: usize,
+    pub(crate) dropping:   bool,
 }
 
-impl RxIdle<'_> {
+impl<N> RxIdle<'_, N>
+    where N: ArrayLength<u8>
+{
     /// Indicates whether data has been received that can be processed
     pub fn can_process(&self) -> bool {
         self.queue.ready()
     }
 
+    /// The highest number of received bytes that were waiting at once
+    ///
+    /// This is measured in the internal buffer, so it only accounts for data
+    /// that has been taken out of the queue for processing.
+    pub fn high_water(&self) -> usize {
+        self.high_water
+    }
+
+    /// Return the high-water mark, and start measuring anew
+    ///
+    /// Like [`high_water`], but only accounts for the data received since the
+    /// previous call.
+    ///
+    /// [`high_water`]: #method.high_water
+    pub fn take_high_water(&mut self) -> usize {
+        let high_water = self.high_water;
+        self.high_water = self.buf.len();
+        high_water
+    }
+
     /// Process received data
     ///
-    /// Copies any available data to the internal buffer. If the buffer is not
-    /// empty, the closure is called, with the buffer data as an argument.
+    /// Copies any available data to the internal buffer, as much as fits. If
+    /// the buffer is not empty, the closure is called, with the buffer data as
+    /// an argument. Any data that didn't fit is left for the next call.
     ///
-    /// The internal buffer is cleared, once the closure returns.
+    /// The internal buffer is cleared, once the closure returns successfully.
+    /// If it returns an error, the data is passed to it again on the next call.
     pub fn process_raw<E>(&mut self, f: impl FnOnce(&[u8]) -> Result<(), E>)
         -> Result<(), ProcessError<E>>
     {
-        while let Some(b) = self.queue.dequeue() {
-            self.buf.push(b)
-                .map_err(|_| ProcessError::BufferFull)?;
+        while self.buf.len() < self.buf.capacity() {
+            match self.queue.dequeue() {
+                Some(b) => {
+                    // Can't fail, as we just checked there's enough room.
+                    let _ = self.buf.push(b);
+                }
+                None => {
+                    break;
+                }
+            }
+            self.high_water = self.high_water.max(self.buf.len());
         }
 
         if self.buf.len() > 0 {
             f(&self.buf)
                 .map_err(|err| ProcessError::Other(err))?;
-            self.buf.clear();
+
+            // Replace the buffer instead of calling `clear`. In `heapless` 0.5,
+            // `Vec::clear` violates the precondition of an unsafe method.
+            self.buf = Vec::new();
         }
 
         Ok(())
     }
 
-    /// Process received message
+    /// Process received messages
     ///
-    /// Copies any available data to the internal buffer until no more data is
-    /// available, or a full message has been received. If a message has been
-    /// received, that message is deserialized and the closure is called.
+    /// Copies any available data to the internal buffer. Whenever a full frame
+    /// has been received, the closure is called with it. The closure can decode
+    /// the message using [`Frame::decode`].
     ///
-    /// After calling this method, you must clear the internal buffer by calling
-    /// [`clear_buf`]. Otherwise, the same message will be processed again on
-    /// the next call.
+    /// Each frame is passed to the closure exactly once, even if the closure
+    /// returns an error. The same is true for frames that can't be decoded.
     ///
-    /// [`clear_buf`]: #method.clear_buf
-    pub fn process_message<'de, M, E>(&'de mut self,
-        f: impl FnOnce(M) -> Result<(), E>,
+    /// If a frame doesn't fit into the internal buffer, it is dropped and
+    /// [`ProcessError::BufferFull`] is returned. Any frames after it are
+    /// processed normally.
+    ///
+    /// Returns on the first error. Frames that haven't been processed at that
+    /// point are left for the next call.
+    ///
+    /// [`Frame::decode`]: struct.Frame.html#method.decode
+    /// [`ProcessError::BufferFull`]: enum.ProcessError.html#variant.BufferFull
+    pub fn process_messages<E>(&mut self,
+        mut f: impl FnMut(Frame) -> Result<(), ProcessError<E>>,
     )
         -> Result<(), ProcessError<E>>
-        where M: Deserialize<'de>
     {
         while let Some(b) = self.queue.dequeue() {
-            self.buf.push(b)
-                .map_err(|_| ProcessError::BufferFull)?;
-
             // Requests are COBS-encoded, so we know that `0` means we
             // received a full frame.
-            if b == 0 {
-                let message = postcard::from_bytes_cobs(&mut self.buf)
-                    .map_err(|err| ProcessError::Postcard(err))?;
-                f(message)
-                    .map_err(|err| ProcessError::Other(err))?;
-                return Ok(());
+            let end_of_frame = b == 0;
+
+            if self.dropping {
+                self.dropping = !end_of_frame;
+                continue;
+            }
+
+            if self.buf.push(b).is_err() {
+                self.buf = Vec::new();
+                self.dropping = !end_of_frame;
+                return Err(ProcessError::BufferFull);
+            }
+            self.high_water = self.high_water.max(self.buf.len());
+
+            if end_of_frame {
+                let result = f(Frame(&mut self.buf));
+                self.buf = Vec::new();
+                result?;
             }
         }
 
         Ok(())
     }
 
-    /// Clear the internal buffer
-    ///
-    /// This method _must_ be called after every call to [`process_message`], or
-    /// on the next call, the same message will be processed again.
-    ///
-    /// It would be much nice, if this functionality could be included in
-    /// [`process_message`], but unfortunately there's no straight-forward way
-    /// to do this, as the lifetime required by the use of `Deserialize`
-    /// interferes.
+    /// Discard all received data that hasn't been processed yet
     ///
-    /// [`process_message`]: #method.process_message
-    pub fn clear_buf(&mut self) {
-        self.buf.clear();
+    /// This includes data in the queue, as well as any partial message in the
+    /// internal buffer.
+    pub fn discard(&mut self) {
+        while self.queue.dequeue().is_some() {}
+        self.buf = Vec::new();
+        self.dropping = false;
+    }
+}
+
+
+/// A full frame of received data
+///
+/// Passed to the closure by [`RxIdle::process_messages`].
+///
+/// [`RxIdle::process_messages`]: struct.RxIdle.html#method.process_messages
+pub struct Frame<'a>(&'a mut [u8]);
+
+impl<'a> Frame<'a> {
+    /// Decode the message in this frame
+    pub fn decode<M>(self) -> Result<M, postcard::Error>
+        where M: Deserialize<'a>
+    {
+        postcard::from_bytes_cobs(self.0)
     }
 }
 
 
 /// Error receiving data from USART
 #[derive(Debug)]
-pub enum ReceiveError {
+pub enum ReceiveError<E> {
     /// The internal queue is full
     QueueFull,
 
     /// An error was returned by the wrapped USART receiver
-    Usart(usart::Error<u8>),
+    Usart(E),
 }
 
 /// Error processing received USART data
@@ -176,3 +289,9 @@ pub enum ProcessError<E> {
     /// This is an error that was returned from the user-provided closure.
     Other(E),
 }
+
+impl<E> From<postcard::Error> for ProcessError<E> {
+    fn from(err: postcard::Error) -> Self {
+        Self::Postcard(err)
+    }
+}
@@ -0,0 +1,267 @@
+//! Timing measurement of USART frames
+//!
+//! Works with the edge events of any pin interrupt or timer capture, as long as
+//! they are timestamped precisely enough. See [`Meter`].
+//!
+//! [`Meter`]: struct.Meter.html
+
+
+use protocol::{
+    pin::Level,
+    usart::{
+        MeasureTiming,
+        Timing,
+    },
+};
+
+use crate::pin_interrupt::Event;
+
+
+/// Measures the timing of USART frames from the edges on the line
+///
+/// Expects to see each edge on the line as an [`Event`], with the period
+/// measured in ticks of a timer with a known frequency.
+///
+/// Frames are identified by their start bit. Every edge within a frame should
+/// occur at a whole number of bit times after the start bit, which is used to
+/// calculate the average bit time of the sender. The time between the starts
+/// of two consecutive frames, minus the length of a frame, is the gap between
+/// them.
+///
+/// [`Event`]: ../../pin_interrupt/struct.Event.html
+pub struct Meter {
+    tick_hz: u32,
+    params:  Option<Params>,
+    state:   State,
+}
+
+impl Meter {
+    /// Create a new instance of `Meter`
+    ///
+    /// `tick_hz` is the frequency of the timer that measures the periods
+    /// between events. The new instance doesn't measure anything, until
+    /// [`start`] is called.
+    ///
+    /// [`start`]: #method.start
+    pub const fn new(tick_hz: u32) -> Self {
+        Self {
+            tick_hz,
+            params: None,
+            state:  State::new(),
+        }
+    }
+
+    /// Start a new measurement
+    ///
+    /// Discards the results of any previous measurement. Assumes that the
+    /// line is idle when the first event arrives.
+    pub fn start<Id>(&mut self, measure: &MeasureTiming<Id>) {
+        let bit_ticks = self.tick_hz / measure.baud.max(1);
+
+        self.params = Some(Params {
+            baud:       measure.baud,
+            bit_ticks:  bit_ticks.max(1),
+            frame_bits: measure.frame_bits(),
+        });
+        self.state = State::new();
+    }
+
+    /// Stop measuring and discard the results
+    pub fn stop(&mut self) {
+        self.params = None;
+        self.state  = State::new();
+    }
+
+    /// Indicates whether a measurement is in progress
+    pub fn is_measuring(&self) -> bool {
+        self.params.is_some()
+    }
+
+    /// Process an edge on the line
+    ///
+    /// Events are ignored, unless a measurement is in progress.
+    pub fn process(&mut self, event: Event) {
+        let params = match self.params {
+            Some(params) => params,
+            None         => return,
+        };
+        let state = &mut self.state;
+
+        let previous = state.level.replace(event.level);
+        if previous == Some(event.level) {
+            // We've seen the same level twice in a row, so there must have
+            // been an edge in between that we missed. There's no telling where
+            // we are in the frame, so wait until the line is idle again.
+            state.missed_edges = state.missed_edges.wrapping_add(1);
+            state.frame = None;
+            return;
+        }
+
+        let frame_ticks = params.frame_bits * params.bit_ticks;
+
+        let elapsed = state.frame
+            .and_then(|elapsed| {
+                event.period.map(|period| elapsed.saturating_add(period))
+            });
+
+        match (event.level, elapsed) {
+            (Level::High, Some(elapsed)) => {
+                state.record_edge(elapsed, &params);
+                state.frame = Some(elapsed);
+            }
+            (Level::High, None) => {
+                // We're waiting for the line to become idle.
+            }
+            (Level::Low, Some(elapsed)) => {
+                // The last falling edge within a frame occurs one bit time
+                // before its end, at the latest. Anything later must be the
+                // start bit of the next frame.
+                let within_frame =
+                    elapsed.saturating_add(params.bit_ticks / 2) < frame_ticks;
+
+                if within_frame {
+                    state.record_edge(elapsed, &params);
+                    state.frame = Some(elapsed);
+                }
+                else {
+                    state.record_interval(elapsed, frame_ticks);
+                    state.start_frame();
+                }
+            }
+            (Level::Low, None) => {
+                // Since this is a falling edge, the period is the time the line
+                // has been high. If we don't know it, we're either just
+                // starting, or it was too long to measure.
+                let idle = match event.period {
+                    Some(period) => period >= frame_ticks,
+                    None         => true,
+                };
+
+                if idle {
+                    state.start_frame();
+                }
+            }
+        }
+    }
+
+    /// Returns the results of the current measurement
+    pub fn timing<Id>(&self, usart: Id) -> Timing<Id> {
+        let state = &self.state;
+
+        let mut timing = Timing {
+            usart,
+            frames:        state.frames,
+            bit_time_ns:   None,
+            deviation_ppm: None,
+            min_gap_ns:    None,
+            max_gap_ns:    None,
+            idle_periods:  state.idle_periods,
+            missed_edges:  state.missed_edges,
+        };
+
+        let params = match self.params {
+            Some(params) => params,
+            None         => return timing,
+        };
+        if state.bits == 0 {
+            return timing;
+        }
+
+        let tick_hz = self.tick_hz as u128;
+        let ticks   = state.ticks as u128;
+        let bits    = state.bits as u128;
+
+        let bit_time_ns = ticks * 1_000_000_000 / (bits * tick_hz);
+        let relative_ppm =
+            ticks * params.baud as u128 * 1_000_000 / (bits * tick_hz);
+
+        timing.bit_time_ns   = Some(bit_time_ns as u32);
+        timing.deviation_ppm = Some(relative_ppm as i32 - 1_000_000);
+
+        let frame_ticks = ticks * params.frame_bits as u128 / bits;
+        let gap_ns = |interval: u32| {
+            let gap_ticks = (interval as u128).saturating_sub(frame_ticks);
+            (gap_ticks * 1_000_000_000 / tick_hz) as u32
+        };
+
+        timing.min_gap_ns = state.min_interval.map(gap_ns);
+        timing.max_gap_ns = state.max_interval.map(gap_ns);
+
+        timing
+    }
+}
+
+
+#[derive(Clone, Copy)]
+struct Params {
+    baud:       u32,
+    bit_ticks:  u32,
+    frame_bits: u32,
+}
+
+
+struct State {
+    /// The level of the line after the last edge
+    level: Option<Level>,
+
+    /// Ticks since the start of the current frame
+    ///
+    /// `None`, if we don't know where the current frame started.
+    frame: Option<u32>,
+
+    frames:       u32,
+    ticks:        u64,
+    bits:         u64,
+    min_interval: Option<u32>,
+    max_interval: Option<u32>,
+    idle_periods: u32,
+    missed_edges: u32,
+}
+
+impl State {
+    const fn new() -> Self {
+        Self {
+            level:        None,
+            frame:        None,
+            frames:       0,
+            ticks:        0,
+            bits:         0,
+            min_interval: None,
+            max_interval: None,
+            idle_periods: 0,
+            missed_edges: 0,
+        }
+    }
+
+    fn start_frame(&mut self) {
+        self.frame  = Some(0);
+        self.frames = self.frames.wrapping_add(1);
+    }
+
+    fn record_edge(&mut self, elapsed: u32, params: &Params) {
+        let bits =
+            elapsed.saturating_add(params.bit_ticks / 2) / params.bit_ticks;
+
+        // Edges outside of the frame, for example at the end of a break, don't
+        // tell us anything about the bit time.
+        if bits == 0 || bits >= params.frame_bits {
+            return;
+        }
+
+        self.ticks += elapsed as u64;
+        self.bits  += bits as u64;
+    }
+
+    fn record_interval(&mut self, interval: u32, frame_ticks: u32) {
+        self.min_interval = Some(
+            self.min_interval.map_or(interval, |min| min.min(interval))
+        );
+        self.max_interval = Some(
+            self.max_interval.map_or(interval, |max| max.max(interval))
+        );
+
+        if interval >= frame_ticks * 2 {
+            self.idle_periods = self.idle_periods.wrapping_add(1);
+        }
+    }
+}
@@ -1,37 +1,42 @@
 //! Sending part of the interrupt-enabled USART API
 
 
-use lpc8xx_hal::{
-    prelude::*,
-    usart::{
-        self,
-        state::{
-            Enabled,
-            NoThrottle,
-        },
+use core::{
+    marker::PhantomData,
+    sync::atomic::{
+        AtomicUsize,
+        Ordering,
     },
 };
-use serde::Serialize;
-use void::{
-    ResultVoidExt,
-    Void,
+
+use embedded_hal::{
+    blocking::serial::Write,
+    serial,
 };
+use heapless::{
+    ArrayLength,
+    consts::U256,
+    spsc,
+};
+use serde::Serialize;
+
+use protocol::rpc;
 
 
 /// Wraps a USART transmitter
 ///
 /// Provides some convenience methods on top of the wrapped transmitter.
-pub struct Tx<I, Mode> {
-    pub usart: usart::Tx<I, Enabled<u8, Mode>, NoThrottle>,
+pub struct Tx<W> {
+    pub usart: W,
 }
 
-impl<I, Mode> Tx<I, Mode>
-    where I: usart::Instance
+impl<W> Tx<W>
+    where W: Write<u8>
 {
     /// Sends raw data through the wrapped USART instance
     ///
     /// Blocks until the data has been sent.
-    pub fn send_raw(&mut self, data: &[u8]) -> Result<(), Void> {
+    pub fn send_raw(&mut self, data: &[u8]) -> Result<(), W::Error> {
         self.usart.bwrite_all(data)
     }
 
@@ -41,16 +46,311 @@ impl<I, Mode> Tx<I, Mode>
     /// serialized message, and must be large enough for that purpose. Any
     /// previous contents of the buffer will be ignored.
     pub fn send_message<T>(&mut self, message: &T, buf: &mut [u8])
-        -> Result<(), Error>
+        -> Result<(), Error<W::Error>>
         where T: Serialize
     {
-        let data = postcard::to_slice_cobs(message, buf)?;
+        let data = postcard::to_slice_cobs(message, buf)
+            .map_err(Error::Postcard)?;
         self.usart.bwrite_all(data)
-            .void_unwrap();
+            .map_err(Error::Usart)?;
         Ok(())
     }
+
+    /// Sends a message to the host that isn't a reply to any request
+    ///
+    /// Wraps the message into an [`rpc::Response`], as the host expects, then
+    /// sends it like [`send_message`].
+    ///
+    /// [`rpc::Response`]: ../../../protocol/rpc/struct.Response.html
+    /// [`send_message`]: #method.send_message
+    pub fn send_notification<T>(&mut self, message: T, buf: &mut [u8])
+        -> Result<(), Error<W::Error>>
+        where T: Serialize
+    {
+        self.send_message(&rpc::Response::notification(message), buf)
+    }
 }
 
+impl<I, N> Tx<Queued<'_, I, N>>
+    where
+        I: TxInterrupt,
+        N: ArrayLength<u8>,
+{
+    /// Queues raw data for sending, if there's enough room
+    ///
+    /// Never blocks. Returns [`QueueFull`], if the data doesn't fit into the
+    /// queue right now. Nothing is queued in that case.
+    ///
+    /// [`QueueFull`]: struct.QueueFull.html
+    pub fn try_send_raw(&mut self, data: &[u8]) -> Result<(), QueueFull> {
+        self.usart.try_write_all(data)
+    }
 
-/// Error occurred while serializing message
-pub type Error = postcard::Error;
+    /// Queues a message for sending, if there's enough room
+    ///
+    /// Like [`send_message`], but never blocks. See [`try_send_raw`].
+    ///
+    /// [`send_message`]: #method.send_message
+    /// [`try_send_raw`]: #method.try_send_raw
+    pub fn try_send_message<T>(&mut self, message: &T, buf: &mut [u8])
+        -> Result<(), Error<QueueFull>>
+        where T: Serialize
+    {
+        let data = postcard::to_slice_cobs(message, buf)
+            .map_err(Error::Postcard)?;
+        self.usart.try_write_all(data)
+            .map_err(Error::Usart)?;
+        Ok(())
+    }
+
+    /// Queues a notification for sending, if there's enough room
+    ///
+    /// Like [`send_notification`], but never blocks. See [`try_send_raw`].
+    ///
+    /// [`send_notification`]: #method.send_notification
+    /// [`try_send_raw`]: #method.try_send_raw
+    pub fn try_send_notification<T>(&mut self, message: T, buf: &mut [u8])
+        -> Result<(), Error<QueueFull>>
+        where T: Serialize
+    {
+        self.try_send_message(&rpc::Response::notification(message), buf)
+    }
+}
+
+
+/// Queue for sending data from an interrupt handler
+///
+/// Can be allocated in a `static`, like [`Usart`]. Once initialized, it turns a
+/// [`Tx`] that blocks until the data has been sent into one that adds the data
+/// to a queue. The data is then sent by [`TxInt`], from the interrupt handler.
+///
+/// `N` is the capacity of the queue.
+///
+/// [`Usart`]: ../struct.Usart.html
+/// [`Tx`]: struct.Tx.html
+/// [`TxInt`]: struct.TxInt.html
+pub struct TxQueue<N = U256>
+    where N: ArrayLength<u8>
+{
+    queue: spsc::Queue<u8, N>,
+    sent:  AtomicUsize,
+}
+
+impl<N> TxQueue<N>
+    where N: ArrayLength<u8>
+{
+    /// Create a new instance of `TxQueue`
+    pub const fn new() -> Self {
+        Self {
+            queue: spsc::Queue(heapless::i::Queue::new()),
+            sent:  AtomicUsize::new(0),
+        }
+    }
+
+    /// Initialize the queue
+    ///
+    /// The USART transmitter is moved into the returned [`TxInt`], which
+    /// should be moved into the interrupt handler. `I` controls the interrupt
+    /// that fires, whenever the transmitter is ready to send.
+    ///
+    /// [`TxInt`]: struct.TxInt.html
+    pub fn init<W, I>(&mut self, tx: Tx<W>)
+        -> (TxInt<'_, W, I, N>, Tx<Queued<'_, I, N>>)
+        where I: TxInterrupt
+    {
+        let capacity = self.queue.capacity();
+        let (prod, cons) = self.queue.split();
+
+        let tx_int = TxInt {
+            usart:      tx.usart,
+            queue:      cons,
+            sent:       &self.sent,
+            _interrupt: PhantomData,
+        };
+        let tx = Tx {
+            usart: Queued {
+                queue:      prod,
+                capacity,
+                queued:     0,
+                sent:       &self.sent,
+                _interrupt: PhantomData,
+            },
+        };
+
+        (tx_int, tx)
+    }
+}
+
+impl<N> Default for TxQueue<N>
+    where N: ArrayLength<u8>
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+
+/// API for sending queued data from an interrupt handler
+///
+/// You can get an instance of this struct by calling [`TxQueue::init`].
+///
+/// [`TxQueue::init`]: struct.TxQueue.html#method.init
+pub struct TxInt<'r, W, I, N = U256>
+    where N: ArrayLength<u8>
+{
+    pub usart: W,
+
+    queue:      spsc::Consumer<'r, u8, N>,
+    sent:       &'r AtomicUsize,
+    _interrupt: PhantomData<I>,
+}
+
+impl<W, I, N> TxInt<'_, W, I, N>
+    where
+        W: serial::Write<u8>,
+        I: TxInterrupt,
+        N: ArrayLength<u8>,
+{
+    /// Send queued data
+    ///
+    /// This method should be called from the interrupt handler. Sends as much
+    /// of the queued data as the USART accepts without blocking. Disables the
+    /// interrupt once the queue is empty.
+    ///
+    /// The queued [`Tx`] must not be used in a context that can preempt the
+    /// interrupt handler.
+    ///
+    /// [`Tx`]: struct.Tx.html
+    pub fn transmit(&mut self) -> Result<(), W::Error> {
+        while let Some(&b) = self.queue.peek() {
+            match self.usart.write(b) {
+                Ok(()) => {
+                    self.queue.dequeue();
+
+                    // We're the only one writing `sent`, so we don't need an
+                    // atomic read-modify-write operation.
+                    let sent = self.sent.load(Ordering::Relaxed);
+                    self.sent.store(sent.wrapping_add(1), Ordering::Release);
+                }
+                Err(nb::Error::WouldBlock) => {
+                    return Ok(());
+                }
+                Err(nb::Error::Other(err)) => {
+                    return Err(err);
+                }
+            }
+        }
+
+        I::disable();
+        Ok(())
+    }
+}
+
+
+/// A USART transmitter that adds data to a queue
+///
+/// Used as the wrapped transmitter of [`Tx`], once [`TxQueue::init`] has been
+/// called. Writes through `embedded-hal`'s blocking trait wait until there's
+/// enough room in the queue. See [`Tx::try_send_raw`] for a non-blocking
+/// alternative.
+///
+/// [`Tx`]: struct.Tx.html
+/// [`TxQueue::init`]: struct.TxQueue.html#method.init
+/// [`Tx::try_send_raw`]: struct.Tx.html#method.try_send_raw
+pub struct Queued<'r, I, N = U256>
+    where N: ArrayLength<u8>
+{
+    queue:      spsc::Producer<'r, u8, N>,
+    capacity:   usize,
+    queued:     usize,
+    sent:       &'r AtomicUsize,
+    _interrupt: PhantomData<I>,
+}
+
+impl<I, N> Queued<'_, I, N>
+    where
+        I: TxInterrupt,
+        N: ArrayLength<u8>,
+{
+    /// The number of bytes that can currently be added to the queue
+    pub fn free(&self) -> usize {
+        let sent = self.sent.load(Ordering::Acquire);
+        self.capacity - self.queued.wrapping_sub(sent)
+    }
+
+    fn try_write_all(&mut self, data: &[u8]) -> Result<(), QueueFull> {
+        // Only add complete frames to the queue. Otherwise, a partial frame
+        // would corrupt the next one.
+        if data.len() > self.free() {
+            return Err(QueueFull);
+        }
+
+        for &b in data {
+            self.queue.enqueue(b)
+                .map_err(|_| QueueFull)?;
+        }
+        self.queued = self.queued.wrapping_add(data.len());
+
+        I::enable();
+        Ok(())
+    }
+}
+
+impl<I, N> Write<u8> for Queued<'_, I, N>
+    where
+        I: TxInterrupt,
+        N: ArrayLength<u8>,
+{
+    type Error = QueueFull;
+
+    /// Waits until there's enough room, then adds the data to the queue
+    ///
+    /// Relies on the interrupt handler to make room. Returns [`QueueFull`]
+    /// right away, if the data is larger than the whole queue.
+    ///
+    /// [`QueueFull`]: struct.QueueFull.html
+    fn bwrite_all(&mut self, data: &[u8]) -> Result<(), Self::Error> {
+        if data.len() > self.capacity {
+            return Err(QueueFull);
+        }
+
+        while self.try_write_all(data).is_err() {}
+        Ok(())
+    }
+
+    /// Waits until all queued data has been sent
+    fn bflush(&mut self) -> Result<(), Self::Error> {
+        while self.free() < self.capacity {}
+        Ok(())
+    }
+}
+
+
+/// Controls the interrupt that fires, when a USART is ready to send
+///
+/// Called from the interrupt handler, as well as the context that queues the
+/// data. Implementations must not interfere with any other code that accesses
+/// the USART.
+pub trait TxInterrupt {
+    /// Enable the interrupt
+    fn enable();
+
+    /// Disable the interrupt
+    fn disable();
+}
+
+
+/// There's not enough room in the queue for the data
+#[derive(Debug)]
+pub struct QueueFull;
+
+
+/// Error sending a message
+#[derive(Debug)]
+pub enum Error<E> {
+    /// The message could not be serialized
+    Postcard(postcard::Error),
+
+    /// An error was returned by the wrapped USART transmitter
+    Usart(E),
+}
@@ -0,0 +1,42 @@
+use firmware_lib::analog::Scale;
+
+
+#[test]
+fn it_should_convert_voltages_to_the_closest_value() {
+    let scale = Scale::new(10, 3300);
+
+    assert_eq!(scale.value(0), 0);
+    assert_eq!(scale.value(1650), 512);
+    assert_eq!(scale.value(3300), 1023);
+
+    // One step is about 3.2 mV.
+    assert_eq!(scale.value(1), 0);
+    assert_eq!(scale.value(2), 1);
+}
+
+#[test]
+fn it_should_clamp_voltages_above_the_reference() {
+    let scale = Scale::new(10, 3300);
+
+    assert_eq!(scale.value(5000), 1023);
+    assert_eq!(scale.millivolts(2000), 3300);
+}
+
+#[test]
+fn it_should_convert_values_back_to_voltages() {
+    let scale = Scale::new(12, 3300);
+
+    for &millivolts in &[0, 1, 500, 1650, 2999, 3300] {
+        let value  = scale.value(millivolts);
+        let result = scale.millivolts(value);
+
+        // Half a step of a 12-bit converter is about 0.4 mV.
+        assert!(
+            (result as i32 - millivolts as i32).abs() <= 1,
+            "{} mV -> {} -> {} mV",
+            millivolts,
+            value,
+            result,
+        );
+    }
+}
@@ -0,0 +1,100 @@
+mod mock;
+
+
+use std::convert::TryFrom;
+
+use firmware_lib::{
+    dispatch::Dispatcher,
+    usart::Tx,
+};
+use protocol::rpc;
+
+
+#[derive(Debug)]
+enum Request {
+    Ping,
+    Add(u8, u8),
+}
+
+struct Ping;
+
+impl TryFrom<Request> for Ping {
+    type Error = Request;
+
+    fn try_from(value: Request) -> Result<Self, Self::Error> {
+        match value {
+            Request::Ping => Ok(Ping),
+            _             => Err(value),
+        }
+    }
+}
+
+struct Add(u8, u8);
+
+impl TryFrom<Request> for Add {
+    type Error = Request;
+
+    fn try_from(value: Request) -> Result<Self, Self::Error> {
+        match value {
+            Request::Add(a, b) => Ok(Add(a, b)),
+            _                  => Err(value),
+        }
+    }
+}
+
+
+#[test]
+fn it_should_reply_with_the_request_id() {
+    let mut tx = Tx { usart: mock::Tx(Vec::new()) };
+    let mut buf = [0; 32];
+    let mut pinged = false;
+
+    let request = rpc::Request { id: 3, message: Request::Add(1, 2) };
+    Dispatcher::new(request, &mut tx, &mut buf)
+        .on(|Ping| {
+            pinged = true;
+            None
+        })
+        .on(|Add(a, b)| Some(a + b))
+        .finish()
+        .unwrap();
+
+    let response: rpc::Response<u8> =
+        postcard::from_bytes_cobs(&mut tx.usart.0).unwrap();
+
+    assert!(!pinged);
+    assert_eq!(response.kind, rpc::Kind::Reply(3));
+    assert_eq!(response.message, Ok(3));
+}
+
+#[test]
+fn it_should_not_send_anything_if_the_handler_returns_no_reply() {
+    let mut tx = Tx { usart: mock::Tx(Vec::new()) };
+    let mut buf = [0; 32];
+
+    let request = rpc::Request { id: 1, message: Request::Ping };
+    Dispatcher::<_, _, u8>::new(request, &mut tx, &mut buf)
+        .on(|Ping| None)
+        .finish()
+        .unwrap();
+
+    assert!(tx.usart.0.is_empty());
+}
+
+#[test]
+fn it_should_reject_unsupported_requests() {
+    let mut tx = Tx { usart: mock::Tx(Vec::new()) };
+    let mut buf = [0; 32];
+
+    let request = rpc::Request { id: 5, message: Request::Add(1, 2) };
+    Dispatcher::new(request, &mut tx, &mut buf)
+        .on(|Ping| Some(0u8))
+        .finish()
+        .unwrap();
+
+    let response: rpc::Response<u8> =
+        postcard::from_bytes_cobs(&mut tx.usart.0).unwrap();
+
+    assert_eq!(response.kind, rpc::Kind::Reply(5));
+    assert_eq!(response.message, Err(rpc::Error::Unsupported));
+}
@@ -0,0 +1,95 @@
+use firmware_lib::i2c::Device;
+use protocol::i2c;
+
+
+fn device(fault: Option<i2c::Fault>) -> Device {
+    Device::new(&i2c::Device {
+        address:        0x50,
+        registers:      &[0x10, 0x20, 0x30, 0x40],
+        read_only:      &[1],
+        auto_increment: true,
+        fault,
+    })
+    .unwrap()
+}
+
+
+#[test]
+fn it_should_read_from_the_selected_register() {
+    let mut device = device(None);
+
+    device.start();
+    assert!(device.write(2));
+
+    device.start();
+    assert_eq!(device.read(), 0x30);
+    assert_eq!(device.read(), 0x40);
+    assert_eq!(device.read(), 0xff);
+}
+
+#[test]
+fn it_should_write_to_writable_registers_only() {
+    let mut device = device(None);
+
+    device.start();
+    assert!(device.write(0));
+    assert!(device.write(0xaa));
+    assert!(device.write(0xbb));
+    assert!(device.write(0xcc));
+
+    assert_eq!(device.registers(), [0xaa, 0x20, 0xcc, 0x40]);
+}
+
+#[test]
+fn it_should_stay_on_the_selected_register_without_auto_increment() {
+    let mut device = Device::new(&i2c::Device {
+        address:        0x50,
+        registers:      &[0x10, 0x20],
+        read_only:      &[],
+        auto_increment: false,
+        fault:          None,
+    })
+    .unwrap();
+
+    device.start();
+    assert!(device.write(1));
+    assert!(device.write(0xaa));
+    assert!(device.write(0xbb));
+
+    device.start();
+    assert_eq!(device.read(), 0xbb);
+    assert_eq!(device.read(), 0xbb);
+
+    assert_eq!(device.registers(), [0x10, 0xbb]);
+}
+
+#[test]
+fn it_should_not_acknowledge_the_configured_byte() {
+    let mut device = device(Some(i2c::Fault::NackByte(1)));
+
+    device.start();
+    assert!(device.write(0));
+    assert!(!device.write(0xaa));
+
+    // The count starts over with every transaction.
+    device.start();
+    assert!(device.write(0));
+    assert!(!device.write(0xaa));
+
+    assert_eq!(device.registers(), [0x10, 0x20, 0x30, 0x40]);
+}
+
+#[test]
+fn it_should_reject_too_many_registers() {
+    let registers = [0; 129];
+
+    let result = Device::new(&i2c::Device {
+        address:        0x50,
+        registers:      &registers,
+        read_only:      &[],
+        auto_increment: true,
+        fault:          None,
+    });
+
+    assert!(matches!(result, Err(i2c::Error::TooLong)));
+}
@@ -0,0 +1,82 @@
+//! Mock implementations of the `embedded-hal` serial traits
+
+// Not every test uses every mock.
+#![allow(dead_code)]
+
+
+use std::{
+    cell::Cell,
+    collections::VecDeque,
+};
+
+use embedded_hal::{
+    blocking,
+    serial,
+};
+
+use firmware_lib::usart::tx;
+
+
+/// Mock USART receiver that returns preconfigured results
+pub struct Rx(pub VecDeque<nb::Result<u8, ()>>);
+
+impl Rx {
+    pub fn new(data: &[u8]) -> Self {
+        Self(data.iter().map(|&b| Ok(b)).collect())
+    }
+}
+
+impl serial::Read<u8> for Rx {
+    type Error = ();
+
+    fn read(&mut self) -> nb::Result<u8, Self::Error> {
+        self.0.pop_front()
+            .unwrap_or(Err(nb::Error::WouldBlock))
+    }
+}
+
+
+/// Mock USART transmitter that records everything that's written to it
+pub struct Tx(pub Vec<u8>);
+
+impl serial::Write<u8> for Tx {
+    type Error = ();
+
+    fn write(&mut self, word: u8) -> nb::Result<(), Self::Error> {
+        self.0.push(word);
+        Ok(())
+    }
+
+    fn flush(&mut self) -> nb::Result<(), Self::Error> {
+        Ok(())
+    }
+}
+
+impl blocking::serial::write::Default<u8> for Tx {}
+
+
+thread_local! {
+    static TX_INTERRUPT_ENABLED: Cell<bool> = const { Cell::new(false) };
+}
+
+/// Mock transmit interrupt
+///
+/// The state is tracked per thread, so tests running in parallel don't
+/// interfere with each other.
+pub struct TxInterrupt;
+
+impl TxInterrupt {
+    pub fn is_enabled() -> bool {
+        TX_INTERRUPT_ENABLED.with(|enabled| enabled.get())
+    }
+}
+
+impl tx::TxInterrupt for TxInterrupt {
+    fn enable() {
+        TX_INTERRUPT_ENABLED.with(|enabled| enabled.set(true));
+    }
+
+    fn disable() {
+        TX_INTERRUPT_ENABLED.with(|enabled| enabled.set(false));
+    }
+}
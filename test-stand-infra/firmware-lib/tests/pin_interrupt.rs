@@ -0,0 +1,91 @@
+use std::{
+    cell::Cell,
+    rc::Rc,
+};
+
+use heapless::consts::U2;
+
+use firmware_lib::pin_interrupt::{
+    Edges,
+    PinInterrupt,
+    Stopwatch,
+};
+use protocol::pin::Level;
+
+
+#[derive(Clone, Default)]
+struct MockEdges {
+    rising:  Rc<Cell<bool>>,
+    falling: Rc<Cell<bool>>,
+}
+
+impl Edges for MockEdges {
+    fn clear_rising_edge_flag(&mut self) -> bool {
+        self.rising.replace(false)
+    }
+
+    fn clear_falling_edge_flag(&mut self) -> bool {
+        self.falling.replace(false)
+    }
+}
+
+#[derive(Clone, Default)]
+struct MockStopwatch(Rc<Cell<Option<u32>>>);
+
+impl Stopwatch for MockStopwatch {
+    fn restart(&mut self) {
+        self.0.set(Some(0));
+    }
+
+    fn elapsed(&mut self) -> Option<u32> {
+        self.0.get()
+    }
+}
+
+
+#[test]
+fn it_should_report_edges_and_periods() {
+    let edges     = MockEdges::default();
+    let stopwatch = MockStopwatch::default();
+
+    let mut pin_interrupt: PinInterrupt = PinInterrupt::new();
+    let (mut int, mut idle) =
+        pin_interrupt.init(edges.clone(), stopwatch.clone());
+
+    edges.rising.set(true);
+    int.handle_interrupt();
+
+    let event = idle.next().unwrap();
+    assert_eq!(event.level, Level::High);
+    assert_eq!(event.period, None);
+
+    edges.falling.set(true);
+    stopwatch.0.set(Some(12_000));
+    int.handle_interrupt();
+
+    let event = idle.next().unwrap();
+    assert_eq!(event.level, Level::Low);
+    assert_eq!(event.period, Some(12_000));
+
+    assert!(!idle.is_ready());
+}
+
+#[test]
+fn it_should_count_overflows_instead_of_panicking() {
+    let edges     = MockEdges::default();
+    let stopwatch = MockStopwatch::default();
+
+    let mut pin_interrupt = PinInterrupt::<U2>::new();
+    let (mut int, mut idle) =
+        pin_interrupt.init(edges.clone(), stopwatch.clone());
+
+    for _ in 0 .. 3 {
+        edges.rising.set(true);
+        int.handle_interrupt();
+    }
+
+    assert_eq!(int.overflows(), 1);
+    assert!(idle.next().is_some());
+    assert!(idle.next().is_some());
+    assert!(idle.next().is_none());
+}
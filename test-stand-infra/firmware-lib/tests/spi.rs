@@ -0,0 +1,97 @@
+use firmware_lib::spi::{
+    Device,
+    Log,
+};
+use protocol::spi;
+
+
+fn registers() -> Device {
+    Device::new(&spi::Device::Registers {
+        registers: &[0x10, 0x20, 0x30, 0x40],
+        read:      0x03,
+        write:     0x02,
+    })
+    .unwrap()
+}
+
+
+#[test]
+fn it_should_send_responses_in_order() {
+    let mut device = Device::new(&spi::Device::Responses(&[0x11, 0x22]))
+        .unwrap();
+
+    assert_eq!(device.preload(), 0x11);
+
+    device.start();
+    assert_eq!(device.receive(0x00), 0x22);
+    assert_eq!(device.receive(0x00), 0xff);
+}
+
+#[test]
+fn it_should_read_registers() {
+    let mut device = registers();
+
+    device.start();
+    assert_eq!(device.receive(0x03), 0xff);
+    assert_eq!(device.receive(0x02), 0x30);
+    assert_eq!(device.receive(0x00), 0x40);
+    assert_eq!(device.receive(0x00), 0xff);
+}
+
+#[test]
+fn it_should_write_registers() {
+    let mut device = registers();
+
+    device.start();
+    device.receive(0x02);
+    device.receive(0x01);
+    device.receive(0xaa);
+    device.receive(0xbb);
+
+    device.start();
+    device.receive(0x03);
+    assert_eq!(device.receive(0x00), 0x10);
+    assert_eq!(device.receive(0x00), 0xaa);
+    assert_eq!(device.receive(0x00), 0xbb);
+    assert_eq!(device.receive(0x00), 0x40);
+}
+
+#[test]
+fn it_should_ignore_unknown_commands() {
+    let mut device = registers();
+
+    device.start();
+    device.receive(0x05);
+    assert_eq!(device.receive(0x00), 0xff);
+    assert_eq!(device.receive(0x03), 0xff);
+}
+
+#[test]
+fn it_should_log_transfers() {
+    let mut log = Log::default();
+
+    log.push(0x01);
+    log.push(0x02);
+    log.end();
+    log.push(0x03);
+    log.end();
+
+    assert_eq!(log.transfers(), [2, 0x01, 0x02, 1, 0x03]);
+    assert!(!log.overflowed());
+
+    log.clear();
+    assert_eq!(log.transfers(), []);
+}
+
+#[test]
+fn it_should_report_an_overflow() {
+    let mut log = Log::default();
+
+    for _ in 0 .. 128 {
+        log.push(0x01);
+    }
+
+    assert_eq!(log.transfers().len(), 128);
+    assert_eq!(log.transfers()[0], 127);
+    assert!(log.overflowed());
+}
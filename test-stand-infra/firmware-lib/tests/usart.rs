@@ -0,0 +1,371 @@
+mod mock;
+
+
+use heapless::consts::{
+    U8,
+    U16,
+};
+
+use firmware_lib::{
+    pin_interrupt::Event,
+    usart::{
+        TxQueue,
+        Usart,
+        rx::{
+            ProcessError,
+            ReceiveError,
+        },
+        timing::Meter,
+        tx::Error,
+    },
+};
+use protocol::{
+    pin::Level,
+    rpc,
+    usart::MeasureTiming,
+};
+
+
+#[test]
+fn it_should_pass_received_data_to_idle_context() {
+    let mut usart: Usart = Usart::new();
+    let (mut rx_int, mut rx_idle, _) =
+        usart.init(mock::Rx::new(b"data"), mock::Tx(Vec::new()));
+
+    rx_int.receive().unwrap();
+    assert!(rx_idle.can_process());
+
+    let mut received = Vec::new();
+    rx_idle
+        .process_raw(|data| {
+            received.extend_from_slice(data);
+            Ok::<_, ()>(())
+        })
+        .unwrap();
+
+    assert_eq!(received, b"data");
+    assert!(!rx_idle.can_process());
+    assert_eq!(rx_idle.high_water(), 4);
+}
+
+#[test]
+fn it_should_restart_the_high_water_mark_when_taking_it() {
+    let mut usart: Usart = Usart::new();
+    let (mut rx_int, mut rx_idle, _) =
+        usart.init(mock::Rx::new(b"data"), mock::Tx(Vec::new()));
+
+    rx_int.receive().unwrap();
+    rx_idle.process_raw(|_| Ok::<_, ()>(())).unwrap();
+
+    assert_eq!(rx_idle.take_high_water(), 4);
+    assert_eq!(rx_idle.take_high_water(), 0);
+}
+
+#[test]
+fn it_should_keep_raw_data_that_doesnt_fit_into_the_buffer() {
+    let mut usart: Usart = Usart::new();
+    let (mut rx_int, mut rx_idle, _) =
+        usart.init(mock::Rx::new(&[1; 200]), mock::Tx(Vec::new()));
+
+    // Fill the buffer, then the queue, by failing to process the data.
+    rx_int.receive().unwrap();
+    let result = rx_idle.process_raw(|_| Err(()));
+    assert!(matches!(result, Err(ProcessError::Other(()))));
+    rx_int.usart = mock::Rx::new(&[2; 200]);
+    rx_int.receive().unwrap();
+
+    let mut received = Vec::new();
+    for _ in 0 .. 2 {
+        rx_idle
+            .process_raw(|data| {
+                received.push(data.to_vec());
+                Ok::<_, ()>(())
+            })
+            .unwrap();
+    }
+
+    assert_eq!(received[0].len(), 256);
+    assert_eq!(received[0][..200], [1; 200][..]);
+    assert_eq!(received[1], [2; 144]);
+}
+
+#[test]
+fn it_should_decode_received_messages() {
+    let mut buf = [0; 32];
+    let frame = postcard::to_slice_cobs(&(1u8, 2u8), &mut buf).unwrap();
+
+    let mut usart: Usart = Usart::new();
+    let (mut rx_int, mut rx_idle, _) =
+        usart.init(mock::Rx::new(frame), mock::Tx(Vec::new()));
+
+    rx_int.receive().unwrap();
+
+    let mut received = None;
+    rx_idle
+        .process_messages(|frame| {
+            received = Some(frame.decode::<(u8, u8)>()?);
+            Ok::<_, ProcessError<()>>(())
+        })
+        .unwrap();
+
+    assert_eq!(received, Some((1, 2)));
+}
+
+#[test]
+fn it_should_process_each_message_exactly_once() {
+    let mut data = Vec::new();
+    for message in &[(1u8, 2u8), (3, 4)] {
+        let mut buf = [0; 32];
+        let frame = postcard::to_slice_cobs(message, &mut buf).unwrap();
+        data.extend_from_slice(frame);
+    }
+
+    let mut usart: Usart = Usart::new();
+    let (mut rx_int, mut rx_idle, _) =
+        usart.init(mock::Rx::new(&data), mock::Tx(Vec::new()));
+
+    rx_int.receive().unwrap();
+
+    let mut received = Vec::new();
+    for _ in 0 .. 2 {
+        rx_idle
+            .process_messages(|frame| {
+                received.push(frame.decode::<(u8, u8)>()?);
+                Ok::<_, ProcessError<()>>(())
+            })
+            .unwrap();
+    }
+
+    assert_eq!(received, [(1, 2), (3, 4)]);
+}
+
+#[test]
+fn it_should_count_receive_errors() {
+    let mut rx = mock::Rx::new(&[]);
+    rx.0.push_back(Err(nb::Error::Other(())));
+
+    let mut usart: Usart = Usart::new();
+    let (mut rx_int, _, _) = usart.init(rx, mock::Tx(Vec::new()));
+
+    assert!(matches!(rx_int.receive(), Err(ReceiveError::Usart(()))));
+    assert_eq!(rx_int.errors(), 1);
+}
+
+#[test]
+fn it_should_report_a_full_queue() {
+    // One byte more than fits into the queue.
+    let data = [1; 257];
+
+    let mut usart: Usart = Usart::new();
+    let (mut rx_int, _, _) =
+        usart.init(mock::Rx::new(&data), mock::Tx(Vec::new()));
+
+    assert!(matches!(rx_int.receive(), Err(ReceiveError::QueueFull)));
+    assert_eq!(rx_int.errors(), 1);
+}
+
+#[test]
+fn it_should_use_the_configured_queue_capacity() {
+    let mut usart = Usart::<U16>::new();
+    let (mut rx_int, _, tx) =
+        usart.init(mock::Rx::new(&[1; 17]), mock::Tx(Vec::new()));
+
+    assert!(matches!(rx_int.receive(), Err(ReceiveError::QueueFull)));
+
+    let mut queue = TxQueue::<U8>::new();
+    let (_, tx) = queue.init::<_, mock::TxInterrupt>(tx);
+    assert_eq!(tx.usart.free(), 8);
+}
+
+#[test]
+fn it_should_drop_undecodable_messages() {
+    let mut data = vec![0xff, 0];
+    let mut buf = [0; 32];
+    data.extend_from_slice(
+        postcard::to_slice_cobs(&(1u8, 2u8), &mut buf).unwrap(),
+    );
+
+    let mut usart: Usart = Usart::new();
+    let (mut rx_int, mut rx_idle, _) =
+        usart.init(mock::Rx::new(&data), mock::Tx(Vec::new()));
+
+    rx_int.receive().unwrap();
+
+    let mut received = Vec::new();
+    let mut process = || {
+        rx_idle.process_messages(|frame| {
+            received.push(frame.decode::<(u8, u8)>()?);
+            Ok::<_, ProcessError<()>>(())
+        })
+    };
+
+    assert!(matches!(process(), Err(ProcessError::Postcard(_))));
+    assert!(matches!(process(), Ok(())));
+    assert_eq!(received, [(1, 2)]);
+}
+
+#[test]
+fn it_should_drop_messages_that_dont_fit_into_the_buffer() {
+    let mut usart: Usart = Usart::new();
+    let (mut rx_int, mut rx_idle, _) =
+        usart.init(mock::Rx::new(&[]), mock::Tx(Vec::new()));
+
+    // The queue and the buffer are of the same size, so the oversized frame
+    // needs to be received in two parts.
+    rx_int.usart = mock::Rx::new(&[1; 200]);
+    rx_int.receive().unwrap();
+    rx_idle
+        .process_messages(|_| Ok::<_, ProcessError<()>>(()))
+        .unwrap();
+
+    let mut data = vec![1; 100];
+    data.push(0);
+    let mut buf = [0; 32];
+    data.extend_from_slice(
+        postcard::to_slice_cobs(&(1u8, 2u8), &mut buf).unwrap(),
+    );
+    rx_int.usart = mock::Rx::new(&data);
+    rx_int.receive().unwrap();
+
+    let mut received = Vec::new();
+    let mut process = || {
+        rx_idle.process_messages(|frame| {
+            received.push(frame.decode::<(u8, u8)>()?);
+            Ok::<_, ProcessError<()>>(())
+        })
+    };
+
+    assert!(matches!(process(), Err(ProcessError::BufferFull)));
+    assert!(matches!(process(), Ok(())));
+    assert_eq!(received, [(1, 2)]);
+}
+
+#[test]
+fn it_should_wrap_notifications() {
+    let mut usart: Usart = Usart::new();
+    let (_, _, mut tx) =
+        usart.init(mock::Rx::new(&[]), mock::Tx(Vec::new()));
+
+    let mut buf = [0; 32];
+    tx.send_notification(7u8, &mut buf).unwrap();
+
+    let response: rpc::Response<u8> =
+        postcard::from_bytes_cobs(&mut tx.usart.0).unwrap();
+    assert_eq!(response, rpc::Response::notification(7));
+}
+
+
+#[test]
+fn it_should_send_queued_data_from_interrupt_handler() {
+    let mut usart: Usart = Usart::new();
+    let (_, _, tx) = usart.init(mock::Rx::new(&[]), mock::Tx(Vec::new()));
+
+    let mut queue: TxQueue = TxQueue::new();
+    let (mut tx_int, mut tx) = queue.init::<_, mock::TxInterrupt>(tx);
+
+    tx.send_raw(b"data").unwrap();
+    assert!(mock::TxInterrupt::is_enabled());
+    assert_eq!(tx_int.usart.0, b"");
+
+    tx_int.transmit().unwrap();
+    assert!(!mock::TxInterrupt::is_enabled());
+    assert_eq!(tx_int.usart.0, b"data");
+    assert_eq!(tx.usart.free(), 256);
+}
+
+#[test]
+fn it_should_report_back_pressure_without_queueing_partial_frames() {
+    let mut usart: Usart = Usart::new();
+    let (_, _, tx) = usart.init(mock::Rx::new(&[]), mock::Tx(Vec::new()));
+
+    let mut queue: TxQueue = TxQueue::new();
+    let (mut tx_int, mut tx) = queue.init::<_, mock::TxInterrupt>(tx);
+
+    tx.try_send_raw(&[1; 250]).unwrap();
+
+    let mut buf = [0; 32];
+    let result = tx.try_send_notification([2u8; 8], &mut buf);
+    assert!(matches!(result, Err(Error::Usart(_))));
+
+    tx_int.transmit().unwrap();
+    assert_eq!(tx_int.usart.0, [1; 250]);
+
+    tx.try_send_notification([2u8; 8], &mut buf).unwrap();
+    tx_int.transmit().unwrap();
+
+    let response: rpc::Response<[u8; 8]> =
+        postcard::from_bytes_cobs(&mut tx_int.usart.0[250..]).unwrap();
+    assert_eq!(response, rpc::Response::notification([2; 8]));
+}
+
+#[test]
+fn it_should_measure_bit_time_and_gaps() {
+    // 3% slower than nominal, which is 104.17 ticks at 12 MHz.
+    let events = line(107, &[(0x00, 0), (0x55, 500), (0xf0, 0)]);
+
+    let mut meter = Meter::new(12_000_000);
+    meter.start(&MeasureTiming::new((), 115_200));
+    for event in events {
+        meter.process(event);
+    }
+
+    let timing = meter.timing(());
+    assert_eq!(timing.frames, 3);
+    assert_eq!(timing.bit_time_ns, Some(8916));
+    assert_eq!(timing.deviation_ppm, Some(27_200));
+    assert_eq!(timing.min_gap_ns, Some(0));
+    assert_eq!(timing.max_gap_ns, Some(41_666));
+    assert_eq!(timing.idle_periods, 0);
+    assert_eq!(timing.missed_edges, 0);
+}
+
+#[test]
+fn it_should_resynchronize_after_missing_an_edge() {
+    let mut events = line(104, &[(0x55, 5000), (0x0f, 5000), (0x01, 0)]);
+
+    // Drop an edge in the middle of the first frame
+    events.remove(3);
+
+    let mut meter = Meter::new(12_000_000);
+    meter.start(&MeasureTiming::new((), 115_200));
+    for event in events {
+        meter.process(event);
+    }
+
+    let timing = meter.timing(());
+    assert_eq!(timing.frames, 3);
+    assert_eq!(timing.bit_time_ns, Some(8666));
+    assert_eq!(timing.missed_edges, 1);
+
+    // The gap after the first frame is lost with the missed edge.
+    assert_eq!(timing.idle_periods, 1);
+    assert_eq!(timing.min_gap_ns, Some(416_666));
+}
+
+/// Converts 8N1 frames into the edge events on the line
+///
+/// Each frame is followed by a gap of the given number of ticks.
+fn line(bit_ticks: u32, frames: &[(u8, u32)]) -> Vec<Event> {
+    let mut events = Vec::new();
+    let mut level  = Level::High;
+    let mut period = None;
+
+    for &(data, gap) in frames {
+        let bits = (0 .. 8).map(|i| data >> i & 0x1 == 0x1);
+        let bits = Some(false).into_iter().chain(bits).chain(Some(true));
+
+        for bit in bits {
+            let bit_level = if bit { Level::High } else { Level::Low };
+            if bit_level != level {
+                level = bit_level;
+                events.push(Event { level, period });
+                period = Some(0);
+            }
+            period = period.map(|period| period + bit_ticks);
+        }
+
+        period = period.map(|period| period + gap);
+    }
+
+    events
+}
@@ -0,0 +1,80 @@
+//! API for remotely controlling analog outputs on a test node
+
+
+use std::{
+    fmt::Debug,
+};
+
+use serde::Serialize;
+
+use protocol::analog;
+
+use crate::conn::{
+    Conn,
+    ConnReceiveError,
+    ConnSendError,
+    Message,
+};
+
+
+/// API for remotely controlling an analog output on a test node
+///
+/// This struct is intended as a building block for higher-level interfaces
+/// that control the test nodes of a specific test stand.
+pub struct Output<Id> {
+    channel: Id,
+}
+
+impl<Id> Output<Id>
+    where Id: Copy
+{
+    /// Create a new instance of `Output`
+    pub fn new(channel: Id) -> Self {
+        Self {
+            channel,
+        }
+    }
+
+    /// Commands the node to output a voltage
+    ///
+    /// Waits for the node to confirm, then returns the voltage that is actually
+    /// being output, in millivolts. This can deviate from the requested one,
+    /// due to the resolution and range of the output.
+    pub fn set<'de, Request, Reply>(&mut self,
+        millivolts: u16,
+        conn:       &mut Conn,
+        buf:        &'de mut Vec<u8>,
+    )
+        -> Result<u16, SetError>
+        where
+            Id: Debug + Eq,
+            Request: From<analog::SetOutput<Id>> + Serialize,
+            Reply: Message<'de, analog::OutputSet<Id>>,
+    {
+        let request = analog::SetOutput { channel: self.channel, millivolts };
+        let request: Request = request.into();
+        let id = conn.send(&request)
+            .map_err(SetError::Send)?;
+
+        let timeout = conn.timeouts().reply();
+        conn
+            .receive_as::<Reply, _, _>(Some(id), timeout, buf,
+                |output: analog::OutputSet<Id>| {
+                    if output.channel == self.channel {
+                        Ok(output.millivolts)
+                    }
+                    else {
+                        Err(output)
+                    }
+                }
+            )
+            .map_err(SetError::Receive)
+    }
+}
+
+
+#[derive(Debug)]
+pub enum SetError {
+    Send(ConnSendError),
+    Receive(ConnReceiveError),
+}
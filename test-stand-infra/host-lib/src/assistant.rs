@@ -1,27 +1,62 @@
-use std::time::{
-    Duration,
-    Instant,
+use std::{
+    thread::sleep,
+    time::{
+        Duration,
+        Instant,
+    },
 };
 
 use protocol::{
+    AnalogOutput,
     AssistantToHost,
+    Heartbeat,
     HostToAssistant,
     InputPin,
+    Node,
     OutputPin,
     UsartMode,
+    i2c,
     pin,
+    spi,
+    usart::{
+        Configure as UsartConfigure,
+        Fault as UsartFault,
+        MeasureTiming as UsartMeasureTiming,
+        Timing as UsartTiming,
+    },
 };
 
 use crate::{
+    analog,
+    config::Timeouts,
     conn::{
         Conn,
+        ConnClearError,
         ConnReceiveError,
         ConnSendError,
     },
+    i2c::{
+        EmulateError,
+        I2c,
+        ReadRegistersError,
+        TransactionError,
+    },
     pin::{
         Pin,
         ReadLevelError,
     },
+    spi::{
+        ConfigureError as SpiConfigureError,
+        EmulateError as SpiEmulateError,
+        Log as SpiLog,
+        ReadLogError,
+        Spi,
+        TransferError,
+    },
+    usart::{
+        self,
+        Usart,
+    },
 };
 
 
@@ -35,6 +70,14 @@ pub struct Assistant {
     pwm: Pin<InputPin>,
     cts: Pin<OutputPin>,
     rts: Pin<InputPin>,
+    target_reset: Pin<OutputPin>,
+    analog: analog::Output<AnalogOutput>,
+    i2c: I2c<()>,
+    spi: Spi<()>,
+    usart: Usart<UsartMode>,
+    usart_dma: Usart<UsartMode>,
+    usart_sync: Usart<UsartMode>,
+    usart_flow_control: Usart<UsartMode>,
 }
 
 impl Assistant {
@@ -48,9 +91,144 @@ impl Assistant {
             pwm: Pin::new(InputPin::Pwm),
             cts: Pin::new(OutputPin::Cts),
             rts: Pin::new(InputPin::Rts),
+            target_reset: Pin::new(OutputPin::TargetReset),
+            analog: analog::Output::new(AnalogOutput::Adc),
+            i2c: I2c::new(()),
+            spi: Spi::new(()),
+            usart: Usart::new(UsartMode::Regular),
+            usart_dma: Usart::new(UsartMode::Dma),
+            usart_sync: Usart::new(UsartMode::Sync),
+            usart_flow_control: Usart::new(UsartMode::FlowControl),
+        }
+    }
+
+    /// The default timeouts for communicating with the assistant
+    pub fn timeouts(&self) -> &Timeouts {
+        self.conn.timeouts()
+    }
+
+    /// The last heartbeat received from the assistant, and when it arrived
+    ///
+    /// Heartbeats are only received while waiting for other messages from the
+    /// assistant.
+    pub fn last_heartbeat(&self) -> Option<(Instant, Heartbeat)> {
+        self.conn.last_heartbeat()
+    }
+
+    /// Confirm that the connection actually leads to the test assistant
+    ///
+    /// Asks the node to identify itself and returns an error, if it doesn't
+    /// reply as the assistant within the given timeout.
+    pub fn handshake(&mut self, timeout: Duration)
+        -> Result<(), AssistantError>
+    {
+        self.handshake_inner(timeout)
+            .map_err(AssistantError::Handshake)
+    }
+
+    fn handshake_inner(&mut self, timeout: Duration)
+        -> Result<(), AssistantHandshakeError>
+    {
+        let id = self.conn
+            .send(&HostToAssistant::Identify)
+            .map_err(AssistantHandshakeError::Send)?;
+
+        let mut tmp = Vec::new();
+        let message = self.conn
+            .receive_reply::<AssistantToHost>(id, timeout, &mut tmp)
+            .map_err(AssistantHandshakeError::Receive)?;
+
+        match message {
+            AssistantToHost::Identity(Node::Assistant) => {
+                Ok(())
+            }
+            AssistantToHost::Identity(node) => {
+                Err(AssistantHandshakeError::WrongNode(node))
+            }
+            message => {
+                Err(
+                    AssistantHandshakeError::UnexpectedMessage(
+                        format!("{:?}", message)
+                    )
+                )
+            }
+        }
+    }
+
+    /// Reset the assistant to its initial state
+    ///
+    /// Discards any data from the assistant that hasn't been processed yet,
+    /// then instructs the assistant to reset all its pins, peripherals, and
+    /// buffers. Waits until the assistant confirms the reset.
+    pub fn reset(&mut self) -> Result<(), AssistantError> {
+        self.reset_inner()
+            .map_err(AssistantError::Reset)
+    }
+
+    fn reset_inner(&mut self) -> Result<(), AssistantResetError> {
+        let timeout = self.conn.timeouts().reply();
+
+        self.conn
+            .clear_input()
+            .map_err(AssistantResetError::Clear)?;
+        let id = self.conn
+            .send(&HostToAssistant::Reset)
+            .map_err(AssistantResetError::Send)?;
+        self.conn.restart_heartbeat();
+
+        let start = Instant::now();
+
+        loop {
+            if start.elapsed() > timeout {
+                return Err(AssistantResetError::Timeout);
+            }
+
+            let mut tmp = Vec::new();
+            let message = self.conn
+                .receive_reply::<AssistantToHost>(id, timeout, &mut tmp)
+                .map_err(AssistantResetError::Receive)?;
+
+            // Anything the assistant sent before the reset is left over from
+            // whatever happened before. We can safely ignore it.
+            if let AssistantToHost::ResetComplete = message {
+                return Ok(());
+            }
         }
     }
 
+    /// Reset the target by pulsing its RESET pin
+    ///
+    /// Holds the target in reset for a short while, then releases it. This
+    /// works regardless of the state the target firmware is in, which makes it
+    /// useful for recovering a target that no longer responds.
+    pub fn reset_target(&mut self) -> Result<(), AssistantError> {
+        self.hold_target_in_reset()?;
+        sleep(Duration::from_millis(10));
+        self.release_target_reset()
+    }
+
+    /// Instruct the assistant to hold the target in reset
+    ///
+    /// The target stays in reset until `release_target_reset` is called.
+    pub fn hold_target_in_reset(&mut self) -> Result<(), AssistantError> {
+        self.target_reset
+            .set_level::<HostToAssistant>(
+                pin::Level::Low,
+                &mut self.conn,
+            )
+            .map_err(AssistantError::SetPinLow)
+    }
+
+    /// Instruct the assistant to release the target from reset
+    pub fn release_target_reset(&mut self) -> Result<(), AssistantError> {
+        self.target_reset
+            .set_level::<HostToAssistant>(
+                pin::Level::High,
+                &mut self.conn,
+            )
+            .map_err(AssistantError::SetPinHigh)
+    }
+
     /// Instruct the assistant to set pin 5 high
     pub fn set_pin_5_high(&mut self) -> Result<(), AssistantError> {
         self.pin5
@@ -71,6 +249,22 @@ impl Assistant {
             .map_err(|err| AssistantError::SetPinLow(err))
     }
 
+    /// Instruct the assistant to output a voltage to the target's ADC
+    ///
+    /// Returns the voltage that is actually being output, in millivolts. The
+    /// voltage is output until the assistant is reset.
+    pub fn set_analog(&mut self, millivolts: u16)
+        -> Result<u16, AssistantError>
+    {
+        self.analog
+            .set::<HostToAssistant, AssistantToHost>(
+                millivolts,
+                &mut self.conn,
+                &mut Vec::new(),
+            )
+            .map_err(AssistantError::SetAnalog)
+    }
+
     /// Instruct the assistant to set the target's input pin high
     pub fn set_pin_high(&mut self) -> Result<(), AssistantError> {
         self.red_led
@@ -117,8 +311,9 @@ impl Assistant {
     pub fn pin_is_high(&mut self) -> Result<bool, AssistantError> {
         let pin_state = self.green_led
             .read_level::<HostToAssistant, AssistantToHost>(
-                Duration::from_millis(10),
+                self.conn.timeouts().read(),
                 &mut self.conn,
+                &mut Vec::new(),
             )?;
         Ok(pin_state.0 == pin::Level::High)
     }
@@ -129,8 +324,9 @@ impl Assistant {
     pub fn pin_is_low(&mut self) -> Result<bool, AssistantError> {
         let pin_state = self.green_led
             .read_level::<HostToAssistant, AssistantToHost>(
-                Duration::from_millis(10),
+                self.conn.timeouts().read(),
                 &mut self.conn,
+                &mut Vec::new(),
             )?;
         Ok(pin_state.0 == pin::Level::Low)
     }
@@ -138,30 +334,215 @@ impl Assistant {
     /// Wait for RTS signal to be enabled
     pub fn wait_for_rts(&mut self) -> Result<bool, AssistantError> {
         let pin_state = self.rts.read_level::<HostToAssistant, AssistantToHost>(
-            Duration::from_millis(10),
+            self.conn.timeouts().read(),
             &mut self.conn,
+            &mut Vec::new(),
         )?;
         Ok(pin_state.0 == pin::Level::Low)
     }
 
+    /// Instruct the assistant to write to an I2C slave
+    ///
+    /// The assistant's I2C master shares the bus with the target's, so this
+    /// can address a device that the target emulates.
+    pub fn i2c_write(&mut self, address: u8, data: &[u8])
+        -> Result<(), AssistantError>
+    {
+        Ok(
+            self.i2c.write::<HostToAssistant, AssistantToHost>(
+                address,
+                data,
+                self.conn.timeouts().reply(),
+                &mut self.conn,
+                &mut Vec::new(),
+            )?
+        )
+    }
+
+    /// Instruct the assistant to read from an I2C slave
+    pub fn i2c_read(&mut self, address: u8, len: u16)
+        -> Result<Vec<u8>, AssistantError>
+    {
+        Ok(
+            self.i2c.read::<HostToAssistant, AssistantToHost>(
+                address,
+                len,
+                self.conn.timeouts().reply(),
+                &mut self.conn,
+                &mut Vec::new(),
+            )?
+        )
+    }
+
+    /// Instruct the assistant to write to, then read from an I2C slave
+    pub fn i2c_write_read(&mut self, address: u8, data: &[u8], len: u16)
+        -> Result<Vec<u8>, AssistantError>
+    {
+        Ok(
+            self.i2c.write_read::<HostToAssistant, AssistantToHost>(
+                address,
+                data,
+                len,
+                self.conn.timeouts().reply(),
+                &mut self.conn,
+                &mut Vec::new(),
+            )?
+        )
+    }
+
+    /// Instruct the assistant to emulate an I2C slave device
+    ///
+    /// The device replaces the assistant's default I2C slave, until the
+    /// assistant is reset.
+    pub fn emulate_i2c_device(&mut self, device: i2c::Device)
+        -> Result<(), AssistantError>
+    {
+        self.i2c
+            .emulate::<HostToAssistant, AssistantToHost>(
+                device,
+                &mut self.conn,
+                &mut Vec::new(),
+            )
+            .map_err(AssistantError::I2cEmulate)
+    }
+
+    /// Read the current registers of the emulated I2C slave device
+    pub fn read_i2c_registers(&mut self) -> Result<Vec<u8>, AssistantError> {
+        Ok(
+            self.i2c.read_registers::<HostToAssistant, AssistantToHost>(
+                self.conn.timeouts().reply(),
+                &mut self.conn,
+                &mut Vec::new(),
+            )?
+        )
+    }
+
+    /// Instruct the assistant to configure the SPI slave
+    ///
+    /// The slave is connected to the target's SPI master, and needs to be
+    /// configured the same way, for transfers to work.
+    pub fn configure_spi(&mut self, config: spi::Config)
+        -> Result<(), AssistantError>
+    {
+        self.spi
+            .configure::<HostToAssistant, AssistantToHost>(
+                config,
+                &mut self.conn,
+                &mut Vec::new(),
+            )
+            .map_err(AssistantError::SpiConfigure)
+    }
+
+    /// Instruct the assistant to start an SPI transfer as master
+    ///
+    /// The assistant's SPI slave becomes a master for the duration of the
+    /// transfer. This only works, if the target is in slave mode.
+    pub fn spi_transfer(&mut self,
+        config:      spi::Config,
+        chip_select: spi::ChipSelect,
+        data:        &[u8],
+    )
+        -> Result<Vec<u8>, AssistantError>
+    {
+        Ok(
+            self.spi.transfer::<HostToAssistant, AssistantToHost>(
+                config,
+                chip_select,
+                data,
+                self.conn.timeouts().reply(),
+                &mut self.conn,
+                &mut Vec::new(),
+            )?
+        )
+    }
+
+    /// Instruct the assistant to emulate an SPI slave device
+    ///
+    /// The device replaces the assistant's default SPI slave, until the
+    /// assistant is reset.
+    pub fn emulate_spi_device(&mut self, device: spi::Device)
+        -> Result<(), AssistantError>
+    {
+        self.spi
+            .emulate::<HostToAssistant, AssistantToHost>(
+                device,
+                &mut self.conn,
+                &mut Vec::new(),
+            )
+            .map_err(AssistantError::SpiEmulate)
+    }
+
+    /// Read what the assistant's SPI slave has received from the target
+    ///
+    /// Returns everything received since the last call, or since the
+    /// assistant was reset.
+    pub fn read_spi_log(&mut self) -> Result<SpiLog, AssistantError> {
+        Ok(
+            self.spi.read_log::<HostToAssistant, AssistantToHost>(
+                self.conn.timeouts().reply(),
+                &mut self.conn,
+                &mut Vec::new(),
+            )?
+        )
+    }
+
+    /// Change the baud rate and frame format of a USART connected to the target
+    ///
+    /// Returns the baud rate that the assistant's USART actually runs at. The
+    /// configuration is kept until the assistant is reset.
+    pub fn configure_usart(&mut self, config: UsartConfigure<UsartMode>)
+        -> Result<u32, AssistantError>
+    {
+        Usart::new(config.usart)
+            .configure::<HostToAssistant, AssistantToHost>(
+                config,
+                &mut self.conn,
+                &mut Vec::new(),
+            )
+            .map_err(AssistantError::UsartConfigure)
+    }
+
     /// Instruct assistant to send this message to the target via USART
     pub fn send_to_target_usart(&mut self, data: &[u8])
         -> Result<(), AssistantError>
     {
-        self.conn
-            .send(&HostToAssistant::SendUsart {
-                mode: UsartMode::Regular,
+        self.usart
+            .send::<HostToAssistant, AssistantToHost>(
                 data,
-            })
+                &mut self.conn,
+                &mut Vec::new(),
+            )
             .map_err(|err| AssistantError::UsartSend(err))
     }
 
+    /// Instruct assistant to send data to the target's USART with a fault
+    ///
+    /// Used to test how the target handles errors on the line. Some faults
+    /// require a specific USART configuration. Returns an error for faults
+    /// that the assistant can't inject.
+    pub fn inject_usart_fault(&mut self, fault: UsartFault, data: &[u8])
+        -> Result<(), AssistantError>
+    {
+        self.usart
+            .inject::<HostToAssistant, AssistantToHost>(
+                fault,
+                data,
+                &mut self.conn,
+                &mut Vec::new(),
+            )
+            .map_err(AssistantError::UsartInject)
+    }
+
     /// Instruct assistant to send this message to the target's USART/DMA
     pub fn send_to_target_usart_dma(&mut self, data: &[u8])
         -> Result<(), AssistantError>
     {
-        self.conn
-            .send(&HostToAssistant::SendUsart { mode: UsartMode::Dma, data })
+        self.usart_dma
+            .send::<HostToAssistant, AssistantToHost>(
+                data,
+                &mut self.conn,
+                &mut Vec::new(),
+            )
             .map_err(|err| AssistantError::UsartSend(err))
     }
 
@@ -169,11 +550,105 @@ impl Assistant {
     pub fn send_to_target_usart_sync(&mut self, data: &[u8])
         -> Result<(), AssistantError>
     {
-        self.conn
-            .send(&HostToAssistant::SendUsart { mode: UsartMode::Sync, data })
+        self.usart_sync
+            .send::<HostToAssistant, AssistantToHost>(
+                data,
+                &mut self.conn,
+                &mut Vec::new(),
+            )
             .map_err(|err| AssistantError::UsartSend(err))
     }
 
+    /// Instruct assistant to send this message to the target's USART, using
+    /// flow control
+    ///
+    /// The assistant only sends while the target asserts RTS. The target only
+    /// drives RTS while it sends with flow control itself, or after it has
+    /// been told to stall the assistant. Returns an error, if the data doesn't
+    /// fit into the assistant's queue.
+    pub fn send_to_target_usart_with_flow_control(&mut self, data: &[u8])
+        -> Result<(), AssistantError>
+    {
+        self.usart_flow_control
+            .send::<HostToAssistant, AssistantToHost>(
+                data,
+                &mut self.conn,
+                &mut Vec::new(),
+            )
+            .map_err(AssistantError::UsartSend)
+    }
+
+    /// Instruct assistant to stall the target's USART, using flow control
+    ///
+    /// The assistant deasserts the target's CTS signal, once it has received
+    /// `after` bytes from the target, plus one that it holds back until the
+    /// target is released. Until the assistant is reset, CTS follows its RTS
+    /// signal, and can no longer be controlled using [`enable_cts`] and
+    /// [`disable_cts`].
+    ///
+    /// [`enable_cts`]: #method.enable_cts
+    /// [`disable_cts`]: #method.disable_cts
+    pub fn stall_target_usart(&mut self, after: u32)
+        -> Result<(), AssistantError>
+    {
+        self.usart_flow_control
+            .stall::<HostToAssistant, AssistantToHost>(
+                after,
+                &mut self.conn,
+                &mut Vec::new(),
+            )
+            .map_err(AssistantError::UsartStall)
+    }
+
+    /// Instruct assistant to release the target's stalled USART
+    pub fn release_target_usart(&mut self) -> Result<(), AssistantError> {
+        self.usart_flow_control
+            .release::<HostToAssistant, AssistantToHost>(
+                &mut self.conn,
+                &mut Vec::new(),
+            )
+            .map_err(AssistantError::UsartStall)
+    }
+
+    /// Instruct assistant to measure the timing of the target's USART
+    ///
+    /// The assistant measures what the target sends, comparing it against the
+    /// baud rate and frame format in `params`. Any previous measurement is
+    /// discarded. The measurement runs until the assistant is reset.
+    pub fn measure_target_usart_timing(&mut self,
+        params: UsartMeasureTiming<UsartMode>,
+    )
+        -> Result<(), AssistantError>
+    {
+        Usart::new(params.usart)
+            .measure_timing::<HostToAssistant, AssistantToHost>(
+                params.baud,
+                params.data_bits,
+                params.parity,
+                params.stop_bits,
+                &mut self.conn,
+                &mut Vec::new(),
+            )
+            .map_err(AssistantError::UsartTiming)
+    }
+
+    /// Read the results of the current timing measurement
+    ///
+    /// Covers everything the target has sent since the measurement was started
+    /// using [`measure_target_usart_timing`].
+    ///
+    /// [`measure_target_usart_timing`]: #method.measure_target_usart_timing
+    pub fn read_target_usart_timing(&mut self)
+        -> Result<UsartTiming<UsartMode>, AssistantError>
+    {
+        self.usart
+            .read_timing::<HostToAssistant, AssistantToHost>(
+                &mut self.conn,
+                &mut Vec::new(),
+            )
+            .map_err(AssistantError::UsartTiming)
+    }
+
     /// Wait to receive the provided data via USART
     ///
     /// Returns the receive buffer, once the data was received. Returns an
@@ -181,12 +656,11 @@ impl Assistant {
     pub fn receive_from_target_usart(&mut self, data: &[u8], timeout: Duration)
         -> Result<Vec<u8>, AssistantError>
     {
-        Ok(
-            self.receive_from_target_usart_inner(
-                data,
-                timeout,
-                UsartMode::Regular,
-            )?
+        Self::receive_from_target_usart_inner(
+            &mut self.conn,
+            &mut self.usart,
+            data,
+            timeout,
         )
     }
 
@@ -200,52 +674,37 @@ impl Assistant {
     )
         -> Result<Vec<u8>, AssistantError>
     {
-        Ok(
-            self.receive_from_target_usart_inner(
-                data,
-                timeout,
-                UsartMode::Sync,
-            )?
+        Self::receive_from_target_usart_inner(
+            &mut self.conn,
+            &mut self.usart_sync,
+            data,
+            timeout,
         )
     }
 
-    pub fn receive_from_target_usart_inner(&mut self,
-        data:          &[u8],
-        timeout:       Duration,
-        expected_mode: UsartMode,
+    fn receive_from_target_usart_inner(
+        conn:    &mut Conn,
+        usart:   &mut Usart<UsartMode>,
+        data:    &[u8],
+        timeout: Duration,
     )
-        -> Result<Vec<u8>, AssistantUsartWaitError>
+        -> Result<Vec<u8>, AssistantError>
     {
-        let mut buf   = Vec::new();
-        let     start = Instant::now();
+        let mut received = Vec::new();
+        let     start    = Instant::now();
 
         loop {
-            if buf.windows(data.len()).any(|window| window == data) {
-                return Ok(buf);
+            if received.windows(data.len()).any(|window| window == data) {
+                return Ok(received);
             }
             if start.elapsed() > timeout {
-                return Err(AssistantUsartWaitError::Timeout);
+                return Err(usart::ReceiveError::Timeout.into());
             }
 
             let mut tmp = Vec::new();
-            let message = self.conn
-                .receive::<AssistantToHost>(timeout, &mut tmp)
-                .map_err(|err| AssistantUsartWaitError::Receive(err))?;
-
-            match message {
-                AssistantToHost::UsartReceive { mode, data }
-                    if mode == expected_mode
-                => {
-                    buf.extend(data)
-                }
-                _ => {
-                    return Err(
-                        AssistantUsartWaitError::UnexpectedMessage(
-                            format!("{:?}", message)
-                        )
-                    );
-                }
-            }
+            let chunk = usart
+                .receive::<AssistantToHost>(timeout, conn, &mut tmp)?;
+            received.extend_from_slice(chunk);
         }
     }
 
@@ -307,6 +766,7 @@ impl Assistant {
             .read_level::<HostToAssistant, AssistantToHost>(
                 timeout,
                 conn,
+                &mut Vec::new(),
             )?;
 
         for _ in 0 .. samples {
@@ -314,6 +774,7 @@ impl Assistant {
                 .read_level::<HostToAssistant, AssistantToHost>(
                     timeout,
                     conn,
+                    &mut Vec::new(),
                 )?;
             print!("{:?}, {:?}\n", new_state, period_ms);
 
@@ -361,29 +822,25 @@ impl Assistant {
     fn expect_nothing_from_target_inner(&mut self, timeout: Duration)
         -> Result<(), AssistantExpectNothingError>
     {
-        loop {
-            let mut tmp = Vec::new();
-            let message = self.conn
-                .receive::<AssistantToHost>(timeout, &mut tmp);
-
-            match message {
-                Ok(message) => {
-                    return Err(
-                        AssistantExpectNothingError::UnexpectedMessage(
-                            format!("{:?}", message)
-                        )
-                    );
-                }
-                Err(err) if err.is_timeout() => {
-                    break;
-                }
-                Err(err) => {
-                    return Err(AssistantExpectNothingError::Receive(err));
-                }
+        let mut tmp = Vec::new();
+        let message = self.conn
+            .receive::<AssistantToHost>(timeout, &mut tmp);
+
+        match message {
+            Ok(message) => {
+                Err(
+                    AssistantExpectNothingError::UnexpectedMessage(
+                        format!("{:?}", message)
+                    )
+                )
+            }
+            Err(err) if err.is_timeout() => {
+                Ok(())
+            }
+            Err(err) => {
+                Err(AssistantExpectNothingError::Receive(err))
             }
         }
-
-        Ok(())
     }
 }
 
@@ -399,11 +856,25 @@ pub struct GpioPeriodMeasurement {
 #[derive(Debug)]
 pub enum AssistantError {
     ExpectNothing(AssistantExpectNothingError),
+    Handshake(AssistantHandshakeError),
+    I2cEmulate(EmulateError),
+    I2cReadRegisters(ReadRegistersError),
+    I2cTransaction(TransactionError),
     PinRead(ReadLevelError),
+    Reset(AssistantResetError),
+    SetAnalog(analog::SetError),
     SetPinHigh(ConnSendError),
     SetPinLow(ConnSendError),
-    UsartSend(ConnSendError),
-    UsartWait(AssistantUsartWaitError),
+    SpiConfigure(SpiConfigureError),
+    SpiEmulate(SpiEmulateError),
+    SpiReadLog(ReadLogError),
+    SpiTransfer(TransferError),
+    UsartConfigure(usart::ConfigureError),
+    UsartInject(usart::InjectError),
+    UsartSend(usart::SendError),
+    UsartStall(usart::StallError),
+    UsartTiming(usart::TimingError),
+    UsartWait(usart::ReceiveError),
 }
 
 impl From<ReadLevelError> for AssistantError {
@@ -412,20 +883,56 @@ impl From<ReadLevelError> for AssistantError {
     }
 }
 
-impl From<AssistantUsartWaitError> for AssistantError {
-    fn from(err: AssistantUsartWaitError) -> Self {
+impl From<ReadRegistersError> for AssistantError {
+    fn from(err: ReadRegistersError) -> Self {
+        Self::I2cReadRegisters(err)
+    }
+}
+
+impl From<TransactionError> for AssistantError {
+    fn from(err: TransactionError) -> Self {
+        Self::I2cTransaction(err)
+    }
+}
+
+impl From<ReadLogError> for AssistantError {
+    fn from(err: ReadLogError) -> Self {
+        Self::SpiReadLog(err)
+    }
+}
+
+impl From<TransferError> for AssistantError {
+    fn from(err: TransferError) -> Self {
+        Self::SpiTransfer(err)
+    }
+}
+
+impl From<usart::ReceiveError> for AssistantError {
+    fn from(err: usart::ReceiveError) -> Self {
         Self::UsartWait(err)
     }
 }
 
 
 #[derive(Debug)]
-pub enum AssistantUsartWaitError {
+pub enum AssistantHandshakeError {
+    Send(ConnSendError),
     Receive(ConnReceiveError),
-    Timeout,
+
+    /// A different kind of node replied to the handshake
+    WrongNode(Node),
+
     UnexpectedMessage(String),
 }
 
+#[derive(Debug)]
+pub enum AssistantResetError {
+    Clear(ConnClearError),
+    Send(ConnSendError),
+    Receive(ConnReceiveError),
+    Timeout,
+}
+
 #[derive(Debug)]
 pub enum AssistantExpectNothingError {
     Receive(ConnReceiveError),
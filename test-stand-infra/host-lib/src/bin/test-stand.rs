@@ -0,0 +1,68 @@
+//! Command-line helper for setting up a test stand
+//!
+//! Run `test-stand list-ports` to list the available USB serial ports, along
+//! with the selectors that can be used to refer to them in `test-stand.toml`.
+
+
+use std::{
+    env,
+    process,
+};
+
+use host_lib::port;
+
+
+fn main() {
+    let command = env::args().nth(1);
+
+    match command.as_deref() {
+        Some("list-ports") => {
+            list_ports();
+        }
+        _ => {
+            eprintln!("Usage: test-stand list-ports");
+            process::exit(1);
+        }
+    }
+}
+
+fn list_ports() {
+    let ports = match port::list() {
+        Ok(ports) => ports,
+        Err(err) => {
+            eprintln!("Error enumerating serial ports: {}", err);
+            process::exit(1);
+        }
+    };
+
+    if ports.is_empty() {
+        println!("No USB serial ports found.");
+        return;
+    }
+
+    for (path, info) in ports {
+        println!("{}", path);
+        if let Some(manufacturer) = &info.manufacturer {
+            println!("    manufacturer: {}", manufacturer);
+        }
+        if let Some(product) = &info.product {
+            println!("    product:      {}", product);
+        }
+
+        let mut selector = format!(
+            "{{ vid = {:#06x}, pid = {:#06x}",
+            info.vid,
+            info.pid,
+        );
+        if let Some(serial_number) = &info.serial_number {
+            selector.push_str(&format!(", serial_number = {:?}", serial_number));
+        }
+        if let Some(interface) = info.interface {
+            selector.push_str(&format!(", interface = {}", interface));
+        }
+        selector.push_str(" }");
+
+        println!("    selector:     {}", selector);
+        println!();
+    }
+}
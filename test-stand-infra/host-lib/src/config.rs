@@ -2,49 +2,731 @@
 
 
 use std::{
-    fs::File,
-    io::prelude::*,
+    env,
+    fs,
+    io,
+    path::{
+        Path,
+        PathBuf,
+    },
+    time::Duration,
 };
 
 use serde::Deserialize;
+use toml::{
+    Value,
+    value::Table,
+};
 
-use crate::Error;
+use crate::port::PortSelector;
 
 
-/// The configuration options for the test suite
-#[derive(Deserialize)]
-pub struct Config {
-    /// Path to the serial device connected to the test target
-    pub target: Option<String>,
+/// The name of the configuration file
+pub const FILE_NAME: &str = "test-stand.toml";
+
+/// Environment variable that overrides the path of the configuration file
+pub const CONFIG_VAR: &str = "TEST_STAND_CONFIG";
+
+/// Environment variable that selects a profile from the configuration file
+pub const PROFILE_VAR: &str = "TEST_STAND_PROFILE";
 
-    /// Path to the serial device connected to the test assistant
-    pub assistant: Option<String>,
 
-    /// Path to the serial device connected to the USB/serial converter
-    pub serial: Option<String>,
+/// The configuration options for the test suite
+///
+/// A configuration file can describe a single test stand, using the top-level
+/// `target`, `assistant`, and `serial` keys, or multiple identical test stands
+/// that tests can be distributed across:
+///
+/// ``` toml
+/// [stands.bench-1]
+/// target    = "/dev/ttyACM0"
+/// assistant = "/dev/ttyACM1"
+///
+/// [stands.bench-2]
+/// target    = "/dev/ttyACM2"
+/// assistant = "/dev/ttyACM3"
+/// ```
+///
+/// The configuration file can also define named profiles, which override parts
+/// of the top-level configuration. This is useful, if the same test suite runs
+/// on machines with different wiring or serial ports:
+///
+/// ``` toml
+/// target    = "/dev/ttyACM0"
+/// assistant = "/dev/ttyACM1"
+///
+/// [profile.ci]
+/// target    = "/dev/ttyUSB0"
+///
+/// [profile.ci.assistant]
+/// port = "/dev/ttyUSB1"
+/// timeouts = { usart_ms = 200 }
+/// ```
+///
+/// A profile is selected by setting the `TEST_STAND_PROFILE` environment
+/// variable to its name.
+///
+/// The tolerances of ADC measurements can be adapted to a test stand using an
+/// `adc_tolerances` table, at the top level or in a named test stand. Named
+/// test stands start out with the top-level tolerances.
+#[derive(Debug)]
+pub struct Config {
+    /// The test stands that are available
+    ///
+    /// If the top-level `target`, `assistant`, or `serial` keys are used, they
+    /// make up a test stand named "default", which comes first.
+    pub stands: Vec<StandConfig>,
 }
 
 impl Config {
     /// Read configuration from the `test-stand.toml` file
+    ///
+    /// If the `TEST_STAND_CONFIG` environment variable is set, the file at
+    /// that path is read. Otherwise, the configuration file is searched for in
+    /// the current working directory, the root directory of the crate that is
+    /// being tested, and the root directory of its workspace, in that order.
+    ///
+    /// If the `TEST_STAND_PROFILE` environment variable is set, the profile of
+    /// that name is applied on top of the top-level configuration.
     pub fn read() -> Result<Self, ConfigReadError> {
-        Self::read_inner()
-            .map_err(|err| ConfigReadError(err))
+        let path = match env::var_os(CONFIG_VAR) {
+            Some(path) => PathBuf::from(path),
+            None       => Self::find()?,
+        };
+        let profile = env::var(PROFILE_VAR).ok();
+
+        Self::read_from(&path, profile.as_deref())
+    }
+
+    /// Read configuration from the given file, applying the given profile
+    pub fn read_from(path: &Path, profile: Option<&str>)
+        -> Result<Self, ConfigReadError>
+    {
+        let config = fs::read_to_string(path)
+            .map_err(|err| ConfigReadError::Io(path.to_path_buf(), err))?;
+        let config: Table = toml::from_str(&config)
+            .map_err(|err| ConfigReadError::Parse {
+                path: path.to_path_buf(),
+                key:  None,
+                err,
+            })?;
+
+        Self::from_table(config, profile, path)
+    }
+
+    fn find() -> Result<PathBuf, ConfigReadError> {
+        let mut dirs = Vec::new();
+
+        if let Ok(dir) = env::current_dir() {
+            dirs.push(dir);
+        }
+
+        // Cargo sets this variable when running tests, and it points to the
+        // root of the crate that is being tested. That's not necessarily the
+        // working directory, so let's look there too, as well as in the
+        // workspace root above it.
+        if let Some(dir) = env::var_os("CARGO_MANIFEST_DIR") {
+            let dir       = PathBuf::from(dir);
+            let workspace = find_workspace_root(&dir);
+
+            dirs.push(dir);
+            dirs.extend(workspace);
+        }
+
+        dirs.dedup();
+
+        let candidates: Vec<_> = dirs
+            .into_iter()
+            .map(|dir| dir.join(FILE_NAME))
+            .collect();
+
+        candidates
+            .iter()
+            .find(|path| path.is_file())
+            .cloned()
+            .ok_or(ConfigReadError::NotFound(candidates))
+    }
+
+    fn from_table(mut config: Table, profile: Option<&str>, path: &Path)
+        -> Result<Self, ConfigReadError>
+    {
+        let mut profiles = match config.remove("profile") {
+            Some(Value::Table(profiles)) => profiles,
+            Some(_) => {
+                return Err(ConfigReadError::invalid(
+                    path,
+                    "profile",
+                    "expected a table of profiles",
+                ));
+            }
+            None => Table::new(),
+        };
+
+        if let Some(name) = profile {
+            let key = format!("profile.{}", name);
+
+            match profiles.remove(name) {
+                Some(Value::Table(profile)) => {
+                    merge(&mut config, profile);
+                }
+                Some(_) => {
+                    return Err(ConfigReadError::invalid(
+                        path,
+                        &key,
+                        "expected a table",
+                    ));
+                }
+                None => {
+                    return Err(ConfigReadError::UnknownProfile {
+                        path:    path.to_path_buf(),
+                        profile: name.to_string(),
+                    });
+                }
+            }
+        }
+
+        let named = match config.remove("stands") {
+            Some(Value::Table(stands)) => stands,
+            Some(_) => {
+                return Err(ConfigReadError::invalid(
+                    path,
+                    "stands",
+                    "expected a table of test stands",
+                ));
+            }
+            None => Table::new(),
+        };
+
+        let mut stands = Vec::new();
+
+        let default = StandConfig::from_table(
+            "default",
+            &mut config,
+            AdcTolerances::default(),
+            path,
+        )?;
+        let adc_tolerances = default.adc_tolerances;
+        if !default.is_empty() {
+            stands.push(default);
+        }
+        if let Some(key) = config.keys().next() {
+            return Err(ConfigReadError::invalid(path, key, "unknown key"));
+        }
+
+        for (name, stand) in named {
+            let key = format!("stands.{}", name);
+
+            let mut stand = match stand {
+                Value::Table(stand) => stand,
+                _ => {
+                    return Err(ConfigReadError::invalid(
+                        path,
+                        &key,
+                        "expected a table",
+                    ));
+                }
+            };
+
+            let config = StandConfig::from_table(
+                &name,
+                &mut stand,
+                adc_tolerances,
+                path,
+            )?;
+            if let Some(unknown) = stand.keys().next() {
+                return Err(ConfigReadError::invalid(
+                    path,
+                    &format!("{}.{}", key, unknown),
+                    "unknown key",
+                ));
+            }
+
+            stands.push(config);
+        }
+
+        Ok(
+            Self {
+                stands,
+            }
+        )
+    }
+}
+
+
+/// Configuration of a single test stand
+#[derive(Clone, Debug)]
+pub struct StandConfig {
+    /// The name of the test stand
+    pub name: String,
+
+    /// Serial connection to the test target
+    pub target: Option<NodeConfig>,
+
+    /// Serial connection to the test assistant
+    pub assistant: Option<NodeConfig>,
+
+    /// Serial connection to the USB/serial converter
+    pub serial: Option<NodeConfig>,
+
+    /// The tolerances of the target's ADC measurements
+    pub adc_tolerances: AdcTolerances,
+}
+
+impl StandConfig {
+    fn from_table(
+        name:           &str,
+        config:         &mut Table,
+        adc_tolerances: AdcTolerances,
+        path:           &Path,
+    )
+        -> Result<Self, ConfigReadError>
+    {
+        // Errors in named test stands should point to the right table.
+        let prefix = match name {
+            "default" => String::new(),
+            name      => format!("stands.{}.", name),
+        };
+
+        Ok(
+            Self {
+                name:      name.to_string(),
+                target:    node(config, "target", &prefix, path)?,
+                assistant: node(config, "assistant", &prefix, path)?,
+                serial:    node(config, "serial", &prefix, path)?,
+
+                adc_tolerances: tolerances(
+                    config,
+                    adc_tolerances,
+                    &prefix,
+                    path,
+                )?,
+            }
+        )
+    }
+
+    fn is_empty(&self) -> bool {
+        self.target.is_none()
+            && self.assistant.is_none()
+            && self.serial.is_none()
+    }
+}
+
+
+/// Configuration of the serial connection to a test node
+///
+/// In the configuration file, this can either be a table, or just the port,
+/// if the default settings are fine:
+///
+/// ``` toml
+/// assistant = "/dev/ttyACM1"
+///
+/// [target]
+/// port      = "/dev/ttyACM0"
+/// baud_rate = 9600
+/// parity    = "even"
+///
+/// [target.timeouts]
+/// usart_ms = 200
+/// ```
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct NodeConfig {
+    /// The serial port the node is connected to
+    pub port: PortSelector,
+
+    /// The baud rate of the serial connection
+    #[serde(default = "default_baud_rate")]
+    pub baud_rate: u32,
+
+    /// The parity mode of the serial connection
+    #[serde(default)]
+    pub parity: Parity,
+
+    /// The number of stop bits of the serial connection
+    #[serde(default)]
+    pub stop_bits: StopBits,
+
+    /// The flow control mode of the serial connection
+    #[serde(default)]
+    pub flow_control: FlowControl,
+
+    /// Default timeouts for communication with the node
+    #[serde(default)]
+    pub timeouts: Timeouts,
+}
+
+impl NodeConfig {
+    /// Create a configuration for the given port, with default settings
+    pub fn new(port: PortSelector) -> Self {
+        Self {
+            port,
+            baud_rate:    default_baud_rate(),
+            parity:       Parity::default(),
+            stop_bits:    StopBits::default(),
+            flow_control: FlowControl::default(),
+            timeouts:     Timeouts::default(),
+        }
+    }
+}
+
+fn default_baud_rate() -> u32 {
+    115200
+}
+
+fn node(config: &mut Table, key: &str, prefix: &str, path: &Path)
+    -> Result<Option<NodeConfig>, ConfigReadError>
+{
+    let value = match config.remove(key) {
+        Some(value) => value,
+        None        => return Ok(None),
+    };
+    let key = format!("{}{}", prefix, key);
+
+    // A node can either be specified as a full table, or as just the port,
+    // which itself can be a path or a table. The presence of the `port` key
+    // tells us which of the two tables we're looking at.
+    let mut table = match value {
+        Value::Table(table) if table.contains_key("port") => table,
+        value => {
+            return parse(value, &key, path).map(|port| {
+                Some(NodeConfig::new(port))
+            });
+        }
+    };
+
+    // We could deserialize the whole table at once, but then errors would
+    // only name the node, not the key that is actually wrong.
+    let port = table.remove("port").unwrap_or_else(|| unreachable!());
+    let port = parse(port, &format!("{}.port", key), path)?;
+
+    let mut node = NodeConfig::new(port);
+
+    for (field, value) in table {
+        let key = format!("{}.{}", key, field);
+
+        match field.as_str() {
+            "baud_rate"    => node.baud_rate    = parse(value, &key, path)?,
+            "parity"       => node.parity       = parse(value, &key, path)?,
+            "stop_bits"    => node.stop_bits    = parse(value, &key, path)?,
+            "flow_control" => node.flow_control = parse(value, &key, path)?,
+            "timeouts"     => node.timeouts     = timeouts(value, &key, path)?,
+
+            _ => {
+                return Err(ConfigReadError::invalid(path, &key, "unknown key"));
+            }
+        }
+    }
+
+    Ok(Some(node))
+}
+
+fn timeouts(value: Value, key: &str, path: &Path)
+    -> Result<Timeouts, ConfigReadError>
+{
+    let table = match value {
+        Value::Table(table) => table,
+        _ => {
+            return Err(ConfigReadError::invalid(path, key, "expected a table"));
+        }
+    };
+
+    let mut timeouts = Timeouts::default();
+
+    for (field, value) in table {
+        let key = format!("{}.{}", key, field);
+
+        let timeout = match field.as_str() {
+            "read_ms"      => &mut timeouts.read_ms,
+            "usart_ms"     => &mut timeouts.usart_ms,
+            "reply_ms"     => &mut timeouts.reply_ms,
+            "boot_ms"      => &mut timeouts.boot_ms,
+            "heartbeat_ms" => &mut timeouts.heartbeat_ms,
+
+            _ => {
+                return Err(ConfigReadError::invalid(path, &key, "unknown key"));
+            }
+        };
+
+        *timeout = parse(value, &key, path)?;
+    }
+
+    Ok(timeouts)
+}
+
+fn tolerances(
+    config:         &mut Table,
+    mut tolerances: AdcTolerances,
+    prefix:         &str,
+    path:           &Path,
+)
+    -> Result<AdcTolerances, ConfigReadError>
+{
+    let key = format!("{}adc_tolerances", prefix);
+
+    let table = match config.remove("adc_tolerances") {
+        Some(Value::Table(table)) => table,
+        Some(_) => {
+            return Err(
+                ConfigReadError::invalid(path, &key, "expected a table")
+            );
+        }
+        None => {
+            return Ok(tolerances);
+        }
+    };
+
+    for (field, value) in table {
+        let key = format!("{}.{}", key, field);
+
+        let tolerance = match field.as_str() {
+            "offset_mv"          => &mut tolerances.offset_mv,
+            "gain_error_percent" => &mut tolerances.gain_error_percent,
+            "nonlinearity_mv"    => &mut tolerances.nonlinearity_mv,
+
+            _ => {
+                return Err(ConfigReadError::invalid(path, &key, "unknown key"));
+            }
+        };
+
+        *tolerance = parse(value, &key, path)?;
+    }
+
+    Ok(tolerances)
+}
+
+fn parse<'de, T>(value: Value, key: &str, path: &Path)
+    -> Result<T, ConfigReadError>
+    where T: Deserialize<'de>
+{
+    value
+        .try_into()
+        .map_err(|err| ConfigReadError::Parse {
+            path: path.to_path_buf(),
+            key:  Some(key.to_string()),
+            err,
+        })
+}
+
+fn merge(base: &mut Table, overrides: Table) {
+    for (key, value) in overrides {
+        match (base.get_mut(&key), value) {
+            // A port selector identifies a device as a whole. Merging two of
+            // them would result in a selector that matches neither.
+            (Some(Value::Table(base)), Value::Table(overrides))
+                if key != "port"
+            => {
+                merge(base, overrides);
+            }
+            (_, value) => {
+                base.insert(key, value);
+            }
+        }
+    }
+}
+
+fn find_workspace_root(crate_root: &Path) -> Option<PathBuf> {
+    crate_root
+        .ancestors()
+        .skip(1)
+        .find(|dir| {
+            fs::read_to_string(dir.join("Cargo.toml"))
+                .ok()
+                .and_then(|manifest| manifest.parse::<Value>().ok())
+                .map(|manifest| manifest.get("workspace").is_some())
+                .unwrap_or(false)
+        })
+        .map(|dir| dir.to_path_buf())
+}
+
+
+/// Parity mode of a serial connection
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Parity {
+    #[default]
+    None,
+    Odd,
+    Even,
+}
+
+impl From<Parity> for serialport::Parity {
+    fn from(parity: Parity) -> Self {
+        match parity {
+            Parity::None => Self::None,
+            Parity::Odd  => Self::Odd,
+            Parity::Even => Self::Even,
+        }
+    }
+}
+
+
+/// Number of stop bits of a serial connection
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum StopBits {
+    #[default]
+    One,
+    Two,
+}
+
+impl From<StopBits> for serialport::StopBits {
+    fn from(stop_bits: StopBits) -> Self {
+        match stop_bits {
+            StopBits::One => Self::One,
+            StopBits::Two => Self::Two,
+        }
+    }
+}
+
+
+/// Flow control mode of a serial connection
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum FlowControl {
+    #[default]
+    None,
+    Software,
+    Hardware,
+}
+
+impl From<FlowControl> for serialport::FlowControl {
+    fn from(flow_control: FlowControl) -> Self {
+        match flow_control {
+            FlowControl::None     => Self::None,
+            FlowControl::Software => Self::Software,
+            FlowControl::Hardware => Self::Hardware,
+        }
+    }
+}
+
+
+/// Default timeouts for communicating with a test node
+///
+/// All values are in milliseconds. Test code should use these, instead of
+/// hardcoding its own timeouts, so a slower test stand only needs its
+/// configuration adapted.
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct Timeouts {
+    /// Timeout for simple requests, like reading a pin level
+    pub read_ms: u64,
+
+    /// Timeout for waiting for USART data
+    pub usart_ms: u64,
+
+    /// Timeout for waiting for the reply to an I2C or SPI transaction
+    pub reply_ms: u64,
+
+    /// Timeout for waiting for a node to boot after a reset
+    pub boot_ms: u64,
+
+    /// Time without a heartbeat, after which a node is considered unresponsive
+    pub heartbeat_ms: u64,
+}
+
+impl Timeouts {
+    /// Timeout for simple requests, like reading a pin level
+    pub fn read(&self) -> Duration {
+        Duration::from_millis(self.read_ms)
+    }
+
+    /// Timeout for waiting for USART data
+    pub fn usart(&self) -> Duration {
+        Duration::from_millis(self.usart_ms)
+    }
+
+    /// Timeout for waiting for the reply to an I2C or SPI transaction
+    pub fn reply(&self) -> Duration {
+        Duration::from_millis(self.reply_ms)
     }
 
-    fn read_inner() -> Result<Self, Error> {
-        // Read configuration file
-        let mut config = Vec::new();
-        File::open("test-stand.toml")?
-            .read_to_end(&mut config)?;
+    /// Timeout for waiting for a node to boot after a reset
+    pub fn boot(&self) -> Duration {
+        Duration::from_millis(self.boot_ms)
+    }
+
+    /// Time without a heartbeat, after which a node is considered unresponsive
+    pub fn heartbeat(&self) -> Duration {
+        Duration::from_millis(self.heartbeat_ms)
+    }
+}
+
+impl Default for Timeouts {
+    fn default() -> Self {
+        Self {
+            read_ms:      10,
+            usart_ms:     50,
+            reply_ms:     50,
+            boot_ms:      1000,
+            heartbeat_ms: 500,
+        }
+    }
+}
+
+
+/// The maximum errors of the target's ADC measurements
+///
+/// Measurements are compared against the voltages that the assistant applies.
+/// The defaults are generous, as the assistant's DAC and the target's ADC use
+/// different supply voltages as their reference, which adds to the errors of
+/// both.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct AdcTolerances {
+    /// The maximum offset error, in millivolts
+    pub offset_mv: f64,
 
-        // Parse configuration file
-        let config = toml::from_slice(&config)?;
+    /// The maximum gain error, in percent
+    pub gain_error_percent: f64,
 
-        Ok(config)
+    /// The maximum deviation of a single measurement from the best-fit line,
+    /// in millivolts
+    pub nonlinearity_mv: f64,
+}
+
+impl Default for AdcTolerances {
+    fn default() -> Self {
+        Self {
+            offset_mv:          50.0,
+            gain_error_percent: 5.0,
+            nonlinearity_mv:    25.0,
+        }
     }
 }
 
 
 /// Error reading the configuration file
 #[derive(Debug)]
-pub struct ConfigReadError(pub Error);
+pub enum ConfigReadError {
+    /// No configuration file was found at any of these paths
+    NotFound(Vec<PathBuf>),
+
+    /// Error reading the configuration file
+    Io(PathBuf, io::Error),
+
+    /// Error parsing the configuration file
+    ///
+    /// `key` is the full path of the key whose value is invalid, like
+    /// `stands.bench-1.target.timeouts.usart_ms`, if the error can be
+    /// attributed to one.
+    Parse {
+        path: PathBuf,
+        key:  Option<String>,
+        err:  toml::de::Error,
+    },
+
+    /// The selected profile is not defined in the configuration file
+    UnknownProfile {
+        path:    PathBuf,
+        profile: String,
+    },
+}
+
+impl ConfigReadError {
+    fn invalid(path: &Path, key: &str, message: &str) -> Self {
+        Self::Parse {
+            path: path.to_path_buf(),
+            key:  Some(key.to_string()),
+            err:  serde::de::Error::custom(message),
+        }
+    }
+}
@@ -1,7 +1,12 @@
 use std::{
+    convert::TryInto,
+    fmt::Debug,
     io,
     slice,
-    time::Duration,
+    time::{
+        Duration,
+        Instant,
+    },
 };
 
 use serde::{
@@ -10,26 +15,45 @@ use serde::{
 };
 use serialport::{
     self,
+    ClearBuffer,
     SerialPort,
 };
 
-use crate::Error;
+use protocol::{
+    Heartbeat,
+    Panic,
+    rpc,
+};
+
+use crate::{
+    Error,
+    config::{
+        NodeConfig,
+        Timeouts,
+    },
+};
 
 
 /// A connection to a firmware application
 pub struct Conn {
-    port: Box<dyn SerialPort>,
+    port:      Box<dyn SerialPort>,
+    timeouts:  Timeouts,
+    heartbeat: Option<(Instant, Heartbeat)>,
+    alive:     Instant,
+    request:   rpc::RequestId,
 }
 
 impl Conn {
     /// Open the connection
     ///
     /// `path` is the path to the serial device file that connects to the
-    /// firmware.
-    pub fn new(path: &str) -> Result<Self, ConnInitError> {
-        // The baud rate configuration is hardcoded for now. We might want to
-        // load this from the configuration file later.
-        let port = serialport::new(path, 115200)
+    /// firmware. The serial settings and default timeouts are taken from
+    /// `config`.
+    pub fn new(path: &str, config: &NodeConfig) -> Result<Self, ConnInitError> {
+        let port = serialport::new(path, config.baud_rate)
+            .parity(config.parity.into())
+            .stop_bits(config.stop_bits.into())
+            .flow_control(config.flow_control.into())
             .open()
             .map_err(|err| ConnInitError(err))?;
 
@@ -40,29 +64,75 @@ impl Conn {
         Ok(
             Self {
                 port,
+                timeouts:  config.timeouts,
+                heartbeat: None,
+                alive:     Instant::now(),
+                request:   0,
             }
         )
     }
 
+    /// The default timeouts for communicating through this connection
+    pub fn timeouts(&self) -> &Timeouts {
+        &self.timeouts
+    }
+
+    /// The last heartbeat received from the node, and when it was received
+    ///
+    /// Returns `None`, if the node hasn't sent a heartbeat yet.
+    pub fn last_heartbeat(&self) -> Option<(Instant, Heartbeat)> {
+        self.heartbeat
+    }
+
+    /// Expect a new heartbeat from the node, starting now
+    ///
+    /// Should be called when the node is reset. If the node doesn't send a
+    /// heartbeat within the heartbeat timeout after this, or after the
+    /// connection has been opened, it is considered unresponsive.
+    pub fn restart_heartbeat(&mut self) {
+        self.heartbeat = None;
+        self.alive     = Instant::now();
+    }
+
     /// Send a message
     ///
-    /// `message` can be any type that can be serialized using `serde`.
-    pub fn send<T>(&mut self, message: &T) -> Result<(), ConnSendError>
+    /// `message` can be any type that can be serialized using `serde`. It is
+    /// sent as a new request. Returns the ID of that request, which can be
+    /// passed to [`receive_reply`] to wait for the reply.
+    ///
+    /// [`receive_reply`]: #method.receive_reply
+    pub fn send<T>(&mut self, message: &T)
+        -> Result<rpc::RequestId, ConnSendError>
         where T: Serialize
     {
         self.send_inner(message)
             .map_err(|err| ConnSendError(err))
     }
 
-    fn send_inner<T>(&mut self, message: &T) -> Result<(), Error>
+    fn send_inner<T>(&mut self, message: &T) -> Result<rpc::RequestId, Error>
         where T: Serialize
     {
         let mut buf = [0; 256];
 
-        let serialized = postcard::to_slice_cobs(message, &mut buf)?;
+        self.request = self.request.wrapping_add(1);
+        let request = rpc::Request {
+            id: self.request,
+            message,
+        };
+
+        let serialized = postcard::to_slice_cobs(&request, &mut buf)?;
         self.port.write_all(serialized)?;
 
-        Ok(())
+        Ok(self.request)
+    }
+
+    /// Discard any data that has been received, but not read yet
+    ///
+    /// This can be used to get rid of leftovers from a previous test case,
+    /// which would otherwise confuse the next one.
+    pub fn clear_input(&mut self) -> Result<(), ConnClearError> {
+        self.port.clear(ClearBuffer::Input)
+            .map_err(|err| ConnClearError(Error::Serial(err)))
     }
 
     /// Receive a message
@@ -73,20 +143,154 @@ impl Conn {
     /// - `buf` is the buffer used to receive data into. Its lifetime is tied to
     ///   the return value, as the received type might still borrow data from
     ///   this buffer.
+    ///
+    /// Heartbeats are recorded and skipped, so they are never returned from
+    /// this method. If nothing is received before the timeout, and the node
+    /// hasn't sent a heartbeat for a while either, the error indicates that
+    /// the node is unresponsive, rather than just a timeout. If the node
+    /// reports a panic, that is returned as an error too, as is a node's
+    /// notice that it couldn't handle a request.
+    ///
+    /// Only returns messages that the node sent on its own accord. Replies are
+    /// skipped, as nobody is waiting for them. Use [`receive_reply`] to wait
+    /// for the reply to a request.
+    ///
+    /// [`receive_reply`]: #method.receive_reply
     pub fn receive<'de, T>(&mut self, timeout: Duration, buf: &'de mut Vec<u8>)
         -> Result<T, ConnReceiveError>
         where T: Deserialize<'de>
     {
-        self.receive_inner(timeout, buf)
+        self.receive_inner(None, timeout, buf)
+            .map_err(ConnReceiveError)
+    }
+
+    /// Receive the reply to a request
+    ///
+    /// `request` is the ID of the request, as returned by [`send`]. Works like
+    /// [`receive`], except that the reply to that request is returned too.
+    /// Replies to any other requests are skipped.
+    ///
+    /// [`send`]: #method.send
+    /// [`receive`]: #method.receive
+    pub fn receive_reply<'de, T>(&mut self,
+        request: rpc::RequestId,
+        timeout: Duration,
+        buf:     &'de mut Vec<u8>,
+    )
+        -> Result<T, ConnReceiveError>
+        where T: Deserialize<'de>
+    {
+        self.receive_inner(Some(request), timeout, buf)
             .map_err(|err| ConnReceiveError(err))
     }
 
+    /// Receive a message of a specific type
+    ///
+    /// Works like [`receive_reply`], if `request` is `Some`, or like
+    /// [`receive`] otherwise. The message is converted into `T` and passed to
+    /// `f`, which hands it back as `Err`, if it isn't the one expected (for
+    /// example, because it concerns another peripheral). Returns an error in
+    /// that case, as well as if the message couldn't be converted.
+    ///
+    /// [`receive_reply`]: #method.receive_reply
+    /// [`receive`]: #method.receive
+    pub(crate) fn receive_as<'de, M, T, R>(&mut self,
+        request: Option<rpc::RequestId>,
+        timeout: Duration,
+        buf:     &'de mut Vec<u8>,
+        f:       impl FnOnce(T) -> Result<R, T>,
+    )
+        -> Result<R, ConnReceiveError>
+        where
+            M: Message<'de, T>,
+            T: Debug,
+    {
+        let message: M = self.receive_inner(request, timeout, buf)
+            .map_err(ConnReceiveError)?;
+
+        let message = message.try_into()
+            .map_err(|message| unexpected_message(message))?;
+        f(message)
+            .map_err(|message| unexpected_message(message))
+    }
+
     fn receive_inner<'de, T>(&mut self,
+        request: Option<rpc::RequestId>,
         timeout: Duration,
         buf:     &'de mut Vec<u8>,
     )
         -> Result<T, Error>
         where T: Deserialize<'de>
+    {
+        let start = Instant::now();
+
+        loop {
+            let remaining = timeout
+                .checked_sub(start.elapsed())
+                .unwrap_or_default();
+
+            if let Err(err) = self.receive_frame(remaining, buf) {
+                return Err(self.check_heartbeat(err));
+            }
+
+            // This decodes the COBS-encoded frame in place, so everything
+            // else can be decoded from `buf` directly. We only decode the kind
+            // of response for now. The message itself is decoded once we know
+            // that we're going to return it, as it might borrow from `buf`,
+            // which we'd still need for receiving the next frame otherwise.
+            let (kind, _): (rpc::Kind, _) =
+                postcard::take_from_bytes_cobs(buf)?;
+
+            match kind {
+                // This is a reply to a request that the caller isn't waiting
+                // for.
+                rpc::Kind::Reply(id) if Some(id) != request => {
+                    continue;
+                }
+                rpc::Kind::Reply(_) | rpc::Kind::Notification => {
+                    break;
+                }
+                rpc::Kind::Heartbeat => {
+                    let response: rpc::Response<Heartbeat> =
+                        postcard::from_bytes(buf)?;
+                    if let Ok(heartbeat) = response.message {
+                        self.alive     = Instant::now();
+                        self.heartbeat = Some((self.alive, heartbeat));
+                    }
+                    continue;
+                }
+                rpc::Kind::Panic => {
+                    let response: rpc::Response<Panic> =
+                        postcard::from_bytes(buf)?;
+                    let panic = response.message
+                        .map_err(Error::Request)?;
+
+                    let location = panic.location.map(|location| {
+                        format!(
+                            "{}:{}:{}",
+                            location.file,
+                            location.line,
+                            location.column,
+                        )
+                    });
+
+                    return Err(
+                        Error::NodePanicked {
+                            location,
+                            message: panic.message.to_string(),
+                        }
+                    );
+                }
+            }
+        }
+
+        let response: rpc::Response<T> = postcard::from_bytes(buf)?;
+        response.message
+            .map_err(Error::Request)
+    }
+
+    fn receive_frame(&mut self, timeout: Duration, buf: &mut Vec<u8>)
+        -> Result<(), Error>
     {
         self.port.set_timeout(timeout)?;
         buf.clear();
@@ -95,6 +299,13 @@ impl Conn {
             let mut b = 0; // initialized to `0`, but could be any value
             self.port.read_exact(slice::from_mut(&mut b))?;
 
+            if b == 0 && buf.is_empty() {
+                // An empty frame. Nodes send one to terminate any message they
+                // might have been sending when they panicked. Nothing to
+                // decode here.
+                continue;
+            }
+
             buf.push(b);
 
             if b == 0 {
@@ -104,12 +315,48 @@ impl Conn {
             }
         }
 
-        let message = postcard::from_bytes_cobs(buf)?;
-        Ok(message)
+        Ok(())
+    }
+
+    /// Turn a timeout into an error, if the node has stopped sending heartbeats
+    fn check_heartbeat(&self, err: Error) -> Error {
+        let is_timeout = match &err {
+            Error::Io(err) => err.kind() == io::ErrorKind::TimedOut,
+            _              => false,
+        };
+
+        let elapsed = self.alive.elapsed();
+
+        if is_timeout && elapsed > self.timeouts.heartbeat() {
+            return Error::Unresponsive {
+                last_heartbeat: self.heartbeat.map(|(_, heartbeat)| heartbeat),
+                elapsed,
+            };
+        }
+
+        err
     }
 }
 
 
+/// A message that can be received from a node and converted into `T`
+///
+/// This is implemented for every type that fulfills the bounds. It only exists
+/// to keep the bounds of the methods that receive messages short.
+pub trait Message<'de, T>:
+    TryInto<T, Error=Self> + Debug + Deserialize<'de>
+{}
+
+impl<'de, T, M> Message<'de, T> for M
+    where M: TryInto<T, Error=M> + Debug + Deserialize<'de>
+{}
+
+
+fn unexpected_message(message: impl Debug) -> ConnReceiveError {
+    ConnReceiveError(Error::UnexpectedMessage(format!("{:?}", message)))
+}
+
+
 /// Error initializing connection
 #[derive(Debug)]
 pub struct ConnInitError(pub serialport::Error);
@@ -120,11 +367,31 @@ pub struct ConnInitError(pub serialport::Error);
 pub struct ConnSendError(pub Error);
 
 
+/// Error discarding received data
+#[derive(Debug)]
+pub struct ConnClearError(pub Error);
+
+
 /// Error receiving from a connection
 #[derive(Debug)]
 pub struct ConnReceiveError(pub Error);
 
 impl ConnReceiveError {
+    /// Indicates whether the node has stopped sending heartbeats
+    pub fn is_unresponsive(&self) -> bool {
+        matches!(self.0, Error::Unresponsive { .. })
+    }
+
+    /// Indicates whether the node has reported a panic
+    pub fn is_panic(&self) -> bool {
+        matches!(self.0, Error::NodePanicked { .. })
+    }
+
+    /// Indicates whether the node doesn't support the request
+    pub fn is_unsupported(&self) -> bool {
+        matches!(self.0, Error::Request(rpc::Error::Unsupported))
+    }
+
     pub fn is_timeout(&self) -> bool {
         match &self.0 {
             Error::Io(err) if err.kind() == io::ErrorKind::TimedOut => {
@@ -1,7 +1,15 @@
 /// Defines the error type for this library
 
 
-use std::io;
+use std::{
+    io,
+    time::Duration,
+};
+
+use protocol::{
+    Heartbeat,
+    rpc,
+};
 
 
 /// The result type for this library
@@ -26,6 +34,33 @@ pub enum Error {
 
     /// Error occurred while accessing the serial port
     Serial(serialport::Error),
+
+    /// The node stopped sending heartbeats
+    ///
+    /// This usually means that the firmware has crashed or hangs.
+    /// `last_heartbeat` is the last heartbeat that was received, `elapsed` the
+    /// time that has passed since then. If the node hasn't sent a heartbeat
+    /// since the connection was opened or the node was reset, `last_heartbeat`
+    /// is `None`, and `elapsed` counts from then.
+    Unresponsive {
+        last_heartbeat: Option<Heartbeat>,
+        elapsed:        Duration,
+    },
+
+    /// The node's firmware has panicked
+    ///
+    /// `location` is the location of the panic in the firmware's source code,
+    /// formatted as `file:line:column`, if the node reported it.
+    NodePanicked {
+        location: Option<String>,
+        message:  String,
+    },
+
+    /// The node couldn't handle a request
+    Request(rpc::Error),
+
+    /// The node sent a different message than the one expected
+    UnexpectedMessage(String),
 }
 
 impl From<toml::de::Error> for Error {
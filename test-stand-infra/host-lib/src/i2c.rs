@@ -0,0 +1,229 @@
+//! API for remotely controlling an I2C master on a test node
+
+
+use std::{
+    fmt::Debug,
+    time::Duration,
+};
+
+use serde::Serialize;
+
+use protocol::i2c;
+
+use crate::conn::{
+    Conn,
+    ConnReceiveError,
+    ConnSendError,
+    Message,
+};
+
+
+/// API for remotely controlling an I2C master on a test node
+///
+/// This struct is intended as a building block for higher-level interfaces
+/// that control the test nodes of a specific test stand.
+pub struct I2c<Id> {
+    bus: Id,
+}
+
+impl<Id> I2c<Id>
+    where Id: Copy + Debug + Eq
+{
+    /// Create a new instance of `I2c`
+    pub fn new(bus: Id) -> Self {
+        Self {
+            bus,
+        }
+    }
+
+    /// Commands the node to write to a slave
+    ///
+    /// Waits for the node to complete the transaction.
+    pub fn write<'d, 'de, Request, Reply>(&mut self,
+        address: u8,
+        data:    &'d [u8],
+        timeout: Duration,
+        conn:    &mut Conn,
+        buf:     &'de mut Vec<u8>,
+    )
+        -> Result<(), TransactionError>
+        where
+            Request: From<i2c::Write<'d, Id>> + Serialize,
+            Reply: Message<'de, i2c::Completed<'de, Id>>,
+    {
+        let request = i2c::Write { bus: self.bus, address, data };
+        self.transaction::<Request, Reply>(
+            request.into(),
+            timeout,
+            conn,
+            buf,
+        )?;
+        Ok(())
+    }
+
+    /// Commands the node to read from a slave
+    ///
+    /// Waits for the node to complete the transaction, then returns the data
+    /// that was read.
+    pub fn read<'de, Request, Reply>(&mut self,
+        address: u8,
+        len:     u16,
+        timeout: Duration,
+        conn:    &mut Conn,
+        buf:     &'de mut Vec<u8>,
+    )
+        -> Result<Vec<u8>, TransactionError>
+        where
+            Request: From<i2c::Read<Id>> + Serialize,
+            Reply: Message<'de, i2c::Completed<'de, Id>>,
+    {
+        let request = i2c::Read { bus: self.bus, address, len };
+        self.transaction::<Request, Reply>(
+            request.into(),
+            timeout,
+            conn,
+            buf,
+        )
+    }
+
+    /// Commands the node to write to, then read from a slave
+    ///
+    /// Uses a repeated start condition between writing and reading. Waits for
+    /// the node to complete the transaction, then returns the data that was
+    /// read.
+    pub fn write_read<'d, 'de, Request, Reply>(&mut self,
+        address: u8,
+        data:    &'d [u8],
+        len:     u16,
+        timeout: Duration,
+        conn:    &mut Conn,
+        buf:     &'de mut Vec<u8>,
+    )
+        -> Result<Vec<u8>, TransactionError>
+        where
+            Request: From<i2c::WriteRead<'d, Id>> + Serialize,
+            Reply: Message<'de, i2c::Completed<'de, Id>>,
+    {
+        let request = i2c::WriteRead { bus: self.bus, address, data, len };
+        self.transaction::<Request, Reply>(
+            request.into(),
+            timeout,
+            conn,
+            buf,
+        )
+    }
+
+    /// Commands the node to emulate an I2C slave device
+    ///
+    /// Waits for the node to confirm that it is emulating the device.
+    pub fn emulate<'d, 'de, Request, Reply>(&mut self,
+        device: i2c::Device<'d>,
+        conn:   &mut Conn,
+        buf:    &'de mut Vec<u8>,
+    )
+        -> Result<(), EmulateError>
+        where
+            Request: From<i2c::Emulate<'d, Id>> + Serialize,
+            Reply: Message<'de, i2c::Emulated<Id>>,
+    {
+        let request: Request = i2c::Emulate { bus: self.bus, device }.into();
+        let id = conn.send(&request)
+            .map_err(EmulateError::Send)?;
+
+        let timeout = conn.timeouts().reply();
+        conn
+            .receive_as::<Reply, _, _>(Some(id), timeout, buf,
+                |emulated: i2c::Emulated<Id>| {
+                    if emulated.bus == self.bus {
+                        Ok(emulated.result)
+                    }
+                    else {
+                        Err(emulated)
+                    }
+                }
+            )
+            .map_err(EmulateError::Receive)?
+            .map_err(EmulateError::I2c)
+    }
+
+    /// Reads the current registers of the device the node is emulating
+    ///
+    /// Returns an empty `Vec`, if the node isn't emulating a device.
+    pub fn read_registers<'de, Request, Reply>(&mut self,
+        timeout: Duration,
+        conn:    &mut Conn,
+        buf:     &'de mut Vec<u8>,
+    )
+        -> Result<Vec<u8>, ReadRegistersError>
+        where
+            Request: From<i2c::ReadRegisters<Id>> + Serialize,
+            Reply: Message<'de, i2c::Registers<'de, Id>>,
+    {
+        let request: Request = i2c::ReadRegisters { bus: self.bus }.into();
+        let id = conn.send(&request)
+            .map_err(ReadRegistersError::Send)?;
+
+        conn
+            .receive_as::<Reply, _, _>(Some(id), timeout, buf,
+                |registers: i2c::Registers<Id>| {
+                    if registers.bus == self.bus {
+                        Ok(registers.registers.to_vec())
+                    }
+                    else {
+                        Err(registers)
+                    }
+                }
+            )
+            .map_err(ReadRegistersError::Receive)
+    }
+
+    fn transaction<'de, Request, Reply>(&mut self,
+        request: Request,
+        timeout: Duration,
+        conn:    &mut Conn,
+        buf:     &'de mut Vec<u8>,
+    )
+        -> Result<Vec<u8>, TransactionError>
+        where
+            Request: Serialize,
+            Reply: Message<'de, i2c::Completed<'de, Id>>,
+    {
+        let id = conn.send(&request)
+            .map_err(TransactionError::Send)?;
+
+        conn
+            .receive_as::<Reply, _, _>(Some(id), timeout, buf,
+                |completed: i2c::Completed<Id>| {
+                    if completed.bus == self.bus {
+                        Ok(completed.result.map(|data| data.to_vec()))
+                    }
+                    else {
+                        Err(completed)
+                    }
+                }
+            )
+            .map_err(TransactionError::Receive)?
+            .map_err(TransactionError::I2c)
+    }
+}
+
+
+#[derive(Debug)]
+pub enum TransactionError {
+    Send(ConnSendError),
+    Receive(ConnReceiveError),
+    I2c(i2c::Error),
+}
+
+#[derive(Debug)]
+pub enum EmulateError {
+    Send(ConnSendError),
+    Receive(ConnReceiveError),
+    I2c(i2c::Error),
+}
+
+#[derive(Debug)]
+pub enum ReadRegistersError {
+    Send(ConnSendError),
+    Receive(ConnReceiveError),
+}
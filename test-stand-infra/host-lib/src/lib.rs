@@ -1,12 +1,19 @@
 //! Library to support the test suite running on the host computer
 
 
+pub mod analog;
 pub mod assistant;
 pub mod config;
 pub mod conn;
 pub mod error;
+pub mod i2c;
 pub mod pin;
+pub mod pool;
+pub mod port;
+pub mod serial;
+pub mod spi;
 pub mod test_stand;
+pub mod usart;
 
 
 pub use self::{
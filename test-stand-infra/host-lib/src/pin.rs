@@ -2,17 +2,12 @@
 
 
 use std::{
-    convert::TryInto,
     fmt::Debug,
-    mem::transmute,
     thread::sleep,
     time::Duration,
 };
 
-use serde::{
-    Deserialize,
-    Serialize,
-};
+use serde::Serialize;
 
 use protocol::pin;
 
@@ -20,6 +15,7 @@ use crate::conn::{
     Conn,
     ConnReceiveError,
     ConnSendError,
+    Message,
 };
 
 
@@ -68,14 +64,13 @@ impl<Id> Pin<Id>
     pub fn read_level<'de, Request, Reply>(&mut self,
         timeout: Duration,
         conn: &mut Conn,
+        buf:  &'de mut Vec<u8>,
     )
         -> Result<(pin::Level, Option<u32>), ReadLevelError>
         where
             Id: Debug + Eq,
             Request: From<pin::ReadLevel<Id>> + Serialize,
-            Reply: TryInto<pin::ReadLevelResult<Id>, Error=Reply>
-                + Debug
-                + Deserialize<'de>,
+            Reply: Message<'de, pin::ReadLevelResult<Id>>,
     {
         // Wait for a bit, to give whatever event is expected to change the
         // level some time to happen.
@@ -83,48 +78,21 @@ impl<Id> Pin<Id>
 
         let request = pin::ReadLevel {  pin: self.pin };
         let request: Request = request.into();
-        conn.send(&request)
+        let id = conn.send(&request)
             .map_err(|err| ReadLevelError::Send(err))?;
 
-        // The compiler believes that `buf` doesn't live long enough, because
-        // the lifetime of the buffer needs to be `'de`, due to the
-        // `Deserialize` bound on `Reply`. This is wrong though: Nothing we
-        // return from this method still references the buffer, so the following
-        // `transmute`, which transmutes a mutable reference to `buf` to a
-        // mutable reference with unbounded lifetime, is sound.
-        let mut buf: Vec<u8> = Vec::new();
-        let buf = unsafe { transmute(&mut buf) };
-
-        let reply = conn.receive::<Reply>(timeout, buf)
-            .map_err(|err| ReadLevelError::Receive(err))?;
-
-        match reply.try_into() {
-            Ok(
-                pin::ReadLevelResult {
-                    pin,
-                    level,
-                    period_ms,
+        conn
+            .receive_as::<Reply, _, _>(Some(id), timeout, buf,
+                |result: pin::ReadLevelResult<Id>| {
+                    if result.pin == self.pin {
+                        Ok((result.level, result.period_ms))
+                    }
+                    else {
+                        Err(result)
+                    }
                 }
             )
-                if pin == self.pin
-            => {
-                Ok((level, period_ms))
-            }
-            Err(message) => {
-                Err(
-                    ReadLevelError::UnexpectedMessage(
-                        format!("{:?}", message)
-                    )
-                )
-            }
-            message => {
-                Err(
-                    ReadLevelError::UnexpectedMessage(
-                        format!("{:?}", message)
-                    )
-                )
-            }
-        }
+            .map_err(ReadLevelError::Receive)
     }
 }
 
@@ -133,6 +101,5 @@ impl<Id> Pin<Id>
 pub enum ReadLevelError {
     Send(ConnSendError),
     Receive(ConnReceiveError),
-    UnexpectedMessage(String),
     Timeout,
 }
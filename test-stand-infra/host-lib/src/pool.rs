@@ -0,0 +1,168 @@
+//! Exclusive access to one of multiple test stands
+//!
+//! Test cases can't share a test stand, but if multiple identical test stands
+//! are available, test cases can run on them in parallel. This module hands
+//! out test stands to test cases, making sure that each test stand is only
+//! used by one test case at a time.
+//!
+//! Test stands are locked using lock files, which means this works across
+//! threads as well as across processes.
+
+
+use std::{
+    env,
+    fs::{
+        File,
+        OpenOptions,
+    },
+    io,
+    path::PathBuf,
+    thread::sleep,
+    time::Duration,
+};
+
+use fs2::FileExt as _;
+
+use crate::{
+    config::StandConfig,
+    port::PortSelector,
+};
+
+
+/// Environment variable that overrides the directory the lock files are put in
+pub const LOCK_DIR_VAR: &str = "TEST_STAND_LOCK_DIR";
+
+
+/// Guarantees exclusive access to a test stand
+///
+/// The test stand is released when this struct is dropped, or when the process
+/// holding it exits.
+#[derive(Debug)]
+pub struct StandGuard {
+    name: String,
+    _file: File,
+}
+
+impl StandGuard {
+    /// The name of the test stand this guard provides access to
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+}
+
+
+/// Check out one of the given test stands
+///
+/// Returns the index of the test stand that was checked out, along with the
+/// guard that grants exclusive access to it. If all test stands are currently
+/// in use, this function blocks until one of them becomes free.
+///
+/// # Panics
+///
+/// Panics, if `stands` is empty.
+pub fn check_out(stands: &[StandConfig])
+    -> Result<(usize, StandGuard), PoolError>
+{
+    assert!(!stands.is_empty());
+
+    let mut files = Vec::new();
+    for stand in stands {
+        let path = lock_file(stand);
+        let file = OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(&path)
+            .map_err(|err| PoolError::LockFile(path, err))?;
+
+        files.push(file);
+    }
+
+    loop {
+        for (i, file) in files.iter().enumerate() {
+            match file.try_lock_exclusive() {
+                Ok(()) => {
+                    let guard = StandGuard {
+                        name:  stands[i].name.clone(),
+                        _file: files.swap_remove(i),
+                    };
+
+                    return Ok((i, guard));
+                }
+                Err(err) if is_contended(&err) => {
+                    continue;
+                }
+                Err(err) => {
+                    return Err(
+                        PoolError::LockFile(lock_file(&stands[i]), err)
+                    );
+                }
+            }
+        }
+
+        // All test stands are in use. Wait for a bit before trying again.
+        sleep(Duration::from_millis(10));
+    }
+}
+
+/// The path of a test stand's lock file
+///
+/// The lock file is named after the serial ports of the test stand, not the
+/// name of the test stand in the configuration file. Different test stands
+/// can have the same name, for example "default", while test suites that use
+/// the same hardware have to take turns, even if they call it different
+/// names.
+fn lock_file(stand: &StandConfig) -> PathBuf {
+    let dir = env::var_os(LOCK_DIR_VAR)
+        .map(PathBuf::from)
+        .unwrap_or_else(env::temp_dir);
+
+    let ports: Vec<_> = [&stand.target, &stand.assistant, &stand.serial]
+        .iter()
+        .filter_map(|node| node.as_ref())
+        .map(|node| port_name(&node.port))
+        .collect();
+
+    dir.join(format!("test-stand-{}.lock", ports.join("_")))
+}
+
+fn port_name(port: &PortSelector) -> String {
+    let name = match port {
+        PortSelector::Path(path) => {
+            path.clone()
+        }
+        PortSelector::Usb(usb) => {
+            let any = || "any".to_string();
+
+            format!(
+                "usb-{:04x}-{:04x}-{}-{}",
+                usb.vid,
+                usb.pid,
+                usb.serial_number.clone().unwrap_or_else(any),
+                usb.interface.map(|i| i.to_string()).unwrap_or_else(any),
+            )
+        }
+    };
+
+    // Replace everything that might not be valid in a file name, like the
+    // slashes in "/dev/ttyACM0".
+    let name: String = name
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() || c == '.' { c } else { '-' })
+        .collect();
+
+    name.trim_matches('-').to_string()
+}
+
+fn is_contended(err: &io::Error) -> bool {
+    err.kind() == io::ErrorKind::WouldBlock
+        || err.raw_os_error() == fs2::lock_contended_error().raw_os_error()
+}
+
+
+/// Error checking out a test stand
+#[derive(Debug)]
+pub enum PoolError {
+    /// Error accessing the lock file of a test stand
+    LockFile(PathBuf, io::Error),
+}
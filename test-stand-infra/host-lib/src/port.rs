@@ -0,0 +1,137 @@
+//! Selection and discovery of serial ports
+
+
+use serde::Deserialize;
+use serialport::{
+    SerialPortInfo,
+    SerialPortType,
+    UsbPortInfo,
+};
+
+
+/// Selects the serial port that a test node is connected to
+///
+/// Can be specified in the configuration file either as a plain path, or as a
+/// table that is matched against the USB serial ports that are currently
+/// available:
+///
+/// ``` toml
+/// target    = "/dev/ttyACM0"
+/// assistant = { vid = 0x0d28, pid = 0x0204, serial_number = "02360b000d..." }
+/// ```
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq)]
+#[serde(untagged)]
+pub enum PortSelector {
+    /// Path to the serial device file
+    Path(String),
+
+    /// Properties of a USB serial port
+    Usb(UsbSelector),
+}
+
+impl PortSelector {
+    /// Determine the path of the selected serial port
+    ///
+    /// If the port is selected by path, that path is returned without checking
+    /// whether it exists. Otherwise, the available serial ports are searched
+    /// for a USB port that matches the selector. Exactly one port must match.
+    pub fn resolve(&self) -> Result<String, PortResolveError> {
+        let selector = match self {
+            Self::Path(path)    => return Ok(path.clone()),
+            Self::Usb(selector) => selector,
+        };
+
+        let ports = serialport::available_ports()
+            .map_err(PortResolveError::Enumerate)?;
+
+        let mut matches = ports
+            .into_iter()
+            .filter(|port| selector.matches(port))
+            .map(|port| port.port_name);
+
+        let path = matches.next()
+            .ok_or_else(|| PortResolveError::NoMatch(selector.clone()))?;
+
+        let ambiguous: Vec<_> = matches.collect();
+        if !ambiguous.is_empty() {
+            let mut paths = vec![path];
+            paths.extend(ambiguous);
+            return Err(PortResolveError::Ambiguous(selector.clone(), paths));
+        }
+
+        Ok(path)
+    }
+}
+
+
+/// Selects a USB serial port by its properties
+///
+/// All properties that are specified must match. Properties that are left out
+/// match any port.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct UsbSelector {
+    /// USB vendor ID
+    pub vid: u16,
+
+    /// USB product ID
+    pub pid: u16,
+
+    /// The serial number of the USB device
+    pub serial_number: Option<String>,
+
+    /// The USB interface number of the serial port
+    ///
+    /// Can be used to distinguish between multiple serial ports provided by
+    /// the same USB device.
+    pub interface: Option<u8>,
+}
+
+impl UsbSelector {
+    /// Indicates whether the given serial port matches this selector
+    pub fn matches(&self, port: &SerialPortInfo) -> bool {
+        let info = match &port.port_type {
+            SerialPortType::UsbPort(info) => info,
+            _                             => return false,
+        };
+
+        info.vid == self.vid
+            && info.pid == self.pid
+            && (self.serial_number.is_none()
+                || info.serial_number == self.serial_number)
+            && (self.interface.is_none() || info.interface == self.interface)
+    }
+}
+
+
+/// Returns information about all available USB serial ports
+///
+/// Serial ports that are not connected via USB can't be selected by anything
+/// but their path, and are left out.
+pub fn list() -> Result<Vec<(String, UsbPortInfo)>, serialport::Error> {
+    let ports = serialport::available_ports()?
+        .into_iter()
+        .filter_map(|port| {
+            match port.port_type {
+                SerialPortType::UsbPort(info) => Some((port.port_name, info)),
+                _                             => None,
+            }
+        })
+        .collect();
+
+    Ok(ports)
+}
+
+
+/// Error resolving a `PortSelector` to a serial device path
+#[derive(Debug)]
+pub enum PortResolveError {
+    /// The available serial ports could not be enumerated
+    Enumerate(serialport::Error),
+
+    /// No available serial port matches the selector
+    NoMatch(UsbSelector),
+
+    /// More than one available serial port matches the selector
+    Ambiguous(UsbSelector, Vec<String>),
+}
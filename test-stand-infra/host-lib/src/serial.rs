@@ -0,0 +1,207 @@
+//! Raw serial connection to a USB/serial converter
+//!
+//! Unlike the connections to the test nodes, this doesn't speak any protocol.
+//! The converter is wired directly to a USART of the test target, which makes
+//! it an independent observer of that USART, and allows for testing it in
+//! ways the test assistant can't.
+
+
+use std::{
+    io::{
+        self,
+        Read as _,
+        Write as _,
+    },
+    time::{
+        Duration,
+        Instant,
+    },
+};
+
+use serialport::{
+    self,
+    ClearBuffer,
+    SerialPort,
+};
+
+use protocol::usart;
+
+use crate::{
+    Error,
+    config::{
+        NodeConfig,
+        Timeouts,
+    },
+};
+
+
+/// A raw serial connection to a USB/serial converter
+pub struct Serial {
+    port:   Box<dyn SerialPort>,
+    config: NodeConfig,
+}
+
+impl Serial {
+    /// Open the connection
+    ///
+    /// `path` is the path to the serial device file of the converter. The
+    /// initial line settings and default timeouts are taken from `config`.
+    pub fn new(path: &str, config: &NodeConfig)
+        -> Result<Self, SerialInitError>
+    {
+        let port = serialport::new(path, config.baud_rate)
+            .parity(config.parity.into())
+            .stop_bits(config.stop_bits.into())
+            .flow_control(config.flow_control.into())
+            .open()
+            .map_err(SerialInitError)?;
+
+        Ok(
+            Self {
+                port,
+                config: config.clone(),
+            }
+        )
+    }
+
+    /// The default timeouts for communicating through this connection
+    pub fn timeouts(&self) -> &Timeouts {
+        &self.config.timeouts
+    }
+
+    /// Change the line settings to match a USART configuration
+    ///
+    /// This is intended to match the settings of the target's USART, after it
+    /// has been configured with the same `config`. Only the baud rate and frame
+    /// format are applied. Flow control is kept as configured, as it depends
+    /// on the wiring.
+    pub fn configure<Id>(&mut self, config: &usart::Configure<Id>)
+        -> Result<(), SerialError>
+    {
+        let data_bits = match config.data_bits {
+            usart::DataBits::Seven => serialport::DataBits::Seven,
+            usart::DataBits::Eight => serialport::DataBits::Eight,
+            usart::DataBits::Nine  => {
+                return Err(SerialError::UnsupportedConfig);
+            }
+        };
+        let parity = match config.parity {
+            usart::Parity::None => serialport::Parity::None,
+            usart::Parity::Odd  => serialport::Parity::Odd,
+            usart::Parity::Even => serialport::Parity::Even,
+        };
+        let stop_bits = match config.stop_bits {
+            usart::StopBits::One => serialport::StopBits::One,
+            usart::StopBits::Two => serialport::StopBits::Two,
+        };
+
+        self.set_line(config.baud, data_bits, parity, stop_bits)
+    }
+
+    /// Restore the configured line settings and discard any received data
+    ///
+    /// This can be used to get rid of leftovers from a previous test case,
+    /// which would otherwise confuse the next one.
+    pub fn reset(&mut self) -> Result<(), SerialError> {
+        self.set_line(
+            self.config.baud_rate,
+            serialport::DataBits::Eight,
+            self.config.parity.into(),
+            self.config.stop_bits.into(),
+        )?;
+        self.clear_input()
+    }
+
+    /// Discard any data that has been received, but not read yet
+    pub fn clear_input(&mut self) -> Result<(), SerialError> {
+        self.port.clear(ClearBuffer::Input)
+            .map_err(|err| SerialError::Port(Error::Serial(err)))
+    }
+
+    /// Send data
+    pub fn send(&mut self, data: &[u8]) -> Result<(), SerialError> {
+        self.port.write_all(data)
+            .map_err(|err| SerialError::Port(Error::Io(err)))
+    }
+
+    /// Receive `len` bytes
+    ///
+    /// Returns an error, if fewer bytes arrive before the timeout. The error
+    /// contains whatever was received up to that point.
+    pub fn receive(&mut self, len: usize, timeout: Duration)
+        -> Result<Vec<u8>, SerialError>
+    {
+        let mut received = vec![0; len];
+        let mut n        = 0;
+        let     start    = Instant::now();
+
+        while n < len {
+            let remaining = match timeout.checked_sub(start.elapsed()) {
+                Some(remaining) if remaining > Duration::from_millis(0) => {
+                    remaining
+                }
+                _ => {
+                    received.truncate(n);
+                    return Err(SerialError::Timeout(received));
+                }
+            };
+
+            self.port.set_timeout(remaining)
+                .map_err(|err| SerialError::Port(Error::Serial(err)))?;
+
+            match self.port.read(&mut received[n..]) {
+                Ok(read) => {
+                    n += read;
+                }
+                Err(err) if err.kind() == io::ErrorKind::TimedOut => {
+                    // Try again, so the timeout is handled above.
+                }
+                Err(err) => {
+                    return Err(SerialError::Port(Error::Io(err)));
+                }
+            }
+        }
+
+        Ok(received)
+    }
+
+    fn set_line(&mut self,
+        baud_rate: u32,
+        data_bits: serialport::DataBits,
+        parity:    serialport::Parity,
+        stop_bits: serialport::StopBits,
+    )
+        -> Result<(), SerialError>
+    {
+        let mut set = || -> serialport::Result<()> {
+            self.port.set_baud_rate(baud_rate)?;
+            self.port.set_data_bits(data_bits)?;
+            self.port.set_parity(parity)?;
+            self.port.set_stop_bits(stop_bits)?;
+            Ok(())
+        };
+
+        set().map_err(|err| SerialError::Port(Error::Serial(err)))
+    }
+}
+
+
+/// Error opening a serial connection
+#[derive(Debug)]
+pub struct SerialInitError(pub serialport::Error);
+
+
+/// Error using a serial connection
+#[derive(Debug)]
+pub enum SerialError {
+    /// Error accessing the serial port
+    Port(Error),
+
+    /// The line settings are not supported by the serial connection
+    UnsupportedConfig,
+
+    /// Timed out waiting for data
+    ///
+    /// Contains the data that was received before the timeout.
+    Timeout(Vec<u8>),
+}
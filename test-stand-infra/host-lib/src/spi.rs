@@ -0,0 +1,237 @@
+//! API for remotely controlling an SPI peripheral on a test node
+
+
+use std::{
+    fmt::Debug,
+    time::Duration,
+};
+
+use serde::Serialize;
+
+use protocol::spi;
+
+use crate::conn::{
+    Conn,
+    ConnReceiveError,
+    ConnSendError,
+    Message,
+};
+
+
+/// API for remotely controlling an SPI peripheral on a test node
+///
+/// This struct is intended as a building block for higher-level interfaces
+/// that control the test nodes of a specific test stand.
+pub struct Spi<Id> {
+    bus: Id,
+}
+
+impl<Id> Spi<Id>
+    where Id: Copy + Debug + Eq
+{
+    /// Create a new instance of `Spi`
+    pub fn new(bus: Id) -> Self {
+        Self {
+            bus,
+        }
+    }
+
+    /// Commands the node to configure its SPI slave
+    ///
+    /// Waits for the node to confirm that it has applied the configuration.
+    pub fn configure<'de, Request, Reply>(&mut self,
+        config: spi::Config,
+        conn:   &mut Conn,
+        buf:    &'de mut Vec<u8>,
+    )
+        -> Result<(), ConfigureError>
+        where
+            Request: From<spi::Configure<Id>> + Serialize,
+            Reply: Message<'de, spi::Configured<Id>>,
+    {
+        let request: Request = spi::Configure { bus: self.bus, config }.into();
+        let id = conn.send(&request)
+            .map_err(ConfigureError::Send)?;
+
+        let timeout = conn.timeouts().reply();
+        conn
+            .receive_as::<Reply, _, _>(Some(id), timeout, buf,
+                |configured: spi::Configured<Id>| {
+                    if configured.bus == self.bus {
+                        Ok(configured.result)
+                    }
+                    else {
+                        Err(configured)
+                    }
+                }
+            )
+            .map_err(ConfigureError::Receive)?
+            .map_err(ConfigureError::Spi)
+    }
+
+    /// Commands the node to start a transfer as SPI master
+    ///
+    /// Waits for the node to complete the transfer, then returns the frames
+    /// that were received from the slave. See [`spi::Transfer`] for how frames
+    /// are encoded.
+    ///
+    /// [`spi::Transfer`]: ../../protocol/spi/struct.Transfer.html
+    pub fn transfer<'d, 'de, Request, Reply>(&mut self,
+        config:      spi::Config,
+        chip_select: spi::ChipSelect,
+        data:        &'d [u8],
+        timeout:     Duration,
+        conn:        &mut Conn,
+        buf:         &'de mut Vec<u8>,
+    )
+        -> Result<Vec<u8>, TransferError>
+        where
+            Request: From<spi::Transfer<'d, Id>> + Serialize,
+            Reply: Message<'de, spi::Completed<'de, Id>>,
+    {
+        let request = spi::Transfer {
+            bus: self.bus,
+            config,
+            chip_select,
+            data,
+        };
+        let request: Request = request.into();
+
+        let id = conn.send(&request)
+            .map_err(TransferError::Send)?;
+
+        conn
+            .receive_as::<Reply, _, _>(Some(id), timeout, buf,
+                |completed: spi::Completed<Id>| {
+                    if completed.bus == self.bus {
+                        Ok(completed.result.map(|data| data.to_vec()))
+                    }
+                    else {
+                        Err(completed)
+                    }
+                }
+            )
+            .map_err(TransferError::Receive)?
+            .map_err(TransferError::Spi)
+    }
+
+    /// Commands the node to emulate an SPI slave device
+    ///
+    /// Waits for the node to confirm that it is emulating the device.
+    pub fn emulate<'d, 'de, Request, Reply>(&mut self,
+        device: spi::Device<'d>,
+        conn:   &mut Conn,
+        buf:    &'de mut Vec<u8>,
+    )
+        -> Result<(), EmulateError>
+        where
+            Request: From<spi::Emulate<'d, Id>> + Serialize,
+            Reply: Message<'de, spi::Emulated<Id>>,
+    {
+        let request: Request = spi::Emulate { bus: self.bus, device }.into();
+        let id = conn.send(&request)
+            .map_err(EmulateError::Send)?;
+
+        let timeout = conn.timeouts().reply();
+        conn
+            .receive_as::<Reply, _, _>(Some(id), timeout, buf,
+                |emulated: spi::Emulated<Id>| {
+                    if emulated.bus == self.bus {
+                        Ok(emulated.result)
+                    }
+                    else {
+                        Err(emulated)
+                    }
+                }
+            )
+            .map_err(EmulateError::Receive)?
+            .map_err(EmulateError::Spi)
+    }
+
+    /// Reads what the node's SPI slave has received since the last call
+    pub fn read_log<'de, Request, Reply>(&mut self,
+        timeout: Duration,
+        conn:    &mut Conn,
+        buf:     &'de mut Vec<u8>,
+    )
+        -> Result<Log, ReadLogError>
+        where
+            Request: From<spi::ReadLog<Id>> + Serialize,
+            Reply: Message<'de, spi::Log<'de, Id>>,
+    {
+        let request: Request = spi::ReadLog { bus: self.bus }.into();
+        let id = conn.send(&request)
+            .map_err(ReadLogError::Send)?;
+
+        conn
+            .receive_as::<Reply, _, _>(Some(id), timeout, buf,
+                |log: spi::Log<Id>| {
+                    if log.bus == self.bus {
+                        Ok(Log {
+                            transfers:  decode_transfers(log.transfers),
+                            overflowed: log.overflowed,
+                        })
+                    }
+                    else {
+                        Err(log)
+                    }
+                }
+            )
+            .map_err(ReadLogError::Receive)
+    }
+}
+
+
+/// What an SPI slave has received
+#[derive(Debug)]
+pub struct Log {
+    /// The frames received during each transfer
+    pub transfers: Vec<Vec<u8>>,
+
+    /// Whether frames were dropped, because the node's log was full
+    pub overflowed: bool,
+}
+
+
+/// Splits the encoded transfers, as described in [`spi::Log`]
+///
+/// [`spi::Log`]: ../../protocol/spi/struct.Log.html
+fn decode_transfers(mut data: &[u8]) -> Vec<Vec<u8>> {
+    let mut transfers = Vec::new();
+
+    while let Some((&len, rest)) = data.split_first() {
+        let len = usize::min(len as usize, rest.len());
+        transfers.push(rest[..len].to_vec());
+        data = &rest[len..];
+    }
+
+    transfers
+}
+
+
+#[derive(Debug)]
+pub enum ConfigureError {
+    Send(ConnSendError),
+    Receive(ConnReceiveError),
+    Spi(spi::Error),
+}
+
+#[derive(Debug)]
+pub enum TransferError {
+    Send(ConnSendError),
+    Receive(ConnReceiveError),
+    Spi(spi::Error),
+}
+
+#[derive(Debug)]
+pub enum EmulateError {
+    Send(ConnSendError),
+    Receive(ConnReceiveError),
+    Spi(spi::Error),
+}
+
+#[derive(Debug)]
+pub enum ReadLogError {
+    Send(ConnSendError),
+    Receive(ConnReceiveError),
+}
@@ -1,14 +1,10 @@
-use std::sync::{
-    LockResult,
-    Mutex,
-    MutexGuard,
-};
-
-use lazy_static::lazy_static;
-
 use crate::{
-    assistant::Assistant,
+    assistant::{
+        Assistant,
+        AssistantError,
+    },
     config::{
+        AdcTolerances,
         Config,
         ConfigReadError,
     },
@@ -16,6 +12,16 @@ use crate::{
         Conn,
         ConnInitError,
     },
+    pool::{
+        self,
+        PoolError,
+        StandGuard,
+    },
+    port::PortResolveError,
+    serial::{
+        Serial,
+        SerialInitError,
+    },
 };
 
 
@@ -23,12 +29,6 @@ use crate::{
 ///
 /// Holds all the resources that a test case might require.
 pub struct TestStand {
-    /// Guarantees exclusive access to the test target
-    ///
-    /// Must not be dropped while this exclusive access is required. Once it is
-    /// dropped, another test case might start running immediately.
-    pub guard: LockResult<MutexGuard<'static, ()>>,
-
     /// Connection to the test target
     ///
     /// This field will be `Err`, if the test target has not been specified in
@@ -40,45 +40,84 @@ pub struct TestStand {
     /// This field will be `Err`, if the test assistant has not been specified
     /// in the configuration file.
     pub assistant: Result<Assistant, NotConfiguredError>,
+
+    /// Raw serial connection to the USB/serial converter
+    ///
+    /// This field will be `Err`, if the USB/serial converter has not been
+    /// specified in the configuration file.
+    pub serial: Result<Serial, NotConfiguredError>,
+
+    /// The tolerances of the target's ADC measurements on this test stand
+    pub adc_tolerances: AdcTolerances,
+
+    /// Guarantees exclusive access to the test stand
+    ///
+    /// Must not be dropped while this exclusive access is required. Once it is
+    /// dropped, another test case might start running on this test stand
+    /// immediately. Declared last, so it is dropped after the connections.
+    pub guard: StandGuard,
 }
 
 impl TestStand {
     /// Create a new instance of `TestStand`
+    ///
+    /// If multiple test stands are configured, checks out one that is not
+    /// currently in use by another test case, blocking until one becomes
+    /// available.
     pub fn new() -> Result<Self, TestStandInitError> {
-        // By default, Rust runs tests in parallel on multiple threads. This can
-        // be controlled through a command-line argument and an environment
-        // variable, but there doesn't seem to be a way to configure this in
-        // `Cargo.toml` or a configuration file.
-        //
-        // Let's just use a mutex here to prevent our tests from running in
-        // parallel. The returned guard will be stored as a field, meaning the
-        // mutex will be held until this struct is dropped. Concurrent
-        // instantiations of this method will block here, until the `TestStand`
-        // instance holding the mutex has been dropped.
-        //
-        // Please note that this returns a `Result` that we don't unwrap. Doing
-        // so is not necessary, as the error case just tells us that another
-        // thread holding this lock panicked. We don't care about that, as the
-        // mutex is still acquired in that case.
-        lazy_static! { static ref MUTEX: Mutex<()> = Mutex::new(()); }
-        let guard = MUTEX.lock();
-
-        let config = Config::read()
+        let mut config = Config::read()
             .map_err(|err| TestStandInitError::ConfigRead(err))?;
 
+        if config.stands.is_empty() {
+            return Err(TestStandInitError::NoStands);
+        }
+
+        // By default, Rust runs tests in parallel on multiple threads. We can't
+        // have multiple test cases use the same test stand at the same time,
+        // so let's check out a test stand that nobody else is using right now.
+        // This also works across processes, so multiple test suites can run at
+        // the same time.
+        //
+        // The returned guard will be stored as a field, meaning the test stand
+        // will be held until this struct is dropped.
+        let (i, guard) = pool::check_out(&config.stands)
+            .map_err(TestStandInitError::Pool)?;
+        let stand = config.stands.swap_remove(i);
+
         let mut target    = Err(NotConfiguredError("target"));
         let mut assistant = Err(NotConfiguredError("assistant"));
+        let mut serial    = Err(NotConfiguredError("serial"));
 
-        if let Some(path) = config.target {
+        if let Some(node) = stand.target {
+            let path = node.port.resolve()
+                .map_err(TestStandInitError::PortResolve)?;
             target = Ok(
-                Conn::new(&path)
+                Conn::new(&path, &node)
                     .map_err(|err| TestStandInitError::ConnInit(err))?
             );
         }
-        if let Some(path) = config.assistant {
-            let conn = Conn::new(&path)
+        if let Some(node) = stand.assistant {
+            let path = node.port.resolve()
+                .map_err(TestStandInitError::PortResolve)?;
+            let conn = Conn::new(&path, &node)
                 .map_err(|err| TestStandInitError::ConnInit(err))?;
-            assistant = Ok(Assistant::new(conn));
+
+            // Make sure we're actually talking to the assistant. If the port
+            // configuration is wrong, we might have opened the target instead.
+            let mut node = Assistant::new(conn);
+            let timeout  = node.timeouts().reply();
+            node.handshake(timeout)
+                .map_err(TestStandInitError::Handshake)?;
+
+            assistant = Ok(node);
+        }
+        if let Some(node) = stand.serial {
+            let path = node.port.resolve()
+                .map_err(TestStandInitError::PortResolve)?;
+            serial = Ok(
+                Serial::new(&path, &node)
+                    .map_err(TestStandInitError::SerialInit)?
+            );
         }
 
         Ok(
@@ -86,6 +125,8 @@ impl TestStand {
                 guard,
                 target,
                 assistant,
+                serial,
+                adc_tolerances: stand.adc_tolerances,
             },
         )
     }
@@ -98,8 +139,23 @@ pub enum TestStandInitError {
     /// Error reading configuration
     ConfigRead(ConfigReadError),
 
+    /// The configuration file doesn't define any test stands
+    NoStands,
+
+    /// Error checking out a test stand
+    Pool(PoolError),
+
+    /// Error determining the path of a configured serial port
+    PortResolve(PortResolveError),
+
     /// Error initializing a serial connection
     ConnInit(ConnInitError),
+
+    /// Error opening the connection to the USB/serial converter
+    SerialInit(SerialInitError),
+
+    /// The configured assistant port is not connected to the test assistant
+    Handshake(AssistantError),
 }
 
 /// The resource you tried to access was not specified in the configuration file
@@ -0,0 +1,402 @@
+//! API for remotely controlling and monitoring USARTs on a test node
+
+
+use std::{
+    fmt::Debug,
+    time::Duration,
+};
+
+use serde::Serialize;
+
+use protocol::usart;
+
+use crate::conn::{
+    Conn,
+    ConnReceiveError,
+    ConnSendError,
+    Message,
+};
+
+
+/// API for remotely controlling and monitoring a USART on a test node
+///
+/// This struct is intended as a building block for higher-level interfaces
+/// that control the test nodes of a specific test stand.
+pub struct Usart<Id> {
+    usart: Id,
+}
+
+impl<Id> Usart<Id>
+    where Id: Copy
+{
+    /// Create a new instance of `Usart`
+    pub fn new(usart: Id) -> Self {
+        Self {
+            usart,
+        }
+    }
+
+    /// Commands the node to configure the USART
+    ///
+    /// Waits for the node to confirm the new configuration, then returns the
+    /// baud rate that the USART actually runs at. This can deviate slightly
+    /// from `config.baud`, depending on the node's clock. `config.usart` is
+    /// ignored, as the command always concerns this USART.
+    pub fn configure<'de, Request, Reply>(&mut self,
+        config: usart::Configure<Id>,
+        conn:   &mut Conn,
+        buf:    &'de mut Vec<u8>,
+    )
+        -> Result<u32, ConfigureError>
+        where
+            Id: Debug + Eq,
+            Request: From<usart::Configure<Id>> + Serialize,
+            Reply: Message<'de, usart::Configured<Id>>,
+    {
+        let request = usart::Configure { usart: self.usart, .. config };
+        let request: Request = request.into();
+        let id = conn.send(&request)
+            .map_err(ConfigureError::Send)?;
+
+        let timeout = conn.timeouts().reply();
+        conn
+            .receive_as::<Reply, _, _>(Some(id), timeout, buf,
+                |configured: usart::Configured<Id>| {
+                    if configured.usart == self.usart {
+                        Ok(configured.result)
+                    }
+                    else {
+                        Err(configured)
+                    }
+                }
+            )
+            .map_err(ConfigureError::Receive)?
+            .map_err(ConfigureError::Node)
+    }
+
+    /// Commands the node to measure the timing of the USART's receive line
+    ///
+    /// The measurement compares the sender's timing against the given baud
+    /// rate and frame format. Waits until the node has started measuring. The
+    /// results can be read using [`read_timing`].
+    ///
+    /// [`read_timing`]: #method.read_timing
+    pub fn measure_timing<'de, Request, Reply>(&mut self,
+        baud:      u32,
+        data_bits: usart::DataBits,
+        parity:    usart::Parity,
+        stop_bits: usart::StopBits,
+        conn:      &mut Conn,
+        buf:       &'de mut Vec<u8>,
+    )
+        -> Result<(), TimingError>
+        where
+            Id: Debug + Eq,
+            Request: From<usart::MeasureTiming<Id>> + Serialize,
+            Reply: Message<'de, usart::TimingStarted<Id>>,
+    {
+        let request = usart::MeasureTiming {
+            usart: self.usart,
+            baud,
+            data_bits,
+            parity,
+            stop_bits,
+        };
+        let request: Request = request.into();
+        let id = conn.send(&request)
+            .map_err(TimingError::Send)?;
+
+        let timeout = conn.timeouts().reply();
+        conn
+            .receive_as::<Reply, _, _>(Some(id), timeout, buf,
+                |started: usart::TimingStarted<Id>| {
+                    if started.usart == self.usart {
+                        Ok(started.result)
+                    }
+                    else {
+                        Err(started)
+                    }
+                }
+            )
+            .map_err(TimingError::Receive)?
+            .map_err(TimingError::Node)
+    }
+
+    /// Requests the results of the node's current timing measurement
+    ///
+    /// See [`measure_timing`].
+    ///
+    /// [`measure_timing`]: #method.measure_timing
+    pub fn read_timing<'de, Request, Reply>(&mut self,
+        conn: &mut Conn,
+        buf:  &'de mut Vec<u8>,
+    )
+        -> Result<usart::Timing<Id>, TimingError>
+        where
+            Id: Debug + Eq,
+            Request: From<usart::ReadTiming<Id>> + Serialize,
+            Reply: Message<'de, usart::Timing<Id>>,
+    {
+        let request: Request = usart::ReadTiming { usart: self.usart }.into();
+        let id = conn.send(&request)
+            .map_err(TimingError::Send)?;
+
+        let timeout = conn.timeouts().reply();
+        conn
+            .receive_as::<Reply, _, _>(Some(id), timeout, buf,
+                |timing: usart::Timing<Id>| {
+                    if timing.usart == self.usart {
+                        Ok(timing)
+                    }
+                    else {
+                        Err(timing)
+                    }
+                }
+            )
+            .map_err(TimingError::Receive)
+    }
+
+    /// Commands the node to send data via the USART
+    ///
+    /// Waits for the node to confirm that it has sent, or at least queued, the
+    /// data. Returns an error, if the node can't send all of it.
+    pub fn send<'d, 'de, Request, Reply>(&mut self,
+        data: &'d [u8],
+        conn: &mut Conn,
+        buf:  &'de mut Vec<u8>,
+    )
+        -> Result<(), SendError>
+        where
+            Id: Debug + Eq,
+            Request: From<usart::Send<'d, Id>> + Serialize,
+            Reply: Message<'de, usart::Sent<Id>>,
+    {
+        let request: Request = usart::Send { usart: self.usart, data }.into();
+        let id = conn.send(&request)
+            .map_err(SendError::Send)?;
+
+        let timeout = conn.timeouts().reply();
+        conn
+            .receive_as::<Reply, _, _>(Some(id), timeout, buf,
+                |sent: usart::Sent<Id>| {
+                    if sent.usart == self.usart {
+                        Ok(sent.result)
+                    }
+                    else {
+                        Err(sent)
+                    }
+                }
+            )
+            .map_err(SendError::Receive)?
+            .map_err(SendError::Node)
+    }
+
+    /// Commands the node to send data via the USART, injecting a fault
+    ///
+    /// Waits for the node to confirm that it has sent the data. Returns an
+    /// error, if the node can't inject the fault.
+    pub fn inject<'d, 'de, Request, Reply>(&mut self,
+        fault: usart::Fault,
+        data:  &'d [u8],
+        conn:  &mut Conn,
+        buf:   &'de mut Vec<u8>,
+    )
+        -> Result<(), InjectError>
+        where
+            Id: Debug + Eq,
+            Request: From<usart::Inject<'d, Id>> + Serialize,
+            Reply: Message<'de, usart::Injected<Id>>,
+    {
+        let request = usart::Inject { usart: self.usart, fault, data };
+        let request: Request = request.into();
+        let id = conn.send(&request)
+            .map_err(InjectError::Send)?;
+
+        let timeout = conn.timeouts().reply();
+        conn
+            .receive_as::<Reply, _, _>(Some(id), timeout, buf,
+                |injected: usart::Injected<Id>| {
+                    if injected.usart == self.usart {
+                        Ok(injected.result)
+                    }
+                    else {
+                        Err(injected)
+                    }
+                }
+            )
+            .map_err(InjectError::Receive)?
+            .map_err(InjectError::Node)
+    }
+
+    /// Commands the node to stall the sender, after receiving `after` bytes
+    ///
+    /// See [`usart::Stall`] for how the sender is stalled.
+    ///
+    /// Waits for the node to confirm. Returns an error, if the node can't
+    /// stall the sender on this USART.
+    ///
+    /// [`usart::Stall`]: ../../protocol/usart/struct.Stall.html
+    pub fn stall<'de, Request, Reply>(&mut self,
+        after: u32,
+        conn:  &mut Conn,
+        buf:   &'de mut Vec<u8>,
+    )
+        -> Result<(), StallError>
+        where
+            Id: Debug + Eq,
+            Request: From<usart::Stall<Id>> + Serialize,
+            Reply: Message<'de, usart::Stalled<Id>>,
+    {
+        let request: Request = usart::Stall { usart: self.usart, after }.into();
+        let id = conn.send(&request)
+            .map_err(StallError::Send)?;
+
+        let timeout = conn.timeouts().reply();
+        conn
+            .receive_as::<Reply, _, _>(Some(id), timeout, buf,
+                |stalled: usart::Stalled<Id>| {
+                    if stalled.usart == self.usart {
+                        Ok(stalled.result)
+                    }
+                    else {
+                        Err(stalled)
+                    }
+                }
+            )
+            .map_err(StallError::Receive)?
+            .map_err(StallError::Node)
+    }
+
+    /// Commands the node to release a sender it has stalled
+    ///
+    /// Waits for the node to confirm that it reads from the USART again.
+    pub fn release<'de, Request, Reply>(&mut self,
+        conn: &mut Conn,
+        buf:  &'de mut Vec<u8>,
+    )
+        -> Result<(), StallError>
+        where
+            Id: Debug + Eq,
+            Request: From<usart::Release<Id>> + Serialize,
+            Reply: Message<'de, usart::Released<Id>>,
+    {
+        let request: Request = usart::Release { usart: self.usart }.into();
+        let id = conn.send(&request)
+            .map_err(StallError::Send)?;
+
+        let timeout = conn.timeouts().reply();
+        conn
+            .receive_as::<Reply, _, _>(Some(id), timeout, buf,
+                |released: usart::Released<Id>| {
+                    if released.usart == self.usart {
+                        Ok(released.result)
+                    }
+                    else {
+                        Err(released)
+                    }
+                }
+            )
+            .map_err(StallError::Receive)?
+            .map_err(StallError::Node)
+    }
+
+    /// Receive data that the node has received via the USART
+    ///
+    /// Waits for the node to report data that this USART has received, then
+    /// returns it. Returns an error, if anything else arrives first.
+    pub fn receive<'de, Reply>(&mut self,
+        timeout: Duration,
+        conn:    &mut Conn,
+        buf:     &'de mut Vec<u8>,
+    )
+        -> Result<&'de [u8], ReceiveError>
+        where
+            Id: Debug + Eq,
+            Reply: Message<'de, usart::Received<'de, Id>>,
+    {
+        conn
+            .receive_as::<Reply, _, _>(None, timeout, buf,
+                |received: usart::Received<'de, Id>| {
+                    if received.usart == self.usart {
+                        Ok(received.data)
+                    }
+                    else {
+                        Err(received)
+                    }
+                }
+            )
+            .map_err(ReceiveError::Receive)
+    }
+
+    /// Wait for the node to report a condition on the USART's line
+    pub fn wait_for_line_event<'de, Reply>(&mut self,
+        timeout: Duration,
+        conn:    &mut Conn,
+        buf:     &'de mut Vec<u8>,
+    )
+        -> Result<usart::LineCondition, ReceiveError>
+        where
+            Id: Debug + Eq,
+            Reply: Message<'de, usart::LineEvent<Id>>,
+    {
+        conn
+            .receive_as::<Reply, _, _>(None, timeout, buf,
+                |event: usart::LineEvent<Id>| {
+                    if event.usart == self.usart {
+                        Ok(event.condition)
+                    }
+                    else {
+                        Err(event)
+                    }
+                }
+            )
+            .map_err(ReceiveError::Receive)
+    }
+}
+
+
+#[derive(Debug)]
+pub enum ConfigureError {
+    Send(ConnSendError),
+    Receive(ConnReceiveError),
+    Node(usart::Error),
+}
+
+
+#[derive(Debug)]
+pub enum SendError {
+    Send(ConnSendError),
+    Receive(ConnReceiveError),
+    Node(usart::Error),
+}
+
+
+#[derive(Debug)]
+pub enum InjectError {
+    Send(ConnSendError),
+    Receive(ConnReceiveError),
+    Node(usart::Error),
+}
+
+
+#[derive(Debug)]
+pub enum StallError {
+    Send(ConnSendError),
+    Receive(ConnReceiveError),
+    Node(usart::Error),
+}
+
+
+#[derive(Debug)]
+pub enum TimingError {
+    Send(ConnSendError),
+    Receive(ConnReceiveError),
+    Node(usart::Error),
+}
+
+
+#[derive(Debug)]
+pub enum ReceiveError {
+    Receive(ConnReceiveError),
+    Timeout,
+}
@@ -0,0 +1,263 @@
+use std::{
+    env,
+    fs,
+    path::PathBuf,
+};
+
+use host_lib::{
+    config::{
+        AdcTolerances,
+        Config,
+        ConfigReadError,
+        Parity,
+    },
+    port::PortSelector,
+};
+
+
+#[test]
+fn it_should_read_the_top_level_configuration_without_a_profile() {
+    let config = read("top-level", CONFIG, None).unwrap();
+
+    assert_eq!(config.stands.len(), 1);
+    let stand = &config.stands[0];
+
+    let target = stand.target.as_ref().unwrap();
+    assert_eq!(target.port, path("/dev/ttyACM0"));
+
+    let assistant = stand.assistant.as_ref().unwrap();
+    assert_eq!(assistant.port, path("/dev/ttyACM1"));
+    assert_eq!(assistant.parity, Parity::Even);
+    assert_eq!(assistant.timeouts.usart_ms, 100);
+    assert_eq!(assistant.timeouts.reply_ms, 200);
+}
+
+#[test]
+fn it_should_let_the_profile_override_the_top_level_configuration() {
+    let config = read("profile", CONFIG, Some("ci")).unwrap();
+
+    assert_eq!(config.stands.len(), 1);
+    let stand = &config.stands[0];
+
+    // Replaced by the profile
+    let target = stand.target.as_ref().unwrap();
+    assert_eq!(target.port, path("/dev/ttyUSB0"));
+
+    // Merged with the profile. Only the keys that the profile sets change.
+    let assistant = stand.assistant.as_ref().unwrap();
+    assert_eq!(assistant.port, path("/dev/ttyUSB1"));
+    assert_eq!(assistant.parity, Parity::Even);
+    assert_eq!(assistant.timeouts.usart_ms, 300);
+    assert_eq!(assistant.timeouts.reply_ms, 200);
+}
+
+#[test]
+fn it_should_report_a_missing_profile() {
+    let err = read("missing-profile", CONFIG, Some("bench-3")).unwrap_err();
+
+    match err {
+        ConfigReadError::UnknownProfile { profile, .. } => {
+            assert_eq!(profile, "bench-3");
+        }
+        err => {
+            panic!("Unexpected error: {:?}", err);
+        }
+    }
+}
+
+#[test]
+fn it_should_read_named_test_stands() {
+    let config = r#"
+        [stands.bench-1]
+        target    = "/dev/ttyACM0"
+        assistant = "/dev/ttyACM1"
+
+        [stands.bench-2]
+        target    = "/dev/ttyACM2"
+        assistant = "/dev/ttyACM3"
+    "#;
+
+    let config = read("stands", config, None).unwrap();
+
+    let names: Vec<_> = config.stands
+        .iter()
+        .map(|stand| stand.name.as_str())
+        .collect();
+    assert_eq!(names, ["bench-1", "bench-2"]);
+}
+
+#[test]
+fn it_should_read_adc_tolerances() {
+    let config = r#"
+        target    = "/dev/ttyACM0"
+        assistant = "/dev/ttyACM1"
+
+        [adc_tolerances]
+        offset_mv = 80
+    "#;
+
+    let config = read("adc-tolerances", config, None).unwrap();
+
+    assert_eq!(
+        config.stands[0].adc_tolerances,
+        AdcTolerances {
+            offset_mv: 80.0,
+            .. AdcTolerances::default()
+        },
+    );
+}
+
+#[test]
+fn it_should_apply_top_level_adc_tolerances_to_named_test_stands() {
+    let config = r#"
+        [adc_tolerances]
+        offset_mv = 80
+
+        [stands.bench-1]
+        target    = "/dev/ttyACM0"
+        assistant = "/dev/ttyACM1"
+
+        [stands.bench-2]
+        target    = "/dev/ttyACM2"
+        assistant = "/dev/ttyACM3"
+
+        [stands.bench-2.adc_tolerances]
+        nonlinearity_mv = 40.5
+    "#;
+
+    let config = read("stand-adc-tolerances", config, None).unwrap();
+
+    assert_eq!(config.stands.len(), 2);
+    assert_eq!(
+        config.stands[0].adc_tolerances,
+        AdcTolerances {
+            offset_mv: 80.0,
+            .. AdcTolerances::default()
+        },
+    );
+    assert_eq!(
+        config.stands[1].adc_tolerances,
+        AdcTolerances {
+            offset_mv:       80.0,
+            nonlinearity_mv: 40.5,
+            .. AdcTolerances::default()
+        },
+    );
+}
+
+#[test]
+fn it_should_name_the_full_path_of_an_unknown_key() {
+    let config = r#"
+        [target]
+        port = "/dev/ttyACM0"
+
+        [target.timeouts]
+        usart = 100
+    "#;
+
+    assert_eq!(error_key("unknown-key", config), "target.timeouts.usart");
+}
+
+#[test]
+fn it_should_name_the_full_path_of_an_invalid_value() {
+    let config = r#"
+        [stands.bench-1.assistant]
+        port      = "/dev/ttyACM1"
+        baud_rate = "fast"
+    "#;
+
+    assert_eq!(
+        error_key("invalid-value", config),
+        "stands.bench-1.assistant.baud_rate",
+    );
+}
+
+#[test]
+fn it_should_name_the_full_path_of_an_invalid_adc_tolerance() {
+    let config = r#"
+        [stands.bench-1]
+        target = "/dev/ttyACM0"
+
+        [stands.bench-1.adc_tolerances]
+        offset_mv = "small"
+    "#;
+
+    assert_eq!(
+        error_key("invalid-adc-tolerance", config),
+        "stands.bench-1.adc_tolerances.offset_mv",
+    );
+}
+
+#[test]
+fn it_should_name_the_full_path_of_an_invalid_profile_value() {
+    let config = r#"
+        target = "/dev/ttyACM0"
+
+        [profile.ci.target]
+        port   = "/dev/ttyUSB0"
+        parity = "mark"
+    "#;
+
+    let err = read("invalid-profile-value", config, Some("ci")).unwrap_err();
+
+    match err {
+        ConfigReadError::Parse { key: Some(key), .. } => {
+            assert_eq!(key, "target.parity");
+        }
+        err => {
+            panic!("Unexpected error: {:?}", err);
+        }
+    }
+}
+
+
+const CONFIG: &str = r#"
+    target = "/dev/ttyACM0"
+
+    [assistant]
+    port     = "/dev/ttyACM1"
+    parity   = "even"
+    timeouts = { usart_ms = 100, reply_ms = 200 }
+
+    [profile.ci]
+    target = "/dev/ttyUSB0"
+
+    [profile.ci.assistant]
+    port     = "/dev/ttyUSB1"
+    timeouts = { usart_ms = 300 }
+"#;
+
+
+/// Write the configuration to a temporary file, and read it from there
+fn read(name: &str, config: &str, profile: Option<&str>)
+    -> Result<Config, ConfigReadError>
+{
+    let path = temp_path(name);
+    fs::write(&path, config).unwrap();
+
+    let result = Config::read_from(&path, profile);
+
+    fs::remove_file(&path).unwrap();
+    result
+}
+
+fn error_key(name: &str, config: &str) -> String {
+    match read(name, config, None) {
+        Err(ConfigReadError::Parse { key: Some(key), .. }) => {
+            key
+        }
+        result => {
+            panic!("Unexpected result: {:?}", result);
+        }
+    }
+}
+
+fn temp_path(name: &str) -> PathBuf {
+    env::temp_dir().join(
+        format!("host-lib-config-{}-{}.toml", name, std::process::id())
+    )
+}
+
+fn path(path: &str) -> PortSelector {
+    PortSelector::Path(path.to_string())
+}
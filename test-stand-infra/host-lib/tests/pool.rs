@@ -0,0 +1,117 @@
+use std::{
+    env,
+    fs,
+    sync::{
+        mpsc,
+        Once,
+    },
+    thread,
+    time::Duration,
+};
+
+use host_lib::{
+    config::{
+        AdcTolerances,
+        NodeConfig,
+        StandConfig,
+    },
+    pool::{
+        self,
+        LOCK_DIR_VAR,
+    },
+    port::PortSelector,
+};
+
+
+#[test]
+fn it_should_release_a_test_stand_when_the_guard_is_dropped() {
+    let stands = [stand("default", "acquire-release")];
+
+    let (i, guard) = pool::check_out(&stands).unwrap();
+    assert_eq!(i, 0);
+    assert_eq!(guard.name(), "default");
+    drop(guard);
+
+    let (i, _guard) = pool::check_out(&stands).unwrap();
+    assert_eq!(i, 0);
+}
+
+#[test]
+fn it_should_check_out_a_free_test_stand() {
+    let stands = [
+        stand("bench-1", "free-1"),
+        stand("bench-2", "free-2"),
+    ];
+
+    let (first, _first) = pool::check_out(&stands).unwrap();
+    let (second, _second) = pool::check_out(&stands).unwrap();
+
+    assert_ne!(first, second);
+}
+
+#[test]
+fn it_should_wait_until_a_test_stand_is_released() {
+    let stands = [stand("default", "contention")];
+
+    let (_, guard) = pool::check_out(&stands).unwrap();
+
+    let (tx, rx) = mpsc::channel();
+    let waiting = stands.clone();
+    thread::spawn(move || {
+        let result = pool::check_out(&waiting).map(|(i, _)| i);
+        tx.send(result).unwrap();
+    });
+
+    // The test stand is still checked out, so the other thread has to wait.
+    assert!(rx.recv_timeout(Duration::from_millis(100)).is_err());
+
+    drop(guard);
+    let i = rx.recv_timeout(Duration::from_secs(1)).unwrap().unwrap();
+    assert_eq!(i, 0);
+}
+
+#[test]
+fn it_should_not_lock_test_stands_with_the_same_name_but_other_ports() {
+    let first = [stand("default", "same-name-1")];
+    let second = [stand("default", "same-name-2")];
+
+    let (tx, rx) = mpsc::channel();
+    let (_, _guard) = pool::check_out(&first).unwrap();
+    thread::spawn(move || {
+        let result = pool::check_out(&second).map(|(i, _)| i);
+        tx.send(result).unwrap();
+    });
+
+    let i = rx.recv_timeout(Duration::from_secs(1)).unwrap().unwrap();
+    assert_eq!(i, 0);
+}
+
+
+/// Create a test stand whose ports are unique to the calling test
+///
+/// The tests run in parallel, and each test must only contend with itself.
+fn stand(name: &str, ports: &str) -> StandConfig {
+    static LOCK_DIR: Once = Once::new();
+    LOCK_DIR.call_once(|| {
+        let dir = env::temp_dir().join(
+            format!("host-lib-pool-{}", std::process::id())
+        );
+        fs::create_dir_all(&dir).unwrap();
+        env::set_var(LOCK_DIR_VAR, dir);
+    });
+
+    let node = |node: &str| {
+        Some(NodeConfig::new(PortSelector::Path(
+            format!("/dev/test-{}-{}", ports, node)
+        )))
+    };
+
+    StandConfig {
+        name:      name.to_string(),
+        target:    node("target"),
+        assistant: node("assistant"),
+        serial:    None,
+
+        adc_tolerances: AdcTolerances::default(),
+    }
+}
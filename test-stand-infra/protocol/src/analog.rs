@@ -0,0 +1,40 @@
+//! Generic protocol related to analog signals
+//!
+//! The types in this module are not specific to any test stand setup, and can
+//! be re-used for different test stands.
+
+
+use serde::{
+    Deserialize,
+    Serialize,
+};
+
+
+/// Sent by the host to command a test node to output an analog voltage
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, Eq, PartialEq)]
+pub struct SetOutput<Id> {
+    /// The analog output whose voltage should be set
+    pub channel: Id,
+
+    /// The requested voltage, in millivolts
+    ///
+    /// The test node outputs the closest voltage it can produce. Voltages
+    /// outside of its output range are clamped.
+    pub millivolts: u16,
+}
+
+
+/// Sent by a test node in response to a `SetOutput` message
+///
+/// The voltage is being output, by the time this message is sent.
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, Eq, PartialEq)]
+pub struct OutputSet<Id> {
+    /// The analog output whose voltage has been set
+    pub channel: Id,
+
+    /// The nominal voltage that is being output, in millivolts
+    ///
+    /// This can differ from the requested voltage, due to the resolution and
+    /// range of the output.
+    pub millivolts: u16,
+}
@@ -0,0 +1,189 @@
+//! Generic protocol related to I2C
+//!
+//! The types in this module are not specific to any test stand setup, and can
+//! be re-used for different test stands.
+
+
+use serde::{
+    Deserialize,
+    Serialize,
+};
+
+
+/// Sent by the host to command a test node to write to an I2C slave
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, Eq, PartialEq)]
+pub struct Write<'r, Id> {
+    /// The I2C bus to use
+    pub bus: Id,
+
+    /// The 7-bit address of the slave
+    pub address: u8,
+
+    /// The data to write
+    pub data: &'r [u8],
+}
+
+
+/// Sent by the host to command a test node to read from an I2C slave
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, Eq, PartialEq)]
+pub struct Read<Id> {
+    /// The I2C bus to use
+    pub bus: Id,
+
+    /// The 7-bit address of the slave
+    pub address: u8,
+
+    /// The number of bytes to read
+    pub len: u16,
+}
+
+
+/// Sent by the host to command a test node to write to, then read from a slave
+///
+/// The read follows the write after a repeated start condition, without
+/// releasing the bus in between. This is how registers of most I2C sensors are
+/// read: The data that is written selects the register, the data that is read
+/// is the register's value.
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, Eq, PartialEq)]
+pub struct WriteRead<'r, Id> {
+    /// The I2C bus to use
+    pub bus: Id,
+
+    /// The 7-bit address of the slave
+    pub address: u8,
+
+    /// The data to write
+    pub data: &'r [u8],
+
+    /// The number of bytes to read after writing
+    pub len: u16,
+}
+
+
+/// Sent by a test node in response to `Write`, `Read`, or `WriteRead`
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, Eq, PartialEq)]
+pub struct Completed<'r, Id> {
+    /// The I2C bus that was used
+    pub bus: Id,
+
+    /// The data that was read, or the reason the transaction failed
+    ///
+    /// The data is empty, if the transaction didn't read anything.
+    #[serde(borrow)]
+    pub result: Result<&'r [u8], Error>,
+}
+
+
+/// The reason an I2C transaction failed
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, Eq, PartialEq)]
+pub enum Error {
+    /// No slave acknowledged the address
+    AddressNack,
+
+    /// The slave didn't acknowledge the data that was written
+    DataNack,
+
+    /// Another master took over the bus
+    ArbitrationLost,
+
+    /// The transaction is larger than the test node can handle
+    TooLong,
+
+    /// Any other error reported by the I2C peripheral
+    Other,
+}
+
+
+/// Sent by the host to command a test node to emulate an I2C slave device
+///
+/// The test node keeps emulating the device until it is reset, or commanded to
+/// emulate another one.
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, Eq, PartialEq)]
+pub struct Emulate<'r, Id> {
+    /// The I2C bus the device is connected to
+    pub bus: Id,
+
+    /// The device to emulate
+    #[serde(borrow)]
+    pub device: Device<'r>,
+}
+
+
+/// Sent by a test node in response to `Emulate`
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, Eq, PartialEq)]
+pub struct Emulated<Id> {
+    /// The I2C bus the device is connected to
+    pub bus: Id,
+
+    /// Whether the test node is emulating the device now, or why not
+    ///
+    /// The error is [`Error::TooLong`], if the device has more registers than
+    /// the test node can emulate.
+    ///
+    /// [`Error::TooLong`]: enum.Error.html#variant.TooLong
+    pub result: Result<(), Error>,
+}
+
+
+/// Sent by the host to ask a test node for the registers of an emulated device
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, Eq, PartialEq)]
+pub struct ReadRegisters<Id> {
+    /// The I2C bus the device is connected to
+    pub bus: Id,
+}
+
+
+/// Sent by a test node in response to `ReadRegisters`
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, Eq, PartialEq)]
+pub struct Registers<'r, Id> {
+    /// The I2C bus the device is connected to
+    pub bus: Id,
+
+    /// The current contents of the device's registers
+    pub registers: &'r [u8],
+}
+
+
+/// An I2C slave device with a register map, like an EEPROM or a sensor
+///
+/// The first byte the master writes in a transaction selects a register. Any
+/// further bytes are written to the selected register. Reads return the value
+/// of the selected register.
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, Eq, PartialEq)]
+pub struct Device<'r> {
+    /// The 7-bit address the device responds to
+    pub address: u8,
+
+    /// The initial contents of the registers
+    ///
+    /// Registers are numbered from zero. Reading from a register that doesn't
+    /// exist returns `0xff`, writing to it has no effect. Test nodes may limit
+    /// the number of registers they can emulate.
+    pub registers: &'r [u8],
+
+    /// The numbers of the registers that the master can't write to
+    ///
+    /// Writes to these registers are acknowledged, but ignored. All other
+    /// registers take the value that was written.
+    pub read_only: &'r [u8],
+
+    /// Whether the selected register advances after each byte
+    pub auto_increment: bool,
+
+    /// A fault that the device should exhibit, if any
+    pub fault: Option<Fault>,
+}
+
+
+/// A fault that an emulated I2C slave device can exhibit
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, Eq, PartialEq)]
+pub enum Fault {
+    /// Don't acknowledge the Nth byte that the master writes
+    ///
+    /// Bytes are counted from zero, per transaction. The address is not
+    /// counted, so `NackByte(0)` refers to the byte that selects the register.
+    NackByte(u16),
+
+    /// Hold the clock low for this many microseconds before each byte
+    StretchClock(u32),
+}
@@ -1,10 +1,16 @@
 #![no_std]
 
 
-pub mod pin;
+#[macro_use]
+mod macros;
 
+pub mod analog;
+pub mod i2c;
+pub mod pin;
+pub mod rpc;
+pub mod spi;
+pub mod usart;
 
-use core::convert::TryFrom;
 
 use serde::{
     Deserialize,
@@ -16,16 +22,103 @@ use serde::{
 #[derive(Debug, Deserialize, Serialize)]
 pub enum HostToAssistant<'r> {
     /// Instruct the assistant to send data to the target via USART
+    ///
+    /// The assistant replies with `UsartSent`.
     SendUsart {
         mode: UsartMode,
         data: &'r [u8],
     },
 
+    /// Instruct the assistant to change the baud rate and frame format of a
+    /// USART connected to the target
+    ///
+    /// The assistant replies with `UsartConfigured`. The configuration is kept
+    /// until the assistant is reset.
+    ConfigureUsart(usart::Configure<UsartMode>),
+
+    /// Instruct the assistant to stall the target's USART via flow control
+    ///
+    /// While the stall is in effect, the assistant's RTS signal replaces the
+    /// CTS pin that is otherwise controlled through `SetPin`. The assistant
+    /// replies with `UsartStalled`.
+    StallUsart(usart::Stall<UsartMode>),
+
+    /// Instruct the assistant to release the target's stalled USART
+    ///
+    /// The assistant replies with `UsartReleased`.
+    ReleaseUsart(usart::Release<UsartMode>),
+
+    /// Instruct the assistant to measure the timing of the target's USART
+    ///
+    /// The assistant replies with `UsartTimingStarted`, once it has started
+    /// measuring.
+    MeasureUsartTiming(usart::MeasureTiming<UsartMode>),
+
+    /// Ask the assistant for the results of the current timing measurement
+    ReadUsartTiming(usart::ReadTiming<UsartMode>),
+
+    /// Instruct the assistant to send data to the target with a line fault
+    InjectUsartFault(#[serde(borrow)] usart::Inject<'r, UsartMode>),
+
     /// Instruct the assistant to change level of the target's input pin
     SetPin(pin::SetLevel<OutputPin>),
 
     /// Ask the assistant for the current level of a pin
     ReadPin(pin::ReadLevel<InputPin>),
+
+    /// Instruct the assistant to output an analog voltage
+    ///
+    /// The assistant replies with `AnalogSet`. The voltage is output until the
+    /// assistant is reset.
+    SetAnalog(analog::SetOutput<AnalogOutput>),
+
+    /// Instruct the assistant to configure the SPI slave connected to the
+    /// target's SPI master
+    ConfigureSpi(spi::Configure<()>),
+
+    /// Instruct the assistant to start an SPI transfer as master
+    ///
+    /// The target must have been put into SPI slave mode before.
+    SpiTransfer(#[serde(borrow)] spi::Transfer<'r, ()>),
+
+    /// Instruct the assistant to emulate an SPI slave device
+    ///
+    /// The device is connected to the target's SPI master. The assistant
+    /// emulates it until it is reset.
+    EmulateSpiDevice(#[serde(borrow)] spi::Emulate<'r, ()>),
+
+    /// Ask the assistant what its SPI slave has received from the target
+    ReadSpiLog(spi::ReadLog<()>),
+
+    /// Instruct the assistant to emulate an I2C slave device
+    ///
+    /// The device is connected to the target's I2C master. The assistant
+    /// emulates it until it is reset.
+    EmulateI2cDevice(#[serde(borrow)] i2c::Emulate<'r, ()>),
+
+    /// Ask the assistant for the registers of the emulated I2C slave device
+    ReadI2cRegisters(i2c::ReadRegisters<()>),
+
+    /// Instruct the assistant to write to an I2C slave, as master
+    I2cWrite(#[serde(borrow)] i2c::Write<'r, ()>),
+
+    /// Instruct the assistant to read from an I2C slave, as master
+    I2cRead(i2c::Read<()>),
+
+    /// Instruct the assistant to write to, then read from an I2C slave, as
+    /// master
+    I2cWriteRead(#[serde(borrow)] i2c::WriteRead<'r, ()>),
+
+    /// Ask the node to identify itself
+    ///
+    /// Used by the host to confirm that it has opened the right serial port.
+    Identify,
+
+    /// Instruct the assistant to reset all pins, peripherals, and buffers
+    ///
+    /// The assistant confirms with `ResetComplete`, once it is back in its
+    /// initial state.
+    Reset,
 }
 
 impl From<pin::SetLevel<OutputPin>> for HostToAssistant<'_> {
@@ -40,6 +133,144 @@ impl From<pin::ReadLevel<InputPin>> for HostToAssistant<'_> {
     }
 }
 
+impl From<analog::SetOutput<AnalogOutput>> for HostToAssistant<'_> {
+    fn from(set_output: analog::SetOutput<AnalogOutput>) -> Self {
+        Self::SetAnalog(set_output)
+    }
+}
+
+impl From<spi::Configure<()>> for HostToAssistant<'_> {
+    fn from(configure: spi::Configure<()>) -> Self {
+        Self::ConfigureSpi(configure)
+    }
+}
+
+impl<'r> From<spi::Transfer<'r, ()>> for HostToAssistant<'r> {
+    fn from(transfer: spi::Transfer<'r, ()>) -> Self {
+        Self::SpiTransfer(transfer)
+    }
+}
+
+impl<'r> From<spi::Emulate<'r, ()>> for HostToAssistant<'r> {
+    fn from(emulate: spi::Emulate<'r, ()>) -> Self {
+        Self::EmulateSpiDevice(emulate)
+    }
+}
+
+impl From<spi::ReadLog<()>> for HostToAssistant<'_> {
+    fn from(read_log: spi::ReadLog<()>) -> Self {
+        Self::ReadSpiLog(read_log)
+    }
+}
+
+impl<'r> From<i2c::Emulate<'r, ()>> for HostToAssistant<'r> {
+    fn from(emulate: i2c::Emulate<'r, ()>) -> Self {
+        Self::EmulateI2cDevice(emulate)
+    }
+}
+
+impl From<i2c::ReadRegisters<()>> for HostToAssistant<'_> {
+    fn from(read_registers: i2c::ReadRegisters<()>) -> Self {
+        Self::ReadI2cRegisters(read_registers)
+    }
+}
+
+impl<'r> From<i2c::Write<'r, ()>> for HostToAssistant<'r> {
+    fn from(write: i2c::Write<'r, ()>) -> Self {
+        Self::I2cWrite(write)
+    }
+}
+
+impl From<i2c::Read<()>> for HostToAssistant<'_> {
+    fn from(read: i2c::Read<()>) -> Self {
+        Self::I2cRead(read)
+    }
+}
+
+impl<'r> From<i2c::WriteRead<'r, ()>> for HostToAssistant<'r> {
+    fn from(write_read: i2c::WriteRead<'r, ()>) -> Self {
+        Self::I2cWriteRead(write_read)
+    }
+}
+
+impl<'r> From<usart::Send<'r, UsartMode>> for HostToAssistant<'r> {
+    fn from(send: usart::Send<'r, UsartMode>) -> Self {
+        Self::SendUsart {
+            mode: send.usart,
+            data: send.data,
+        }
+    }
+}
+
+impl From<usart::Configure<UsartMode>> for HostToAssistant<'_> {
+    fn from(configure: usart::Configure<UsartMode>) -> Self {
+        Self::ConfigureUsart(configure)
+    }
+}
+
+impl From<usart::Stall<UsartMode>> for HostToAssistant<'_> {
+    fn from(stall: usart::Stall<UsartMode>) -> Self {
+        Self::StallUsart(stall)
+    }
+}
+
+impl From<usart::Release<UsartMode>> for HostToAssistant<'_> {
+    fn from(release: usart::Release<UsartMode>) -> Self {
+        Self::ReleaseUsart(release)
+    }
+}
+
+impl From<usart::MeasureTiming<UsartMode>> for HostToAssistant<'_> {
+    fn from(measure: usart::MeasureTiming<UsartMode>) -> Self {
+        Self::MeasureUsartTiming(measure)
+    }
+}
+
+impl From<usart::ReadTiming<UsartMode>> for HostToAssistant<'_> {
+    fn from(read: usart::ReadTiming<UsartMode>) -> Self {
+        Self::ReadUsartTiming(read)
+    }
+}
+
+impl<'r> From<usart::Inject<'r, UsartMode>> for HostToAssistant<'r> {
+    fn from(inject: usart::Inject<'r, UsartMode>) -> Self {
+        Self::InjectUsartFault(inject)
+    }
+}
+
+try_from_message!(HostToAssistant {
+    ConfigureUsart     => usart::Configure<UsartMode>,
+    StallUsart         => usart::Stall<UsartMode>,
+    ReleaseUsart       => usart::Release<UsartMode>,
+    MeasureUsartTiming => usart::MeasureTiming<UsartMode>,
+    ReadUsartTiming    => usart::ReadTiming<UsartMode>,
+    InjectUsartFault   => usart::Inject<'r, UsartMode>,
+    SetPin             => pin::SetLevel<OutputPin>,
+    ReadPin            => pin::ReadLevel<InputPin>,
+    SetAnalog          => analog::SetOutput<AnalogOutput>,
+    ConfigureSpi       => spi::Configure<()>,
+    SpiTransfer        => spi::Transfer<'r, ()>,
+    EmulateSpiDevice   => spi::Emulate<'r, ()>,
+    ReadSpiLog         => spi::ReadLog<()>,
+    EmulateI2cDevice   => i2c::Emulate<'r, ()>,
+    ReadI2cRegisters   => i2c::ReadRegisters<()>,
+    I2cWrite           => i2c::Write<'r, ()>,
+    I2cRead            => i2c::Read<()>,
+    I2cWriteRead       => i2c::WriteRead<'r, ()>,
+});
+
+try_from_message!(HostToAssistant => SendUsart<'r> {
+    HostToAssistant::SendUsart { mode, data } => SendUsart { mode, data }
+});
+
+try_from_message!(HostToAssistant => Identify {
+    HostToAssistant::Identify => Identify
+});
+
+try_from_message!(HostToAssistant => Reset {
+    HostToAssistant::Reset => Reset
+});
+
 
 /// A message from the test assistant to the test suite on the host
 #[derive(Debug, Deserialize, Serialize)]
@@ -50,27 +281,177 @@ pub enum AssistantToHost<'r> {
         data: &'r [u8],
     },
 
+    /// Reply to a `SendUsart` request
+    UsartSent(usart::Sent<UsartMode>),
+
+    /// Reply to a `ConfigureUsart` request
+    UsartConfigured(usart::Configured<UsartMode>),
+
+    /// Reply to a `StallUsart` request
+    UsartStalled(usart::Stalled<UsartMode>),
+
+    /// Reply to a `ReleaseUsart` request
+    UsartReleased(usart::Released<UsartMode>),
+
+    /// Reply to an `InjectUsartFault` request
+    UsartInjected(usart::Injected<UsartMode>),
+
+    /// Reply to a `MeasureUsartTiming` request
+    UsartTimingStarted(usart::TimingStarted<UsartMode>),
+
+    /// Reply to a `ReadUsartTiming` request
+    UsartTiming(usart::Timing<UsartMode>),
+
     /// Notify the host that the level of a pin has changed
     ReadPinResult(Option<pin::ReadLevelResult<InputPin>>),
+
+    /// Reply to a `SetAnalog` request
+    AnalogSet(analog::OutputSet<AnalogOutput>),
+
+    /// Reply to an `I2cWrite`, `I2cRead`, or `I2cWriteRead` request
+    I2cCompleted(#[serde(borrow)] i2c::Completed<'r, ()>),
+
+    /// Reply to an `EmulateI2cDevice` request
+    I2cEmulated(i2c::Emulated<()>),
+
+    /// Reply to a `ReadI2cRegisters` request
+    I2cRegisters(#[serde(borrow)] i2c::Registers<'r, ()>),
+
+    /// Reply to an `SpiTransfer` request
+    SpiCompleted(#[serde(borrow)] spi::Completed<'r, ()>),
+
+    /// Reply to a `ConfigureSpi` request
+    SpiConfigured(spi::Configured<()>),
+
+    /// Reply to an `EmulateSpiDevice` request
+    SpiEmulated(spi::Emulated<()>),
+
+    /// Reply to a `ReadSpiLog` request
+    SpiLog(#[serde(borrow)] spi::Log<'r, ()>),
+
+    /// Reply to an `Identify` request
+    Identity(Node),
+
+    /// Confirms that the assistant has been reset
+    ResetComplete,
 }
 
-impl<'r> TryFrom<AssistantToHost<'r>> for pin::ReadLevelResult<InputPin> {
-    type Error = AssistantToHost<'r>;
+try_from_message!(AssistantToHost {
+    UsartSent       => usart::Sent<UsartMode>,
+    UsartConfigured => usart::Configured<UsartMode>,
+    UsartStalled    => usart::Stalled<UsartMode>,
+    UsartReleased   => usart::Released<UsartMode>,
+    UsartInjected   => usart::Injected<UsartMode>,
+    UsartTimingStarted => usart::TimingStarted<UsartMode>,
+    UsartTiming     => usart::Timing<UsartMode>,
+    AnalogSet       => analog::OutputSet<AnalogOutput>,
+    I2cCompleted    => i2c::Completed<'r, ()>,
+    I2cEmulated     => i2c::Emulated<()>,
+    I2cRegisters    => i2c::Registers<'r, ()>,
+    SpiCompleted    => spi::Completed<'r, ()>,
+    SpiConfigured   => spi::Configured<()>,
+    SpiEmulated     => spi::Emulated<()>,
+    SpiLog          => spi::Log<'r, ()>,
+});
 
-    fn try_from(value: AssistantToHost<'r>) -> Result<Self, Self::Error> {
-        match value {
-            AssistantToHost::ReadPinResult(Some(result)) => {
-                Ok(result)
-            }
-            _ => {
-                Err(value)
-            }
-        }
+try_from_message!(AssistantToHost => usart::Received<'r, UsartMode> {
+    AssistantToHost::UsartReceive { mode, data } => {
+        usart::Received { usart: mode, data }
     }
+});
+
+try_from_message!(AssistantToHost => pin::ReadLevelResult<InputPin> {
+    AssistantToHost::ReadPinResult(Some(result)) => result
+});
+
+
+/// The contents of a request to send data via USART
+///
+/// Can be extracted from the `SendUsart` variant of a request, to pass it to
+/// the handler of that request.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct SendUsart<'r> {
+    pub mode: UsartMode,
+    pub data: &'r [u8],
+}
+
+/// A request for the node to identify itself
+///
+/// Can be extracted from the `Identify` variant of a request, to pass it to the
+/// handler of that request.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Identify;
+
+/// A request for the node to reset itself
+///
+/// Can be extracted from the `Reset` variant of a request, to pass it to the
+/// handler of that request.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Reset;
+
+
+/// Identifies the kind of test node
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, Eq, PartialEq)]
+pub enum Node {
+    Target,
+    Assistant,
+}
+
+
+/// Status report that a test node sends to the host periodically
+///
+/// As long as these keep arriving, the host knows that the node is still
+/// running, even if it doesn't reply to a request.
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, Eq, PartialEq)]
+pub struct Heartbeat {
+    /// Time since the node started, in milliseconds
+    pub uptime_ms: u32,
+
+    /// Number of errors that occurred while receiving via USART
+    pub rx_errors: u32,
+
+    /// Highest number of bytes that were waiting in a receive queue at once
+    ///
+    /// Only accounts for the time since the previous heartbeat.
+    pub queue_high_water: u32,
+
+    /// Number of pin events that were dropped, because a queue was full
+    ///
+    /// Always zero for nodes that don't capture pin events.
+    pub events_dropped: u32,
+}
+
+
+/// Information about a panic in the firmware of a test node
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, Eq, PartialEq)]
+pub struct Panic<'r> {
+    /// Where in the firmware's source code the panic occurred, if known
+    #[serde(borrow)]
+    pub location: Option<Location<'r>>,
+
+    /// The panic message
+    ///
+    /// Might be truncated, if it doesn't fit into the node's buffer.
+    pub message: &'r str,
+}
+
+/// A location in the source code of a firmware
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, Eq, PartialEq)]
+pub struct Location<'r> {
+    /// The path of the source file
+    ///
+    /// Might be shortened to its end, if it doesn't fit into the node's buffer.
+    pub file:   &'r str,
+    pub line:   u32,
+    pub column: u32,
 }
 
 
 /// Specifies which mode a USART transmission uses
+///
+/// Also serves as the USART ID for the generic types in [`usart`].
+///
+/// [`usart`]: usart/index.html
 #[derive(Clone, Copy, Debug, Deserialize, Serialize, Eq, PartialEq)]
 pub enum UsartMode {
     Regular,
@@ -95,4 +476,12 @@ pub enum OutputPin {
     Pin5,
     Cts,
     Red,
+    TargetReset,
+}
+
+/// Represents one of the analog outputs of the assistant
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, Eq, PartialEq)]
+pub enum AnalogOutput {
+    /// The output that is connected to the target's analog input
+    Adc,
 }
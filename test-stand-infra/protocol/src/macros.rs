@@ -0,0 +1,53 @@
+/// Implements `TryFrom` for the types that message variants carry
+///
+/// This allows the firmware and the host to extract a specific message from
+/// the message enums, without matching on the enum themselves. The enum is
+/// returned as the error, if the message is of a different variant.
+///
+/// The first form is for variants that carry their type as their only field:
+///
+/// ``` ignore
+/// try_from_message!(HostToAssistant {
+///     ConfigureSpi => spi::Configure<()>,
+///     SpiTransfer  => spi::Transfer<'r, ()>,
+/// });
+/// ```
+///
+/// The second form is for any other variant. It takes a pattern that matches
+/// the variant, and an expression that creates the type from it:
+///
+/// ``` ignore
+/// try_from_message!(HostToAssistant => SendUsart<'r> {
+///     HostToAssistant::SendUsart { mode, data } => SendUsart { mode, data }
+/// });
+/// ```
+///
+/// The lifetime of the message enum is always called `'r`.
+#[macro_export]
+macro_rules! try_from_message {
+    ($enum:ident { $($variant:ident => $type:ty),* $(,)? }) => {
+        $(
+            $crate::try_from_message!($enum => $type {
+                $enum::$variant(message) => message
+            });
+        )*
+    };
+    ($enum:ident => $type:ty { $pattern:pat => $value:expr }) => {
+        impl<'r> ::core::convert::TryFrom<$enum<'r>> for $type {
+            type Error = $enum<'r>;
+
+            fn try_from(value: $enum<'r>)
+                -> ::core::result::Result<Self, Self::Error>
+            {
+                match value {
+                    $pattern => {
+                        Ok($value)
+                    }
+                    _ => {
+                        Err(value)
+                    }
+                }
+            }
+        }
+    };
+}
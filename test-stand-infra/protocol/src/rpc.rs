@@ -0,0 +1,118 @@
+//! Envelopes that match replies to the requests they belong to
+//!
+//! Every message from the host to a test node is wrapped in a [`Request`],
+//! every message from a test node to the host in a [`Response`]. The types in
+//! this module are not specific to any test stand setup, and can be re-used
+//! for different test stands.
+//!
+//! [`Request`]: struct.Request.html
+//! [`Response`]: struct.Response.html
+
+
+use serde::{
+    Deserialize,
+    Serialize,
+};
+
+use crate::{
+    Heartbeat,
+    Panic,
+};
+
+
+/// Identifies a request, so the reply to it can be recognized
+pub type RequestId = u16;
+
+
+/// Wraps a message from the host to a test node
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, Eq, PartialEq)]
+pub struct Request<T> {
+    /// The ID of this request
+    ///
+    /// The host uses a different ID for each request it sends.
+    pub id: RequestId,
+
+    /// The message itself
+    pub message: T,
+}
+
+
+/// Wraps a message from a test node to the host
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, Eq, PartialEq)]
+pub struct Response<T> {
+    /// What kind of message this is
+    ///
+    /// Comes first, so the host can decide how to decode the rest of the
+    /// response.
+    pub kind: Kind,
+
+    /// The message, or the reason why the request couldn't be handled
+    pub message: Result<T, Error>,
+}
+
+impl<T> Response<T> {
+    /// Create a response that isn't a reply to any request
+    pub fn notification(message: T) -> Self {
+        Self {
+            kind:    Kind::Notification,
+            message: Ok(message),
+        }
+    }
+}
+
+impl Response<Heartbeat> {
+    /// Create a response that carries a heartbeat
+    pub fn heartbeat(heartbeat: Heartbeat) -> Self {
+        Self {
+            kind:    Kind::Heartbeat,
+            message: Ok(heartbeat),
+        }
+    }
+}
+
+impl<'r> Response<Panic<'r>> {
+    /// Create a response that reports a panic
+    pub fn panic(panic: Panic<'r>) -> Self {
+        Self {
+            kind:    Kind::Panic,
+            message: Ok(panic),
+        }
+    }
+}
+
+
+/// The kind of a [`Response`]
+///
+/// Heartbeats and panics are the same for every test stand setup, so their
+/// messages have types of their own. All other responses carry a message of
+/// the type that is specific to the test stand setup.
+///
+/// [`Response`]: struct.Response.html
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, Eq, PartialEq)]
+pub enum Kind {
+    /// A reply to the request with the given ID
+    Reply(RequestId),
+
+    /// A message the test node sent on its own accord
+    ///
+    /// For example, to notify the host of received data.
+    Notification,
+
+    /// The message is a [`Heartbeat`]
+    ///
+    /// [`Heartbeat`]: ../struct.Heartbeat.html
+    Heartbeat,
+
+    /// The message is a [`Panic`]
+    ///
+    /// [`Panic`]: ../struct.Panic.html
+    Panic,
+}
+
+
+/// Sent by a test node instead of a reply, if it couldn't handle a request
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, Eq, PartialEq)]
+pub enum Error {
+    /// The test node doesn't support this kind of request
+    Unsupported,
+}
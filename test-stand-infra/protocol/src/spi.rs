@@ -0,0 +1,285 @@
+//! Generic protocol related to SPI
+//!
+//! The types in this module are not specific to any test stand setup, and can
+//! be re-used for different test stands.
+
+
+use serde::{
+    Deserialize,
+    Serialize,
+};
+
+
+/// Sent by the host to command a test node to configure an SPI slave
+///
+/// SPI masters are configured as part of each [`Transfer`] instead.
+///
+/// [`Transfer`]: struct.Transfer.html
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, Eq, PartialEq)]
+pub struct Configure<Id> {
+    /// The SPI bus that should be configured
+    pub bus: Id,
+
+    /// The configuration to apply
+    pub config: Config,
+}
+
+
+/// Sent by a test node in response to `Configure`
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, Eq, PartialEq)]
+pub struct Configured<Id> {
+    /// The SPI bus that was configured
+    pub bus: Id,
+
+    /// Whether the configuration was applied, or why not
+    pub result: Result<(), Error>,
+}
+
+
+/// Sent by the host to command a test node to start an SPI transfer as master
+///
+/// SPI is full-duplex, so the slave's reply is received while `data` is sent.
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, Eq, PartialEq)]
+pub struct Transfer<'r, Id> {
+    /// The SPI bus to use
+    pub bus: Id,
+
+    /// The configuration to apply before the transfer
+    pub config: Config,
+
+    /// How to drive the chip select signal during the transfer
+    pub chip_select: ChipSelect,
+
+    /// The frames to send
+    ///
+    /// Each frame takes up one byte. Frames larger than 8 bits take up two
+    /// bytes, least significant byte first.
+    pub data: &'r [u8],
+}
+
+
+/// Sent by a test node in response to `Transfer`
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, Eq, PartialEq)]
+pub struct Completed<'r, Id> {
+    /// The SPI bus that was used
+    pub bus: Id,
+
+    /// The frames received from the slave, or the reason the transfer failed
+    ///
+    /// The frames are encoded like the ones in [`Transfer`].
+    ///
+    /// [`Transfer`]: struct.Transfer.html
+    #[serde(borrow)]
+    pub result: Result<&'r [u8], Error>,
+}
+
+
+/// The configuration of an SPI peripheral
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, Eq, PartialEq)]
+pub struct Config {
+    /// Clock polarity and phase
+    pub mode: Mode,
+
+    /// Whether the most or least significant bit of a frame is sent first
+    pub bit_order: BitOrder,
+
+    /// The number of bits per frame
+    pub frame_size: u8,
+}
+
+impl Default for Config {
+    /// SPI mode 0, most significant bit first, 8-bit frames
+    fn default() -> Self {
+        Self {
+            mode:       MODE_0,
+            bit_order:  BitOrder::MsbFirst,
+            frame_size: 8,
+        }
+    }
+}
+
+
+/// Clock polarity and phase
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, Eq, PartialEq)]
+pub struct Mode {
+    /// The level of the clock signal while idle (CPOL)
+    pub polarity: Polarity,
+
+    /// The clock transition on which data is captured (CPHA)
+    pub phase: Phase,
+}
+
+/// CPOL = 0, CPHA = 0
+pub const MODE_0: Mode = Mode {
+    polarity: Polarity::IdleLow,
+    phase:    Phase::CaptureOnFirstTransition,
+};
+
+/// CPOL = 0, CPHA = 1
+pub const MODE_1: Mode = Mode {
+    polarity: Polarity::IdleLow,
+    phase:    Phase::CaptureOnSecondTransition,
+};
+
+/// CPOL = 1, CPHA = 0
+pub const MODE_2: Mode = Mode {
+    polarity: Polarity::IdleHigh,
+    phase:    Phase::CaptureOnFirstTransition,
+};
+
+/// CPOL = 1, CPHA = 1
+pub const MODE_3: Mode = Mode {
+    polarity: Polarity::IdleHigh,
+    phase:    Phase::CaptureOnSecondTransition,
+};
+
+/// The level of the clock signal while idle
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, Eq, PartialEq)]
+pub enum Polarity {
+    IdleLow,
+    IdleHigh,
+}
+
+/// The clock transition on which data is captured
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, Eq, PartialEq)]
+pub enum Phase {
+    CaptureOnFirstTransition,
+    CaptureOnSecondTransition,
+}
+
+/// Whether the most or least significant bit of a frame is sent first
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, Eq, PartialEq)]
+pub enum BitOrder {
+    MsbFirst,
+    LsbFirst,
+}
+
+
+/// How the master drives the chip select signal during a transfer
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, Eq, PartialEq)]
+pub enum ChipSelect {
+    /// Asserted for the whole transfer, then deasserted
+    Transfer,
+
+    /// Asserted for each frame separately
+    Frame,
+
+    /// Asserted for the whole transfer, and left asserted afterwards
+    ///
+    /// This allows the next transfer to continue the current one.
+    Hold,
+}
+
+
+/// The reason an SPI transfer failed
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, Eq, PartialEq)]
+pub enum Error {
+    /// The test node doesn't support the requested configuration
+    UnsupportedConfig,
+
+    /// The transfer is larger than the test node can handle
+    TooLong,
+
+    /// Any other error reported by the SPI peripheral
+    Other,
+}
+
+
+/// Sent by the host to command a test node to emulate an SPI slave device
+///
+/// The test node keeps emulating the device until it is reset, or commanded to
+/// emulate another one.
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, Eq, PartialEq)]
+pub struct Emulate<'r, Id> {
+    /// The SPI bus the device is connected to
+    pub bus: Id,
+
+    /// The device to emulate
+    #[serde(borrow)]
+    pub device: Device<'r>,
+}
+
+
+/// Sent by a test node in response to `Emulate`
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, Eq, PartialEq)]
+pub struct Emulated<Id> {
+    /// The SPI bus the device is connected to
+    pub bus: Id,
+
+    /// Whether the test node is emulating the device now, or why not
+    ///
+    /// The error is [`Error::TooLong`], if the device has more responses or
+    /// registers than the test node can emulate.
+    ///
+    /// [`Error::TooLong`]: enum.Error.html#variant.TooLong
+    pub result: Result<(), Error>,
+}
+
+
+/// Sent by the host to ask a test node what an SPI slave has received
+///
+/// The test node replies with [`Log`], then clears its log.
+///
+/// [`Log`]: struct.Log.html
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, Eq, PartialEq)]
+pub struct ReadLog<Id> {
+    /// The SPI bus the slave is connected to
+    pub bus: Id,
+}
+
+
+/// Sent by a test node in response to `ReadLog`
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, Eq, PartialEq)]
+pub struct Log<'r, Id> {
+    /// The SPI bus the slave is connected to
+    pub bus: Id,
+
+    /// The frames the slave has received, grouped by transfer
+    ///
+    /// A transfer starts when chip select is asserted, and ends when it is
+    /// deasserted. Each transfer is encoded as its number of frames (one byte),
+    /// followed by the frames, one byte each. Only the lower 8 bits of larger
+    /// frames are logged.
+    pub transfers: &'r [u8],
+
+    /// Whether frames were dropped, because the log was full
+    pub overflowed: bool,
+}
+
+
+/// An SPI slave device that a test node can emulate
+///
+/// Emulated devices work with 8-bit frames. Only the lower 8 bits of larger
+/// frames are considered. The device replies to each frame during the next
+/// one, so the reply to the last frame of a transfer is sent during the first
+/// frame of the next transfer.
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, Eq, PartialEq)]
+pub enum Device<'r> {
+    /// Sends the given frames in order, regardless of what it receives
+    ///
+    /// The first frame is sent during the first frame after the device was
+    /// set up. Once all frames have been sent, the device sends `0xff`.
+    Responses(&'r [u8]),
+
+    /// A device with a register map, like an SPI EEPROM
+    ///
+    /// The first frame of each transfer is a command, the second one selects a
+    /// register. If the command is `read`, the device sends the value of the
+    /// selected register during each following frame. If the command is
+    /// `write`, each following frame is written to the selected register. The
+    /// selected register advances after each frame. Any other command is
+    /// ignored.
+    Registers {
+        /// The initial contents of the registers
+        ///
+        /// Registers are numbered from zero. Reading from a register that
+        /// doesn't exist returns `0xff`, writing to it has no effect.
+        registers: &'r [u8],
+
+        /// The command that reads registers
+        read: u8,
+
+        /// The command that writes registers
+        write: u8,
+    },
+}
@@ -0,0 +1,436 @@
+//! Generic protocol related to USARTs
+//!
+//! The types in this module are not specific to any test stand setup, and can
+//! be re-used for different test stands.
+
+
+use serde::{
+    Deserialize,
+    Serialize,
+};
+
+
+/// Sent by the host to command a test node to configure a USART
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, Eq, PartialEq)]
+pub struct Configure<Id> {
+    /// The USART that should be configured
+    pub usart: Id,
+
+    /// The baud rate
+    pub baud: u32,
+
+    /// The number of data bits per frame
+    pub data_bits: DataBits,
+
+    /// The parity mode
+    pub parity: Parity,
+
+    /// The number of stop bits per frame
+    pub stop_bits: StopBits,
+
+    /// The flow control mode
+    pub flow_control: FlowControl,
+}
+
+impl<Id> Configure<Id> {
+    /// Create a configuration with the given baud rate and 8N1 framing
+    pub fn new(usart: Id, baud: u32) -> Self {
+        Self {
+            usart,
+            baud,
+            data_bits:    DataBits::default(),
+            parity:       Parity::default(),
+            stop_bits:    StopBits::default(),
+            flow_control: FlowControl::default(),
+        }
+    }
+}
+
+
+/// Sent by a test node in reply to [`Configure`]
+///
+/// [`Configure`]: struct.Configure.html
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, Eq, PartialEq)]
+pub struct Configured<Id> {
+    /// The USART that was configured
+    pub usart: Id,
+
+    /// The baud rate the USART actually runs at, or why it wasn't configured
+    ///
+    /// The actual baud rate can differ from the requested one, as the USART's
+    /// clock can only be divided by whole numbers.
+    pub result: Result<u32, Error>,
+}
+
+
+/// Sent by the host to command a test node to send data via a USART
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, Eq, PartialEq)]
+pub struct Send<'r, Id> {
+    /// The USART that should send the data
+    pub usart: Id,
+
+    /// The data to send
+    pub data: &'r [u8],
+}
+
+
+/// Sent by a test node in response to [`Send`], after it has sent the data
+///
+/// Nodes that send in the background reply once the data has been queued.
+///
+/// [`Send`]: struct.Send.html
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, Eq, PartialEq)]
+pub struct Sent<Id> {
+    /// The USART that sent the data
+    pub usart: Id,
+
+    /// Whether the data was sent, or why not
+    pub result: Result<(), Error>,
+}
+
+
+/// Sent by the host to command a test node to send data with a line fault
+///
+/// This is used to test how the receiving USART handles errors.
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, Eq, PartialEq)]
+pub struct Inject<'r, Id> {
+    /// The USART that should send the data
+    pub usart: Id,
+
+    /// The fault to inject
+    pub fault: Fault,
+
+    /// The data to send
+    pub data: &'r [u8],
+}
+
+
+/// Sent by a test node in response to [`Inject`], after sending the data
+///
+/// [`Inject`]: struct.Inject.html
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, Eq, PartialEq)]
+pub struct Injected<Id> {
+    /// The USART that sent the data
+    pub usart: Id,
+
+    /// Whether the data was sent with the fault, or why not
+    pub result: Result<(), Error>,
+}
+
+
+/// Sent by the host to command a test node to stall a sender via flow control
+///
+/// The node stops reading from the USART after it has received `after` more
+/// bytes. The next frame fills the USART's receive register, which makes the
+/// USART deassert RTS. A sender that respects flow control stops sending then,
+/// until the node is commanded to [`Release`] the USART.
+///
+/// The node drives RTS from the moment it receives this command, until it is
+/// reset.
+///
+/// [`Release`]: struct.Release.html
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, Eq, PartialEq)]
+pub struct Stall<Id> {
+    /// The USART that should stall the sender
+    pub usart: Id,
+
+    /// The number of bytes to receive before stalling the sender
+    pub after: u32,
+}
+
+
+/// Sent by a test node in response to [`Stall`], once it drives RTS
+///
+/// [`Stall`]: struct.Stall.html
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, Eq, PartialEq)]
+pub struct Stalled<Id> {
+    /// The USART that stalls the sender
+    pub usart: Id,
+
+    /// Whether the sender is going to be stalled, or why not
+    pub result: Result<(), Error>,
+}
+
+
+/// Sent by the host to command a test node to release a stalled sender
+///
+/// The node resumes reading from the USART, including the frame that is left
+/// in its receive register. See [`Stall`].
+///
+/// [`Stall`]: struct.Stall.html
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, Eq, PartialEq)]
+pub struct Release<Id> {
+    /// The USART that should release the sender
+    pub usart: Id,
+}
+
+
+/// Sent by a test node in response to [`Release`], after resuming to read
+///
+/// [`Release`]: struct.Release.html
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, Eq, PartialEq)]
+pub struct Released<Id> {
+    /// The USART that released the sender
+    pub usart: Id,
+
+    /// Whether the sender was released, or why not
+    pub result: Result<(), Error>,
+}
+
+
+/// Sent by the host to command a test node to measure a USART's timing
+///
+/// The node measures the timing of the frames on the USART's receive line,
+/// until it receives another `MeasureTiming` command, or is reset. Once it has
+/// started measuring, it replies with an empty [`Timing`]. The results can be
+/// requested at any time using [`ReadTiming`].
+///
+/// [`Timing`]: struct.Timing.html
+/// [`ReadTiming`]: struct.ReadTiming.html
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, Eq, PartialEq)]
+pub struct MeasureTiming<Id> {
+    /// The USART whose receive line should be measured
+    pub usart: Id,
+
+    /// The nominal baud rate of the sender
+    pub baud: u32,
+
+    /// The number of data bits per frame
+    pub data_bits: DataBits,
+
+    /// The parity mode
+    pub parity: Parity,
+
+    /// The number of stop bits per frame
+    pub stop_bits: StopBits,
+}
+
+impl<Id> MeasureTiming<Id> {
+    /// Measure frames with the given nominal baud rate and 8N1 framing
+    pub fn new(usart: Id, baud: u32) -> Self {
+        Self {
+            usart,
+            baud,
+            data_bits: DataBits::default(),
+            parity:    Parity::default(),
+            stop_bits: StopBits::default(),
+        }
+    }
+
+    /// The number of bits per frame, including start, parity, and stop bits
+    pub fn frame_bits(&self) -> u32 {
+        let data_bits = match self.data_bits {
+            DataBits::Seven => 7,
+            DataBits::Eight => 8,
+            DataBits::Nine  => 9,
+        };
+        let parity_bits = match self.parity {
+            Parity::None => 0,
+            _            => 1,
+        };
+        let stop_bits = match self.stop_bits {
+            StopBits::One => 1,
+            StopBits::Two => 2,
+        };
+
+        1 + data_bits + parity_bits + stop_bits
+    }
+}
+
+
+/// Sent by a test node in reply to [`MeasureTiming`], once it is measuring
+///
+/// [`MeasureTiming`]: struct.MeasureTiming.html
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, Eq, PartialEq)]
+pub struct TimingStarted<Id> {
+    /// The USART whose receive line is measured
+    pub usart: Id,
+
+    /// Whether the measurement has started, or why not
+    pub result: Result<(), Error>,
+}
+
+
+/// Sent by the host to request the results of a timing measurement
+///
+/// See [`MeasureTiming`].
+///
+/// [`MeasureTiming`]: struct.MeasureTiming.html
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, Eq, PartialEq)]
+pub struct ReadTiming<Id> {
+    /// The USART whose measurement results to read
+    pub usart: Id,
+}
+
+
+/// Sent by a test node in reply to [`ReadTiming`]
+///
+/// Covers all frames since the measurement started.
+///
+/// [`ReadTiming`]: struct.ReadTiming.html
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, Eq, PartialEq)]
+pub struct Timing<Id> {
+    /// The USART whose receive line was measured
+    pub usart: Id,
+
+    /// The number of frames that were measured
+    pub frames: u32,
+
+    /// The average bit time, in nanoseconds
+    ///
+    /// Only available once at least one frame has been measured.
+    pub bit_time_ns: Option<u32>,
+
+    /// The deviation of the bit time from nominal, in parts per million
+    ///
+    /// Positive, if the bits are longer than nominal, meaning the sender's baud
+    /// rate is too low.
+    pub deviation_ppm: Option<i32>,
+
+    /// The shortest gap between the end of a frame and the start of the next
+    /// one, in nanoseconds
+    ///
+    /// Only available once at least two frames have been measured.
+    pub min_gap_ns: Option<u32>,
+
+    /// The longest gap between the end of a frame and the start of the next
+    /// one, in nanoseconds
+    pub max_gap_ns: Option<u32>,
+
+    /// The number of gaps between frames that lasted at least one frame time
+    ///
+    /// These are the periods during which a receiver would consider the line
+    /// idle.
+    pub idle_periods: u32,
+
+    /// The number of edges that were missed by the measurement
+    ///
+    /// After a missed edge, the measurement ignores the line until it has been
+    /// idle for a frame time.
+    pub missed_edges: u32,
+}
+
+
+/// Sent by a test node, when it has received data via a USART
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, Eq, PartialEq)]
+pub struct Received<'r, Id> {
+    /// The USART that received the data
+    pub usart: Id,
+
+    /// The received data
+    pub data: &'r [u8],
+}
+
+
+/// Sent by a test node, when it has detected a condition on a USART's line
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, Eq, PartialEq)]
+pub struct LineEvent<Id> {
+    /// The USART that detected the condition
+    pub usart: Id,
+
+    /// The condition that was detected
+    pub condition: LineCondition,
+}
+
+
+/// A condition on a USART's line, other than the regular reception of data
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, Eq, PartialEq)]
+pub enum LineCondition {
+    /// A frame was received without a valid stop bit
+    FramingError,
+
+    /// A frame was received with the wrong parity
+    ParityError,
+
+    /// A frame was received before the previous one was read
+    Overrun,
+
+    /// A frame was received with noise on the line
+    Noise,
+
+    /// The line was held low for longer than a frame
+    Break,
+
+    /// The line became idle after receiving data
+    Idle,
+}
+
+
+/// A fault that a test node can inject when sending via a USART
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, Eq, PartialEq)]
+pub enum Fault {
+    /// Send each byte with an inverted parity bit
+    ///
+    /// Requires the sending USART to be configured with parity.
+    Parity,
+
+    /// Send each byte with a low level where the stop bit should be
+    ///
+    /// Requires the sending USART to be configured with 7 or 8 data bits, and
+    /// without parity.
+    Framing,
+
+    /// Hold the line low for at least 16 bit times, then send the data
+    Break,
+
+    /// Send the data at this baud rate, instead of the configured one
+    Baud(u32),
+}
+
+
+/// The reason a test node couldn't carry out a USART command
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, Eq, PartialEq)]
+pub enum Error {
+    /// The USART's configuration doesn't allow the requested fault
+    UnsupportedFault,
+
+    /// The USART can't be configured for the requested baud rate
+    UnsupportedBaud,
+
+    /// The USART can't be configured with the requested frame format or flow
+    /// control
+    UnsupportedConfig,
+
+    /// The node doesn't support the command for the requested USART
+    UnsupportedMode,
+
+    /// The node can't queue all of the data it was commanded to send
+    QueueFull,
+}
+
+
+/// Number of data bits per USART frame
+#[derive(Clone, Copy, Debug, Default, Deserialize, Serialize, Eq, PartialEq)]
+pub enum DataBits {
+    Seven,
+    #[default]
+    Eight,
+    Nine,
+}
+
+/// Parity mode of a USART
+#[derive(Clone, Copy, Debug, Default, Deserialize, Serialize, Eq, PartialEq)]
+pub enum Parity {
+    #[default]
+    None,
+    Odd,
+    Even,
+}
+
+/// Number of stop bits per USART frame
+#[derive(Clone, Copy, Debug, Default, Deserialize, Serialize, Eq, PartialEq)]
+pub enum StopBits {
+    #[default]
+    One,
+    Two,
+}
+
+/// Flow control mode of a USART
+#[derive(Clone, Copy, Debug, Default, Deserialize, Serialize, Eq, PartialEq)]
+pub enum FlowControl {
+    #[default]
+    None,
+
+    /// Flow control using the RTS and CTS signals
+    Hardware,
+}